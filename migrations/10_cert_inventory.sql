@@ -0,0 +1,47 @@
+-- searchable metadata for issued certificates, existing rows will be filled from their
+-- stored data by Nioca after the next start
+alter table certs_x509
+    add group_id    uuid,
+    add subject     varchar,
+    add sans        varchar[],
+    add fingerprint varchar;
+
+update certs_x509 c
+set group_id = cl.group_id
+from clients_x509 cl
+where c.client_id = cl.id;
+
+update certs_x509 c
+set group_id = a.group_id
+from acme_orders o
+         join acme_accounts a on a.id = o.account_id
+where o.cert_serial = c.serial;
+
+create index certs_x509_group_id_index
+    on certs_x509 (group_id);
+
+create index certs_x509_fingerprint_index
+    on certs_x509 (fingerprint);
+
+alter table certs_ssh
+    add group_id    uuid,
+    add key_id      varchar,
+    add principals  varchar[],
+    add fingerprint varchar;
+
+update certs_ssh c
+set group_id = cl.group_id
+from clients_ssh cl
+where c.client_id = cl.id;
+
+create index certs_ssh_group_id_index
+    on certs_ssh (group_id);
+
+create index certs_ssh_fingerprint_index
+    on certs_ssh (fingerprint);
+
+create index certs_ssh_client_id_index
+    on certs_ssh (client_id);
+
+create index certs_ssh_user_id_index
+    on certs_ssh (user_id);
@@ -0,0 +1,15 @@
+-- remembers which expiry notifications have been sent already, so each threshold is only
+-- notified about once, even with multiple instances
+create table notifications_expiry
+(
+    typ       varchar                  not null,
+    id        varchar                  not null,
+    expires   timestamp with time zone not null,
+    threshold integer                  not null,
+    sent      timestamp with time zone not null,
+    constraint notifications_expiry_pk
+        primary key (typ, id, expires, threshold)
+);
+
+create index notifications_expiry_expires_index
+    on notifications_expiry (expires);
@@ -0,0 +1,50 @@
+-- Each entry is hash-chained to the previous one and MAC'd with a key derived from the
+-- master key. The ids are gapless, which makes removed entries detectable.
+create table audit_log
+(
+    id        bigint                   not null
+        constraint audit_log_pk
+            primary key,
+    timestamp timestamp with time zone not null,
+    actor     varchar                  not null,
+    action    varchar                  not null,
+    target    varchar,
+    details   varchar,
+    success   boolean                  not null,
+    prev_hash bytea                    not null,
+    hash      bytea                    not null,
+    mac       bytea                    not null,
+    key_id    varchar                  not null
+);
+
+create index audit_log_timestamp_index
+    on audit_log (timestamp);
+
+create index audit_log_actor_index
+    on audit_log (actor);
+
+create index audit_log_action_index
+    on audit_log (action);
+
+create index audit_log_target_index
+    on audit_log (target);
+
+-- this does not stop a DBA, but any modification will be detected by the verification anyway
+create function audit_log_append_only() returns trigger as
+$$
+begin
+    raise exception 'audit_log is append-only';
+end;
+$$ language plpgsql;
+
+create trigger audit_log_no_update_delete
+    before update or delete
+    on audit_log
+    for each row
+execute function audit_log_append_only();
+
+create trigger audit_log_no_truncate
+    before truncate
+    on audit_log
+    for each statement
+execute function audit_log_append_only();
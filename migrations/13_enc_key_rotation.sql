@@ -0,0 +1,20 @@
+alter table enc_keys
+    add created timestamp with time zone;
+
+-- progress of the background re-encryption after an enc key rotation, one row per new key
+create table enc_key_rotations
+(
+    enc_key_id uuid                     not null
+        constraint enc_key_rotations_pk
+            primary key
+        constraint enc_key_rotations_enc_keys_id_fk
+            references enc_keys
+            on delete cascade,
+    started    timestamp with time zone not null,
+    updated    timestamp with time zone not null,
+    finished   timestamp with time zone,
+    total      bigint                   not null,
+    done       bigint                   not null,
+    failed     bigint                   not null,
+    error      varchar
+);
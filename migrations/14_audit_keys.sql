@@ -0,0 +1,9 @@
+-- audit log MAC keys derived from previous master keys, encrypted with the current one, which
+-- keeps older entries verifiable after a master key re-key
+create table audit_keys
+(
+    id    varchar not null
+        constraint audit_keys_pk
+            primary key,
+    value bytea   not null
+);
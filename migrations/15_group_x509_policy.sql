@@ -0,0 +1,3 @@
+-- the JSON encoded `X509Policy`, which restricts the certificates issued inside a group
+alter table groups
+    add x509_policy varchar;
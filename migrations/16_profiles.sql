@@ -0,0 +1,45 @@
+-- reusable certificate profiles, the `profile` is the JSON encoded `X509Profile` / `SshProfile`
+create table profiles
+(
+    id      uuid    not null
+        constraint profiles_pk
+            primary key,
+    name    varchar not null,
+    typ     varchar not null,
+    profile varchar not null
+);
+
+create unique index profiles_name_uindex
+    on profiles (name);
+
+alter table clients_x509
+    add profile_id uuid
+        constraint clients_x509_profiles_id_fk
+            references profiles
+            on update cascade on delete restrict;
+
+-- the JSON encoded `X509Profile` with values, which win over the linked profile
+alter table clients_x509
+    add profile_overrides varchar;
+
+alter table clients_ssh
+    add profile_id uuid
+        constraint clients_ssh_profiles_id_fk
+            references profiles
+            on update cascade on delete restrict;
+
+-- the JSON encoded `SshProfile` with values, which win over the linked profile
+alter table clients_ssh
+    add profile_overrides varchar;
+
+alter table users_group_access
+    add profile_ssh uuid
+        constraint users_group_access_profiles_ssh_fk
+            references profiles
+            on update cascade on delete restrict;
+
+alter table users_group_access
+    add profile_x509 uuid
+        constraint users_group_access_profiles_x509_fk
+            references profiles
+            on update cascade on delete restrict;
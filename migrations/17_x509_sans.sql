@@ -0,0 +1,16 @@
+-- URI, RFC822 email and UPN otherName SANs for X509 clients
+alter table clients_x509
+    add alt_names_uri varchar[] not null default '{}';
+
+alter table clients_x509
+    add alt_names_email varchar[] not null default '{}';
+
+alter table clients_x509
+    add alt_names_upn varchar[] not null default '{}';
+
+-- add the users email as RFC822 and / or UPN SAN to user X509 certificates
+alter table users_group_access
+    add x509_san_email bool not null default false;
+
+alter table users_group_access
+    add x509_san_upn bool not null default false;
@@ -0,0 +1,30 @@
+-- groups bound to a SPIFFE trust domain issue X.509-SVIDs and JWT-SVIDs
+alter table groups
+    add spiffe_trust_domain varchar;
+
+create unique index groups_spiffe_trust_domain_uindex
+    on groups (spiffe_trust_domain);
+
+-- the per-group keys JWT-SVIDs are signed with
+create table spiffe_jwt_keys
+(
+    kid        varchar                  not null
+        constraint spiffe_jwt_keys_pk
+            primary key,
+    group_id   uuid                     not null
+        constraint spiffe_jwt_keys_groups_id_fk
+            references groups
+            on delete cascade,
+    -- the encrypted PKCS#8 private key
+    key        bytea                    not null,
+    enc_key_id uuid                     not null
+        constraint spiffe_jwt_keys_enc_keys_id_fk
+            references enc_keys
+            on update cascade on delete restrict,
+    -- the uncompressed EC point of the public key
+    public_key bytea                    not null,
+    created    timestamp with time zone not null
+);
+
+create index spiffe_jwt_keys_group_id_index
+    on spiffe_jwt_keys (group_id);
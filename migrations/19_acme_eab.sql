@@ -0,0 +1,29 @@
+-- RFC 8555 external account binding keys, handed out by an admin per group
+create table acme_eab_keys
+(
+    kid        varchar                  not null
+        constraint acme_eab_keys_pk
+            primary key,
+    group_id   uuid                     not null
+        constraint acme_eab_keys_groups_id_fk
+            references groups
+            on update cascade on delete cascade,
+    -- the encrypted HMAC key
+    hmac_key   bytea                    not null,
+    enc_key_id uuid                     not null
+        constraint acme_eab_keys_enc_keys_id_fk
+            references enc_keys
+            on update cascade on delete restrict,
+    -- each key can only bind a single account
+    account_id uuid
+        constraint acme_eab_keys_acme_accounts_id_fk
+            references acme_accounts
+            on update cascade on delete set null,
+    created    timestamp with time zone not null
+);
+
+create index acme_eab_keys_group_id_index
+    on acme_eab_keys (group_id);
+
+alter table acme_accounts
+    add eab_kid varchar;
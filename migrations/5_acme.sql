@@ -0,0 +1,89 @@
+alter table groups
+    add acme_enabled boolean default false not null;
+
+create table acme_accounts
+(
+    id         uuid                     not null
+        constraint acme_accounts_pk
+            primary key,
+    group_id   uuid                     not null
+        constraint acme_accounts_groups_id_fk
+            references groups
+            on update cascade on delete cascade,
+    thumbprint varchar                  not null,
+    jwk        varchar                  not null,
+    contact    varchar,
+    status     varchar                  not null,
+    created    timestamp with time zone not null,
+    constraint acme_accounts_group_id_thumbprint_uindex
+        unique (group_id, thumbprint)
+);
+
+create table acme_orders
+(
+    id          uuid                     not null
+        constraint acme_orders_pk
+            primary key,
+    account_id  uuid                     not null
+        constraint acme_orders_acme_accounts_id_fk
+            references acme_accounts
+            on update cascade on delete cascade,
+    status      varchar                  not null,
+    expires     timestamp with time zone not null,
+    identifiers varchar                  not null,
+    error       varchar,
+    cert_serial integer
+        constraint acme_orders_certs_x509_serial_fk
+            references certs_x509
+            on update cascade on delete set null,
+    created     timestamp with time zone not null
+);
+
+create index acme_orders_account_id_index
+    on acme_orders (account_id);
+
+create table acme_authorizations
+(
+    id               uuid                     not null
+        constraint acme_authorizations_pk
+            primary key,
+    order_id         uuid                     not null
+        constraint acme_authorizations_acme_orders_id_fk
+            references acme_orders
+            on update cascade on delete cascade,
+    identifier_typ   varchar                  not null,
+    identifier_value varchar                  not null,
+    wildcard         boolean default false    not null,
+    status           varchar                  not null,
+    expires          timestamp with time zone not null
+);
+
+create index acme_authorizations_order_id_index
+    on acme_authorizations (order_id);
+
+create table acme_challenges
+(
+    id        uuid    not null
+        constraint acme_challenges_pk
+            primary key,
+    authz_id  uuid    not null
+        constraint acme_challenges_acme_authorizations_id_fk
+            references acme_authorizations
+            on update cascade on delete cascade,
+    typ       varchar not null,
+    token     varchar not null,
+    status    varchar not null,
+    validated timestamp with time zone,
+    error     varchar
+);
+
+create index acme_challenges_authz_id_index
+    on acme_challenges (authz_id);
+
+create table acme_nonces
+(
+    nonce   varchar                  not null
+        constraint acme_nonces_pk
+            primary key,
+    expires timestamp with time zone not null
+);
@@ -0,0 +1,22 @@
+create table certs_x509_revoked
+(
+    serial          integer                  not null
+        constraint certs_x509_revoked_pk
+            primary key
+        constraint certs_x509_revoked_certs_x509_serial_fk
+            references certs_x509
+            on update cascade on delete cascade,
+    reason          smallint                 not null,
+    revoked         timestamp with time zone not null,
+    invalidity_date timestamp with time zone,
+    revoked_by      varchar                  not null
+);
+
+create index certs_x509_revoked_revoked_index
+    on certs_x509_revoked (revoked desc);
+
+create index certs_x509_client_id_index
+    on certs_x509 (client_id);
+
+create index certs_x509_user_id_index
+    on certs_x509 (user_id);
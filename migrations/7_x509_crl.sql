@@ -0,0 +1,32 @@
+alter table certs_x509
+    add ca_id uuid;
+
+-- best effort for already existing certificates: the CA of the group they have been issued for
+update certs_x509 c
+set ca_id = g.ca_x509
+from clients_x509 cl
+         join groups g on g.id = cl.group_id
+where c.client_id = cl.id;
+
+update certs_x509 c
+set ca_id = g.ca_x509
+from acme_orders o
+         join acme_accounts a on a.id = o.account_id
+         join groups g on g.id = a.group_id
+where c.serial = o.cert_serial;
+
+create index certs_x509_ca_id_index
+    on certs_x509 (ca_id);
+
+create table crls_x509
+(
+    ca_id       uuid                  not null
+        constraint crls_x509_pk
+            primary key,
+    cdp_enabled boolean default true  not null,
+    cdp_url     varchar,
+    number      bigint  default 0     not null,
+    this_update timestamp with time zone,
+    next_update timestamp with time zone,
+    data        bytea
+);
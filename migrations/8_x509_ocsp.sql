@@ -0,0 +1,15 @@
+create table ocsp_x509
+(
+    ca_id             uuid                 not null
+        constraint ocsp_x509_pk
+            primary key,
+    aia_enabled       boolean default true  not null,
+    delegated         boolean default false not null,
+    signer_cert       bytea,
+    signer_key        bytea,
+    signer_enc_key_id uuid
+        constraint ocsp_x509_enc_keys_id_fk
+            references enc_keys
+            on update cascade on delete restrict,
+    signer_expires    timestamp with time zone
+);
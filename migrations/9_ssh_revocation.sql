@@ -0,0 +1,55 @@
+alter table certs_ssh
+    add ca_id uuid;
+
+-- certificates issued for clients can be mapped via their group, user certificates
+-- are resolved via their signature key when they are revoked
+update certs_ssh c
+set ca_id = g.ca_ssh
+from clients_ssh cl
+         join groups g on g.id = cl.group_id
+where c.client_id = cl.id;
+
+create index certs_ssh_ca_id_index
+    on certs_ssh (ca_id);
+
+create table certs_ssh_revoked
+(
+    serial     integer                  not null
+        constraint certs_ssh_revoked_pk
+            primary key
+        constraint certs_ssh_revoked_certs_ssh_serial_fk
+            references certs_ssh
+            on update cascade on delete cascade,
+    ca_id      uuid                     not null
+        constraint certs_ssh_revoked_ca_certs_ssh_id_fk
+            references ca_certs_ssh
+            on delete cascade,
+    revoked    timestamp with time zone not null,
+    revoked_by varchar                  not null
+);
+
+create index certs_ssh_revoked_ca_id_index
+    on certs_ssh_revoked (ca_id);
+
+create table ssh_revoked_key_ids
+(
+    ca_id      uuid                     not null
+        constraint ssh_revoked_key_ids_ca_certs_ssh_id_fk
+            references ca_certs_ssh
+            on delete cascade,
+    key_id     varchar                  not null,
+    revoked    timestamp with time zone not null,
+    revoked_by varchar                  not null,
+    constraint ssh_revoked_key_ids_pk
+        primary key (ca_id, key_id)
+);
+
+create table ssh_revoked_keys
+(
+    fingerprint varchar                  not null
+        constraint ssh_revoked_keys_pk
+            primary key,
+    public_key  varchar                  not null,
+    revoked     timestamp with time zone not null,
+    revoked_by  varchar                  not null
+);
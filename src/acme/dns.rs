@@ -0,0 +1,370 @@
+use crate::acme::b64_url_encode;
+use crate::acme::error::{AcmeError, AcmeErrorType};
+use crate::constants::{
+    ACME_DNS_RESOLVERS, ACME_DNS_UPDATE_SERVER, ACME_DNS_UPDATE_TSIG_ALG,
+    ACME_DNS_UPDATE_TSIG_NAME, ACME_DNS_UPDATE_TSIG_SECRET, ACME_DNS_UPDATE_TTL,
+    ACME_DNS_UPDATE_ZONES,
+};
+use base64::{engine::general_purpose, Engine as _};
+use hickory_proto::op::{update_message, Message, ResponseCode};
+use hickory_proto::rr::dnssec::rdata::tsig::TsigAlgorithm;
+use hickory_proto::rr::dnssec::tsig::TSigner;
+use hickory_proto::rr::rdata::TXT;
+use hickory_proto::rr::{Name, RData, Record, RecordSet, RecordType};
+use hickory_resolver::config::{
+    NameServerConfig, NameServerConfigGroup, Protocol, ResolverConfig, ResolverOpts,
+};
+use hickory_resolver::error::ResolveErrorKind;
+use hickory_resolver::TokioAsyncResolver;
+use once_cell::sync::Lazy;
+use ring::digest;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::time::Duration;
+use time::OffsetDateTime;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tracing::debug;
+
+/// The optional RFC 2136 updater, only available if it has been fully configured
+pub static DNS_UPDATER: Lazy<Option<DnsUpdater>> = Lazy::new(DnsUpdater::from_env);
+
+/// The TXT record value for a DNS-01 challenge from RFC 8555 Section 8.4
+pub fn dns01_txt_value(key_authz: &str) -> String {
+    let hash = digest::digest(&digest::SHA256, key_authz.as_bytes());
+    b64_url_encode(hash.as_ref())
+}
+
+/// The record name a DNS-01 challenge for the given domain is published at
+fn dns01_record_name(domain: &str) -> String {
+    format!("_acme-challenge.{}.", domain)
+}
+
+/// DNS-01 validation from RFC 8555 Section 8.4
+pub async fn validate_dns01(domain: &str, key_authz: &str) -> Result<(), AcmeError> {
+    let name = dns01_record_name(domain);
+    let expected = dns01_txt_value(key_authz);
+    debug!("Validating DNS-01 challenge: {}", name);
+
+    let lookup = resolver()?
+        .txt_lookup(name.as_str())
+        .await
+        .map_err(|err| match err.kind() {
+            ResolveErrorKind::NoRecordsFound { .. } => AcmeError::new(
+                AcmeErrorType::Incorrect,
+                format!("No TXT record found for {}", name),
+            ),
+            _ => AcmeError::new(
+                AcmeErrorType::Dns,
+                format!("DNS lookup for {} failed: {}", name, err),
+            ),
+        })?;
+
+    // a TXT record may be split into multiple character strings
+    let found = lookup.iter().any(|txt| {
+        let value = txt
+            .txt_data()
+            .iter()
+            .flat_map(|data| data.iter().copied())
+            .collect::<Vec<u8>>();
+        value == expected.as_bytes()
+    });
+    if !found {
+        return Err(AcmeError::new(
+            AcmeErrorType::Incorrect,
+            format!("None of the TXT records for {} match", name),
+        ));
+    }
+
+    Ok(())
+}
+
+fn resolver() -> Result<TokioAsyncResolver, AcmeError> {
+    let (config, mut opts) = if ACME_DNS_RESOLVERS.is_empty() {
+        hickory_resolver::system_conf::read_system_conf().map_err(|err| {
+            AcmeError::new(
+                AcmeErrorType::ServerInternal,
+                format!("Cannot read the system DNS config: {}", err),
+            )
+        })?
+    } else {
+        let servers = ACME_DNS_RESOLVERS
+            .iter()
+            .flat_map(|addr| {
+                [
+                    NameServerConfig::new(*addr, Protocol::Udp),
+                    NameServerConfig::new(*addr, Protocol::Tcp),
+                ]
+            })
+            .collect::<Vec<_>>();
+        let config = ResolverConfig::from_parts(None, vec![], NameServerConfigGroup::from(servers));
+        (config, ResolverOpts::default())
+    };
+
+    // we always want to see the current state and never a cached one
+    opts.cache_size = 0;
+    opts.timeout = Duration::from_secs(5);
+
+    Ok(TokioAsyncResolver::tokio(config, opts))
+}
+
+/// The record a DNS-01 challenge is published at and the zone it belongs to
+#[derive(Debug, Clone)]
+pub struct DnsUpdateTarget {
+    pub zone: Name,
+    pub record: Name,
+}
+
+/// Publishes and removes DNS-01 TXT records via RFC 2136 dynamic updates signed with TSIG.
+/// This is only possible for zones which have been delegated to Nioca.
+pub struct DnsUpdater {
+    server: SocketAddr,
+    zones: Vec<Name>,
+    signer: TSigner,
+    ttl: u32,
+}
+
+impl DnsUpdater {
+    fn from_env() -> Option<Self> {
+        let server = (*ACME_DNS_UPDATE_SERVER)?;
+        if ACME_DNS_UPDATE_ZONES.is_empty() {
+            return None;
+        }
+        let key_name = ACME_DNS_UPDATE_TSIG_NAME
+            .as_deref()
+            .expect("ACME_DNS_UPDATE_TSIG_NAME must be set with ACME_DNS_UPDATE_SERVER");
+        let secret = ACME_DNS_UPDATE_TSIG_SECRET
+            .as_deref()
+            .map(|s| general_purpose::STANDARD.decode(s.trim()))
+            .expect("ACME_DNS_UPDATE_TSIG_SECRET must be set with ACME_DNS_UPDATE_SERVER")
+            .expect("ACME_DNS_UPDATE_TSIG_SECRET is not valid base64");
+
+        Some(Self::new(
+            server,
+            &ACME_DNS_UPDATE_ZONES,
+            key_name,
+            &ACME_DNS_UPDATE_TSIG_ALG,
+            secret,
+            *ACME_DNS_UPDATE_TTL,
+        ))
+    }
+
+    pub(crate) fn new(
+        server: SocketAddr,
+        zones: &[String],
+        key_name: &str,
+        alg: &str,
+        secret: Vec<u8>,
+        ttl: u32,
+    ) -> Self {
+        let algorithm = match alg.to_lowercase().as_str() {
+            "hmac-sha256" => TsigAlgorithm::HmacSha256,
+            "hmac-sha384" => TsigAlgorithm::HmacSha384,
+            "hmac-sha512" => TsigAlgorithm::HmacSha512,
+            _ => panic!(
+                "ACME_DNS_UPDATE_TSIG_ALG must be one of hmac-sha256, hmac-sha384, hmac-sha512"
+            ),
+        };
+        let signer_name =
+            Name::from_str(key_name).expect("ACME_DNS_UPDATE_TSIG_NAME is not a valid DNS name");
+        let signer = TSigner::new(secret, algorithm, signer_name, 300)
+            .expect("Cannot build the TSIG signer");
+
+        let zones = zones
+            .iter()
+            .map(|z| {
+                Name::from_str(&format!("{}.", z))
+                    .expect("ACME_DNS_UPDATE_ZONES contains an invalid zone")
+            })
+            .collect();
+
+        Self {
+            server,
+            zones,
+            signer,
+            ttl,
+        }
+    }
+
+    /// The configured zones for logging
+    pub fn zones(&self) -> String {
+        self.zones
+            .iter()
+            .map(|z| z.to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// Finds the record the challenge for the domain must be published at, if it is part of
+    /// one of our zones. A `CNAME` on the challenge name into one of our zones is followed.
+    pub async fn target_for(&self, domain: &str) -> Option<DnsUpdateTarget> {
+        let name = Name::from_str(&dns01_record_name(domain)).ok()?;
+        if let Some(zone) = self.zone_of(&name) {
+            return Some(DnsUpdateTarget { zone, record: name });
+        }
+
+        let lookup = resolver()
+            .ok()?
+            .lookup(name, RecordType::CNAME)
+            .await
+            .ok()?;
+        let record = lookup.iter().find_map(|rdata| match rdata {
+            RData::CNAME(cname) => Some(cname.0.clone()),
+            _ => None,
+        })?;
+        let zone = self.zone_of(&record)?;
+        Some(DnsUpdateTarget { zone, record })
+    }
+
+    /// Returns the most specific configured zone the name is part of
+    fn zone_of(&self, name: &Name) -> Option<Name> {
+        self.zones
+            .iter()
+            .filter(|zone| zone.zone_of(name))
+            .max_by_key(|zone| zone.num_labels())
+            .cloned()
+    }
+
+    /// Adds the TXT record for the challenge
+    pub async fn publish(
+        &self,
+        target: &DnsUpdateTarget,
+        key_authz: &str,
+    ) -> Result<(), AcmeError> {
+        let rrset = self.record_set(target, key_authz);
+        self.send(update_message::append(
+            rrset,
+            target.zone.clone(),
+            false,
+            false,
+        ))
+        .await
+    }
+
+    /// Removes exactly the TXT record for the challenge while leaving all others untouched
+    pub async fn remove(&self, target: &DnsUpdateTarget, key_authz: &str) -> Result<(), AcmeError> {
+        let rrset = self.record_set(target, key_authz);
+        self.send(update_message::delete_by_rdata(
+            rrset,
+            target.zone.clone(),
+            false,
+        ))
+        .await
+    }
+
+    fn record_set(&self, target: &DnsUpdateTarget, key_authz: &str) -> RecordSet {
+        let rdata = RData::TXT(TXT::new(vec![dns01_txt_value(key_authz)]));
+        RecordSet::from(Record::from_rdata(target.record.clone(), self.ttl, rdata))
+    }
+
+    /// Signs the message and sends it via TCP to the configured server
+    async fn send(&self, mut msg: Message) -> Result<(), AcmeError> {
+        let now = OffsetDateTime::now_utc().unix_timestamp() as u32;
+        let verifier = msg.finalize(&self.signer, now).map_err(Self::err)?;
+        let req = msg.to_vec().map_err(Self::err)?;
+
+        let resp = tokio::time::timeout(Duration::from_secs(10), async {
+            let mut stream = TcpStream::connect(self.server).await?;
+            // DNS over TCP prefixes each message with its length
+            stream.write_all(&(req.len() as u16).to_be_bytes()).await?;
+            stream.write_all(&req).await?;
+            let mut len = [0u8; 2];
+            stream.read_exact(&mut len).await?;
+            let mut buf = vec![0u8; u16::from_be_bytes(len) as usize];
+            stream.read_exact(&mut buf).await?;
+            Ok::<_, std::io::Error>(buf)
+        })
+        .await
+        .map_err(|_| Self::err("Timeout while sending the DNS update"))?
+        .map_err(Self::err)?;
+
+        let resp_msg = Message::from_vec(&resp).map_err(Self::err)?;
+        if resp_msg.id() != msg.id() {
+            return Err(Self::err("DNS update response id does not match"));
+        }
+        if resp_msg.response_code() != ResponseCode::NoError {
+            return Err(Self::err(format!(
+                "DNS update has been rejected: {}",
+                resp_msg.response_code()
+            )));
+        }
+        if let Some(mut verifier) = verifier {
+            verifier(&resp).map_err(Self::err)?;
+        }
+
+        Ok(())
+    }
+
+    fn err<E: ToString>(err: E) -> AcmeError {
+        AcmeError::new(AcmeErrorType::Dns, err.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn updater() -> DnsUpdater {
+        DnsUpdater::new(
+            "127.0.0.1:53".parse().unwrap(),
+            &["example.com".to_string(), "int.example.com".to_string()],
+            "nioca-key",
+            "hmac-sha256",
+            b"SuperSecretTsigKey".to_vec(),
+            60,
+        )
+    }
+
+    #[test]
+    fn test_dns01_txt_value() {
+        let value = dns01_txt_value("token.thumbprint");
+        // base64url encoded SHA-256 without padding
+        assert_eq!(value.len(), 43);
+        assert!(!value.contains('='));
+        assert_eq!(value, dns01_txt_value("token.thumbprint"));
+        assert_ne!(value, dns01_txt_value("token.other"));
+    }
+
+    #[test]
+    fn test_zone_of() {
+        let updater = updater();
+        let zone_of = |domain: &str| {
+            let name = Name::from_str(&dns01_record_name(domain)).unwrap();
+            updater.zone_of(&name).map(|z| z.to_string())
+        };
+        assert_eq!(
+            zone_of("db.int.example.com").as_deref(),
+            Some("int.example.com.")
+        );
+        assert_eq!(zone_of("example.com").as_deref(), Some("example.com."));
+        assert_eq!(zone_of("example.org"), None);
+    }
+
+    #[test]
+    fn test_tsig_signed_update() {
+        let updater = updater();
+        let target = DnsUpdateTarget {
+            zone: Name::from_str("example.com.").unwrap(),
+            record: Name::from_str("_acme-challenge.example.com.").unwrap(),
+        };
+        let rrset = updater.record_set(&target, "token.thumbprint");
+        let mut msg = update_message::append(rrset, target.zone, false, false);
+        msg.finalize(&updater.signer, 1_700_000_000).unwrap();
+
+        let bytes = msg.to_vec().unwrap();
+        assert!(updater
+            .signer
+            .verify_message_byte(None, &bytes, true)
+            .is_ok());
+
+        let other = TSigner::new(
+            b"AnotherTsigKey".to_vec(),
+            TsigAlgorithm::HmacSha256,
+            Name::from_str("nioca-key").unwrap(),
+            300,
+        )
+        .unwrap();
+        assert!(other.verify_message_byte(None, &bytes, true).is_err());
+    }
+}
@@ -0,0 +1,127 @@
+use crate::models::api::error_response::{ErrorResponse, ErrorResponseType};
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde::Serialize;
+use tracing::error;
+
+/// The ACME error types from RFC 8555 Section 6.7 we make use of
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AcmeErrorType {
+    AccountDoesNotExist,
+    BadCsr,
+    BadNonce,
+    BadSignatureAlgorithm,
+    Connection,
+    Dns,
+    Incorrect,
+    Malformed,
+    OrderNotReady,
+    RejectedIdentifier,
+    ServerInternal,
+    Unauthorized,
+    UnsupportedIdentifier,
+}
+
+impl AcmeErrorType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::AccountDoesNotExist => "accountDoesNotExist",
+            Self::BadCsr => "badCSR",
+            Self::BadNonce => "badNonce",
+            Self::BadSignatureAlgorithm => "badSignatureAlgorithm",
+            Self::Connection => "connection",
+            Self::Dns => "dns",
+            Self::Incorrect => "incorrectResponse",
+            Self::Malformed => "malformed",
+            Self::OrderNotReady => "orderNotReady",
+            Self::RejectedIdentifier => "rejectedIdentifier",
+            Self::ServerInternal => "serverInternal",
+            Self::Unauthorized => "unauthorized",
+            Self::UnsupportedIdentifier => "unsupportedIdentifier",
+        }
+    }
+
+    fn status(&self) -> StatusCode {
+        match self {
+            Self::Unauthorized => StatusCode::FORBIDDEN,
+            Self::ServerInternal => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::OrderNotReady => StatusCode::FORBIDDEN,
+            _ => StatusCode::BAD_REQUEST,
+        }
+    }
+}
+
+/// An RFC 7807 problem document with an ACME error type
+#[derive(Debug, Clone)]
+pub struct AcmeError {
+    pub typ: AcmeErrorType,
+    pub detail: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AcmeProblem {
+    #[serde(rename = "type")]
+    pub typ: String,
+    pub detail: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<u16>,
+}
+
+impl AcmeError {
+    pub fn new(typ: AcmeErrorType, detail: impl Into<String>) -> Self {
+        Self {
+            typ,
+            detail: detail.into(),
+        }
+    }
+
+    /// The problem document which will be saved for failed challenges and orders
+    pub fn problem(&self, status: Option<u16>) -> AcmeProblem {
+        AcmeProblem {
+            typ: format!("urn:ietf:params:acme:error:{}", self.typ.as_str()),
+            detail: self.detail.clone(),
+            status,
+        }
+    }
+}
+
+impl IntoResponse for AcmeError {
+    fn into_response(self) -> Response {
+        let status = self.typ.status();
+        (
+            status,
+            [(header::CONTENT_TYPE, "application/problem+json")],
+            Json(self.problem(Some(status.as_u16()))),
+        )
+            .into_response()
+    }
+}
+
+impl From<ErrorResponse> for AcmeError {
+    fn from(value: ErrorResponse) -> Self {
+        let typ = match value.typ {
+            ErrorResponseType::BadRequest | ErrorResponseType::NotFound => AcmeErrorType::Malformed,
+            ErrorResponseType::Forbidden
+            | ErrorResponseType::Unauthorized
+            | ErrorResponseType::InvalidToken => AcmeErrorType::Unauthorized,
+            _ => {
+                error!("ACME internal error: {:?}", value);
+                AcmeErrorType::ServerInternal
+            }
+        };
+        Self::new(typ, value.message)
+    }
+}
+
+impl From<serde_json::Error> for AcmeError {
+    fn from(value: serde_json::Error) -> Self {
+        Self::new(AcmeErrorType::Malformed, value.to_string())
+    }
+}
+
+impl From<base64::DecodeError> for AcmeError {
+    fn from(_: base64::DecodeError) -> Self {
+        Self::new(AcmeErrorType::Malformed, "Invalid base64url encoding")
+    }
+}
@@ -0,0 +1,424 @@
+use crate::acme::error::{AcmeError, AcmeErrorType};
+use crate::acme::models::AcmeStatus;
+use crate::acme::{acme_url, b64_url_decode, b64_url_encode};
+use crate::models::db::acme_account::AcmeAccountEntity;
+use crate::models::db::acme_nonce::AcmeNonceEntity;
+use ring::{digest, hmac, signature};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use tracing::debug;
+use uuid::Uuid;
+
+/// A JWS in flattened JSON serialization, which is the only one allowed by RFC 8555
+#[derive(Debug, Deserialize)]
+struct JwsFlattened {
+    protected: String,
+    payload: String,
+    signature: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct JwsProtected {
+    alg: String,
+    nonce: Option<String>,
+    url: String,
+    jwk: Option<Jwk>,
+    kid: Option<String>,
+}
+
+/// A public JWK as it is used for ACME account keys
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Jwk {
+    pub kty: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub crv: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub x: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub y: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub n: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub e: Option<String>,
+}
+
+impl Jwk {
+    /// The RFC 7638 JWK thumbprint with SHA-256
+    pub fn thumbprint(&self) -> Result<String, AcmeError> {
+        // the required members in lexicographic order without any whitespace
+        let canonical = match self.kty.as_str() {
+            "EC" => format!(
+                r#"{{"crv":"{}","kty":"EC","x":"{}","y":"{}"}}"#,
+                Self::member(&self.crv)?,
+                Self::member(&self.x)?,
+                Self::member(&self.y)?,
+            ),
+            "OKP" => format!(
+                r#"{{"crv":"{}","kty":"OKP","x":"{}"}}"#,
+                Self::member(&self.crv)?,
+                Self::member(&self.x)?,
+            ),
+            "RSA" => format!(
+                r#"{{"e":"{}","kty":"RSA","n":"{}"}}"#,
+                Self::member(&self.e)?,
+                Self::member(&self.n)?,
+            ),
+            _ => return Err(Self::unsupported()),
+        };
+        let hash = digest::digest(&digest::SHA256, canonical.as_bytes());
+        Ok(b64_url_encode(hash.as_ref()))
+    }
+
+    /// Verifies a JWS signature over `msg` with this key
+    pub fn verify(&self, alg: &str, msg: &[u8], sig: &[u8]) -> Result<(), AcmeError> {
+        let res = match (alg, self.kty.as_str(), self.crv.as_deref()) {
+            ("ES256", "EC", Some("P-256")) => signature::UnparsedPublicKey::new(
+                &signature::ECDSA_P256_SHA256_FIXED,
+                self.ec_point()?,
+            )
+            .verify(msg, sig),
+            ("ES384", "EC", Some("P-384")) => signature::UnparsedPublicKey::new(
+                &signature::ECDSA_P384_SHA384_FIXED,
+                self.ec_point()?,
+            )
+            .verify(msg, sig),
+            ("EdDSA", "OKP", Some("Ed25519")) => {
+                let x = b64_url_decode(Self::member(&self.x)?)?;
+                signature::UnparsedPublicKey::new(&signature::ED25519, x).verify(msg, sig)
+            }
+            ("RS256", "RSA", _) => {
+                let n = b64_url_decode(Self::member(&self.n)?)?;
+                let e = b64_url_decode(Self::member(&self.e)?)?;
+                signature::RsaPublicKeyComponents { n, e }.verify(
+                    &signature::RSA_PKCS1_2048_8192_SHA256,
+                    msg,
+                    sig,
+                )
+            }
+            _ => {
+                return Err(AcmeError::new(
+                    AcmeErrorType::BadSignatureAlgorithm,
+                    format!("Unsupported JWS algorithm '{}' for the given key", alg),
+                ))
+            }
+        };
+
+        res.map_err(|_| AcmeError::new(AcmeErrorType::Malformed, "Invalid JWS signature"))
+    }
+
+    /// The uncompressed EC point `0x04 || x || y`
+    fn ec_point(&self) -> Result<Vec<u8>, AcmeError> {
+        let x = b64_url_decode(Self::member(&self.x)?)?;
+        let y = b64_url_decode(Self::member(&self.y)?)?;
+        let mut point = Vec::with_capacity(1 + x.len() + y.len());
+        point.push(0x04);
+        point.extend(x);
+        point.extend(y);
+        Ok(point)
+    }
+
+    fn member(value: &Option<String>) -> Result<&str, AcmeError> {
+        value.as_deref().ok_or_else(Self::unsupported)
+    }
+
+    fn unsupported() -> AcmeError {
+        AcmeError::new(AcmeErrorType::BadSignatureAlgorithm, "Unsupported JWK")
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct EabProtected {
+    alg: String,
+    kid: String,
+    url: String,
+    nonce: Option<String>,
+}
+
+/// The `externalAccountBinding` of a new account request from RFC 8555 Section 7.3.4: a JWS
+/// over the account key, MAC'd with a key an admin has handed out for the group.
+#[derive(Debug, Deserialize)]
+#[serde(transparent)]
+pub struct ExternalAccountBinding(JwsFlattened);
+
+impl ExternalAccountBinding {
+    /// The `kid` of the external account key. It is not authenticated before `verify()`.
+    pub fn kid(&self) -> Result<String, AcmeError> {
+        Ok(self.protected()?.kid)
+    }
+
+    /// Verifies the MAC and makes sure the binding was made for the given URL and account key
+    pub fn verify(&self, hmac_key: &[u8], url: &str, jwk: &Jwk) -> Result<(), AcmeError> {
+        let protected = self.protected()?;
+        let alg = match protected.alg.as_str() {
+            "HS256" => hmac::HMAC_SHA256,
+            "HS384" => hmac::HMAC_SHA384,
+            "HS512" => hmac::HMAC_SHA512,
+            _ => {
+                return Err(AcmeError::new(
+                    AcmeErrorType::BadSignatureAlgorithm,
+                    format!(
+                        "Unsupported external account binding algorithm '{}'",
+                        protected.alg
+                    ),
+                ))
+            }
+        };
+        if protected.nonce.is_some() || protected.url != url {
+            return Err(AcmeError::new(
+                AcmeErrorType::Malformed,
+                "The external account binding must contain the request 'url' and no 'nonce'",
+            ));
+        }
+
+        let sig = b64_url_decode(&self.0.signature)?;
+        let msg = format!("{}.{}", self.0.protected, self.0.payload);
+        hmac::verify(&hmac::Key::new(alg, hmac_key), msg.as_bytes(), &sig).map_err(|_| {
+            AcmeError::new(
+                AcmeErrorType::Unauthorized,
+                "Invalid external account binding",
+            )
+        })?;
+
+        let bound = serde_json::from_slice::<Jwk>(&b64_url_decode(&self.0.payload)?)?;
+        if &bound != jwk {
+            return Err(AcmeError::new(
+                AcmeErrorType::Unauthorized,
+                "The external account binding was made for another account key",
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn protected(&self) -> Result<EabProtected, AcmeError> {
+        let res = serde_json::from_slice::<EabProtected>(&b64_url_decode(&self.0.protected)?)?;
+        Ok(res)
+    }
+}
+
+/// How the account key must be referenced in the JWS protected header
+#[derive(Debug, PartialEq, Eq)]
+pub enum AcmeKeyRef {
+    /// Only for `new-account` - the account key is given as `jwk`
+    Jwk,
+    /// Everything else - the account URL is given as `kid`
+    Kid,
+}
+
+/// A verified ACME request
+#[derive(Debug)]
+pub struct AcmeRequest {
+    pub payload: Vec<u8>,
+    pub jwk: Jwk,
+    pub account: Option<AcmeAccountEntity>,
+}
+
+impl AcmeRequest {
+    /// Verifies a POST request body for the given resource path inside the groups directory.
+    /// This checks the URL, consumes the replay nonce, resolves the account if a `kid` is used
+    /// and finally validates the signature.
+    pub async fn verify(
+        group_id: &Uuid,
+        path: &str,
+        key_ref: AcmeKeyRef,
+        body: &[u8],
+    ) -> Result<Self, AcmeError> {
+        let jws = serde_json::from_slice::<JwsFlattened>(body)?;
+        let protected = serde_json::from_slice::<JwsProtected>(&b64_url_decode(&jws.protected)?)?;
+
+        if protected.url != acme_url(group_id, path) {
+            return Err(AcmeError::new(
+                AcmeErrorType::Unauthorized,
+                "The 'url' in the JWS header does not match the request URL",
+            ));
+        }
+
+        let nonce = protected.nonce.as_deref().unwrap_or_default();
+        if !AcmeNonceEntity::consume(nonce).await? {
+            return Err(AcmeError::new(
+                AcmeErrorType::BadNonce,
+                "Invalid or expired nonce",
+            ));
+        }
+
+        let (jwk, account) = match (key_ref, protected.jwk, protected.kid) {
+            (AcmeKeyRef::Jwk, Some(jwk), None) => (jwk, None),
+            (AcmeKeyRef::Kid, None, Some(kid)) => {
+                let account = Self::account_from_kid(group_id, &kid).await?;
+                let jwk = serde_json::from_str::<Jwk>(&account.jwk)?;
+                (jwk, Some(account))
+            }
+            _ => {
+                return Err(AcmeError::new(
+                    AcmeErrorType::Malformed,
+                    "Exactly one of 'jwk' or 'kid' must be given in the JWS header",
+                ))
+            }
+        };
+
+        let sig = b64_url_decode(&jws.signature)?;
+        let msg = format!("{}.{}", jws.protected, jws.payload);
+        jwk.verify(&protected.alg, msg.as_bytes(), &sig)?;
+
+        Ok(Self {
+            payload: b64_url_decode(&jws.payload)?,
+            jwk,
+            account,
+        })
+    }
+
+    /// Returns the account for a `kid` request
+    pub fn account(&self) -> Result<&AcmeAccountEntity, AcmeError> {
+        self.account.as_ref().ok_or_else(|| {
+            AcmeError::new(
+                AcmeErrorType::Malformed,
+                "This request must be made with an account 'kid'",
+            )
+        })
+    }
+
+    /// An empty payload marks a POST-as-GET request
+    pub fn is_post_as_get(&self) -> bool {
+        self.payload.is_empty()
+    }
+
+    pub fn payload<T: DeserializeOwned>(&self) -> Result<T, AcmeError> {
+        let res = serde_json::from_slice::<T>(&self.payload)?;
+        Ok(res)
+    }
+
+    async fn account_from_kid(group_id: &Uuid, kid: &str) -> Result<AcmeAccountEntity, AcmeError> {
+        let prefix = acme_url(group_id, "account/");
+        let id = kid
+            .strip_prefix(&prefix)
+            .and_then(|id| Uuid::from_str(id).ok())
+            .ok_or_else(|| {
+                AcmeError::new(AcmeErrorType::AccountDoesNotExist, "Unknown account 'kid'")
+            })?;
+
+        let account = match AcmeAccountEntity::find(&id).await {
+            Ok(account) if &account.group_id == group_id => account,
+            _ => {
+                debug!("ACME account {} not found in group {}", id, group_id);
+                return Err(AcmeError::new(
+                    AcmeErrorType::AccountDoesNotExist,
+                    "Unknown account 'kid'",
+                ));
+            }
+        };
+        if account.status != AcmeStatus::Valid.as_str() {
+            return Err(AcmeError::new(
+                AcmeErrorType::Unauthorized,
+                "The account is not valid anymore",
+            ));
+        }
+
+        Ok(account)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use ring::rand::SystemRandom;
+    use ring::signature::{EcdsaKeyPair, KeyPair};
+
+    #[test]
+    fn test_jwk_thumbprint() {
+        // example from RFC 7638 Section 3.1
+        let jwk = Jwk {
+            kty: "RSA".to_string(),
+            crv: None,
+            x: None,
+            y: None,
+            n: Some("0vx7agoebGcQSuuPiLJXZptN9nndrQmbXEps2aiAFbWhM78LhWx4cbbfAAtVT86zwu1RK7aPFFxuhDR1L6tSoc_BJECPebWKRXjBZCiFV4n3oknjhMstn64tZ_2W-5JsGY4Hc5n9yBXArwl93lqt7_RN5w6Cf0h4QyQ5v-65YGjQR0_FDW2QvzqY368QQMicAtaSqzs8KJZgnYb9c7d0zgdAZHzu6qMQvRL5hajrn1n91CbOpbISD08qNLyrdkt-bFTWhAI4vMQFh6WeZu0fM4lFd2NcRwr3XPksINHaQ-G_xBniIqbw0Ls1jF44-csFCur-kEgU8awapJzKnqDKgw".to_string()),
+            e: Some("AQAB".to_string()),
+        };
+        assert_eq!(
+            jwk.thumbprint().unwrap(),
+            "NzbLsXh8uDCcd-6MNwXF4W_7noWXFZAfHkxZsRGC9Xs"
+        );
+    }
+
+    #[test]
+    fn test_external_account_binding() {
+        let jwk = Jwk {
+            kty: "OKP".to_string(),
+            crv: Some("Ed25519".to_string()),
+            x: Some("11qYAYKxCrfVS_7TyWQHOg7hcvPapiMlrwIaaPcHURo".to_string()),
+            y: None,
+            n: None,
+            e: None,
+        };
+        let url = "https://ca.example.com/acme/group/new-account";
+        let hmac_key = b"super secret external account key";
+
+        let eab = |alg: &str, url: &str, jwk: &Jwk, key: &[u8]| {
+            let protected = b64_url_encode(
+                format!(r#"{{"alg":"{}","kid":"kid-1","url":"{}"}}"#, alg, url).as_bytes(),
+            );
+            let payload = b64_url_encode(&serde_json::to_vec(jwk).unwrap());
+            let msg = format!("{}.{}", protected, payload);
+            let sig = hmac::sign(&hmac::Key::new(hmac::HMAC_SHA256, key), msg.as_bytes());
+            serde_json::from_value::<ExternalAccountBinding>(serde_json::json!({
+                "protected": protected,
+                "payload": payload,
+                "signature": b64_url_encode(sig.as_ref()),
+            }))
+            .unwrap()
+        };
+
+        let binding = eab("HS256", url, &jwk, hmac_key);
+        assert_eq!(binding.kid().unwrap(), "kid-1");
+        assert!(binding.verify(hmac_key, url, &jwk).is_ok());
+
+        // wrong key
+        let err = binding.verify(b"another key", url, &jwk).unwrap_err();
+        assert_eq!(err.typ, AcmeErrorType::Unauthorized);
+
+        // made for another request
+        let binding = eab("HS256", "https://ca.example.com/other", &jwk, hmac_key);
+        assert!(binding.verify(hmac_key, url, &jwk).is_err());
+
+        // made for another account key
+        let mut other = jwk.clone();
+        other.x = Some("AAAAAKxCrfVS_7TyWQHOg7hcvPapiMlrwIaaPcHURo".to_string());
+        let binding = eab("HS256", url, &other, hmac_key);
+        let err = binding.verify(hmac_key, url, &jwk).unwrap_err();
+        assert_eq!(err.typ, AcmeErrorType::Unauthorized);
+
+        let binding = eab("none", url, &jwk, hmac_key);
+        let err = binding.verify(hmac_key, url, &jwk).unwrap_err();
+        assert_eq!(err.typ, AcmeErrorType::BadSignatureAlgorithm);
+    }
+
+    #[test]
+    fn test_jwk_verify_es256() {
+        let rng = SystemRandom::new();
+        let alg = &signature::ECDSA_P256_SHA256_FIXED_SIGNING;
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(alg, &rng).unwrap();
+        let key = EcdsaKeyPair::from_pkcs8(alg, pkcs8.as_ref(), &rng).unwrap();
+
+        let point = key.public_key().as_ref();
+        let jwk = Jwk {
+            kty: "EC".to_string(),
+            crv: Some("P-256".to_string()),
+            x: Some(b64_url_encode(&point[1..33])),
+            y: Some(b64_url_encode(&point[33..])),
+            n: None,
+            e: None,
+        };
+
+        let msg = b"protected.payload";
+        let sig = key.sign(&rng, msg).unwrap();
+        assert!(jwk.verify("ES256", msg, sig.as_ref()).is_ok());
+        assert!(jwk
+            .verify("ES256", b"protected.modified", sig.as_ref())
+            .is_err());
+
+        let err = jwk.verify("RS256", msg, sig.as_ref()).unwrap_err();
+        assert_eq!(err.typ, AcmeErrorType::BadSignatureAlgorithm);
+    }
+}
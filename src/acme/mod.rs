@@ -0,0 +1,50 @@
+use crate::constants::PUB_URL_FULL;
+use base64::{engine, engine::general_purpose, Engine as _};
+use uuid::Uuid;
+
+pub mod dns;
+pub mod error;
+pub mod jws;
+pub mod models;
+pub mod validation;
+
+const B64_ENGINE: engine::GeneralPurpose = general_purpose::URL_SAFE_NO_PAD;
+
+/// Base64 URL safe encoding without padding, like it is used everywhere in ACME
+#[inline]
+pub fn b64_url_encode(input: &[u8]) -> String {
+    B64_ENGINE.encode(input)
+}
+
+#[inline]
+pub fn b64_url_decode(input: &str) -> Result<Vec<u8>, base64::DecodeError> {
+    B64_ENGINE.decode(input)
+}
+
+/// Builds the full public URL for a resource inside the ACME directory of a group
+pub fn acme_url(group_id: &Uuid, path: &str) -> String {
+    format!("{}/acme/{}/{}", *PUB_URL_FULL, group_id, path)
+}
+
+/// The key authorization for a challenge token as defined in RFC 8555 Section 8.1
+#[inline]
+pub fn key_authorization(token: &str, thumbprint: &str) -> String {
+    format!("{}.{}", token, thumbprint)
+}
+
+/// Checks if the given value is a valid DNS name which can be used as an identifier.
+/// Wildcards are not allowed here and must be stripped beforehand.
+pub fn is_valid_dns_name(name: &str) -> bool {
+    if name.is_empty() || name.len() > 253 {
+        return false;
+    }
+    name.split('.').all(|label| {
+        !label.is_empty()
+            && label.len() <= 63
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+            && label
+                .bytes()
+                .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-')
+    })
+}
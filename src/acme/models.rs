@@ -0,0 +1,261 @@
+use crate::acme::acme_url;
+use crate::acme::jws::ExternalAccountBinding;
+use crate::models::db::acme_account::AcmeAccountEntity;
+use crate::models::db::acme_authz::{AcmeAuthzEntity, AcmeChallengeEntity};
+use crate::models::db::acme_order::AcmeOrderEntity;
+use serde::{Deserialize, Serialize};
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+/// Status values for ACME objects from RFC 8555 Section 7.1.6
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AcmeStatus {
+    Pending,
+    Ready,
+    Processing,
+    Valid,
+    Invalid,
+    Deactivated,
+    Expired,
+    Revoked,
+}
+
+impl AcmeStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Pending => "pending",
+            Self::Ready => "ready",
+            Self::Processing => "processing",
+            Self::Valid => "valid",
+            Self::Invalid => "invalid",
+            Self::Deactivated => "deactivated",
+            Self::Expired => "expired",
+            Self::Revoked => "revoked",
+        }
+    }
+}
+
+impl From<&str> for AcmeStatus {
+    fn from(value: &str) -> Self {
+        match value {
+            "ready" => Self::Ready,
+            "processing" => Self::Processing,
+            "valid" => Self::Valid,
+            "invalid" => Self::Invalid,
+            "deactivated" => Self::Deactivated,
+            "expired" => Self::Expired,
+            "revoked" => Self::Revoked,
+            _ => Self::Pending,
+        }
+    }
+}
+
+/// Challenge types the server can validate
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AcmeChallengeType {
+    Http01,
+    Dns01,
+}
+
+impl AcmeChallengeType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Http01 => "http-01",
+            Self::Dns01 => "dns-01",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AcmeIdentifier {
+    #[serde(rename = "type")]
+    pub typ: String,
+    pub value: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AcmeDirectory {
+    pub new_nonce: String,
+    pub new_account: String,
+    pub new_order: String,
+    pub meta: AcmeDirectoryMeta,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AcmeDirectoryMeta {
+    pub external_account_required: bool,
+}
+
+impl AcmeDirectory {
+    pub fn new(group_id: &Uuid) -> Self {
+        Self {
+            new_nonce: acme_url(group_id, "new-nonce"),
+            new_account: acme_url(group_id, "new-account"),
+            new_order: acme_url(group_id, "new-order"),
+            meta: AcmeDirectoryMeta {
+                external_account_required: false,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AcmeAccountPayload {
+    pub contact: Option<Vec<String>>,
+    pub status: Option<String>,
+    #[serde(default)]
+    pub only_return_existing: bool,
+    pub external_account_binding: Option<ExternalAccountBinding>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AcmeAccountResponse {
+    pub status: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub contact: Vec<String>,
+    pub orders: String,
+}
+
+impl AcmeAccountResponse {
+    pub fn new(group_id: &Uuid, account: &AcmeAccountEntity) -> Self {
+        let contact = account
+            .contact
+            .as_deref()
+            .map(|c| c.split(',').map(String::from).collect())
+            .unwrap_or_default();
+        Self {
+            status: account.status.clone(),
+            contact,
+            orders: acme_url(group_id, &format!("account/{}/orders", account.id)),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct AcmeOrdersListResponse {
+    pub orders: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AcmeNewOrderPayload {
+    pub identifiers: Vec<AcmeIdentifier>,
+    pub not_before: Option<String>,
+    pub not_after: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AcmeFinalizePayload {
+    /// base64url encoded DER CSR
+    pub csr: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AcmeOrderResponse {
+    pub status: String,
+    pub expires: String,
+    pub identifiers: Vec<AcmeIdentifier>,
+    pub authorizations: Vec<String>,
+    pub finalize: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub certificate: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<serde_json::Value>,
+}
+
+impl AcmeOrderResponse {
+    pub fn new(group_id: &Uuid, order: &AcmeOrderEntity, authz_ids: &[Uuid]) -> Self {
+        let certificate = if order.cert_serial.is_some() {
+            Some(acme_url(group_id, &format!("cert/{}", order.id)))
+        } else {
+            None
+        };
+        Self {
+            status: order.status.clone(),
+            expires: rfc3339(order.expires),
+            identifiers: serde_json::from_str(&order.identifiers).unwrap_or_default(),
+            authorizations: authz_ids
+                .iter()
+                .map(|id| acme_url(group_id, &format!("authz/{}", id)))
+                .collect(),
+            finalize: acme_url(group_id, &format!("order/{}/finalize", order.id)),
+            certificate,
+            error: order
+                .error
+                .as_ref()
+                .and_then(|e| serde_json::from_str(e).ok()),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AcmeAuthzResponse {
+    pub identifier: AcmeIdentifier,
+    pub status: String,
+    pub expires: String,
+    pub challenges: Vec<AcmeChallengeResponse>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub wildcard: bool,
+}
+
+impl AcmeAuthzResponse {
+    pub fn new(
+        group_id: &Uuid,
+        authz: &AcmeAuthzEntity,
+        challenges: &[AcmeChallengeEntity],
+    ) -> Self {
+        Self {
+            identifier: AcmeIdentifier {
+                typ: authz.identifier_typ.clone(),
+                value: authz.identifier_value.clone(),
+            },
+            status: authz.status.clone(),
+            expires: rfc3339(authz.expires),
+            challenges: challenges
+                .iter()
+                .map(|c| AcmeChallengeResponse::new(group_id, c))
+                .collect(),
+            wildcard: authz.wildcard,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct AcmeChallengeResponse {
+    #[serde(rename = "type")]
+    pub typ: String,
+    pub url: String,
+    pub status: String,
+    pub token: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub validated: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<serde_json::Value>,
+}
+
+impl AcmeChallengeResponse {
+    pub fn new(group_id: &Uuid, challenge: &AcmeChallengeEntity) -> Self {
+        Self {
+            typ: challenge.typ.clone(),
+            url: acme_url(group_id, &format!("chall/{}", challenge.id)),
+            status: challenge.status.clone(),
+            token: challenge.token.clone(),
+            validated: challenge.validated.map(rfc3339),
+            error: challenge
+                .error
+                .as_ref()
+                .and_then(|e| serde_json::from_str(e).ok()),
+        }
+    }
+}
+
+fn rfc3339(dt: OffsetDateTime) -> String {
+    dt.format(&Rfc3339).unwrap_or_default()
+}
@@ -0,0 +1,309 @@
+use crate::acme::dns::{validate_dns01, DnsUpdateTarget, DnsUpdater, DNS_UPDATER};
+use crate::acme::error::{AcmeError, AcmeErrorType};
+use crate::acme::key_authorization;
+use crate::acme::models::{AcmeChallengeType, AcmeStatus};
+use crate::constants::ACME_HTTP01_PORT;
+use crate::models::api::error_response::ErrorResponse;
+use crate::models::db::acme_account::AcmeAccountEntity;
+use crate::models::db::acme_authz::{AcmeAuthzEntity, AcmeChallengeEntity};
+use crate::models::db::acme_order::AcmeOrderEntity;
+use std::time::Duration;
+use time::OffsetDateTime;
+use tracing::{debug, error, info, warn};
+
+/// How often a challenge validation is tried before it fails
+const VALIDATION_ATTEMPTS: u8 = 3;
+
+/// Validates a challenge and updates the challenge, its authorization and the order afterwards.
+/// Meant to be spawned in the background, since the client will poll for the result.
+pub async fn process_challenge(
+    mut challenge: AcmeChallengeEntity,
+    mut authz: AcmeAuthzEntity,
+    account: AcmeAccountEntity,
+) {
+    let key_authz = key_authorization(&challenge.token, &account.thumbprint);
+
+    // publish the record by ourselves, if the zone has been delegated to us
+    let dns_target = match DNS_UPDATER.as_ref() {
+        Some(updater) if challenge.typ == AcmeChallengeType::Dns01.as_str() => {
+            auto_publish_target(updater, &account, &authz.identifier_value)
+                .await
+                .map(|target| (updater, target))
+        }
+        _ => None,
+    };
+    if let Some((updater, target)) = &dns_target {
+        if let Err(err) = updater.publish(target, &key_authz).await {
+            error!(
+                "Error publishing the DNS-01 record {}: {}",
+                target.record, err.detail
+            );
+        }
+    }
+
+    let mut res = Err(AcmeError::new(
+        AcmeErrorType::ServerInternal,
+        "Challenge was not validated",
+    ));
+    for attempt in 1..=VALIDATION_ATTEMPTS {
+        res = validate(&challenge, &authz, &key_authz).await;
+        if res.is_ok() {
+            break;
+        }
+        debug!(
+            "ACME challenge {} validation attempt {} failed",
+            challenge.id, attempt
+        );
+        if attempt < VALIDATION_ATTEMPTS {
+            tokio::time::sleep(Duration::from_secs(3)).await;
+        }
+    }
+
+    if let Some((updater, target)) = &dns_target {
+        if let Err(err) = updater.remove(target, &key_authz).await {
+            error!(
+                "Error removing the DNS-01 record {}: {}",
+                target.record, err.detail
+            );
+        }
+    }
+
+    if let Err(err) = update_status(&mut challenge, &mut authz, res).await {
+        error!(
+            "Error updating the status for ACME challenge {}: {:?}",
+            challenge.id, err
+        );
+    }
+}
+
+/// Returns the record Nioca publishes the DNS-01 challenge at by itself.
+///
+/// Since the challenge proves nothing in that case, this is only done for accounts bound with an
+/// external account key. Otherwise anyone could create an account and get certificates for every
+/// name below the delegated zones.
+async fn auto_publish_target(
+    updater: &DnsUpdater,
+    account: &AcmeAccountEntity,
+    domain: &str,
+) -> Option<DnsUpdateTarget> {
+    let target = updater.target_for(domain).await?;
+    if account.eab_kid.is_none() {
+        warn!(
+            "Not publishing the DNS-01 record {} for ACME account {} without an external account \
+            binding",
+            target.record, account.id
+        );
+        return None;
+    }
+    Some(target)
+}
+
+async fn validate(
+    challenge: &AcmeChallengeEntity,
+    authz: &AcmeAuthzEntity,
+    key_authz: &str,
+) -> Result<(), AcmeError> {
+    if challenge.typ == AcmeChallengeType::Http01.as_str() {
+        validate_http01(&authz.identifier_value, &challenge.token, key_authz).await
+    } else if challenge.typ == AcmeChallengeType::Dns01.as_str() {
+        validate_dns01(&authz.identifier_value, key_authz).await
+    } else {
+        Err(AcmeError::new(
+            AcmeErrorType::Malformed,
+            format!("Unsupported challenge type '{}'", challenge.typ),
+        ))
+    }
+}
+
+/// HTTP-01 validation from RFC 8555 Section 8.3
+///
+/// The details of a failed request are only logged. The error itself is the same for every
+/// failure, so the challenge cannot be abused to scan the internal network.
+async fn validate_http01(domain: &str, token: &str, key_authz: &str) -> Result<(), AcmeError> {
+    let port = *ACME_HTTP01_PORT;
+    let url = if port == 80 {
+        format!("http://{}/.well-known/acme-challenge/{}", domain, token)
+    } else {
+        format!(
+            "http://{}:{}/.well-known/acme-challenge/{}",
+            domain, port, token
+        )
+    };
+    debug!("Validating HTTP-01 challenge: {}", url);
+
+    let failed = || {
+        AcmeError::new(
+            AcmeErrorType::Connection,
+            format!("No valid HTTP-01 challenge response from {}", domain),
+        )
+    };
+
+    let redirect_domain = domain.to_string();
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .redirect(reqwest::redirect::Policy::custom(move |attempt| {
+            if redirect_allowed(&redirect_domain, attempt.url(), attempt.previous().len()) {
+                attempt.follow()
+            } else {
+                attempt.stop()
+            }
+        }))
+        .build()
+        .map_err(|err| AcmeError::new(AcmeErrorType::ServerInternal, err.to_string()))?;
+
+    let resp = client.get(&url).send().await.map_err(|err| {
+        debug!("Cannot fetch {}: {}", url, err);
+        failed()
+    })?;
+    if !resp.status().is_success() {
+        debug!("{} returned status {}", url, resp.status());
+        return Err(failed());
+    }
+
+    let body = resp.text().await.map_err(|err| {
+        debug!("Cannot read the response from {}: {}", url, err);
+        failed()
+    })?;
+    if body.trim() != key_authz {
+        debug!("The key authorization from {} does not match", url);
+        return Err(failed());
+    }
+
+    Ok(())
+}
+
+/// Redirects are only followed to the identifier itself on the default HTTP(S) ports or the
+/// configured challenge port, and never to any other host.
+fn redirect_allowed(domain: &str, url: &reqwest::Url, previous: usize) -> bool {
+    if previous >= 10 {
+        return false;
+    }
+    let port_ok = matches!(
+        url.port_or_known_default(),
+        Some(port) if port == 80 || port == 443 || port == *ACME_HTTP01_PORT
+    );
+    matches!(url.scheme(), "http" | "https")
+        && url
+            .host_str()
+            .is_some_and(|host| host.eq_ignore_ascii_case(domain))
+        && port_ok
+}
+
+async fn update_status(
+    challenge: &mut AcmeChallengeEntity,
+    authz: &mut AcmeAuthzEntity,
+    res: Result<(), AcmeError>,
+) -> Result<(), ErrorResponse> {
+    let mut order = AcmeOrderEntity::find(&authz.order_id).await?;
+
+    match res {
+        Ok(_) => {
+            info!(
+                "ACME challenge {} for '{}' is valid",
+                challenge.id, authz.identifier_value
+            );
+            challenge.status = AcmeStatus::Valid.as_str().to_string();
+            challenge.validated = Some(OffsetDateTime::now_utc());
+            challenge.update().await?;
+
+            authz.status = AcmeStatus::Valid.as_str().to_string();
+            authz.update_status().await?;
+
+            let all_valid = AcmeAuthzEntity::find_by_order(&order.id)
+                .await?
+                .iter()
+                .all(|a| a.status == AcmeStatus::Valid.as_str());
+            if all_valid && order.status == AcmeStatus::Pending.as_str() {
+                order.status = AcmeStatus::Ready.as_str().to_string();
+                order.update().await?;
+            }
+        }
+        Err(err) => {
+            info!(
+                "ACME challenge {} for '{}' is invalid: {}",
+                challenge.id, authz.identifier_value, err.detail
+            );
+            let problem = serde_json::to_string(&err.problem(None)).ok();
+
+            challenge.status = AcmeStatus::Invalid.as_str().to_string();
+            challenge.error = problem.clone();
+            challenge.update().await?;
+
+            authz.status = AcmeStatus::Invalid.as_str().to_string();
+            authz.update_status().await?;
+
+            order.status = AcmeStatus::Invalid.as_str().to_string();
+            order.error = problem;
+            order.update().await?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    #[test]
+    fn test_http01_redirects() {
+        let allowed = |url: &str, previous: usize| {
+            redirect_allowed(
+                "www.example.com",
+                &reqwest::Url::parse(url).unwrap(),
+                previous,
+            )
+        };
+
+        assert!(allowed(
+            "http://www.example.com/.well-known/acme-challenge/x",
+            0
+        ));
+        assert!(allowed("https://WWW.example.com/challenge", 1));
+        assert!(allowed("https://www.example.com:443/challenge", 1));
+
+        assert!(!allowed("https://www.example.com/challenge", 10));
+        assert!(!allowed("http://10.0.0.1/.well-known/acme-challenge/x", 0));
+        assert!(!allowed("http://localhost/", 0));
+        assert!(!allowed("http://www.example.com.evil.org/", 0));
+        assert!(!allowed("http://www.example.com:8200/v1/sys/health", 0));
+        assert!(!allowed("ftp://www.example.com/challenge", 0));
+    }
+
+    #[tokio::test]
+    async fn test_auto_publish_needs_binding() {
+        let updater = DnsUpdater::new(
+            "127.0.0.1:53".parse().unwrap(),
+            &["acme.example.com".to_string()],
+            "nioca",
+            "hmac-sha256",
+            b"tsig secret".to_vec(),
+            60,
+        );
+        let mut account = AcmeAccountEntity {
+            id: Uuid::new_v4(),
+            group_id: Uuid::new_v4(),
+            thumbprint: "thumbprint".to_string(),
+            jwk: "{}".to_string(),
+            contact: None,
+            status: AcmeStatus::Valid.as_str().to_string(),
+            created: OffsetDateTime::now_utc(),
+            eab_kid: None,
+        };
+
+        let domain = "db.acme.example.com";
+        assert!(auto_publish_target(&updater, &account, domain)
+            .await
+            .is_none());
+
+        account.eab_kid = Some("kid-1".to_string());
+        let target = auto_publish_target(&updater, &account, domain)
+            .await
+            .unwrap();
+        assert_eq!(
+            target.record.to_string(),
+            "_acme-challenge.db.acme.example.com."
+        );
+    }
+}
@@ -1,10 +1,14 @@
 use crate::models::api::error_response::{ErrorResponse, ErrorResponseType};
-use rcgen::{ExtendedKeyUsagePurpose, KeyUsagePurpose};
+use rcgen::{ExtendedKeyUsagePurpose, KeyUsagePurpose, RevocationReason};
 use serde::{Deserialize, Serialize};
 use ssh_key::{Algorithm, EcdsaCurve, HashAlg};
+use time::OffsetDateTime;
 use utoipa::ToSchema;
 
 pub mod encryption;
+pub mod key_storage;
+pub mod pkcs11;
+pub mod spiffe;
 pub mod ssh;
 pub mod x509;
 
@@ -107,6 +111,17 @@ impl SshKeyAlg {
             SshKeyAlg::Ed25519 => Algorithm::Ed25519,
         }
     }
+
+    /// Checks if a public key with the given algorithm can be used for this `SshKeyAlg`.
+    /// Public RSA keys do not contain a hash algorithm, which is why they match both RSA variants.
+    pub fn matches_key_alg(&self, alg: &Algorithm) -> bool {
+        match alg {
+            Algorithm::Rsa { .. } => {
+                matches!(self, SshKeyAlg::RsaSha256 | SshKeyAlg::RsaSha512)
+            }
+            alg => &self.as_alg() == alg,
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, clap::ValueEnum, ToSchema)]
@@ -262,3 +277,86 @@ pub async fn set_file_ro(path: &str) -> anyhow::Result<()> {
     }
     Ok(())
 }
+
+/// The reason for a revocation as defined in RFC 5280 Section 5.3.1.
+/// `certificateHold` and `removeFromCRL` are left out on purpose, since a revocation is final.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub enum X509RevocationReason {
+    Unspecified,
+    KeyCompromise,
+    CaCompromise,
+    AffiliationChanged,
+    Superseded,
+    CessationOfOperation,
+    PrivilegeWithdrawn,
+}
+
+impl X509RevocationReason {
+    pub fn from_value(value: i16) -> Self {
+        match value {
+            1 => Self::KeyCompromise,
+            2 => Self::CaCompromise,
+            3 => Self::AffiliationChanged,
+            4 => Self::Superseded,
+            5 => Self::CessationOfOperation,
+            9 => Self::PrivilegeWithdrawn,
+            _ => Self::Unspecified,
+        }
+    }
+
+    /// The `CRLReason` code
+    pub fn value(&self) -> i16 {
+        match self {
+            X509RevocationReason::Unspecified => 0,
+            X509RevocationReason::KeyCompromise => 1,
+            X509RevocationReason::CaCompromise => 2,
+            X509RevocationReason::AffiliationChanged => 3,
+            X509RevocationReason::Superseded => 4,
+            X509RevocationReason::CessationOfOperation => 5,
+            X509RevocationReason::PrivilegeWithdrawn => 9,
+        }
+    }
+
+    /// The reason code for a CRL entry. RFC 5280 wants it to be absent instead of `unspecified`.
+    pub fn crl_reason(&self) -> Option<RevocationReason> {
+        match self {
+            X509RevocationReason::Unspecified => None,
+            X509RevocationReason::KeyCompromise => Some(RevocationReason::KeyCompromise),
+            X509RevocationReason::CaCompromise => Some(RevocationReason::CaCompromise),
+            X509RevocationReason::AffiliationChanged => Some(RevocationReason::AffiliationChanged),
+            X509RevocationReason::Superseded => Some(RevocationReason::Superseded),
+            X509RevocationReason::CessationOfOperation => {
+                Some(RevocationReason::CessationOfOperation)
+            }
+            X509RevocationReason::PrivilegeWithdrawn => Some(RevocationReason::PrivilegeWithdrawn),
+        }
+    }
+}
+
+/// The current status of an issued certificate. A revoked certificate stays `Revoked` after expiry.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub enum CertStatus {
+    Active,
+    Expired,
+    Revoked,
+}
+
+impl CertStatus {
+    pub fn as_str(&self) -> &str {
+        match self {
+            CertStatus::Active => "active",
+            CertStatus::Expired => "expired",
+            CertStatus::Revoked => "revoked",
+        }
+    }
+
+    pub fn new(expires: OffsetDateTime, revoked: bool) -> Self {
+        if revoked {
+            Self::Revoked
+        } else if expires <= OffsetDateTime::now_utc() {
+            Self::Expired
+        } else {
+            Self::Active
+        }
+    }
+}
@@ -0,0 +1,453 @@
+use crate::constants::{PKCS11_MODULE, PKCS11_PIN, PKCS11_TOKEN_LABEL};
+use crate::models::api::error_response::{ErrorResponse, ErrorResponseType};
+use cryptoki::context::{CInitializeArgs, Pkcs11};
+use cryptoki::mechanism::Mechanism;
+use cryptoki::object::{Attribute, AttributeType, KeyType, ObjectClass, ObjectHandle};
+use cryptoki::session::{Session, SessionState, UserType};
+use cryptoki::types::AuthPin;
+use der::asn1::OctetString;
+use der::Decode;
+use rand_core::{OsRng, RngCore};
+use ring::signature;
+use std::sync::{Mutex, OnceLock, PoisonError};
+use tokio::runtime::{Handle, RuntimeFlavor};
+use tracing::{info, warn};
+
+/// The DER encoded curve OIDs for `CKA_EC_PARAMS`
+const EC_PARAMS_P256: &[u8] = &[0x06, 0x08, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07];
+const EC_PARAMS_P384: &[u8] = &[0x06, 0x05, 0x2b, 0x81, 0x04, 0x00, 0x22];
+const EC_PARAMS_ED25519: &[u8] = &[0x06, 0x03, 0x2b, 0x65, 0x70];
+
+static TOKEN: OnceLock<Mutex<Session>> = OnceLock::new();
+
+/// The algorithm a key inside the token is used with
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SignAlg {
+    EcdsaP256Sha256,
+    EcdsaP384Sha384,
+    Ed25519,
+    RsaSha256,
+    RsaSha512,
+}
+
+impl SignAlg {
+    fn mechanism(&self) -> Mechanism<'static> {
+        match self {
+            Self::EcdsaP256Sha256 => Mechanism::EcdsaSha256,
+            Self::EcdsaP384Sha384 => Mechanism::EcdsaSha384,
+            Self::Ed25519 => Mechanism::Eddsa,
+            Self::RsaSha256 => Mechanism::Sha256RsaPkcs,
+            Self::RsaSha512 => Mechanism::Sha512RsaPkcs,
+        }
+    }
+
+    fn ring_alg(&self) -> &'static dyn signature::VerificationAlgorithm {
+        match self {
+            Self::EcdsaP256Sha256 => &signature::ECDSA_P256_SHA256_FIXED,
+            Self::EcdsaP384Sha384 => &signature::ECDSA_P384_SHA384_FIXED,
+            Self::Ed25519 => &signature::ED25519,
+            Self::RsaSha256 => &signature::RSA_PKCS1_2048_8192_SHA256,
+            Self::RsaSha512 => &signature::RSA_PKCS1_2048_8192_SHA512,
+        }
+    }
+}
+
+/// Private key material to be imported into the token
+pub enum KeyMaterial {
+    /// An EC or Ed25519 private key with its DER encoded curve parameters
+    Ec {
+        key_type: KeyType,
+        params: &'static [u8],
+        value: Vec<u8>,
+    },
+    Rsa {
+        modulus: Vec<u8>,
+        public_exponent: Vec<u8>,
+        private_exponent: Vec<u8>,
+        prime_1: Vec<u8>,
+        prime_2: Vec<u8>,
+        exponent_1: Vec<u8>,
+        exponent_2: Vec<u8>,
+        coefficient: Vec<u8>,
+    },
+}
+
+impl KeyMaterial {
+    pub fn ec_p256(value: Vec<u8>) -> Self {
+        Self::Ec {
+            key_type: KeyType::EC,
+            params: EC_PARAMS_P256,
+            value,
+        }
+    }
+
+    pub fn ec_p384(value: Vec<u8>) -> Self {
+        Self::Ec {
+            key_type: KeyType::EC,
+            params: EC_PARAMS_P384,
+            value,
+        }
+    }
+
+    pub fn ed25519(value: Vec<u8>) -> Self {
+        Self::Ec {
+            key_type: KeyType::EC_EDWARDS,
+            params: EC_PARAMS_ED25519,
+            value,
+        }
+    }
+
+    fn attributes(self) -> Vec<Attribute> {
+        match self {
+            Self::Ec {
+                key_type,
+                params,
+                value,
+            } => vec![
+                Attribute::KeyType(key_type),
+                Attribute::EcParams(params.to_vec()),
+                Attribute::Value(value),
+            ],
+            Self::Rsa {
+                modulus,
+                public_exponent,
+                private_exponent,
+                prime_1,
+                prime_2,
+                exponent_1,
+                exponent_2,
+                coefficient,
+            } => vec![
+                Attribute::KeyType(KeyType::RSA),
+                Attribute::Modulus(modulus),
+                Attribute::PublicExponent(public_exponent),
+                Attribute::PrivateExponent(private_exponent),
+                Attribute::Prime1(prime_1),
+                Attribute::Prime2(prime_2),
+                Attribute::Exponent1(exponent_1),
+                Attribute::Exponent2(exponent_2),
+                Attribute::Coefficient(coefficient),
+            ],
+        }
+    }
+}
+
+/// The public key of a key pair generated inside the token
+pub enum GeneratedPublicKey {
+    /// The uncompressed EC point or the raw Ed25519 key
+    Ec(Vec<u8>),
+    Rsa {
+        modulus: Vec<u8>,
+        public_exponent: Vec<u8>,
+    },
+}
+
+/// Logs into the token from `PKCS11_TOKEN_LABEL`, if `PKCS11_MODULE` is configured
+pub fn init() -> Result<(), ErrorResponse> {
+    let module = match &*PKCS11_MODULE {
+        Some(module) => module,
+        None => return Ok(()),
+    };
+
+    let pkcs11 = Pkcs11::new(module)?;
+    pkcs11.initialize(CInitializeArgs::OsThreads)?;
+
+    let mut slot = None;
+    for s in pkcs11.get_slots_with_token()? {
+        if pkcs11.get_token_info(s)?.label().trim() == PKCS11_TOKEN_LABEL.as_str() {
+            slot = Some(s);
+            break;
+        }
+    }
+    let slot = slot.ok_or_else(|| {
+        ErrorResponse::new(
+            ErrorResponseType::Internal,
+            format!("No PKCS#11 token with label '{}'", *PKCS11_TOKEN_LABEL),
+        )
+    })?;
+
+    let session = pkcs11.open_rw_session(slot)?;
+    login(&session)?;
+    let _ = TOKEN.set(Mutex::new(session));
+
+    info!("Logged in to PKCS#11 token '{}'", *PKCS11_TOKEN_LABEL);
+    Ok(())
+}
+
+fn login(session: &Session) -> Result<(), ErrorResponse> {
+    session.login(UserType::User, Some(&AuthPin::new(PKCS11_PIN.clone())))?;
+    Ok(())
+}
+
+/// Runs `f` with the logged in session. This blocks the current thread for as long as the token
+/// needs, which is why it must never be called directly from an async task.
+fn session_blocking<F, T>(f: F) -> Result<T, ErrorResponse>
+where
+    F: FnOnce(&Session) -> Result<T, ErrorResponse>,
+{
+    let token = TOKEN.get().ok_or_else(|| {
+        ErrorResponse::new(
+            ErrorResponseType::BadRequest,
+            "No PKCS#11 token is configured",
+        )
+    })?;
+    // A panic while holding the lock does not leave the session itself in a broken state
+    let session = token.lock().unwrap_or_else(|err| {
+        warn!("The PKCS#11 session lock has been poisoned");
+        PoisonError::into_inner(err)
+    });
+
+    // Tokens drop the login after a reset or a timeout, while the session handle stays valid.
+    // Each operation would fail with CKR_USER_NOT_LOGGED_IN until we log in again.
+    match session.get_session_info()?.session_state() {
+        SessionState::RoPublic | SessionState::RwPublic => {
+            warn!("The PKCS#11 session has been logged out - logging in again");
+            login(&session)?;
+        }
+        SessionState::RoUser | SessionState::RwUser | SessionState::RwSecurityOfficer => {}
+    }
+
+    f(&session)
+}
+
+/// Runs `f` with the session on the blocking thread pool
+async fn with_session<F, T>(f: F) -> Result<T, ErrorResponse>
+where
+    F: FnOnce(&Session) -> Result<T, ErrorResponse> + Send + 'static,
+    T: Send + 'static,
+{
+    tokio::task::spawn_blocking(move || session_blocking(f)).await?
+}
+
+/// Runs `f` with the session from a sync context, like the rcgen and ssh-key signer traits.
+/// Inside the runtime, the worker thread is handed over to the blocking pool in the meantime.
+fn with_session_sync<F, T>(f: F) -> Result<T, ErrorResponse>
+where
+    F: FnOnce(&Session) -> Result<T, ErrorResponse>,
+{
+    match Handle::try_current() {
+        Ok(handle) if handle.runtime_flavor() == RuntimeFlavor::MultiThread => {
+            tokio::task::block_in_place(|| session_blocking(f))
+        }
+        _ => session_blocking(f),
+    }
+}
+
+fn find_private_key(session: &Session, key_id: &[u8]) -> Result<ObjectHandle, ErrorResponse> {
+    let handles = session.find_objects(&[
+        Attribute::Class(ObjectClass::PRIVATE_KEY),
+        Attribute::Id(key_id.to_vec()),
+    ])?;
+    match handles.as_slice() {
+        [handle] => Ok(*handle),
+        [] => Err(ErrorResponse::new(
+            ErrorResponseType::NotFound,
+            format!(
+                "No private key with id {} in the token",
+                hex::encode(key_id)
+            ),
+        )),
+        _ => Err(ErrorResponse::new(
+            ErrorResponseType::BadRequest,
+            format!(
+                "The private key id {} is not unique in the token",
+                hex::encode(key_id)
+            ),
+        )),
+    }
+}
+
+/// The attributes every CA key gets, which makes sure it can never leave the token
+fn private_key_template(key_id: &[u8], label: &str) -> Vec<Attribute> {
+    vec![
+        Attribute::Token(true),
+        Attribute::Private(true),
+        Attribute::Sensitive(true),
+        Attribute::Extractable(false),
+        Attribute::Sign(true),
+        Attribute::Id(key_id.to_vec()),
+        Attribute::Label(label.as_bytes().to_vec()),
+    ]
+}
+
+fn new_key_id() -> Vec<u8> {
+    let mut id = vec![0u8; 16];
+    OsRng.fill_bytes(&mut id);
+    id
+}
+
+/// Signs the message with the private key in the token.
+///
+/// The signature is returned in the raw PKCS#11 format, which is `r || s` for ECDSA.
+pub fn sign(key_id: &[u8], alg: SignAlg, msg: &[u8]) -> Result<Vec<u8>, ErrorResponse> {
+    with_session_sync(|session| sign_with(session, key_id, alg, msg))
+}
+
+fn sign_with(
+    session: &Session,
+    key_id: &[u8],
+    alg: SignAlg,
+    msg: &[u8],
+) -> Result<Vec<u8>, ErrorResponse> {
+    let handle = find_private_key(session, key_id)?;
+    Ok(session.sign(&alg.mechanism(), handle, msg)?)
+}
+
+/// Makes sure the key in the token actually belongs to the given public key by creating and
+/// verifying a test signature.
+///
+/// The public key must be the uncompressed EC point, the raw Ed25519 key or the PKCS#1 DER
+/// encoded RSA public key.
+pub async fn verify_key(
+    key_id: &[u8],
+    alg: SignAlg,
+    public_key: &[u8],
+) -> Result<(), ErrorResponse> {
+    let mut msg = vec![0u8; 32];
+    OsRng.fill_bytes(&mut msg);
+    let key_id = key_id.to_vec();
+    let sig_msg = msg.clone();
+    let sig = with_session(move |session| sign_with(session, &key_id, alg, &sig_msg)).await?;
+
+    signature::UnparsedPublicKey::new(alg.ring_alg(), public_key)
+        .verify(&msg, &sig)
+        .map_err(|_| {
+            ErrorResponse::new(
+                ErrorResponseType::BadRequest,
+                "The key in the token does not match the public key of the CA",
+            )
+        })
+}
+
+/// Imports a private key as sensitive and non-extractable and returns its new `CKA_ID`
+pub async fn import_key(label: &str, key: KeyMaterial) -> Result<Vec<u8>, ErrorResponse> {
+    let key_id = new_key_id();
+    let mut template = vec![Attribute::Class(ObjectClass::PRIVATE_KEY)];
+    template.append(&mut private_key_template(&key_id, label));
+    template.append(&mut key.attributes());
+
+    with_session(move |session| {
+        session.create_object(&template)?;
+        Ok(())
+    })
+    .await?;
+    info!(
+        "Imported private key {} into the token",
+        hex::encode(&key_id)
+    );
+    Ok(key_id)
+}
+
+/// Generates a new key pair inside the token and returns its `CKA_ID` with the public key.
+pub async fn generate_key(
+    label: &str,
+    alg: SignAlg,
+) -> Result<(Vec<u8>, GeneratedPublicKey), ErrorResponse> {
+    let key_id = new_key_id();
+    let mut pub_template = vec![
+        Attribute::Token(true),
+        Attribute::Verify(true),
+        Attribute::Id(key_id.clone()),
+        Attribute::Label(label.as_bytes().to_vec()),
+    ];
+    let expected_len = match alg {
+        SignAlg::EcdsaP256Sha256 => {
+            pub_template.push(Attribute::EcParams(EC_PARAMS_P256.to_vec()));
+            65
+        }
+        SignAlg::EcdsaP384Sha384 => {
+            pub_template.push(Attribute::EcParams(EC_PARAMS_P384.to_vec()));
+            97
+        }
+        SignAlg::Ed25519 => {
+            pub_template.push(Attribute::EcParams(EC_PARAMS_ED25519.to_vec()));
+            32
+        }
+        SignAlg::RsaSha256 | SignAlg::RsaSha512 => {
+            pub_template.push(Attribute::ModulusBits(4096.into()));
+            pub_template.push(Attribute::PublicExponent(vec![0x01, 0x00, 0x01]));
+            0
+        }
+    };
+    let priv_template = private_key_template(&key_id, label);
+
+    let public_key = with_session(move |session| {
+        // `Mechanism` is not `Send` and must be created on the blocking thread
+        let mechanism = match alg {
+            SignAlg::EcdsaP256Sha256 | SignAlg::EcdsaP384Sha384 => Mechanism::EccKeyPairGen,
+            SignAlg::Ed25519 => Mechanism::EccEdwardsKeyPairGen,
+            SignAlg::RsaSha256 | SignAlg::RsaSha512 => Mechanism::RsaPkcsKeyPairGen,
+        };
+        let (pub_handle, _) =
+            session.generate_key_pair(&mechanism, &pub_template, &priv_template)?;
+
+        let attrs = session.get_attributes(
+            pub_handle,
+            &[
+                AttributeType::EcPoint,
+                AttributeType::Modulus,
+                AttributeType::PublicExponent,
+            ],
+        )?;
+        let mut ec_point = None;
+        let mut modulus = None;
+        let mut public_exponent = None;
+        for attr in attrs {
+            match attr {
+                Attribute::EcPoint(point) => ec_point = Some(point),
+                Attribute::Modulus(m) => modulus = Some(m),
+                Attribute::PublicExponent(e) => public_exponent = Some(e),
+                _ => {}
+            }
+        }
+
+        match (ec_point, modulus, public_exponent) {
+            (Some(point), _, _) => Ok(GeneratedPublicKey::Ec(ec_point_raw(point, expected_len)?)),
+            (_, Some(modulus), Some(public_exponent)) => Ok(GeneratedPublicKey::Rsa {
+                modulus,
+                public_exponent,
+            }),
+            _ => Err(ErrorResponse::new(
+                ErrorResponseType::Internal,
+                "The token did not return the generated public key",
+            )),
+        }
+    })
+    .await?;
+
+    info!(
+        "Generated private key {} inside the token",
+        hex::encode(&key_id)
+    );
+    Ok((key_id, public_key))
+}
+
+/// `CKA_EC_POINT` is usually a DER encoded OCTET STRING, while some tokens return the raw value.
+fn ec_point_raw(point: Vec<u8>, expected_len: usize) -> Result<Vec<u8>, ErrorResponse> {
+    if point.len() == expected_len {
+        return Ok(point);
+    }
+    let octets = OctetString::from_der(&point)?;
+    Ok(octets.into_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ec_point_raw() {
+        let raw = vec![4u8; 65];
+        assert_eq!(ec_point_raw(raw.clone(), 65).unwrap(), raw);
+
+        let mut der = vec![0x04, 65];
+        der.extend_from_slice(&raw);
+        assert_eq!(ec_point_raw(der, 65).unwrap(), raw);
+
+        let ed = vec![7u8; 32];
+        let mut der = vec![0x04, 32];
+        der.extend_from_slice(&ed);
+        assert_eq!(ec_point_raw(der, 32).unwrap(), ed);
+        assert!(ec_point_raw(vec![1, 2, 3], 32).is_err());
+    }
+}
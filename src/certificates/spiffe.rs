@@ -0,0 +1,349 @@
+use crate::acme::b64_url_encode;
+use crate::acme::jws::Jwk;
+use crate::models::api::error_response::{ErrorResponse, ErrorResponseType};
+use rcgen::{CertificateParams, ExtendedKeyUsagePurpose, KeyUsagePurpose, SanType};
+use ring::rand::SystemRandom;
+use ring::signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_FIXED_SIGNING};
+use serde::Serialize;
+use std::fmt::{Display, Formatter};
+use x509_parser::oid_registry::{OID_EC_P256, OID_NIST_EC_P384, OID_SIG_ED25519};
+use x509_parser::public_key::PublicKey;
+use x509_parser::x509::SubjectPublicKeyInfo;
+
+/// A SPIFFE ID like `spiffe://example.org/ns/prod/sa/api`
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpiffeId {
+    pub trust_domain: String,
+    pub path: String,
+}
+
+impl Display for SpiffeId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "spiffe://{}{}", self.trust_domain, self.path)
+    }
+}
+
+impl SpiffeId {
+    /// Parses and validates a SPIFFE ID following the SPIFFE ID specification
+    pub fn parse(value: &str) -> Result<Self, ErrorResponse> {
+        let err = |msg: &str| {
+            ErrorResponse::new(
+                ErrorResponseType::BadRequest,
+                format!("Invalid SPIFFE ID '{}': {}", value, msg),
+            )
+        };
+
+        let rest = value
+            .strip_prefix("spiffe://")
+            .ok_or_else(|| err("must start with 'spiffe://'"))?;
+        let (trust_domain, path) = match rest.find('/') {
+            Some(idx) => rest.split_at(idx),
+            None => (rest, ""),
+        };
+
+        if trust_domain.is_empty()
+            || !trust_domain
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || "-._".contains(c))
+        {
+            return Err(err(
+                "the trust domain may only contain lowercase letters, digits, '-', '.' and '_'",
+            ));
+        }
+        if path.is_empty() {
+            return Err(err("a workload ID needs a path"));
+        }
+        for segment in path[1..].split('/') {
+            if segment.is_empty() || segment == "." || segment == ".." {
+                return Err(err("empty, '.' and '..' path segments are not allowed"));
+            }
+            if !segment
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "-._".contains(c))
+            {
+                return Err(err(
+                    "the path may only contain letters, digits, '-', '.' and '_'",
+                ));
+            }
+        }
+
+        Ok(Self {
+            trust_domain: trust_domain.to_string(),
+            path: path.to_string(),
+        })
+    }
+
+    /// An X.509-SVID must have exactly one URI SAN, which is the SPIFFE ID inside the
+    /// trust domain of the group.
+    pub fn from_uris(uris: &[String], trust_domain: &str) -> Result<Self, ErrorResponse> {
+        if uris.len() != 1 {
+            return Err(ErrorResponse::new(
+                ErrorResponseType::BadRequest,
+                "Clients inside a SPIFFE trust domain need exactly one URI alt name as SPIFFE ID",
+            ));
+        }
+
+        let id = Self::parse(&uris[0])?;
+        if id.trust_domain != trust_domain {
+            return Err(ErrorResponse::new(
+                ErrorResponseType::BadRequest,
+                format!(
+                    "SPIFFE ID '{}' is not inside the trust domain '{}'",
+                    id, trust_domain
+                ),
+            ));
+        }
+        Ok(id)
+    }
+}
+
+/// All groups share the same CAs, which is why a relying party cannot tell the groups apart.
+/// SPIFFE IDs may only be issued inside the group bound to their trust domain, and never in a
+/// group without a `trust_domain`.
+pub fn check_spiffe_ids<'a, I>(uris: I, trust_domain: Option<&str>) -> Result<(), ErrorResponse>
+where
+    I: IntoIterator<Item = &'a String>,
+{
+    for uri in uris {
+        let is_spiffe = uri
+            .get(..9)
+            .is_some_and(|scheme| scheme.eq_ignore_ascii_case("spiffe://"));
+        if !is_spiffe {
+            continue;
+        }
+
+        let is_allowed = match trust_domain {
+            Some(td) => SpiffeId::parse(uri)?.trust_domain == td,
+            None => false,
+        };
+        if !is_allowed {
+            return Err(ErrorResponse::new(
+                ErrorResponseType::BadRequest,
+                format!(
+                    "SPIFFE ID '{}' is only allowed inside the group bound to its trust domain",
+                    uri
+                ),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Turns the params into an X.509-SVID for the given trust domain.
+///
+/// The key usages are reduced to the ones a leaf SVID may have, and `digitalSignature` is
+/// always set. Without configured extended key usages, `serverAuth` and `clientAuth` are added.
+pub fn x509_svid(
+    params: &mut CertificateParams,
+    trust_domain: &str,
+) -> Result<SpiffeId, ErrorResponse> {
+    let uris = params
+        .subject_alt_names
+        .iter()
+        .filter_map(|san| match san {
+            SanType::URI(uri) => Some(uri.clone()),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    let id = SpiffeId::from_uris(&uris, trust_domain)?;
+
+    params.key_usages.retain(|usage| {
+        matches!(
+            usage,
+            KeyUsagePurpose::KeyEncipherment | KeyUsagePurpose::KeyAgreement
+        )
+    });
+    params
+        .key_usages
+        .insert(0, KeyUsagePurpose::DigitalSignature);
+    if params.extended_key_usages.is_empty() {
+        params.extended_key_usages = vec![
+            ExtendedKeyUsagePurpose::ServerAuth,
+            ExtendedKeyUsagePurpose::ClientAuth,
+        ];
+    }
+
+    Ok(id)
+}
+
+/// Generates a new P-256 key for signing JWT-SVIDs.
+/// Returns the PKCS#8 private key and the uncompressed public point.
+pub fn jwt_key_generate() -> Result<(Vec<u8>, Vec<u8>), ErrorResponse> {
+    let rng = SystemRandom::new();
+    let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng)
+        .map_err(|_| jwt_err("Cannot generate a JWT-SVID signing key"))?;
+    let key = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, pkcs8.as_ref(), &rng)
+        .map_err(|_| jwt_err("Cannot parse the JWT-SVID signing key"))?;
+    Ok((pkcs8.as_ref().to_vec(), key.public_key().as_ref().to_vec()))
+}
+
+/// The public JWK for an uncompressed P-256 point
+pub fn jwt_key_jwk(public_key: &[u8]) -> Jwk {
+    Jwk {
+        kty: "EC".to_string(),
+        crv: Some("P-256".to_string()),
+        x: Some(b64_url_encode(&public_key[1..33])),
+        y: Some(b64_url_encode(&public_key[33..])),
+        n: None,
+        e: None,
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct JwtSvidClaims<'a> {
+    sub: String,
+    aud: &'a [String],
+    exp: i64,
+    iat: i64,
+}
+
+/// Signs a JWT-SVID with ES256 for the given SPIFFE ID
+pub fn jwt_svid_sign(
+    pkcs8: &[u8],
+    kid: &str,
+    id: &SpiffeId,
+    audience: &[String],
+    iat: i64,
+    exp: i64,
+) -> Result<String, ErrorResponse> {
+    let header = serde_json::json!({
+        "alg": "ES256",
+        "kid": kid,
+        "typ": "JWT",
+    });
+    let claims = JwtSvidClaims {
+        sub: id.to_string(),
+        aud: audience,
+        exp,
+        iat,
+    };
+    let msg = format!(
+        "{}.{}",
+        b64_url_encode(serde_json::to_string(&header)?.as_bytes()),
+        b64_url_encode(serde_json::to_string(&claims)?.as_bytes()),
+    );
+
+    let rng = SystemRandom::new();
+    let key = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, pkcs8, &rng)
+        .map_err(|_| jwt_err("Cannot parse the JWT-SVID signing key"))?;
+    let sig = key
+        .sign(&rng, msg.as_bytes())
+        .map_err(|_| jwt_err("Cannot sign the JWT-SVID"))?;
+
+    Ok(format!("{}.{}", msg, b64_url_encode(sig.as_ref())))
+}
+
+/// The public JWK of an X509 CA certificate for the trust bundle
+pub fn x509_authority_jwk(spki: &SubjectPublicKeyInfo) -> Result<Jwk, ErrorResponse> {
+    if spki.algorithm.algorithm == OID_SIG_ED25519 {
+        return Ok(Jwk {
+            kty: "OKP".to_string(),
+            crv: Some("Ed25519".to_string()),
+            x: Some(b64_url_encode(&spki.subject_public_key.data)),
+            y: None,
+            n: None,
+            e: None,
+        });
+    }
+
+    match spki.parsed()? {
+        PublicKey::RSA(rsa) => Ok(Jwk {
+            kty: "RSA".to_string(),
+            crv: None,
+            x: None,
+            y: None,
+            n: Some(b64_url_encode(trim_leading_zeros(rsa.modulus))),
+            e: Some(b64_url_encode(trim_leading_zeros(rsa.exponent))),
+        }),
+        PublicKey::EC(point) => {
+            let curve = spki
+                .algorithm
+                .parameters
+                .as_ref()
+                .and_then(|p| p.as_oid().ok());
+            let (crv, len) = match curve {
+                Some(oid) if oid == OID_EC_P256 => ("P-256", 32),
+                Some(oid) if oid == OID_NIST_EC_P384 => ("P-384", 48),
+                _ => return Err(jwt_err("Unsupported EC curve for the SPIFFE bundle")),
+            };
+            let data = point.data();
+            if data.len() != 1 + 2 * len || data[0] != 0x04 {
+                return Err(jwt_err("Expected an uncompressed EC point"));
+            }
+            Ok(Jwk {
+                kty: "EC".to_string(),
+                crv: Some(crv.to_string()),
+                x: Some(b64_url_encode(&data[1..=len])),
+                y: Some(b64_url_encode(&data[1 + len..])),
+                n: None,
+                e: None,
+            })
+        }
+        _ => Err(jwt_err("Unsupported public key for the SPIFFE bundle")),
+    }
+}
+
+fn trim_leading_zeros(value: &[u8]) -> &[u8] {
+    let start = value.iter().position(|b| *b != 0).unwrap_or(value.len());
+    &value[start..]
+}
+
+fn jwt_err(msg: &str) -> ErrorResponse {
+    ErrorResponse::new(ErrorResponseType::Internal, msg.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::acme::b64_url_decode;
+
+    #[test]
+    fn test_spiffe_id_parse() {
+        let id = SpiffeId::parse("spiffe://example.org/ns/prod/sa/api").unwrap();
+        assert_eq!(id.trust_domain, "example.org");
+        assert_eq!(id.path, "/ns/prod/sa/api");
+        assert_eq!(id.to_string(), "spiffe://example.org/ns/prod/sa/api");
+
+        assert!(SpiffeId::parse("https://example.org/api").is_err());
+        assert!(SpiffeId::parse("spiffe://example.org").is_err());
+        assert!(SpiffeId::parse("spiffe://example.org/").is_err());
+        assert!(SpiffeId::parse("spiffe://Example.org/api").is_err());
+        assert!(SpiffeId::parse("spiffe://example.org:8080/api").is_err());
+        assert!(SpiffeId::parse("spiffe://example.org/a//b").is_err());
+        assert!(SpiffeId::parse("spiffe://example.org/a/../b").is_err());
+        assert!(SpiffeId::parse("spiffe://example.org/api?x=1").is_err());
+
+        let uris = vec!["spiffe://example.org/api".to_string()];
+        assert!(SpiffeId::from_uris(&uris, "example.org").is_ok());
+        assert!(SpiffeId::from_uris(&uris, "other.org").is_err());
+        assert!(SpiffeId::from_uris(&[], "example.org").is_err());
+
+        let https = vec!["https://example.org/api".to_string()];
+        assert!(check_spiffe_ids(&https, None).is_ok());
+        assert!(check_spiffe_ids(&uris, None).is_err());
+        assert!(check_spiffe_ids(&vec!["SPIFFE://example.org/api".to_string()], None).is_err());
+        assert!(check_spiffe_ids(&uris, Some("example.org")).is_ok());
+        assert!(check_spiffe_ids(&uris, Some("other.org")).is_err());
+    }
+
+    #[test]
+    fn test_jwt_svid_verify() {
+        let (pkcs8, public_key) = jwt_key_generate().unwrap();
+        let id = SpiffeId::parse("spiffe://example.org/api").unwrap();
+        let aud = vec!["backend".to_string()];
+        let token = jwt_svid_sign(&pkcs8, "kid1", &id, &aud, 1000, 1300).unwrap();
+
+        let (msg, sig) = token.rsplit_once('.').unwrap();
+        let sig = b64_url_decode(sig).unwrap();
+        assert!(jwt_key_jwk(&public_key)
+            .verify("ES256", msg.as_bytes(), &sig)
+            .is_ok());
+
+        let claims = msg.split_once('.').unwrap().1;
+        let claims: serde_json::Value =
+            serde_json::from_slice(&b64_url_decode(claims).unwrap()).unwrap();
+        assert_eq!(claims["sub"], "spiffe://example.org/api");
+        assert_eq!(claims["aud"][0], "backend");
+        assert_eq!(claims["exp"], 1300);
+    }
+}
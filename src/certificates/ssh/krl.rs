@@ -0,0 +1,160 @@
+use crate::models::api::error_response::ErrorResponse;
+use ssh_key::public::KeyData;
+use ssh_key::PublicKey;
+
+// https://github.com/openssh/openssh-portable/blob/master/PROTOCOL.krl
+const KRL_MAGIC: u64 = 0x5353484b524c0a00;
+const KRL_FORMAT_VERSION: u32 = 1;
+
+const KRL_SECTION_CERTIFICATES: u8 = 1;
+const KRL_SECTION_EXPLICIT_KEY: u8 = 2;
+
+const KRL_SECTION_CERT_SERIAL_LIST: u8 = 0x20;
+const KRL_SECTION_CERT_KEY_ID: u8 = 0x23;
+
+/// The content of an OpenSSH Key Revocation List for a single CA
+#[derive(Debug, Default)]
+pub struct Krl {
+    /// Should be increased each time the content changes
+    pub version: u64,
+    /// Generation time as a unix timestamp
+    pub generated: u64,
+    pub comment: String,
+    /// Serials of revoked certificates signed by `ca_key`
+    pub serials: Vec<u64>,
+    /// Key IDs of revoked certificates signed by `ca_key`
+    pub key_ids: Vec<String>,
+    /// Revoked plain public keys, which also revokes all certificates for them
+    pub public_keys: Vec<KeyData>,
+}
+
+impl Krl {
+    /// Builds the binary KRL, which can be used directly with `RevokedKeys` in `sshd_config`
+    /// or checked with `ssh-keygen -Q -f`
+    pub fn to_bytes(&self, ca_key: &KeyData) -> Result<Vec<u8>, ErrorResponse> {
+        let mut buf = Vec::with_capacity(128);
+        buf.extend_from_slice(&KRL_MAGIC.to_be_bytes());
+        buf.extend_from_slice(&KRL_FORMAT_VERSION.to_be_bytes());
+        buf.extend_from_slice(&self.version.to_be_bytes());
+        buf.extend_from_slice(&self.generated.to_be_bytes());
+        // flags
+        buf.extend_from_slice(&0u64.to_be_bytes());
+        // reserved
+        put_string(&mut buf, &[]);
+        put_string(&mut buf, self.comment.as_bytes());
+
+        if !self.serials.is_empty() || !self.key_ids.is_empty() {
+            let mut section = Vec::new();
+            put_string(&mut section, &key_blob(ca_key)?);
+            // reserved
+            put_string(&mut section, &[]);
+
+            if !self.serials.is_empty() {
+                let mut serials = self.serials.clone();
+                serials.sort_unstable();
+                serials.dedup();
+
+                let mut list = Vec::with_capacity(serials.len() * 8);
+                for serial in serials {
+                    list.extend_from_slice(&serial.to_be_bytes());
+                }
+                section.push(KRL_SECTION_CERT_SERIAL_LIST);
+                put_string(&mut section, &list);
+            }
+
+            if !self.key_ids.is_empty() {
+                let mut key_ids = self.key_ids.iter().collect::<Vec<_>>();
+                key_ids.sort_unstable();
+                key_ids.dedup();
+
+                let mut list = Vec::new();
+                for key_id in key_ids {
+                    put_string(&mut list, key_id.as_bytes());
+                }
+                section.push(KRL_SECTION_CERT_KEY_ID);
+                put_string(&mut section, &list);
+            }
+
+            buf.push(KRL_SECTION_CERTIFICATES);
+            put_string(&mut buf, &section);
+        }
+
+        if !self.public_keys.is_empty() {
+            let mut blobs = self
+                .public_keys
+                .iter()
+                .map(key_blob)
+                .collect::<Result<Vec<_>, _>>()?;
+            blobs.sort_unstable();
+            blobs.dedup();
+
+            let mut section = Vec::new();
+            for blob in blobs {
+                put_string(&mut section, &blob);
+            }
+            buf.push(KRL_SECTION_EXPLICIT_KEY);
+            put_string(&mut buf, &section);
+        }
+
+        Ok(buf)
+    }
+}
+
+fn key_blob(key: &KeyData) -> Result<Vec<u8>, ErrorResponse> {
+    let blob = PublicKey::from(key.clone()).to_bytes()?;
+    Ok(blob)
+}
+
+fn put_string(buf: &mut Vec<u8>, value: &[u8]) {
+    buf.extend_from_slice(&(value.len() as u32).to_be_bytes());
+    buf.extend_from_slice(value);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand_core::OsRng;
+    use ssh_key::{Algorithm, PrivateKey};
+
+    #[test]
+    fn test_krl_encoding() {
+        let ca = PrivateKey::random(&mut OsRng, Algorithm::Ed25519).unwrap();
+        let ca_key = ca.public_key().key_data();
+
+        let krl = Krl {
+            version: 3,
+            generated: 1700000000,
+            comment: "test".to_string(),
+            serials: vec![7, 2, 7],
+            key_ids: vec!["nioca-default".to_string()],
+            public_keys: vec![],
+        };
+        let bytes = krl.to_bytes(ca_key).unwrap();
+
+        assert_eq!(&bytes[..8], b"SSHKRL\n\0");
+        assert_eq!(&bytes[8..12], &1u32.to_be_bytes());
+        assert_eq!(&bytes[12..20], &3u64.to_be_bytes());
+
+        // header: magic, format version, krl version, generated, flags, reserved, comment
+        let mut pos = 8 + 4 + 8 + 8 + 8 + 4 + 4 + 4;
+        assert_eq!(bytes[pos], KRL_SECTION_CERTIFICATES);
+        pos += 1;
+        let section_len = u32::from_be_bytes(bytes[pos..pos + 4].try_into().unwrap()) as usize;
+        pos += 4;
+        assert_eq!(pos + section_len, bytes.len());
+
+        let ca_blob = key_blob(ca_key).unwrap();
+        let ca_len = u32::from_be_bytes(bytes[pos..pos + 4].try_into().unwrap()) as usize;
+        assert_eq!(&bytes[pos + 4..pos + 4 + ca_len], ca_blob.as_slice());
+        pos += 4 + ca_len + 4;
+
+        // the serials must be sorted and without duplicates
+        assert_eq!(bytes[pos], KRL_SECTION_CERT_SERIAL_LIST);
+        assert_eq!(&bytes[pos + 1..pos + 5], &16u32.to_be_bytes());
+        assert_eq!(&bytes[pos + 5..pos + 13], &2u64.to_be_bytes());
+        assert_eq!(&bytes[pos + 13..pos + 21], &7u64.to_be_bytes());
+        pos += 21;
+
+        assert_eq!(bytes[pos], KRL_SECTION_CERT_KEY_ID);
+    }
+}
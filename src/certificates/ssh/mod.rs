@@ -1,4 +1,35 @@
+use crate::models::api::error_response::ErrorResponse;
+use ssh_key::certificate::Builder;
+
 pub mod bootstrap;
 pub mod host;
+pub mod krl;
 pub mod root;
 pub mod user;
+
+/// Adds the `permit-*` extensions to a user certificate `Builder` for all values set to `true`
+pub fn add_permit_extensions(
+    cert_builder: &mut Builder,
+    permit_x11_forwarding: Option<bool>,
+    permit_agent_forwarding: Option<bool>,
+    permit_port_forwarding: Option<bool>,
+    permit_pty: Option<bool>,
+    permit_user_rc: Option<bool>,
+) -> Result<(), ErrorResponse> {
+    if permit_x11_forwarding == Some(true) {
+        cert_builder.extension("permit-X11-forwarding", "")?;
+    }
+    if permit_agent_forwarding == Some(true) {
+        cert_builder.extension("permit-agent-forwarding", "")?;
+    }
+    if permit_port_forwarding == Some(true) {
+        cert_builder.extension("permit-port-forwarding", "")?;
+    }
+    if permit_pty == Some(true) {
+        cert_builder.extension("permit-pty", "")?;
+    }
+    if permit_user_rc == Some(true) {
+        cert_builder.extension("permit-user-rc", "")?;
+    }
+    Ok(())
+}
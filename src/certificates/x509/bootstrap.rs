@@ -1,5 +1,7 @@
 use crate::certificates::x509::end_entity::end_entity_cert_cli;
-use crate::certificates::x509::intermediate::{build_intermediate_ca, intermediate_ca_from_folder};
+use crate::certificates::x509::intermediate::{
+    build_intermediate_ca, intermediate_ca_from_folder, sign_intermediate_csr,
+};
 use crate::certificates::x509::root::{build_root_ca, root_ca_from_folder};
 use crate::cli::{X509CliOptions, X509Stage};
 use tokio::fs;
@@ -7,6 +9,7 @@ use tokio::fs;
 pub const OUT_DIR_BASE: &str = "ca/x509";
 pub const OUT_DIR_ROOT: &str = "ca/x509/root";
 pub const OUT_DIR_INTERMEDIATE: &str = "ca/x509/intermediate";
+pub const OUT_DIR_INTERMEDIATE_SIGNED: &str = "ca/x509/intermediate_signed";
 pub const OUT_DIR_END_ENTITY: &str = "ca/x509/end_entity";
 pub const SERIAL_PATH: &str = "ca/x509/end_entity/serial";
 
@@ -34,6 +37,15 @@ pub async fn bootstrap_x509(mut opt: X509CliOptions) -> Result<(), anyhow::Error
         build_intermediate_ca(&opt, &signing_cert).await?;
     }
 
+    // the intermediate key has been generated inside Nioca and only the CSR is signed here
+    if opt.stage == X509Stage::SignIntermediate {
+        let csr_path = opt.csr.as_ref().ok_or_else(|| {
+            anyhow::Error::msg("'--csr' is needed for the 'sign-intermediate' stage")
+        })?;
+        let signing_cert = root_ca_from_folder(&opt).await?;
+        sign_intermediate_csr(&opt, &signing_cert, csr_path).await?;
+    }
+
     // This should only be needed for the very first bootstrap or disaster recovery
     if opt.stage == X509Stage::EndEntity || opt.stage == X509Stage::Full {
         // do read the ca private in from file again to make sure everything works out fine
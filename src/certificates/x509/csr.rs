@@ -0,0 +1,310 @@
+use crate::certificates::x509::san::{is_upn, upn_from_other_name};
+use crate::certificates::X509KeyAlg;
+use crate::models::api::error_response::{ErrorResponse, ErrorResponseType};
+use rcgen::{KeyPair, RcgenError, RemoteKeyPair, SanType, SignatureAlgorithm};
+use std::net::IpAddr;
+use tracing::error;
+use x509_parser::certification_request::X509CertificationRequest;
+use x509_parser::extensions::{GeneralName, ParsedExtension};
+use x509_parser::oid_registry::{
+    OID_EC_P256, OID_KEY_TYPE_EC_PUBLIC_KEY, OID_NIST_EC_P384, OID_PKCS1_RSAENCRYPTION,
+    OID_SIG_ED25519,
+};
+use x509_parser::prelude::FromDer;
+
+/// A parsed PKCS#10 certificate signing request with an already verified self-signature
+#[derive(Debug)]
+pub struct X509Csr {
+    pub common_name: Option<String>,
+    pub alt_names_dns: Vec<String>,
+    pub alt_names_ip: Vec<IpAddr>,
+    pub alt_names_uri: Vec<String>,
+    pub alt_names_email: Vec<String>,
+    pub alt_names_upn: Vec<String>,
+    pub key_alg: X509KeyAlg,
+    pub sig_alg: &'static SignatureAlgorithm,
+    pub public_key: Vec<u8>,
+}
+
+impl X509Csr {
+    /// Parses a PEM encoded CSR and verifies its self-signature.
+    pub fn from_pem(csr_pem: &str) -> Result<Self, ErrorResponse> {
+        let pem = match x509_parser::pem::parse_x509_pem(csr_pem.trim().as_bytes()) {
+            Ok((_, pem)) => pem,
+            Err(err) => {
+                return Err(ErrorResponse::new(
+                    ErrorResponseType::BadRequest,
+                    format!("Cannot parse the CSR PEM: {}", err),
+                ))
+            }
+        };
+        if pem.label != "CERTIFICATE REQUEST" && pem.label != "NEW CERTIFICATE REQUEST" {
+            return Err(ErrorResponse::new(
+                ErrorResponseType::BadRequest,
+                format!("Expected a 'CERTIFICATE REQUEST', got '{}'", pem.label),
+            ));
+        }
+        Self::from_der(&pem.contents)
+    }
+
+    /// Parses a DER encoded CSR and verifies its self-signature.
+    pub fn from_der(csr_der: &[u8]) -> Result<Self, ErrorResponse> {
+        let csr = match X509CertificationRequest::from_der(csr_der) {
+            Ok((_, csr)) => csr,
+            Err(err) => {
+                return Err(ErrorResponse::new(
+                    ErrorResponseType::BadRequest,
+                    format!("Cannot parse the CSR: {}", err),
+                ))
+            }
+        };
+
+        if let Err(err) = csr.verify_signature() {
+            error!("CSR signature verification failed: {}", err);
+            return Err(ErrorResponse::new(
+                ErrorResponseType::BadRequest,
+                "Invalid CSR signature".to_string(),
+            ));
+        }
+
+        let info = &csr.certification_request_info;
+
+        let mut common_name = None;
+        for cn in info.subject.iter_common_name() {
+            if common_name.is_some() {
+                return Err(ErrorResponse::new(
+                    ErrorResponseType::BadRequest,
+                    "Only a single CN is allowed in the CSR subject".to_string(),
+                ));
+            }
+            let cn = cn.as_str().map_err(|_| {
+                ErrorResponse::new(
+                    ErrorResponseType::BadRequest,
+                    "Cannot parse the CN from the CSR subject".to_string(),
+                )
+            })?;
+            common_name = Some(cn.to_string());
+        }
+
+        let mut alt_names_dns = Vec::new();
+        let mut alt_names_ip = Vec::new();
+        let mut alt_names_uri = Vec::new();
+        let mut alt_names_email = Vec::new();
+        let mut alt_names_upn = Vec::new();
+        if let Some(extensions) = csr.requested_extensions() {
+            for ext in extensions {
+                // all other requested extensions are ignored - the client config decides about them
+                if let ParsedExtension::SubjectAlternativeName(san) = ext {
+                    for name in &san.general_names {
+                        match name {
+                            GeneralName::DNSName(dns) => alt_names_dns.push(dns.to_string()),
+                            GeneralName::IPAddress(octets) => {
+                                alt_names_ip.push(ip_from_octets(octets)?)
+                            }
+                            GeneralName::URI(uri) => alt_names_uri.push(uri.to_string()),
+                            GeneralName::RFC822Name(email) => {
+                                alt_names_email.push(email.to_string())
+                            }
+                            GeneralName::OtherName(oid, value) if is_upn(oid) => {
+                                let upn = upn_from_other_name(value).ok_or_else(|| {
+                                    ErrorResponse::new(
+                                        ErrorResponseType::BadRequest,
+                                        "Cannot parse the UPN SAN from the CSR".to_string(),
+                                    )
+                                })?;
+                                alt_names_upn.push(upn);
+                            }
+                            _ => {
+                                return Err(ErrorResponse::new(
+                                    ErrorResponseType::BadRequest,
+                                    format!("Unsupported SAN type in CSR: {}", name),
+                                ))
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        let spki = &info.subject_pki;
+        let (key_alg, sig_alg) = if spki.algorithm.algorithm == OID_PKCS1_RSAENCRYPTION {
+            (X509KeyAlg::RSA, &rcgen::PKCS_RSA_SHA256)
+        } else if spki.algorithm.algorithm == OID_SIG_ED25519 {
+            (X509KeyAlg::EdDSA, &rcgen::PKCS_ED25519)
+        } else if spki.algorithm.algorithm == OID_KEY_TYPE_EC_PUBLIC_KEY {
+            let curve = spki
+                .algorithm
+                .parameters
+                .as_ref()
+                .and_then(|p| p.as_oid().ok());
+            match curve {
+                Some(oid) if oid == OID_EC_P256 => {
+                    (X509KeyAlg::ECDSA, &rcgen::PKCS_ECDSA_P256_SHA256)
+                }
+                Some(oid) if oid == OID_NIST_EC_P384 => {
+                    (X509KeyAlg::ECDSA, &rcgen::PKCS_ECDSA_P384_SHA384)
+                }
+                _ => {
+                    return Err(ErrorResponse::new(
+                        ErrorResponseType::BadRequest,
+                        "Unsupported EC curve in CSR".to_string(),
+                    ))
+                }
+            }
+        } else {
+            return Err(ErrorResponse::new(
+                ErrorResponseType::BadRequest,
+                "Unsupported public key algorithm in CSR".to_string(),
+            ));
+        };
+
+        Ok(Self {
+            common_name,
+            alt_names_dns,
+            alt_names_ip,
+            alt_names_uri,
+            alt_names_email,
+            alt_names_upn,
+            key_alg,
+            sig_alg,
+            public_key: spki.subject_public_key.data.to_vec(),
+        })
+    }
+
+    /// If the CSR requests any SANs at all
+    pub fn has_alt_names(&self) -> bool {
+        !self.alt_names_dns.is_empty()
+            || !self.alt_names_ip.is_empty()
+            || !self.alt_names_uri.is_empty()
+            || !self.alt_names_email.is_empty()
+            || !self.alt_names_upn.is_empty()
+    }
+
+    /// Returns the requested SANs in the format needed for the `rcgen::CertificateParams`.
+    /// UPNs are not included, since rcgen cannot write otherName SANs.
+    pub fn alt_names(&self) -> Vec<SanType> {
+        let mut names = Vec::with_capacity(self.alt_names_dns.len() + self.alt_names_ip.len());
+        for dns in &self.alt_names_dns {
+            names.push(SanType::DnsName(dns.clone()));
+        }
+        for ip in &self.alt_names_ip {
+            names.push(SanType::IpAddress(*ip));
+        }
+        for uri in &self.alt_names_uri {
+            names.push(SanType::URI(uri.clone()));
+        }
+        for email in &self.alt_names_email {
+            names.push(SanType::Rfc822Name(email.clone()));
+        }
+        names
+    }
+
+    /// Builds a public key only `KeyPair` from the CSR, which can be put into the
+    /// `rcgen::CertificateParams` for a certificate that will be signed by a CA.
+    pub fn key_pair(&self) -> Result<KeyPair, ErrorResponse> {
+        let key = CsrPublicKey {
+            public_key: self.public_key.clone(),
+            alg: self.sig_alg,
+        };
+        Ok(KeyPair::from_remote(Box::new(key))?)
+    }
+}
+
+/// Public key from a CSR. The private key never leaves the requesting client, which means this
+/// can only be used for certificates which are signed by another certificate.
+struct CsrPublicKey {
+    public_key: Vec<u8>,
+    alg: &'static SignatureAlgorithm,
+}
+
+impl RemoteKeyPair for CsrPublicKey {
+    fn public_key(&self) -> &[u8] {
+        &self.public_key
+    }
+
+    fn sign(&self, _msg: &[u8]) -> Result<Vec<u8>, RcgenError> {
+        Err(RcgenError::RemoteKeyError)
+    }
+
+    fn algorithm(&self) -> &'static SignatureAlgorithm {
+        self.alg
+    }
+}
+
+fn ip_from_octets(octets: &[u8]) -> Result<IpAddr, ErrorResponse> {
+    if let Ok(ipv4) = <[u8; 4]>::try_from(octets) {
+        Ok(IpAddr::from(ipv4))
+    } else if let Ok(ipv6) = <[u8; 16]>::try_from(octets) {
+        Ok(IpAddr::from(ipv6))
+    } else {
+        Err(ErrorResponse::new(
+            ErrorResponseType::BadRequest,
+            "Invalid IP address SAN in CSR".to_string(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::certificates::x509::singing::gen_ecdsa_key_pair;
+    use pretty_assertions::assert_eq;
+    use rcgen::{BasicConstraints, Certificate, CertificateParams, DnType, IsCa};
+
+    #[test]
+    fn test_csr_parse_verify() {
+        let mut params = CertificateParams::default();
+        params.alg = &rcgen::PKCS_ECDSA_P384_SHA384;
+        params.key_pair = Some(gen_ecdsa_key_pair().unwrap());
+        params
+            .distinguished_name
+            .push(DnType::CommonName, "nioca.local");
+        params.subject_alt_names = vec![
+            SanType::DnsName("nioca.local".to_string()),
+            SanType::IpAddress("127.0.0.1".parse().unwrap()),
+        ];
+        let cert = Certificate::from_params(params).unwrap();
+        let csr_pem = cert.serialize_request_pem().unwrap();
+
+        let csr = X509Csr::from_pem(&csr_pem).unwrap();
+        assert_eq!(csr.common_name.as_deref(), Some("nioca.local"));
+        assert_eq!(csr.alt_names_dns, vec!["nioca.local".to_string()]);
+        assert_eq!(
+            csr.alt_names_ip,
+            vec!["127.0.0.1".parse::<IpAddr>().unwrap()]
+        );
+        assert_eq!(csr.key_alg, X509KeyAlg::ECDSA);
+        assert_eq!(
+            csr.public_key.as_slice(),
+            cert.get_key_pair().public_key_raw()
+        );
+
+        // sign the public key from the CSR with a CA
+        let mut ca_params = CertificateParams::default();
+        ca_params.alg = &rcgen::PKCS_ECDSA_P384_SHA384;
+        ca_params.key_pair = Some(gen_ecdsa_key_pair().unwrap());
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca = Certificate::from_params(ca_params).unwrap();
+
+        let mut params = CertificateParams::default();
+        params.alg = csr.sig_alg;
+        params.key_pair = Some(csr.key_pair().unwrap());
+        params.subject_alt_names = csr.alt_names();
+        let signed = Certificate::from_params(params).unwrap();
+        let signed_der = signed.serialize_der_with_signer(&ca).unwrap();
+        let (_, x509) = x509_parser::parse_x509_certificate(&signed_der).unwrap();
+        assert_eq!(
+            x509.public_key().subject_public_key.data.as_ref(),
+            csr.public_key.as_slice()
+        );
+
+        // a modified CSR must fail the signature verification
+        let mut csr_der = cert.serialize_request_der().unwrap();
+        let idx = csr_der
+            .windows(b"nioca.local".len())
+            .position(|w| w == b"nioca.local")
+            .unwrap();
+        csr_der[idx] = b'm';
+        assert!(X509Csr::from_der(&csr_der).is_err());
+    }
+}
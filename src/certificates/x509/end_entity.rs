@@ -7,6 +7,7 @@ use crate::certificates::x509::singing::{
 use crate::certificates::{set_file_ro, X509KeyAlg};
 use crate::cli::X509CliOptions;
 use crate::constants::DEV_MODE;
+use crate::notifications::{send_in_background, Notification};
 use crate::util::{csv_to_vec, fingerprint};
 use base64::{engine::general_purpose, Engine as _};
 use rcgen::{
@@ -136,8 +137,12 @@ pub async fn nioca_server_cert(
     params.not_after = OffsetDateTime::now_utc().add(Duration::days(375));
     let max_not_after = ca_cert.get_params().not_after.sub(Duration::minutes(1));
     if params.not_after > max_not_after {
+        send_in_background(Notification::ValidityCapped {
+            name: "Nioca server certificate".to_string(),
+            requested: params.not_after.unix_timestamp(),
+            not_after: max_not_after.unix_timestamp(),
+        });
         params.not_after = max_not_after;
-        // TODO send out email notification in this case
         warn!("Cannot issue the certificate for the full duration because of a not long enough valid intermediate certificate");
     }
     params.serial_number = None;
@@ -1,5 +1,7 @@
 use crate::certificates::encryption::{decrypt, encrypt, kdf_danger_static, prompt_password};
-use crate::certificates::x509::bootstrap::{check_out_dir, OUT_DIR_INTERMEDIATE, OUT_DIR_ROOT};
+use crate::certificates::x509::bootstrap::{
+    check_out_dir, OUT_DIR_INTERMEDIATE, OUT_DIR_INTERMEDIATE_SIGNED, OUT_DIR_ROOT,
+};
 use crate::certificates::x509::cert_from_key_der;
 use crate::certificates::x509::singing::{
     gen_ecdsa_key_pair, gen_ed25519_key_pair, gen_rsa_key_pair,
@@ -8,8 +10,8 @@ use crate::certificates::X509KeyAlg;
 use crate::cli::X509CliOptions;
 use crate::util::{fingerprint, get_rand_between};
 use rcgen::{
-    BasicConstraints, Certificate, CertificateParams, CidrSubnet, DistinguishedName, DnType,
-    GeneralSubtree, IsCa, KeyIdMethod, KeyUsagePurpose, NameConstraints,
+    BasicConstraints, Certificate, CertificateParams, CertificateSigningRequest, CidrSubnet,
+    DistinguishedName, DnType, GeneralSubtree, IsCa, KeyIdMethod, KeyUsagePurpose, NameConstraints,
 };
 use std::ops::{Add, Sub};
 use time::Duration;
@@ -61,22 +63,7 @@ pub async fn build_intermediate_ca(
     };
     params.key_pair = Some(key_pair);
 
-    // let key_pair = gen_ed25519_key_pair()?;
-    // params.alg = &rcgen::PKCS_ED25519;
-
-    // set the valid from to some random earlier time to not potentially leak information about
-    // the creation date and therefore make guessing random number generation harder
-    let nbf_sub = Duration::minutes(get_rand_between(1, 525600) as i64);
-    params.not_before = OffsetDateTime::now_utc().sub(nbf_sub);
-    params.not_after = OffsetDateTime::now_utc().add(Duration::days(opt.valid_intermediate as i64));
-    let max_not_after = root_ca.get_params().not_after.sub(Duration::minutes(1));
-    if params.not_after > max_not_after {
-        params.not_after = max_not_after;
-        // TODO send out email notification in this case
-        warn!("Cannot issue the certificate for the full duration because of a not long enough valid root certificate");
-    }
-    params.serial_number = None;
-    params.subject_alt_names = vec![];
+    intermediate_ca_params(&mut params, opt, root_ca);
 
     let mut sub = DistinguishedName::new();
     let cn = format!("{} Intermediate", opt.common_name);
@@ -98,41 +85,6 @@ pub async fn build_intermediate_ca(
     }
     params.distinguished_name = sub;
 
-    // set the path length to 0 so only end entity certificates can be issued
-    params.is_ca = IsCa::Ca(BasicConstraints::Constrained(0));
-
-    params.key_usages = vec![
-        KeyUsagePurpose::CrlSign,
-        KeyUsagePurpose::KeyCertSign,
-        KeyUsagePurpose::DigitalSignature,
-    ];
-    params.extended_key_usages = vec![];
-
-    // name constraints
-    let mut permitted_subtrees = vec![];
-    for dns in &opt.name_constraint_dns {
-        permitted_subtrees.push(GeneralSubtree::DnsName(dns.clone()));
-    }
-    for subnet in &opt.name_constraint_ip {
-        match CidrSubnet::from_str(subnet) {
-            Ok(s) => permitted_subtrees.push(GeneralSubtree::IpAddress(s)),
-            Err(_) => {
-                panic!("Cannot parse {} to a CidrSubnet", subnet);
-            }
-        }
-    }
-    if !permitted_subtrees.is_empty() {
-        let nc = NameConstraints {
-            permitted_subtrees,
-            excluded_subtrees: vec![],
-        };
-        params.name_constraints = Some(nc);
-    }
-
-    params.custom_extensions = vec![];
-    params.use_authority_key_identifier_extension = true;
-    params.key_identifier_method = KeyIdMethod::Sha256;
-
     let cert = Certificate::from_params(params)?;
     let pem_serialized = cert.serialize_pem_with_signer(root_ca)?;
     let pem = ::pem::parse(&pem_serialized).unwrap();
@@ -195,6 +147,67 @@ pub async fn build_intermediate_ca(
     Ok(())
 }
 
+/// Signs the CSR of an intermediate CA, which has been generated inside Nioca, with the root CA.
+///
+/// Only the subject and the public key are taken from the CSR. Everything else is set the same
+/// way as for an intermediate built on the CLI.
+pub async fn sign_intermediate_csr(
+    opt: &X509CliOptions,
+    root_ca: &Certificate,
+    csr_path: &str,
+) -> Result<(), anyhow::Error> {
+    println!("Signing intermediate certificate from CSR");
+
+    let out_dir_root = format!("{}{}", opt.out_dir, OUT_DIR_ROOT);
+    let out_dir_signed = format!("{}{}", opt.out_dir, OUT_DIR_INTERMEDIATE_SIGNED);
+    check_out_dir(&out_dir_signed, opt, false).await?;
+
+    let csr_pem = match fs::read_to_string(csr_path).await {
+        Ok(pem) => pem,
+        Err(_) => {
+            return Err(anyhow::Error::msg(format!(
+                "Could not read the CSR from path '{}'",
+                csr_path
+            )));
+        }
+    };
+    let mut csr = CertificateSigningRequest::from_pem(&csr_pem)?;
+    intermediate_ca_params(&mut csr.params, opt, root_ca);
+
+    let pem_serialized = csr.serialize_pem_with_signer(root_ca)?;
+    let pem = ::pem::parse(&pem_serialized).unwrap();
+    let der_serialized = pem.contents();
+    let fingerprint_full = fingerprint(der_serialized);
+
+    fs::write(
+        format!("{}/intermediate.fingerprint", out_dir_signed),
+        fingerprint_full,
+    )
+    .await?;
+    fs::write(
+        format!("{}/intermediate.cert.pem", out_dir_signed),
+        &pem_serialized,
+    )
+    .await?;
+    fs::write(
+        format!("{}/intermediate.cert.der", out_dir_signed),
+        der_serialized,
+    )
+    .await?;
+
+    let root_pem = fs::read_to_string(format!("{}/root.cert.pem", out_dir_root)).await?;
+    let ca_chain = format!("{}{}", pem_serialized, root_pem);
+    fs::write(format!("{}/ca-chain.pem", out_dir_signed), ca_chain).await?;
+
+    println!(
+        "Signing intermediate certificate successful\n\nUpload '{}/intermediate.cert.pem' \
+        together with '{}/root.cert.pem' to complete the CA inside Nioca\n",
+        out_dir_signed, out_dir_root
+    );
+
+    Ok(())
+}
+
 pub async fn intermediate_ca_from_folder(
     opt: &X509CliOptions,
 ) -> Result<Certificate, anyhow::Error> {
@@ -239,3 +252,104 @@ pub async fn intermediate_ca_from_folder(
     println!("Reading encrypted intermediate certificate from filesystem successful\n");
     Ok(cert)
 }
+
+/// Applies everything to the params of an intermediate CA, which is not part of its subject
+fn intermediate_ca_params(
+    params: &mut CertificateParams,
+    opt: &X509CliOptions,
+    root_ca: &Certificate,
+) {
+    // set the valid from to some random earlier time to not potentially leak information about
+    // the creation date and therefore make guessing random number generation harder
+    let nbf_sub = Duration::minutes(get_rand_between(1, 525600) as i64);
+    params.not_before = OffsetDateTime::now_utc().sub(nbf_sub);
+    params.not_after = OffsetDateTime::now_utc().add(Duration::days(opt.valid_intermediate as i64));
+    let max_not_after = root_ca.get_params().not_after.sub(Duration::minutes(1));
+    if params.not_after > max_not_after {
+        params.not_after = max_not_after;
+        // this only runs on the CLI, where the warning is shown directly
+        warn!("Cannot issue the certificate for the full duration because of a not long enough valid root certificate");
+    }
+    params.serial_number = None;
+    params.subject_alt_names = vec![];
+
+    // set the path length to 0 so only end entity certificates can be issued
+    params.is_ca = IsCa::Ca(BasicConstraints::Constrained(0));
+
+    params.key_usages = vec![
+        KeyUsagePurpose::CrlSign,
+        KeyUsagePurpose::KeyCertSign,
+        KeyUsagePurpose::DigitalSignature,
+    ];
+    params.extended_key_usages = vec![];
+
+    // name constraints
+    let mut permitted_subtrees = vec![];
+    for dns in &opt.name_constraint_dns {
+        permitted_subtrees.push(GeneralSubtree::DnsName(dns.clone()));
+    }
+    for subnet in &opt.name_constraint_ip {
+        match CidrSubnet::from_str(subnet) {
+            Ok(s) => permitted_subtrees.push(GeneralSubtree::IpAddress(s)),
+            Err(_) => {
+                panic!("Cannot parse {} to a CidrSubnet", subnet);
+            }
+        }
+    }
+    if !permitted_subtrees.is_empty() {
+        let nc = NameConstraints {
+            permitted_subtrees,
+            excluded_subtrees: vec![],
+        };
+        params.name_constraints = Some(nc);
+    }
+
+    params.custom_extensions = vec![];
+    params.use_authority_key_identifier_extension = true;
+    params.key_identifier_method = KeyIdMethod::Sha256;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::certificates::x509::verification::x509_der_from_bytes;
+    use clap::Parser;
+
+    #[test]
+    fn test_sign_intermediate_csr() {
+        let opt = X509CliOptions::parse_from(["x509", "--stage", "sign-intermediate"]);
+
+        let mut root_params = CertificateParams::new(vec![]);
+        root_params.alg = &rcgen::PKCS_ECDSA_P384_SHA384;
+        root_params.key_pair = Some(gen_ecdsa_key_pair().unwrap());
+        root_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let root_ca = Certificate::from_params(root_params).unwrap();
+
+        // the CSR like Nioca generates it
+        let mut params = CertificateParams::default();
+        params.alg = &rcgen::PKCS_ED25519;
+        params.key_pair = Some(gen_ed25519_key_pair().unwrap());
+        params
+            .distinguished_name
+            .push(DnType::CommonName, "Nioca Intermediate");
+        let it = Certificate::from_params(params).unwrap();
+        let csr_pem = it.serialize_request_pem().unwrap();
+
+        let mut csr = CertificateSigningRequest::from_pem(&csr_pem).unwrap();
+        intermediate_ca_params(&mut csr.params, &opt, &root_ca);
+        let it_der = csr.serialize_der_with_signer(&root_ca).unwrap();
+        let root_der = root_ca.serialize_der().unwrap();
+
+        let root_cert = x509_der_from_bytes(&root_der).unwrap();
+        let it_cert = x509_der_from_bytes(&it_der).unwrap();
+        assert!(it_cert.is_ca());
+        assert!(it_cert
+            .verify_signature(Some(root_cert.public_key()))
+            .is_ok());
+        assert_eq!(
+            it_cert.public_key().subject_public_key.data.as_ref(),
+            it.get_key_pair().public_key_raw()
+        );
+        assert!(it_cert.validity.not_after.to_datetime() <= root_ca.get_params().not_after);
+    }
+}
@@ -1,9 +1,13 @@
 use rcgen::{Certificate, CertificateParams};
 
 pub mod bootstrap;
+pub mod csr;
 pub mod end_entity;
 pub mod intermediate;
+pub mod ocsp;
+pub mod policy;
 pub mod root;
+pub mod san;
 pub mod singing;
 pub mod verification;
 
@@ -0,0 +1,574 @@
+use crate::models::api::error_response::{ErrorResponse, ErrorResponseType};
+use rcgen::{CustomExtension, KeyPair, RemoteKeyPair, RevocationReason, SignatureAlgorithm};
+use ring::digest;
+use ring::rand::SystemRandom;
+use ring::signature::{
+    EcdsaKeyPair, Ed25519KeyPair, RsaKeyPair, ECDSA_P256_SHA256_ASN1_SIGNING,
+    ECDSA_P384_SHA384_ASN1_SIGNING, RSA_PKCS1_SHA256,
+};
+use time::OffsetDateTime;
+use x509_parser::prelude::{FromDer, X509Certificate};
+use yasna::models::{GeneralizedTime, ObjectIdentifier};
+use yasna::{DERWriter, Tag};
+
+const OID_AIA: &[u64] = &[1, 3, 6, 1, 5, 5, 7, 1, 1];
+const OID_AD_OCSP: &[u64] = &[1, 3, 6, 1, 5, 5, 7, 48, 1];
+const OID_OCSP_BASIC: &[u64] = &[1, 3, 6, 1, 5, 5, 7, 48, 1, 1];
+const OID_OCSP_NONCE: &[u64] = &[1, 3, 6, 1, 5, 5, 7, 48, 1, 2];
+const OID_OCSP_NOCHECK: &[u64] = &[1, 3, 6, 1, 5, 5, 7, 48, 1, 5];
+
+const OID_SHA1: &[u64] = &[1, 3, 14, 3, 2, 26];
+const OID_SHA256: &[u64] = &[2, 16, 840, 1, 101, 3, 4, 2, 1];
+const OID_SHA384: &[u64] = &[2, 16, 840, 1, 101, 3, 4, 2, 2];
+const OID_SHA512: &[u64] = &[2, 16, 840, 1, 101, 3, 4, 2, 3];
+
+const OID_ECDSA_SHA256: &[u64] = &[1, 2, 840, 10045, 4, 3, 2];
+const OID_ECDSA_SHA384: &[u64] = &[1, 2, 840, 10045, 4, 3, 3];
+const OID_ED25519: &[u64] = &[1, 3, 101, 112];
+const OID_RSA_SHA256: &[u64] = &[1, 2, 840, 113549, 1, 1, 11];
+
+/// RFC 8954 limits the nonce to 32 bytes
+const MAX_NONCE_LEN: usize = 32;
+
+/// The `OCSPResponseStatus` for all non-successful responses
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OcspResponseStatus {
+    MalformedRequest = 1,
+    InternalError = 2,
+    Unauthorized = 6,
+}
+
+impl From<ErrorResponse> for OcspResponseStatus {
+    fn from(_: ErrorResponse) -> Self {
+        Self::InternalError
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum OcspCertStatus {
+    Good,
+    Revoked {
+        time: OffsetDateTime,
+        reason: Option<RevocationReason>,
+    },
+    Unknown,
+}
+
+#[derive(Debug, Clone)]
+pub struct OcspCertId {
+    /// The original DER, which will be sent back as it is in the response
+    raw: Vec<u8>,
+    hash_alg: ObjectIdentifier,
+    issuer_name_hash: Vec<u8>,
+    issuer_key_hash: Vec<u8>,
+    serial: Vec<u8>,
+}
+
+impl OcspCertId {
+    fn from_der(raw: Vec<u8>) -> yasna::ASN1Result<Self> {
+        let (hash_alg, issuer_name_hash, issuer_key_hash, serial) = yasna::parse_ber(&raw, |r| {
+            r.read_sequence(|r| {
+                let hash_alg = r.next().read_sequence(|r| {
+                    let oid = r.next().read_oid()?;
+                    // the parameters are either absent or NULL
+                    r.read_optional(|r| r.read_der())?;
+                    Ok(oid)
+                })?;
+                let issuer_name_hash = r.next().read_bytes()?;
+                let issuer_key_hash = r.next().read_bytes()?;
+                let (serial, _) = r.next().read_bigint_bytes()?;
+                Ok((hash_alg, issuer_name_hash, issuer_key_hash, serial))
+            })
+        })?;
+
+        Ok(Self {
+            raw,
+            hash_alg,
+            issuer_name_hash,
+            issuer_key_hash,
+            serial,
+        })
+    }
+
+    /// The serial as it is saved in the database, if it fits
+    pub fn serial(&self) -> Option<i32> {
+        let serial = match self.serial.iter().position(|b| *b != 0) {
+            Some(idx) => &self.serial[idx..],
+            None => return None,
+        };
+        if serial.len() > 4 {
+            return None;
+        }
+
+        let mut buf = [0u8; 4];
+        buf[4 - serial.len()..].copy_from_slice(serial);
+        let serial = i32::from_be_bytes(buf);
+        if serial > 0 {
+            Some(serial)
+        } else {
+            None
+        }
+    }
+
+    /// Checks if the issuer name and key hashes match the given DER encoded CA certificate
+    pub fn is_issued_by(&self, ca_der: &[u8]) -> bool {
+        let alg = match hash_alg(&self.hash_alg) {
+            Some(alg) => alg,
+            None => return false,
+        };
+        let ca = match X509Certificate::from_der(ca_der) {
+            Ok((_, ca)) => ca,
+            Err(_) => return false,
+        };
+
+        let name_hash = digest::digest(alg, ca.subject().as_raw());
+        let key_hash = digest::digest(alg, &ca.public_key().subject_public_key.data);
+        name_hash.as_ref() == self.issuer_name_hash && key_hash.as_ref() == self.issuer_key_hash
+    }
+}
+
+/// An RFC 6960 `OCSPRequest`. Request signatures are not checked and simply ignored.
+#[derive(Debug)]
+pub struct OcspRequest {
+    pub cert_ids: Vec<OcspCertId>,
+    /// The complete `extnValue` of the nonce extension, which must be echoed back
+    pub nonce: Option<Vec<u8>>,
+}
+
+impl OcspRequest {
+    pub fn from_der(der: &[u8]) -> Result<Self, OcspResponseStatus> {
+        let (raw_ids, extensions) = yasna::parse_ber(der, |r| {
+            r.read_sequence(|r| {
+                let tbs = r.next().read_sequence(|r| {
+                    // version
+                    r.read_optional(|r| r.read_tagged(Tag::context(0), |r| r.read_der()))?;
+                    // requestorName
+                    r.read_optional(|r| r.read_tagged(Tag::context(1), |r| r.read_der()))?;
+                    let raw_ids = r.next().collect_sequence_of(|r| {
+                        r.read_sequence(|r| {
+                            let cert_id = r.next().read_der()?;
+                            // singleRequestExtensions
+                            r.read_optional(|r| r.read_tagged(Tag::context(0), |r| r.read_der()))?;
+                            Ok(cert_id)
+                        })
+                    })?;
+                    let extensions = r.read_optional(|r| {
+                        r.read_tagged(Tag::context(2), |r| {
+                            r.collect_sequence_of(|r| {
+                                r.read_sequence(|r| {
+                                    let oid = r.next().read_oid()?;
+                                    r.read_optional(|r| r.read_bool())?;
+                                    let value = r.next().read_bytes()?;
+                                    Ok((oid, value))
+                                })
+                            })
+                        })
+                    })?;
+                    Ok((raw_ids, extensions.unwrap_or_default()))
+                })?;
+                // optionalSignature
+                r.read_optional(|r| r.read_tagged(Tag::context(0), |r| r.read_der()))?;
+                Ok(tbs)
+            })
+        })
+        .map_err(|_| OcspResponseStatus::MalformedRequest)?;
+
+        let cert_ids = raw_ids
+            .into_iter()
+            .map(OcspCertId::from_der)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| OcspResponseStatus::MalformedRequest)?;
+        if cert_ids.is_empty() {
+            return Err(OcspResponseStatus::MalformedRequest);
+        }
+
+        let nonce_oid = ObjectIdentifier::from_slice(OID_OCSP_NONCE);
+        let nonce = extensions
+            .into_iter()
+            .find(|(oid, _)| oid == &nonce_oid)
+            .map(|(_, value)| value);
+        if let Some(nonce) = &nonce {
+            // 2 more bytes for the inner OCTET STRING header
+            if nonce.len() > MAX_NONCE_LEN + 2 {
+                return Err(OcspResponseStatus::MalformedRequest);
+            }
+        }
+
+        Ok(Self { cert_ids, nonce })
+    }
+}
+
+/// The key an OCSP response is signed with, either the CA itself or a delegated responder
+pub struct OcspSigner {
+    alg: &'static SignatureAlgorithm,
+    key: SignerKey,
+    /// SHA-1 of the public key, used as the `ResponderID`
+    key_hash: Vec<u8>,
+    /// A delegated responder certificate, which will be included in the responses
+    cert_der: Option<Vec<u8>>,
+}
+
+enum SignerKey {
+    Pkcs8(Vec<u8>),
+    Remote(Box<dyn RemoteKeyPair + Send + Sync>),
+}
+
+impl OcspSigner {
+    pub fn from_key_pem(key_pem: &str, cert_der: Option<Vec<u8>>) -> Result<Self, ErrorResponse> {
+        let key_pair = KeyPair::from_pem(key_pem)?;
+        let key_hash = digest::digest(&digest::SHA1_FOR_LEGACY_USE_ONLY, key_pair.public_key_raw());
+
+        Ok(Self {
+            alg: key_pair.algorithm(),
+            key: SignerKey::Pkcs8(key_pair.serialize_der()),
+            key_hash: key_hash.as_ref().to_vec(),
+            cert_der,
+        })
+    }
+
+    /// A signer for a CA key, which is not available in memory, like inside the PKCS#11 token.
+    /// The signatures must already be in the X509 format.
+    pub fn from_remote(key: Box<dyn RemoteKeyPair + Send + Sync>) -> Self {
+        let key_hash = digest::digest(&digest::SHA1_FOR_LEGACY_USE_ONLY, key.public_key());
+        Self {
+            alg: key.algorithm(),
+            key_hash: key_hash.as_ref().to_vec(),
+            key: SignerKey::Remote(key),
+            cert_der: None,
+        }
+    }
+
+    pub fn sign(&self, msg: &[u8]) -> Result<Vec<u8>, ErrorResponse> {
+        let rng = SystemRandom::new();
+        let err = |err: String| {
+            ErrorResponse::new(
+                ErrorResponseType::Internal,
+                format!("Cannot sign the OCSP response: {}", err),
+            )
+        };
+
+        let key_pkcs8 = match &self.key {
+            SignerKey::Pkcs8(key) => key,
+            SignerKey::Remote(key) => return key.sign(msg).map_err(|e| err(e.to_string())),
+        };
+        let sig = if self.alg == &rcgen::PKCS_ECDSA_P256_SHA256
+            || self.alg == &rcgen::PKCS_ECDSA_P384_SHA384
+        {
+            let alg = if self.alg == &rcgen::PKCS_ECDSA_P256_SHA256 {
+                &ECDSA_P256_SHA256_ASN1_SIGNING
+            } else {
+                &ECDSA_P384_SHA384_ASN1_SIGNING
+            };
+            let key =
+                EcdsaKeyPair::from_pkcs8(alg, key_pkcs8, &rng).map_err(|e| err(e.to_string()))?;
+            key.sign(&rng, msg)
+                .map_err(|e| err(e.to_string()))?
+                .as_ref()
+                .to_vec()
+        } else if self.alg == &rcgen::PKCS_ED25519 {
+            let key = Ed25519KeyPair::from_pkcs8_maybe_unchecked(key_pkcs8)
+                .map_err(|e| err(e.to_string()))?;
+            key.sign(msg).as_ref().to_vec()
+        } else {
+            let key = RsaKeyPair::from_pkcs8(key_pkcs8).map_err(|e| err(e.to_string()))?;
+            let mut sig = vec![0; key.public().modulus_len()];
+            key.sign(&RSA_PKCS1_SHA256, &rng, msg, &mut sig)
+                .map_err(|e| err(e.to_string()))?;
+            sig
+        };
+
+        Ok(sig)
+    }
+
+    fn write_alg_ident(&self, writer: DERWriter) {
+        writer.write_sequence(|w| {
+            if self.alg == &rcgen::PKCS_ECDSA_P256_SHA256 {
+                w.next()
+                    .write_oid(&ObjectIdentifier::from_slice(OID_ECDSA_SHA256));
+            } else if self.alg == &rcgen::PKCS_ECDSA_P384_SHA384 {
+                w.next()
+                    .write_oid(&ObjectIdentifier::from_slice(OID_ECDSA_SHA384));
+            } else if self.alg == &rcgen::PKCS_ED25519 {
+                w.next()
+                    .write_oid(&ObjectIdentifier::from_slice(OID_ED25519));
+            } else {
+                w.next()
+                    .write_oid(&ObjectIdentifier::from_slice(OID_RSA_SHA256));
+                w.next().write_null();
+            }
+        })
+    }
+}
+
+/// Builds a signed, successful `OCSPResponse` of type `id-pkix-ocsp-basic`
+pub fn build_response(
+    signer: &OcspSigner,
+    nonce: Option<&[u8]>,
+    responses: &[(&OcspCertId, OcspCertStatus)],
+    this_update: OffsetDateTime,
+    next_update: OffsetDateTime,
+) -> Result<Vec<u8>, ErrorResponse> {
+    let this_update = generalized_time(this_update);
+    let next_update = generalized_time(next_update);
+
+    let response_data = yasna::construct_der(|w| {
+        w.write_sequence(|w| {
+            // responderID byKey
+            w.next()
+                .write_tagged(Tag::context(2), |w| w.write_bytes(&signer.key_hash));
+            w.next().write_generalized_time(&this_update);
+            w.next().write_sequence_of(|w| {
+                for (cert_id, status) in responses {
+                    w.next().write_sequence(|w| {
+                        w.next().write_der(&cert_id.raw);
+                        match status {
+                            OcspCertStatus::Good => w
+                                .next()
+                                .write_tagged_implicit(Tag::context(0), |w| w.write_null()),
+                            OcspCertStatus::Revoked { time, reason } => {
+                                w.next().write_tagged_implicit(Tag::context(1), |w| {
+                                    w.write_sequence(|w| {
+                                        w.next().write_generalized_time(&generalized_time(*time));
+                                        if let Some(reason) = reason {
+                                            w.next().write_tagged(Tag::context(0), |w| {
+                                                w.write_enum(*reason as i64)
+                                            });
+                                        }
+                                    })
+                                })
+                            }
+                            OcspCertStatus::Unknown => w
+                                .next()
+                                .write_tagged_implicit(Tag::context(2), |w| w.write_null()),
+                        }
+                        w.next().write_generalized_time(&this_update);
+                        w.next().write_tagged(Tag::context(0), |w| {
+                            w.write_generalized_time(&next_update)
+                        });
+                    });
+                }
+            });
+            if let Some(nonce) = nonce {
+                w.next().write_tagged(Tag::context(1), |w| {
+                    w.write_sequence(|w| {
+                        w.next().write_sequence(|w| {
+                            w.next()
+                                .write_oid(&ObjectIdentifier::from_slice(OID_OCSP_NONCE));
+                            w.next().write_bytes(nonce);
+                        })
+                    })
+                });
+            }
+        })
+    });
+
+    let signature = signer.sign(&response_data)?;
+    let basic = yasna::construct_der(|w| {
+        w.write_sequence(|w| {
+            w.next().write_der(&response_data);
+            signer.write_alg_ident(w.next());
+            w.next().write_bitvec_bytes(&signature, signature.len() * 8);
+            if let Some(cert) = &signer.cert_der {
+                w.next().write_tagged(Tag::context(0), |w| {
+                    w.write_sequence(|w| w.next().write_der(cert))
+                });
+            }
+        })
+    });
+
+    Ok(yasna::construct_der(|w| {
+        w.write_sequence(|w| {
+            // successful
+            w.next().write_enum(0);
+            w.next().write_tagged(Tag::context(0), |w| {
+                w.write_sequence(|w| {
+                    w.next()
+                        .write_oid(&ObjectIdentifier::from_slice(OID_OCSP_BASIC));
+                    w.next().write_bytes(&basic);
+                })
+            });
+        })
+    }))
+}
+
+/// Builds an unsigned error response, which only contains the status
+pub fn build_error_response(status: OcspResponseStatus) -> Vec<u8> {
+    yasna::construct_der(|w| w.write_sequence(|w| w.next().write_enum(status as i64)))
+}
+
+/// Authority Information Access extension pointing to the given OCSP responder
+pub fn aia_extension(url: &str) -> CustomExtension {
+    let content = yasna::construct_der(|w| {
+        w.write_sequence(|w| {
+            w.next().write_sequence(|w| {
+                w.next()
+                    .write_oid(&ObjectIdentifier::from_slice(OID_AD_OCSP));
+                w.next()
+                    .write_tagged_implicit(Tag::context(6), |w| w.write_ia5_string(url));
+            })
+        })
+    });
+    CustomExtension::from_oid_content(OID_AIA, content)
+}
+
+/// `id-pkix-ocsp-nocheck` for delegated responder certificates
+pub fn nocheck_extension() -> CustomExtension {
+    let content = yasna::construct_der(|w| w.write_null());
+    CustomExtension::from_oid_content(OID_OCSP_NOCHECK, content)
+}
+
+fn hash_alg(oid: &ObjectIdentifier) -> Option<&'static digest::Algorithm> {
+    let components = oid.components().as_slice();
+    if components == OID_SHA1 {
+        Some(&digest::SHA1_FOR_LEGACY_USE_ONLY)
+    } else if components == OID_SHA256 {
+        Some(&digest::SHA256)
+    } else if components == OID_SHA384 {
+        Some(&digest::SHA384)
+    } else if components == OID_SHA512 {
+        Some(&digest::SHA512)
+    } else {
+        None
+    }
+}
+
+/// DER does not allow fractional seconds
+fn generalized_time(dt: OffsetDateTime) -> GeneralizedTime {
+    GeneralizedTime::from_datetime(dt.replace_nanosecond(0).unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rcgen::{BasicConstraints, Certificate, CertificateParams, IsCa};
+    use ring::signature::{UnparsedPublicKey, ECDSA_P256_SHA256_ASN1};
+
+    fn request_der(ca: &Certificate, serial: u64, nonce: &[u8]) -> Vec<u8> {
+        let ca_der = ca.serialize_der().unwrap();
+        let (_, ca_x509) = X509Certificate::from_der(&ca_der).unwrap();
+        let name_hash = digest::digest(
+            &digest::SHA1_FOR_LEGACY_USE_ONLY,
+            ca_x509.subject().as_raw(),
+        );
+        let key_hash = digest::digest(
+            &digest::SHA1_FOR_LEGACY_USE_ONLY,
+            &ca_x509.public_key().subject_public_key.data,
+        );
+
+        yasna::construct_der(|w| {
+            w.write_sequence(|w| {
+                w.next().write_sequence(|w| {
+                    w.next().write_sequence_of(|w| {
+                        w.next().write_sequence(|w| {
+                            w.next().write_sequence(|w| {
+                                w.next().write_sequence(|w| {
+                                    w.next().write_oid(&ObjectIdentifier::from_slice(OID_SHA1));
+                                    w.next().write_null();
+                                });
+                                w.next().write_bytes(name_hash.as_ref());
+                                w.next().write_bytes(key_hash.as_ref());
+                                w.next().write_u64(serial);
+                            })
+                        });
+                    });
+                    w.next().write_tagged(Tag::context(2), |w| {
+                        w.write_sequence(|w| {
+                            w.next().write_sequence(|w| {
+                                w.next()
+                                    .write_oid(&ObjectIdentifier::from_slice(OID_OCSP_NONCE));
+                                w.next().write_bytes(nonce);
+                            })
+                        })
+                    });
+                })
+            })
+        })
+    }
+
+    #[test]
+    fn test_ocsp_request_response() {
+        let mut params = CertificateParams::new(vec![]);
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        params.alg = &rcgen::PKCS_ECDSA_P256_SHA256;
+        let ca = Certificate::from_params(params).unwrap();
+        let ca_der = ca.serialize_der().unwrap();
+
+        let nonce = yasna::construct_der(|w| w.write_bytes(b"0123456789abcdef"));
+        let req = OcspRequest::from_der(&request_der(&ca, 1337, &nonce)).unwrap();
+        assert_eq!(req.cert_ids.len(), 1);
+        assert_eq!(req.cert_ids[0].serial(), Some(1337));
+        assert!(req.cert_ids[0].is_issued_by(&ca_der));
+        assert_eq!(req.nonce.as_deref(), Some(nonce.as_slice()));
+
+        let other = Certificate::from_params(CertificateParams::new(vec![])).unwrap();
+        assert!(!req.cert_ids[0].is_issued_by(&other.serialize_der().unwrap()));
+
+        assert_eq!(
+            OcspRequest::from_der(b"garbage").unwrap_err(),
+            OcspResponseStatus::MalformedRequest
+        );
+
+        let signer = OcspSigner::from_key_pem(&ca.serialize_private_key_pem(), None).unwrap();
+        let now = OffsetDateTime::now_utc();
+        let status = OcspCertStatus::Revoked {
+            time: now,
+            reason: Some(RevocationReason::KeyCompromise),
+        };
+        let resp = build_response(
+            &signer,
+            req.nonce.as_deref(),
+            &[(&req.cert_ids[0], status)],
+            now,
+            now + time::Duration::hours(1),
+        )
+        .unwrap();
+
+        // unwrap the BasicOCSPResponse and verify its signature with the CA public key
+        let (status, basic) = yasna::parse_der(&resp, |r| {
+            r.read_sequence(|r| {
+                let status = r.next().read_enum()?;
+                let basic = r.next().read_tagged(Tag::context(0), |r| {
+                    r.read_sequence(|r| {
+                        r.next().read_oid()?;
+                        r.next().read_bytes()
+                    })
+                })?;
+                Ok((status, basic))
+            })
+        })
+        .unwrap();
+        assert_eq!(status, 0);
+
+        let (tbs, sig) = yasna::parse_der(&basic, |r| {
+            r.read_sequence(|r| {
+                let tbs = r.next().read_der()?;
+                r.next().read_der()?;
+                let (sig, _) = r.next().read_bitvec_bytes()?;
+                Ok((tbs, sig))
+            })
+        })
+        .unwrap();
+        UnparsedPublicKey::new(&ECDSA_P256_SHA256_ASN1, ca.get_key_pair().public_key_raw())
+            .verify(&tbs, &sig)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_ocsp_serial() {
+        let cert_id = |serial: Vec<u8>| OcspCertId {
+            raw: vec![],
+            hash_alg: ObjectIdentifier::from_slice(OID_SHA1),
+            issuer_name_hash: vec![],
+            issuer_key_hash: vec![],
+            serial,
+        };
+        assert_eq!(cert_id(vec![0, 1]).serial(), Some(1));
+        assert_eq!(
+            cert_id(vec![0x7f, 0xff, 0xff, 0xff]).serial(),
+            Some(i32::MAX)
+        );
+        assert_eq!(cert_id(vec![0x80, 0, 0, 0]).serial(), None);
+        assert_eq!(cert_id(vec![1, 0, 0, 0, 0]).serial(), None);
+        assert_eq!(cert_id(vec![0]).serial(), None);
+    }
+}
@@ -0,0 +1,525 @@
+use crate::certificates::{X509KeyAlg, X509KeyUsages, X509KeyUsagesExt};
+use crate::models::api::error_response::{ErrorResponse, ErrorResponseType};
+use crate::models::api::request::ClientX509Request;
+use rcgen::{
+    CertificateParams, DnType, DnValue, ExtendedKeyUsagePurpose, KeyUsagePurpose, SanType,
+    SignatureAlgorithm,
+};
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+use std::str::FromStr;
+use utoipa::ToSchema;
+
+/// Restricts the X509 certificates, which can be issued inside a group.
+///
+/// It is checked when clients are created or updated and again for each issued certificate.
+/// Empty values do not restrict anything, apart from URI, email and UPN SANs, which are denied
+/// without a matching rule.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct X509Policy {
+    /// Host rules like `example.com` allow this name and all names below it like
+    /// `api.example.com`, but never a wildcard.
+    /// Wildcard rules like `*.example.com` only allow wildcard names like `*.example.com` or
+    /// `*.api.example.com`, but never a host name.
+    #[serde(default)]
+    pub allowed_dns: Vec<String>,
+    /// IP SANs must be inside one of these ranges, like `10.0.0.0/8` or `fd00::/8`
+    #[serde(default)]
+    pub allowed_cidrs: Vec<String>,
+    /// URI SANs must start with one of these prefixes, like `https://corp.example/`
+    #[serde(default)]
+    pub allowed_uri_prefixes: Vec<String>,
+    /// Email and UPN SANs must be inside one of these domains, like `corp.example` for
+    /// `admin@corp.example`
+    #[serde(default)]
+    pub allowed_email_domains: Vec<String>,
+    pub max_valid_hours: Option<i32>,
+    #[serde(default)]
+    pub key_algs: Vec<X509KeyAlg>,
+    #[serde(default)]
+    pub key_usages: Vec<X509KeyUsages>,
+    #[serde(default)]
+    pub key_usages_ext: Vec<X509KeyUsagesExt>,
+}
+
+impl X509Policy {
+    /// Makes sure all rules can be parsed before the policy is saved
+    pub fn validate(&self) -> Result<(), ErrorResponse> {
+        for rule in &self.allowed_dns {
+            if !is_valid_domain(rule.strip_prefix("*.").unwrap_or(rule)) {
+                return Err(policy_err(format!("Invalid DNS rule '{}'", rule)));
+            }
+        }
+
+        for prefix in &self.allowed_uri_prefixes {
+            // without the path separator, `https://corp.example` would allow
+            // `https://corp.example.evil` as well
+            let is_valid = prefix
+                .split_once("://")
+                .map(|(scheme, rest)| !scheme.is_empty() && rest.contains('/'))
+                .unwrap_or(false)
+                && prefix.ends_with('/');
+            if !is_valid {
+                return Err(policy_err(format!(
+                    "Invalid URI prefix '{}' - it must look like 'https://corp.example/'",
+                    prefix
+                )));
+            }
+        }
+
+        for domain in &self.allowed_email_domains {
+            if !is_valid_domain(domain) {
+                return Err(policy_err(format!("Invalid email domain '{}'", domain)));
+            }
+        }
+
+        for cidr in &self.allowed_cidrs {
+            if Cidr::parse(cidr).is_none() {
+                return Err(policy_err(format!("Invalid CIDR '{}'", cidr)));
+            }
+        }
+
+        if let Some(hours) = self.max_valid_hours {
+            if hours < 1 {
+                return Err(policy_err("'maxValidHours' must be at least 1"));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks the config of a client against this policy
+    pub fn check_client(&self, client: &ClientX509Request) -> Result<(), ErrorResponse> {
+        self.check_common_name(&client.common_name)?;
+        for name in &client.alt_names_dns {
+            self.check_dns(name)?;
+        }
+        for ip in &client.alt_names_ip {
+            let ip = IpAddr::from_str(ip)
+                .map_err(|_| policy_err(format!("Cannot parse IP alt name '{}'", ip)))?;
+            self.check_ip(&ip)?;
+        }
+        for uri in &client.alt_names_uri {
+            self.check_uri(uri)?;
+        }
+        for email in &client.alt_names_email {
+            self.check_email_domain(email, "Email")?;
+        }
+        for upn in &client.alt_names_upn {
+            self.check_email_domain(upn, "UPN")?;
+        }
+
+        self.check_key_alg(&client.key_alg)?;
+        for usage in &client.key_usage {
+            self.check_key_usage(&KeyUsagePurpose::from(usage.clone()))?;
+        }
+        for usage in &client.key_usage_ext {
+            self.check_key_usage_ext(&ExtendedKeyUsagePurpose::from(usage.clone()))?;
+        }
+        self.check_valid_hours(client.valid_hours as i64)
+    }
+
+    /// Checks the params of a certificate right before it is signed, together with the `upns`,
+    /// which will be added as SANs
+    pub fn check_params(
+        &self,
+        params: &CertificateParams,
+        upns: &[String],
+        valid_hours: i64,
+    ) -> Result<(), ErrorResponse> {
+        if let Some(cn) = common_name(params) {
+            self.check_common_name(&cn)?;
+        }
+        for san in &params.subject_alt_names {
+            match san {
+                SanType::DnsName(name) => self.check_dns(name)?,
+                SanType::IpAddress(ip) => self.check_ip(ip)?,
+                SanType::URI(uri) => self.check_uri(uri)?,
+                SanType::Rfc822Name(email) => self.check_email_domain(email, "Email")?,
+                san => {
+                    return Err(policy_err(format!(
+                        "SAN {:?} is not allowed by the group policy",
+                        san
+                    )))
+                }
+            }
+        }
+        for upn in upns {
+            self.check_email_domain(upn, "UPN")?;
+        }
+
+        match key_alg(params.alg) {
+            Some(alg) => self.check_key_alg(&alg)?,
+            None if !self.key_algs.is_empty() => {
+                return Err(policy_err(
+                    "The key algorithm is not allowed by the group policy",
+                ))
+            }
+            None => {}
+        }
+        for usage in &params.key_usages {
+            self.check_key_usage(usage)?;
+        }
+        for usage in &params.extended_key_usages {
+            self.check_key_usage_ext(usage)?;
+        }
+        self.check_valid_hours(valid_hours)
+    }
+
+    /// The CN is only checked, when it is an IP or a hostname. mTLS consumers like Postgres
+    /// `cert` auth trust single label ones like `db` as well. A free text like `Backup Service`
+    /// is never used for the hostname verification.
+    fn check_common_name(&self, cn: &str) -> Result<(), ErrorResponse> {
+        if let Ok(ip) = IpAddr::from_str(cn) {
+            self.check_ip(&ip)
+        } else if is_dns_name(cn) {
+            self.check_dns(cn)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn check_dns(&self, name: &str) -> Result<(), ErrorResponse> {
+        if self.allowed_dns.is_empty() {
+            return Ok(());
+        }
+
+        let name = name.to_lowercase();
+        let is_allowed = self.allowed_dns.iter().any(|rule| {
+            let rule = rule.to_lowercase();
+            match (rule.strip_prefix("*."), name.strip_prefix("*.")) {
+                (None, None) => is_same_or_below(&name, &rule),
+                (Some(rule_base), Some(base)) => is_same_or_below(base, rule_base),
+                // host rules never allow wildcards and wildcard rules never allow hosts
+                _ => false,
+            }
+        });
+
+        if is_allowed {
+            Ok(())
+        } else {
+            Err(policy_err(format!(
+                "DNS name '{}' is not allowed by the group policy",
+                name
+            )))
+        }
+    }
+
+    fn check_ip(&self, ip: &IpAddr) -> Result<(), ErrorResponse> {
+        if self.allowed_cidrs.is_empty()
+            || self
+                .allowed_cidrs
+                .iter()
+                .filter_map(|cidr| Cidr::parse(cidr))
+                .any(|cidr| cidr.contains(ip))
+        {
+            Ok(())
+        } else {
+            Err(policy_err(format!(
+                "IP '{}' is not allowed by the group policy",
+                ip
+            )))
+        }
+    }
+
+    fn check_uri(&self, uri: &str) -> Result<(), ErrorResponse> {
+        let uri_lower = uri.to_lowercase();
+        if self
+            .allowed_uri_prefixes
+            .iter()
+            .any(|prefix| uri_lower.starts_with(&prefix.to_lowercase()))
+        {
+            Ok(())
+        } else {
+            Err(policy_err(format!(
+                "URI '{}' is not allowed by the group policy",
+                uri
+            )))
+        }
+    }
+
+    /// `typ` is only used for the error message
+    fn check_email_domain(&self, name: &str, typ: &str) -> Result<(), ErrorResponse> {
+        let is_allowed = name.rsplit_once('@').is_some_and(|(_, domain)| {
+            self.allowed_email_domains
+                .iter()
+                .any(|allowed| allowed.eq_ignore_ascii_case(domain))
+        });
+        if is_allowed {
+            Ok(())
+        } else {
+            Err(policy_err(format!(
+                "{} '{}' is not allowed by the group policy",
+                typ, name
+            )))
+        }
+    }
+
+    fn check_key_alg(&self, alg: &X509KeyAlg) -> Result<(), ErrorResponse> {
+        if self.key_algs.is_empty() || self.key_algs.contains(alg) {
+            Ok(())
+        } else {
+            Err(policy_err(format!(
+                "Key algorithm '{}' is not allowed by the group policy",
+                alg.as_str()
+            )))
+        }
+    }
+
+    fn check_key_usage(&self, usage: &KeyUsagePurpose) -> Result<(), ErrorResponse> {
+        if self.key_usages.is_empty()
+            || self
+                .key_usages
+                .iter()
+                .any(|u| &KeyUsagePurpose::from(u.clone()) == usage)
+        {
+            Ok(())
+        } else {
+            Err(policy_err(format!(
+                "Key usage '{:?}' is not allowed by the group policy",
+                usage
+            )))
+        }
+    }
+
+    fn check_key_usage_ext(&self, usage: &ExtendedKeyUsagePurpose) -> Result<(), ErrorResponse> {
+        if self.key_usages_ext.is_empty()
+            || self
+                .key_usages_ext
+                .iter()
+                .any(|u| &ExtendedKeyUsagePurpose::from(u.clone()) == usage)
+        {
+            Ok(())
+        } else {
+            Err(policy_err(format!(
+                "Extended key usage '{:?}' is not allowed by the group policy",
+                usage
+            )))
+        }
+    }
+
+    fn check_valid_hours(&self, hours: i64) -> Result<(), ErrorResponse> {
+        match self.max_valid_hours {
+            Some(max) if hours > max as i64 => Err(policy_err(format!(
+                "A validity of {} hours exceeds the group policy maximum of {} hours",
+                hours, max
+            ))),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The CN of the certificate subject, if any
+pub fn common_name(params: &CertificateParams) -> Option<String> {
+    match params.distinguished_name.get(&DnType::CommonName)? {
+        DnValue::Utf8String(cn) | DnValue::PrintableString(cn) => Some(cn.clone()),
+        _ => None,
+    }
+}
+
+fn is_valid_domain(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('.')
+        && !name.ends_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.')
+}
+
+/// A hostname of one or more RFC 1123 labels, single ones like `db` or `localhost` included
+fn is_dns_name(name: &str) -> bool {
+    let name = name.strip_prefix("*.").unwrap_or(name);
+    name.split('.').all(|label| {
+        !label.is_empty()
+            && label.len() <= 63
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+            && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+    })
+}
+
+fn is_same_or_below(name: &str, base: &str) -> bool {
+    name == base || name.ends_with(&format!(".{}", base))
+}
+
+fn key_alg(alg: &SignatureAlgorithm) -> Option<X509KeyAlg> {
+    if alg == &rcgen::PKCS_RSA_SHA256
+        || alg == &rcgen::PKCS_RSA_SHA384
+        || alg == &rcgen::PKCS_RSA_SHA512
+    {
+        Some(X509KeyAlg::RSA)
+    } else if alg == &rcgen::PKCS_ECDSA_P256_SHA256 || alg == &rcgen::PKCS_ECDSA_P384_SHA384 {
+        Some(X509KeyAlg::ECDSA)
+    } else if alg == &rcgen::PKCS_ED25519 {
+        Some(X509KeyAlg::EdDSA)
+    } else {
+        None
+    }
+}
+
+fn policy_err<S: Into<String>>(msg: S) -> ErrorResponse {
+    ErrorResponse::new(ErrorResponseType::BadRequest, msg)
+}
+
+/// An IP range in CIDR notation
+struct Cidr {
+    addr: IpAddr,
+    prefix: u32,
+}
+
+impl Cidr {
+    fn parse(value: &str) -> Option<Self> {
+        let (addr, prefix) = value.trim().split_once('/')?;
+        let addr = IpAddr::from_str(addr).ok()?;
+        let prefix = prefix.parse::<u32>().ok()?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        (prefix <= max).then_some(Self { addr, prefix })
+    }
+
+    fn contains(&self, ip: &IpAddr) -> bool {
+        match (self.addr, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix).unwrap_or(0);
+                u32::from(net) & mask == u32::from(*ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix).unwrap_or(0);
+                u128::from(net) & mask == u128::from(*ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_x509_policy_names() {
+        let policy = X509Policy {
+            allowed_dns: vec!["dev.corp.example".to_string(), "*.apps.example".to_string()],
+            allowed_cidrs: vec!["10.10.0.0/16".to_string(), "fd00::/8".to_string()],
+            ..Default::default()
+        };
+        assert!(policy.validate().is_ok());
+
+        assert!(policy.check_dns("dev.corp.example").is_ok());
+        assert!(policy.check_dns("API.dev.corp.example").is_ok());
+        assert!(policy.check_dns("corp.example").is_err());
+        assert!(policy.check_dns("xdev.corp.example").is_err());
+        assert!(policy.check_dns("*.corp.example").is_err());
+        assert!(policy.check_dns("*.dev.corp.example").is_err());
+        assert!(policy.check_dns("*.apps.example").is_ok());
+        assert!(policy.check_dns("*.team.apps.example").is_ok());
+        // a wildcard rule does not allow any host names
+        assert!(policy.check_dns("apps.example").is_err());
+        assert!(policy.check_dns("web.apps.example").is_err());
+        assert!(policy.check_dns("*.example").is_err());
+
+        assert!(policy.check_ip(&"10.10.3.4".parse().unwrap()).is_ok());
+        assert!(policy.check_ip(&"10.11.0.1".parse().unwrap()).is_err());
+        assert!(policy.check_ip(&"fd12::1".parse().unwrap()).is_ok());
+        assert!(policy.check_ip(&"2001:db8::1".parse().unwrap()).is_err());
+
+        let invalid = X509Policy {
+            allowed_cidrs: vec!["10.0.0.0/33".to_string()],
+            ..Default::default()
+        };
+        assert!(invalid.validate().is_err());
+    }
+
+    #[test]
+    fn test_x509_policy_common_name() {
+        let policy = X509Policy {
+            allowed_dns: vec!["dev.corp.example".to_string()],
+            allowed_cidrs: vec!["10.10.0.0/16".to_string()],
+            ..Default::default()
+        };
+
+        // free text is not a name a client could verify
+        assert!(policy.check_common_name("Backup Service").is_ok());
+        assert!(policy
+            .check_common_name("Backup Service (dev.corp.example)")
+            .is_ok());
+        assert!(policy.check_common_name("-backup").is_ok());
+
+        // single labels are hostnames too
+        assert!(policy.check_common_name("backup").is_err());
+        assert!(policy.check_common_name("db").is_err());
+        assert!(policy.check_common_name("localhost").is_err());
+        assert!(policy.check_common_name("Postgres").is_err());
+        assert!(policy.check_common_name("dev").is_err());
+
+        assert!(policy.check_common_name("backup.dev.corp.example").is_ok());
+        assert!(policy
+            .check_common_name("backup.prod.corp.example")
+            .is_err());
+        assert!(policy.check_common_name("*.dev.corp.example").is_err());
+        assert!(policy.check_common_name("10.10.0.5").is_ok());
+        assert!(policy.check_common_name("10.20.0.5").is_err());
+
+        let mut params = CertificateParams::new(vec!["backup.dev.corp.example".to_string()]);
+        params
+            .distinguished_name
+            .push(DnType::CommonName, "Backup Service");
+        assert!(policy.check_params(&params, &[], 24).is_ok());
+        params
+            .distinguished_name
+            .push(DnType::CommonName, "backup.corp.example");
+        assert!(policy.check_params(&params, &[], 24).is_err());
+    }
+
+    #[test]
+    fn test_x509_policy_uri_email_upn() {
+        let mut params = CertificateParams::new(vec!["api.corp.example".to_string()]);
+        params
+            .subject_alt_names
+            .push(SanType::URI("https://corp.example/api".to_string()));
+        params
+            .subject_alt_names
+            .push(SanType::Rfc822Name("admin@corp.example".to_string()));
+        let upns = vec!["admin@ad.corp.example".to_string()];
+
+        // denied by default as soon as a policy exists
+        let policy = X509Policy::default();
+        assert!(policy.check_uri("https://corp.example/api").is_err());
+        assert!(policy
+            .check_email_domain("admin@corp.example", "Email")
+            .is_err());
+        assert!(policy.check_params(&params, &[], 24).is_err());
+
+        let policy = X509Policy {
+            allowed_uri_prefixes: vec!["https://corp.example/".to_string()],
+            allowed_email_domains: vec!["corp.example".to_string()],
+            ..Default::default()
+        };
+        assert!(policy.validate().is_ok());
+        assert!(policy.check_uri("HTTPS://corp.example/api").is_ok());
+        assert!(policy.check_uri("https://corp.example.evil/api").is_err());
+        assert!(policy.check_uri("spiffe://corp.example/api").is_err());
+        assert!(policy
+            .check_email_domain("admin@Corp.Example", "UPN")
+            .is_ok());
+        assert!(policy
+            .check_email_domain("admin@ad.corp.example", "UPN")
+            .is_err());
+        assert!(policy.check_email_domain("admin", "UPN").is_err());
+
+        assert!(policy.check_params(&params, &[], 24).is_ok());
+        // UPNs are only added to the params after the check
+        assert!(policy.check_params(&params, &upns, 24).is_err());
+
+        let invalid = X509Policy {
+            allowed_uri_prefixes: vec!["https://corp.example".to_string()],
+            ..Default::default()
+        };
+        assert!(invalid.validate().is_err());
+        let invalid = X509Policy {
+            allowed_email_domains: vec!["@corp.example".to_string()],
+            ..Default::default()
+        };
+        assert!(invalid.validate().is_err());
+    }
+}
@@ -0,0 +1,126 @@
+use rcgen::{CertificateParams, CustomExtension, SanType};
+use std::net::IpAddr;
+use tracing::warn;
+use x509_parser::der_parser::oid::Oid;
+use yasna::models::ObjectIdentifier;
+use yasna::Tag;
+
+const OID_SUBJECT_ALT_NAME: &[u64] = &[2, 5, 29, 17];
+/// The otherName type of a Microsoft User Principal Name
+const OID_UPN: &[u64] = &[1, 3, 6, 1, 4, 1, 311, 20, 2, 3];
+
+/// rcgen cannot write otherName SANs. If UPNs are requested, all SANs of the params are moved
+/// into a custom extension, since a certificate must not contain more than one SAN extension.
+pub fn add_upns(params: &mut CertificateParams, upns: &[String]) {
+    if upns.is_empty() {
+        return;
+    }
+
+    let content = yasna::construct_der(|w| {
+        w.write_sequence(|w| {
+            for san in &params.subject_alt_names {
+                let (tag, value) = match san {
+                    SanType::Rfc822Name(name) => (1, name.as_bytes().to_vec()),
+                    SanType::DnsName(name) => (2, name.as_bytes().to_vec()),
+                    SanType::URI(uri) => (6, uri.as_bytes().to_vec()),
+                    SanType::IpAddress(IpAddr::V4(ip)) => (7, ip.octets().to_vec()),
+                    SanType::IpAddress(IpAddr::V6(ip)) => (7, ip.octets().to_vec()),
+                    san => {
+                        warn!("Skipping unsupported SAN type {:?}", san);
+                        continue;
+                    }
+                };
+                w.next()
+                    .write_tagged_implicit(Tag::context(tag), |w| w.write_bytes(&value));
+            }
+            for upn in upns {
+                w.next().write_tagged_implicit(Tag::context(0), |w| {
+                    w.write_sequence(|w| {
+                        w.next().write_oid(&ObjectIdentifier::from_slice(OID_UPN));
+                        w.next()
+                            .write_tagged(Tag::context(0), |w| w.write_utf8_string(upn));
+                    })
+                });
+            }
+        })
+    });
+
+    params.subject_alt_names.clear();
+    params
+        .custom_extensions
+        .push(CustomExtension::from_oid_content(
+            OID_SUBJECT_ALT_NAME,
+            content,
+        ));
+}
+
+/// If the type id of an otherName SAN is a UPN
+pub fn is_upn(oid: &Oid) -> bool {
+    oid.iter()
+        .map(|arcs| arcs.eq(OID_UPN.iter().copied()))
+        .unwrap_or(false)
+}
+
+/// Decodes the value of a UPN otherName, which is everything after the type id
+pub fn upn_from_other_name(value: &[u8]) -> Option<String> {
+    yasna::parse_der(value, |r| {
+        r.read_tagged(Tag::context(0), |r| r.read_utf8string())
+    })
+    .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use x509_parser::extensions::GeneralName;
+    use x509_parser::prelude::{FromDer, X509Certificate};
+
+    #[test]
+    fn test_upn_san_extension() {
+        let mut params = CertificateParams::new(vec!["host.example.com".to_string()]);
+        params.subject_alt_names.push(SanType::URI(
+            "spiffe://example.com/ns/prod/sa/api".to_string(),
+        ));
+        params
+            .subject_alt_names
+            .push(SanType::Rfc822Name("jdoe@example.com".to_string()));
+        params
+            .subject_alt_names
+            .push(SanType::IpAddress("10.0.0.1".parse().unwrap()));
+        add_upns(&mut params, &["jdoe@corp.example.com".to_string()]);
+        assert!(params.subject_alt_names.is_empty());
+
+        let der = rcgen::Certificate::from_params(params)
+            .unwrap()
+            .serialize_der()
+            .unwrap();
+        let (_, cert) = X509Certificate::from_der(&der).unwrap();
+        let names = &cert
+            .subject_alternative_name()
+            .unwrap()
+            .unwrap()
+            .value
+            .general_names;
+        assert_eq!(names.len(), 5);
+        assert!(matches!(names[0], GeneralName::DNSName("host.example.com")));
+        assert!(matches!(
+            names[1],
+            GeneralName::URI("spiffe://example.com/ns/prod/sa/api")
+        ));
+        assert!(matches!(
+            names[2],
+            GeneralName::RFC822Name("jdoe@example.com")
+        ));
+        assert!(matches!(names[3], GeneralName::IPAddress(&[10, 0, 0, 1])));
+        match &names[4] {
+            GeneralName::OtherName(oid, value) => {
+                assert!(is_upn(oid));
+                assert_eq!(
+                    upn_from_other_name(value).as_deref(),
+                    Some("jdoe@corp.example.com")
+                );
+            }
+            name => panic!("expected an otherName, got {}", name),
+        }
+    }
+}
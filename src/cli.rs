@@ -11,6 +11,52 @@ pub enum Cli {
     Server,
     Ssh(Box<SshOptions>),
     X509(Box<X509CliOptions>),
+    DecryptShare(DecryptShareOptions),
+    Backup(BackupOptions),
+    Restore(RestoreOptions),
+}
+
+/// Decrypt a master key share, which has been encrypted to your custodian key during the
+/// initialization, before submitting it for unsealing.
+#[derive(Debug, Clone, PartialEq, Parser)]
+#[command(author, version)]
+pub struct DecryptShareOptions {
+    /// The age identity file or OpenSSH private key of the custodian
+    #[clap(short, long)]
+    pub identity: String,
+
+    /// The file with the encrypted share. It is read from stdin, if not given.
+    #[clap(short, long)]
+    pub file: Option<String>,
+}
+
+/// Export all data into an archive, which stays encrypted and is signed by the intermediate CA.
+/// The database config is taken from the environment, like for the server.
+#[derive(Debug, Clone, PartialEq, Parser)]
+#[command(author, version)]
+pub struct BackupOptions {
+    /// The file to write the archive to
+    #[clap(short, long)]
+    pub file: String,
+}
+
+/// Restore a backup archive after validating it against the master key shares.
+/// A full restore must only be done while no Nioca server is running.
+#[derive(Debug, Clone, PartialEq, Parser)]
+#[command(author, version)]
+pub struct RestoreOptions {
+    /// The backup archive
+    #[clap(short, long)]
+    pub file: String,
+
+    /// Only add the missing rows of this table. Can be given multiple times.
+    /// Everything is replaced, if not given.
+    #[clap(short, long)]
+    pub table: Vec<String>,
+
+    /// Only validate the archive and show its content without writing anything
+    #[clap(long, default_value = OsStr::from("false"))]
+    pub dry_run: bool,
 }
 
 /// Issue X509 Certificates or bootstrap a full CA with Root / Intermediate / EndEntity certificates.
@@ -88,6 +134,11 @@ pub struct X509CliOptions {
     #[clap(long = "constraint-ip")]
     pub name_constraint_ip: Vec<String>,
 
+    /// Path to the CSR of an intermediate CA generated inside Nioca for the 'sign-intermediate'
+    /// stage
+    #[clap(long = "csr")]
+    pub csr: Option<String>,
+
     /// The output directory
     #[clap(short = 'o', long = "out-dir", default_value = "./")]
     pub out_dir: String,
@@ -115,6 +166,8 @@ pub enum X509Stage {
     Full,
     Root,
     Intermediate,
+    /// Sign the CSR of an intermediate CA, which has been generated inside Nioca
+    SignIntermediate,
     EndEntity,
 }
 
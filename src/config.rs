@@ -1,5 +1,6 @@
-use crate::certificates::x509::cert_from_key_pem;
+use crate::certificates::key_storage::CaKeyStorage;
 use crate::constants::{PUB_URL_FULL, TOKEN_CACHE_LIFESPAN};
+use crate::metrics;
 use crate::models::api::error_response::ErrorResponse;
 use crate::models::db::ca_cert_x509::{CaCertX509Nioca, CaCertX509Root};
 use crate::models::db::config_oidc::ConfigOidcEntity;
@@ -8,11 +9,13 @@ use crate::models::db::master_key::MasterKeyEntity;
 use crate::models::db::sealed::SealedEntity;
 use crate::oidc::validation;
 use crate::oidc::validation::{OidcConfig, OidcProvider, TokenCacheReq};
+use crate::service::audit;
 use crate::util::secure_random;
 use anyhow::Context;
 use rcgen::Certificate;
 use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
-use sqlx::{Postgres, Transaction};
+use sqlx::{query, Postgres, Transaction};
+use std::collections::BTreeMap;
 use std::env;
 use std::sync::{Arc, OnceLock};
 use std::time::Duration;
@@ -75,12 +78,39 @@ impl Db {
         DB.get().unwrap()
     }
 
+    /// Returns `true` if the database is reachable
+    pub async fn ping() -> bool {
+        match DB.get() {
+            Some(db) => query!("SELECT 1 AS one").fetch_one(db).await.is_ok(),
+            None => false,
+        }
+    }
+
     pub async fn txn<'a>() -> Result<Transaction<'a, Postgres>, ErrorResponse> {
         let txn = DB.get().unwrap().begin().await?;
         Ok(txn)
     }
 }
 
+/// Runs a test against the database from the `DB_*` env vars. The pool is bound to the runtime
+/// it has been created on, which is why all database tests share a single one.
+///
+/// These tests are `#[ignore]`d and run against a dedicated test database with
+/// `cargo test -- --ignored`.
+#[cfg(test)]
+pub fn db_test<F: std::future::Future>(test: F) -> F::Output {
+    static RUNTIME: OnceLock<tokio::runtime::Runtime> = OnceLock::new();
+
+    let rt = RUNTIME.get_or_init(|| {
+        dotenvy::dotenv().ok();
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(Db::init())
+            .expect("Cannot connect to the test database");
+        rt
+    });
+    rt.block_on(test)
+}
+
 /// Application config
 pub struct Config {
     pub enc_keys: EncKeys,
@@ -101,14 +131,17 @@ impl Config {
             );
         }
 
+        audit::init(&enc_keys).await;
+        metrics::set_unsealed();
+
         let root_cert = CaCertX509Root::find_default(&enc_keys, false).await?;
         let nioca_cert = CaCertX509Nioca::find_default(&enc_keys).await?;
-        let nioca_signing_cert = cert_from_key_pem(&nioca_cert.key, &nioca_cert.cert_pem)?;
+        let nioca_signing_cert =
+            CaKeyStorage::parse(&nioca_cert.key)?.x509_signing_cert(&nioca_cert.cert_pem)?;
         let ca_chain_pem = format!("{}\n{}", nioca_cert.cert_pem, root_cert.cert_pem);
 
         // This builds the reqwest client with Niocas own Root CA added to the trust anchors for OIDC SSO
-        let reqwest_root_ca =
-            reqwest::tls::Certificate::from_der(root_cert.cert_der.as_bytes())?;
+        let reqwest_root_ca = reqwest::tls::Certificate::from_der(root_cert.cert_der.as_bytes())?;
         OidcProvider::init_client(reqwest_root_ca);
 
         let tx_token_cache = match ConfigOidcEntity::find(&enc_keys).await {
@@ -191,8 +224,8 @@ impl ConfigSealed {
 
 #[derive(Debug, Clone, Default)]
 pub struct EncKeys {
-    pub master_shard_1: Option<String>,
-    pub master_shard_2: Option<String>,
+    /// The collected master key shares by index
+    pub master_shares: BTreeMap<u8, String>,
     pub master_key: Vec<u8>,
     pub pepper: String,
     pub enc_key: EncKeyEntity,
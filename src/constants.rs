@@ -2,9 +2,16 @@ use axum::http::header;
 use once_cell::sync::Lazy;
 use regex::Regex;
 use std::env;
+use std::net::{IpAddr, SocketAddr};
 use std::string::ToString;
 use uuid::Uuid;
 
+pub const ACME_NONCE_LIFESPAN: time::Duration = time::Duration::minutes(10);
+pub const ACME_ORDER_LIFESPAN: time::Duration = time::Duration::hours(24);
+
+pub const OCSP_SIGNER_LIFESPAN: time::Duration = time::Duration::days(30);
+pub const OCSP_SIGNER_CACHE_LIFESPAN: time::Duration = time::Duration::minutes(5);
+
 pub const DEV_MODE_OIDC_REDIRECT: &str = "http://localhost:5173";
 
 pub const MAX_SESSION_TIME: time::Duration = time::Duration::hours(2);
@@ -15,6 +22,7 @@ pub const SESSION_TIMEOUT: time::Duration = time::Duration::minutes(15);
 pub const SESSION_TIMEOUT_NEW: time::Duration = time::Duration::minutes(3);
 
 pub const XSRF_HEADER: &str = "X-NIOCA-XSRF";
+pub const VAULT_TOKEN_HEADER: &str = "X-Vault-Token";
 
 pub const TOKEN_CACHE_LIFESPAN: u64 = 30;
 
@@ -31,6 +39,172 @@ pub static AUTO_UNSEAL: Lazy<bool> = Lazy::new(|| {
         .expect("AUTO_UNSEAL cannot be parsed to bool")
 });
 
+// Validity of certificates issued via ACME
+pub static ACME_CERT_VALID_HOURS: Lazy<i64> = Lazy::new(|| {
+    env::var("ACME_CERT_VALID_HOURS")
+        .unwrap_or_else(|_| "2160".to_string())
+        .parse::<i64>()
+        .expect("ACME_CERT_VALID_HOURS cannot be parsed to i64")
+});
+// The port HTTP-01 challenges will be validated against. Only useful for testing.
+pub static ACME_HTTP01_PORT: Lazy<u16> = Lazy::new(|| {
+    env::var("ACME_HTTP01_PORT")
+        .unwrap_or_else(|_| "80".to_string())
+        .parse::<u16>()
+        .expect("ACME_HTTP01_PORT cannot be parsed to u16")
+});
+// The resolvers DNS-01 challenges will be looked up with. Uses the system config if empty.
+pub static ACME_DNS_RESOLVERS: Lazy<Vec<SocketAddr>> = Lazy::new(|| {
+    env::var("ACME_DNS_RESOLVERS")
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|r| !r.is_empty())
+        .map(|r| {
+            r.parse::<SocketAddr>()
+                .or_else(|_| r.parse::<IpAddr>().map(|ip| SocketAddr::new(ip, 53)))
+                .expect("ACME_DNS_RESOLVERS must be a list of 'ip' or 'ip:port'")
+        })
+        .collect()
+});
+// An optional RFC 2136 DNS server Nioca publishes DNS-01 TXT records to by itself
+pub static ACME_DNS_UPDATE_SERVER: Lazy<Option<SocketAddr>> = Lazy::new(|| {
+    env::var("ACME_DNS_UPDATE_SERVER").ok().map(|s| {
+        s.parse::<SocketAddr>()
+            .expect("ACME_DNS_UPDATE_SERVER must be in the format 'ip:port'")
+    })
+});
+// The zones Nioca has been delegated to update via ACME_DNS_UPDATE_SERVER
+pub static ACME_DNS_UPDATE_ZONES: Lazy<Vec<String>> = Lazy::new(|| {
+    env::var("ACME_DNS_UPDATE_ZONES")
+        .unwrap_or_default()
+        .split(',')
+        .map(|z| z.trim().trim_end_matches('.').to_lowercase())
+        .filter(|z| !z.is_empty())
+        .collect()
+});
+pub static ACME_DNS_UPDATE_TSIG_NAME: Lazy<Option<String>> =
+    Lazy::new(|| env::var("ACME_DNS_UPDATE_TSIG_NAME").ok());
+pub static ACME_DNS_UPDATE_TSIG_ALG: Lazy<String> = Lazy::new(|| {
+    env::var("ACME_DNS_UPDATE_TSIG_ALG").unwrap_or_else(|_| "hmac-sha256".to_string())
+});
+// base64 encoded TSIG secret, like in a BIND 'key' statement
+pub static ACME_DNS_UPDATE_TSIG_SECRET: Lazy<Option<String>> =
+    Lazy::new(|| env::var("ACME_DNS_UPDATE_TSIG_SECRET").ok());
+pub static ACME_DNS_UPDATE_TTL: Lazy<u32> = Lazy::new(|| {
+    env::var("ACME_DNS_UPDATE_TTL")
+        .unwrap_or_else(|_| "60".to_string())
+        .parse::<u32>()
+        .expect("ACME_DNS_UPDATE_TTL cannot be parsed to u32")
+});
+
+// How long a freshly generated CRL is valid. It will be re-generated after half of this time.
+pub static CRL_VALIDITY_HOURS: Lazy<i64> = Lazy::new(|| {
+    env::var("CRL_VALIDITY_HOURS")
+        .unwrap_or_else(|_| "24".to_string())
+        .parse::<i64>()
+        .expect("CRL_VALIDITY_HOURS cannot be parsed to i64")
+});
+
+// How long OCSP responses may be cached by clients
+pub static OCSP_VALIDITY_MINUTES: Lazy<i64> = Lazy::new(|| {
+    env::var("OCSP_VALIDITY_MINUTES")
+        .unwrap_or_else(|_| "60".to_string())
+        .parse::<i64>()
+        .expect("OCSP_VALIDITY_MINUTES cannot be parsed to i64")
+});
+
+// The default lifetime of a JWT-SVID, if the request does not ask for a shorter one
+pub static SPIFFE_JWT_SVID_TTL: Lazy<i64> = Lazy::new(|| {
+    env::var("SPIFFE_JWT_SVID_TTL")
+        .unwrap_or_else(|_| "300".to_string())
+        .parse::<i64>()
+        .expect("SPIFFE_JWT_SVID_TTL cannot be parsed to i64")
+});
+// How often SPIFFE bundle consumers should poll for an updated trust bundle in seconds
+pub const SPIFFE_BUNDLE_REFRESH_HINT: i64 = 300;
+
+// Days before the expiry of a CA, client or certificate when notifications are sent out,
+// sorted ascending
+pub static NOTIFY_EXPIRY_DAYS: Lazy<Vec<i64>> = Lazy::new(|| {
+    let mut days = env::var("NOTIFY_EXPIRY_DAYS")
+        .unwrap_or_else(|_| "30,14,7,1".to_string())
+        .split(',')
+        .map(str::trim)
+        .filter(|d| !d.is_empty())
+        .map(|d| {
+            d.parse::<i64>()
+                .expect("NOTIFY_EXPIRY_DAYS must be a list of days as i64")
+        })
+        .collect::<Vec<_>>();
+    days.sort_unstable();
+    days.dedup();
+    days
+});
+// If expiry notifications should additionally be sent to the email of the client or user
+pub static NOTIFY_EXPIRY_OWNERS: Lazy<bool> = Lazy::new(|| {
+    env::var("NOTIFY_EXPIRY_OWNERS")
+        .unwrap_or_else(|_| "false".to_string())
+        .parse::<bool>()
+        .expect("NOTIFY_EXPIRY_OWNERS cannot be parsed to bool")
+});
+// The admin email addresses notifications are sent to
+pub static NOTIFY_EMAILS: Lazy<Vec<String>> = Lazy::new(|| {
+    env::var("NOTIFY_EMAILS")
+        .unwrap_or_default()
+        .split(',')
+        .map(|e| e.trim().to_string())
+        .filter(|e| !e.is_empty())
+        .collect()
+});
+// Generic webhooks notifications are POSTed to as JSON
+pub static NOTIFY_WEBHOOKS: Lazy<Vec<String>> = Lazy::new(|| {
+    env::var("NOTIFY_WEBHOOKS")
+        .unwrap_or_default()
+        .split(',')
+        .map(|u| u.trim().to_string())
+        .filter(|u| !u.is_empty())
+        .collect()
+});
+// If set, each webhook request is signed with a HMAC-SHA256 with this secret
+pub static NOTIFY_WEBHOOK_SECRET: Lazy<Option<String>> =
+    Lazy::new(|| env::var("NOTIFY_WEBHOOK_SECRET").ok());
+
+// If set, `/metrics` is served on this port via plain HTTP only instead of the main port
+pub static METRICS_PORT: Lazy<Option<u16>> = Lazy::new(|| {
+    env::var("METRICS_PORT")
+        .ok()
+        .map(|p| p.parse::<u16>().expect("Cannot parse METRICS_PORT to u16"))
+});
+
+pub static SMTP_HOST: Lazy<Option<String>> = Lazy::new(|| env::var("SMTP_HOST").ok());
+// One of 'tls', 'starttls' or 'none'
+pub static SMTP_TLS: Lazy<String> = Lazy::new(|| {
+    let tls = env::var("SMTP_TLS").unwrap_or_else(|_| "starttls".to_string());
+    if !["tls", "starttls", "none"].contains(&tls.as_str()) {
+        panic!("SMTP_TLS must be one of 'tls', 'starttls' or 'none'");
+    }
+    tls
+});
+// Defaults to the standard port for the SMTP_TLS mode
+pub static SMTP_PORT: Lazy<Option<u16>> = Lazy::new(|| {
+    env::var("SMTP_PORT")
+        .ok()
+        .map(|p| p.parse::<u16>().expect("SMTP_PORT cannot be parsed to u16"))
+});
+pub static SMTP_USER: Lazy<Option<String>> = Lazy::new(|| env::var("SMTP_USER").ok());
+pub static SMTP_PASSWORD: Lazy<Option<String>> = Lazy::new(|| env::var("SMTP_PASSWORD").ok());
+pub static SMTP_FROM: Lazy<String> = Lazy::new(|| {
+    env::var("SMTP_FROM").unwrap_or_else(|_| {
+        let url = PUB_URL
+            .split_once("://")
+            .map(|(_, u)| u)
+            .unwrap_or(&PUB_URL);
+        let host = url.split([':', '/']).next().unwrap_or_default();
+        format!("Nioca <nioca@{}>", host)
+    })
+});
+
 pub static UNSEAL_RATE_LIMIT: Lazy<u32> = Lazy::new(|| {
     env::var("UNSEAL_RATE_LIMIT")
         .unwrap_or_else(|_| "10".to_string())
@@ -38,6 +212,28 @@ pub static UNSEAL_RATE_LIMIT: Lazy<u32> = Lazy::new(|| {
         .expect("UNSEAL_RATE_LIMIT cannot be parsed to u32")
 });
 
+// The default Shamir shares config for the master key during init
+pub static UNSEAL_SHARES_THRESHOLD: Lazy<u8> = Lazy::new(|| {
+    env::var("UNSEAL_SHARES_THRESHOLD")
+        .unwrap_or_else(|_| "3".to_string())
+        .parse::<u8>()
+        .expect("UNSEAL_SHARES_THRESHOLD cannot be parsed to u8")
+});
+pub static UNSEAL_SHARES_TOTAL: Lazy<u8> = Lazy::new(|| {
+    env::var("UNSEAL_SHARES_TOTAL")
+        .unwrap_or_else(|_| "5".to_string())
+        .parse::<u8>()
+        .expect("UNSEAL_SHARES_TOTAL cannot be parsed to u8")
+});
+
+// If set, CA private keys can be stored inside a PKCS#11 token loaded from this module
+pub static PKCS11_MODULE: Lazy<Option<String>> = Lazy::new(|| env::var("PKCS11_MODULE").ok());
+pub static PKCS11_TOKEN_LABEL: Lazy<String> =
+    Lazy::new(|| env::var("PKCS11_TOKEN_LABEL").unwrap_or_else(|_| "nioca".to_string()));
+pub static PKCS11_PIN: Lazy<String> = Lazy::new(|| {
+    env::var("PKCS11_PIN").expect("PKCS11_PIN must be set when PKCS11_MODULE is used")
+});
+
 // The public url for direct access in case of a HA deployment behind a load balancer
 pub static DIRECT_ACCESS_PUB_URL: Lazy<Option<String>> =
     Lazy::new(|| match env::var("DIRECT_ACCESS_PUB_URL") {
@@ -97,11 +293,24 @@ pub static RE_INIT_KEY: Lazy<Regex> = Lazy::new(|| Regex::new(r"^[a-zA-Z0-9]{0,1
 pub static RE_LINUX_USER: Lazy<Regex> = Lazy::new(|| Regex::new(r"^[a-z0-9-_@.]{2,30}$").unwrap());
 // Lazy::new(|| Regex::new(r"^[a-z_]([a-z0-9_-]{0,31}|[a-z0-9_-]{0,30}\$)$").unwrap());
 pub static RE_MASTER_SHARD_KEY: Lazy<Regex> =
-    Lazy::new(|| Regex::new(r"^[a-zA-Z0-9]{48}$").unwrap());
+    Lazy::new(|| Regex::new(r"^([a-zA-Z0-9]{48}|[a-fA-F0-9]{130})$").unwrap());
 pub static RE_XSRF: Lazy<Regex> = Lazy::new(|| Regex::new(r"^[a-zA-Z0-9]{48}$").unwrap());
 
+// the trust domain may be empty to remove it from a group
+pub static RE_SPIFFE_TRUST_DOMAIN: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^([a-z0-9\-_]+(\.[a-z0-9\-_]+)*)?$").unwrap());
+
 // X509 validation regexes
 pub static RE_SUBJECT_NAME: Lazy<Regex> = Lazy::new(|| Regex::new(r"^[a-zA-Z0-9.*-]+$").unwrap());
 pub static RE_SUBJECT_NAME_OPT: Lazy<Regex> =
     Lazy::new(|| Regex::new(r"^[a-zA-Z0-9-.*\s]+$").unwrap());
 pub static RE_DNS_SIMPLE: Lazy<Regex> = Lazy::new(|| Regex::new(r"[a-zA-Z0-9.\-*]+").unwrap());
+// an absolute URI with a scheme and only printable ASCII, like `spiffe://corp.example/ns/prod/sa/api`
+pub static RE_SAN_URI: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^[a-zA-Z][a-zA-Z0-9+.\-]*:[!-~]+$").unwrap());
+pub static RE_SAN_EMAIL: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"^[a-zA-Z0-9.!#$%&'*+/=?^_`{|}~\-]+@[a-zA-Z0-9\-]+(\.[a-zA-Z0-9\-]+)+$").unwrap()
+});
+// the UPN may contain any UTF8 characters in the user part, but the suffix is a domain name
+pub static RE_SAN_UPN: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^[^@\s]+@[a-zA-Z0-9\-]+(\.[a-zA-Z0-9\-]+)*$").unwrap());
@@ -7,8 +7,12 @@ use crate::certificates::x509::bootstrap::bootstrap_x509;
 use crate::cli::Cli;
 use crate::logging::setup_logging;
 use crate::server::run_server;
+use crate::service::backup::{backup_cli, restore_cli};
+use crate::service::custodian::decrypt_share;
 use clap::Parser;
 
+/// ACME server (RFC 8555)
+mod acme;
 /// Encryption and Signing
 mod certificates;
 /// CLI arguments parser
@@ -19,8 +23,12 @@ mod config;
 mod constants;
 /// Logging modules
 mod logging;
+/// Prometheus metrics
+mod metrics;
 /// Models / Structs used in the application
 mod models;
+/// Email and webhook notifications
+mod notifications;
 /// OIDC SSO module
 mod oidc;
 /// API routes
@@ -33,6 +41,8 @@ mod server;
 mod service;
 /// Utilities and Helpers
 mod util;
+/// Vault PKI secrets engine compatible API
+mod vault;
 
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
 
@@ -44,5 +54,8 @@ async fn main() -> Result<(), anyhow::Error> {
         Cli::Server => run_server(level.as_str()).await,
         Cli::Ssh(opt) => bootstrap_ssh(*opt).await,
         Cli::X509(opt) => bootstrap_x509(*opt).await,
+        Cli::DecryptShare(opt) => decrypt_share(opt).await,
+        Cli::Backup(opt) => backup_cli(opt).await,
+        Cli::Restore(opt) => restore_cli(opt).await,
     }
 }
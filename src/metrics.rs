@@ -0,0 +1,274 @@
+use crate::models::api::error_response::ErrorResponse;
+use ::metrics::{counter, describe_counter, describe_gauge, describe_histogram, gauge, histogram};
+use axum::extract::{MatchedPath, Request};
+use axum::middleware::Next;
+use axum::response::Response;
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+const CERTS_ISSUED: &str = "nioca_certs_issued_total";
+const CERTS_ISSUE_FAILURES: &str = "nioca_certs_issue_failures_total";
+const UNSEAL_ATTEMPTS: &str = "nioca_unseal_attempts_total";
+const SEALED: &str = "nioca_sealed";
+const SESSIONS_CREATED: &str = "nioca_sessions_created_total";
+const SESSIONS_ACTIVE: &str = "nioca_sessions_active";
+const OIDC_TOKEN_CACHE: &str = "nioca_oidc_token_cache_total";
+const PASSWORD_HASH_WAIT: &str = "nioca_password_hash_queue_wait_seconds";
+const CA_EXPIRY_DAYS: &str = "nioca_ca_expiry_days";
+const HTTP_REQUEST_DURATION: &str = "nioca_http_request_duration_seconds";
+
+static HANDLE: OnceLock<PrometheusHandle> = OnceLock::new();
+static IS_SEALED: AtomicBool = AtomicBool::new(true);
+
+/// The type of an issued certificate
+#[derive(Debug, Clone, Copy)]
+pub enum CertType {
+    X509,
+    Ssh,
+}
+
+impl CertType {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::X509 => "x509",
+            Self::Ssh => "ssh",
+        }
+    }
+}
+
+/// How a certificate has been issued
+#[derive(Debug, Clone, Copy)]
+pub enum IssueFormat {
+    /// x509 with a generated key in PEM format
+    Pem,
+    /// x509 with a generated key in PKCS12 format
+    Pkcs12,
+    /// x509 from a CSR
+    Csr,
+    /// x509 via ACME
+    Acme,
+    /// x509 via the Vault PKI compatible API
+    Vault,
+    /// SSH with a generated key pair
+    KeyPair,
+    /// SSH for an existing public key
+    PublicKey,
+}
+
+impl IssueFormat {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Pem => "pem",
+            Self::Pkcs12 => "pkcs12",
+            Self::Csr => "csr",
+            Self::Acme => "acme",
+            Self::Vault => "vault",
+            Self::KeyPair => "key_pair",
+            Self::PublicKey => "public_key",
+        }
+    }
+}
+
+/// Installs the global Prometheus recorder. Must be called once at startup.
+pub fn init() {
+    let handle = PrometheusBuilder::new()
+        .set_buckets_for_metric(
+            Matcher::Full(HTTP_REQUEST_DURATION.to_string()),
+            &[
+                0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+            ],
+        )
+        .and_then(|b| {
+            b.set_buckets_for_metric(
+                Matcher::Full(PASSWORD_HASH_WAIT.to_string()),
+                &[0.001, 0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0],
+            )
+        })
+        .expect("Valid histogram buckets")
+        .install_recorder()
+        .expect("Installing the Prometheus recorder");
+    let _ = HANDLE.set(handle);
+
+    describe_counter!(CERTS_ISSUED, "Issued certificates");
+    describe_counter!(
+        CERTS_ISSUE_FAILURES,
+        "Failed certificate issuance requests by error type"
+    );
+    describe_counter!(UNSEAL_ATTEMPTS, "Unseal attempts by step and result");
+    describe_gauge!(SEALED, "1 if this instance is sealed");
+    describe_counter!(SESSIONS_CREATED, "Created sessions");
+    describe_gauge!(
+        SESSIONS_ACTIVE,
+        "Authenticated and not yet expired sessions"
+    );
+    describe_counter!(OIDC_TOKEN_CACHE, "OIDC token validation cache lookups");
+    describe_histogram!(
+        PASSWORD_HASH_WAIT,
+        ::metrics::Unit::Seconds,
+        "Time a password hash waited in the queue"
+    );
+    describe_gauge!(CA_EXPIRY_DAYS, "Days until a CA certificate expires");
+    describe_histogram!(
+        HTTP_REQUEST_DURATION,
+        ::metrics::Unit::Seconds,
+        "HTTP request latency"
+    );
+
+    gauge!(SEALED).set(1.0);
+}
+
+/// Renders all metrics in the Prometheus text format
+pub fn render() -> String {
+    HANDLE.get().map(|h| h.render()).unwrap_or_default()
+}
+
+pub fn is_sealed() -> bool {
+    IS_SEALED.load(Ordering::Relaxed)
+}
+
+pub fn set_unsealed() {
+    IS_SEALED.store(false, Ordering::Relaxed);
+    gauge!(SEALED).set(0.0);
+}
+
+pub fn cert_issued(typ: CertType, format: IssueFormat, group_id: &Uuid, ca_id: Option<&Uuid>) {
+    counter!(
+        CERTS_ISSUED,
+        "typ" => typ.as_str(),
+        "format" => format.as_str(),
+        "group" => group_id.to_string(),
+        "ca" => ca_id.map(|id| id.to_string()).unwrap_or_default(),
+    )
+    .increment(1);
+}
+
+pub fn cert_issue_failed(typ: CertType, err: &ErrorResponse) {
+    counter!(
+        CERTS_ISSUE_FAILURES,
+        "typ" => typ.as_str(),
+        "error" => format!("{:?}", err.typ),
+    )
+    .increment(1);
+}
+
+/// `step` is either `shard` or `unseal`
+pub fn unseal_attempt(step: &'static str, success: bool) {
+    let result = if success { "success" } else { "failure" };
+    counter!(UNSEAL_ATTEMPTS, "step" => step, "result" => result).increment(1);
+}
+
+pub fn session_created(local: bool) {
+    let typ = if local { "local" } else { "oidc" };
+    counter!(SESSIONS_CREATED, "typ" => typ).increment(1);
+}
+
+pub fn sessions_active(count: i64) {
+    gauge!(SESSIONS_ACTIVE).set(count as f64);
+}
+
+pub fn oidc_token_cache(hit: bool) {
+    let result = if hit { "hit" } else { "miss" };
+    counter!(OIDC_TOKEN_CACHE, "result" => result).increment(1);
+}
+
+pub fn password_hash_wait(wait: Duration) {
+    histogram!(PASSWORD_HASH_WAIT).record(wait.as_secs_f64());
+}
+
+/// `typ` is either `root` or `intermediate`
+pub fn ca_expiry(ca_id: &Uuid, name: &str, typ: &'static str, days: i64) {
+    gauge!(
+        CA_EXPIRY_DAYS,
+        "ca" => ca_id.to_string(),
+        "name" => name.to_string(),
+        "typ" => typ,
+    )
+    .set(days as f64);
+}
+
+/// Middleware which records the latency for each request by its matched route
+pub async fn track_http(req: Request, next: Next) -> Response {
+    let path = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|p| p.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".to_string());
+    let method = req.method().to_string();
+
+    let start = Instant::now();
+    let resp = next.run(req).await;
+
+    histogram!(
+        HTTP_REQUEST_DURATION,
+        "method" => method,
+        "path" => path,
+        "status" => resp.status().as_u16().to_string(),
+    )
+    .record(start.elapsed().as_secs_f64());
+
+    resp
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::api::error_response::ErrorResponseType;
+    use axum::body::Body;
+    use axum::routing::get;
+    use axum::Router;
+    use tower::ServiceExt;
+
+    /// The only test installing the global recorder, which can only be done once per process
+    #[tokio::test]
+    async fn test_metrics() {
+        init();
+
+        let group_id = Uuid::new_v4();
+        cert_issued(CertType::X509, IssueFormat::Csr, &group_id, None);
+        cert_issued(CertType::X509, IssueFormat::Csr, &group_id, None);
+        cert_issue_failed(
+            CertType::Ssh,
+            &ErrorResponse::new(ErrorResponseType::Forbidden, "test"),
+        );
+        unseal_attempt("shard", false);
+        set_unsealed();
+        assert!(!is_sealed());
+
+        let app = Router::new()
+            .route("/test/:id", get(|| async { "ok" }))
+            .layer(axum::middleware::from_fn(track_http));
+        let req = Request::builder()
+            .uri("/test/1337")
+            .body(Body::empty())
+            .unwrap();
+        let resp = app.oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), 200);
+
+        let rendered = render();
+        let expected = [
+            format!(
+                r#"nioca_certs_issued_total{{typ="x509",format="csr",group="{}",ca=""}} 2"#,
+                group_id
+            ),
+            r#"nioca_certs_issue_failures_total{typ="ssh",error="Forbidden"} 1"#.to_string(),
+            r#"nioca_unseal_attempts_total{step="shard",result="failure"} 1"#.to_string(),
+            "nioca_sealed 0".to_string(),
+            // the route instead of the path keeps the cardinality low
+            r#"nioca_http_request_duration_seconds_count{method="GET",path="/test/:id",status="200"} 1"#
+                .to_string(),
+            r#"nioca_http_request_duration_seconds_bucket{method="GET",path="/test/:id",status="200",le="0.005"}"#
+                .to_string(),
+        ];
+        for line in expected {
+            assert!(
+                rendered.contains(&line),
+                "missing '{}' in:\n{}",
+                line,
+                rendered
+            );
+        }
+    }
+}
@@ -43,11 +43,9 @@ impl ErrorResponse {
             message: msg.into(),
         }
     }
-}
 
-impl IntoResponse for ErrorResponse {
-    fn into_response(self) -> Response {
-        let status = match self.typ {
+    pub fn status_code(&self) -> StatusCode {
+        match self.typ {
             ErrorResponseType::BadRequest => StatusCode::BAD_REQUEST,
             ErrorResponseType::Connection => StatusCode::SERVICE_UNAVAILABLE,
             ErrorResponseType::Forbidden => StatusCode::FORBIDDEN,
@@ -57,9 +55,13 @@ impl IntoResponse for ErrorResponse {
             ErrorResponseType::NotFound => StatusCode::NOT_FOUND,
             ErrorResponseType::TooManyRequests => StatusCode::TOO_MANY_REQUESTS,
             _ => StatusCode::INTERNAL_SERVER_ERROR,
-        };
+        }
+    }
+}
 
-        (status, Json(self)).into_response()
+impl IntoResponse for ErrorResponse {
+    fn into_response(self) -> Response {
+        (self.status_code(), Json(self)).into_response()
     }
 }
 
@@ -280,3 +282,51 @@ impl From<SystemTimeError> for ErrorResponse {
         }
     }
 }
+
+impl From<serde_json::Error> for ErrorResponse {
+    fn from(value: serde_json::Error) -> Self {
+        error!("From<serde_json::Error>: {}", value);
+        Self {
+            typ: ErrorResponseType::Internal,
+            message: "Internal JSON serialization error".to_string(),
+        }
+    }
+}
+
+impl From<lettre::address::AddressError> for ErrorResponse {
+    fn from(value: lettre::address::AddressError) -> Self {
+        Self {
+            typ: ErrorResponseType::BadRequest,
+            message: format!("Invalid email address: {}", value),
+        }
+    }
+}
+
+impl From<lettre::error::Error> for ErrorResponse {
+    fn from(value: lettre::error::Error) -> Self {
+        error!("From<lettre::error::Error>: {}", value);
+        Self {
+            typ: ErrorResponseType::Internal,
+            message: format!("Error building the email: {}", value),
+        }
+    }
+}
+
+impl From<lettre::transport::smtp::Error> for ErrorResponse {
+    fn from(value: lettre::transport::smtp::Error) -> Self {
+        Self {
+            typ: ErrorResponseType::Connection,
+            message: format!("SMTP error: {}", value),
+        }
+    }
+}
+
+impl From<cryptoki::error::Error> for ErrorResponse {
+    fn from(value: cryptoki::error::Error) -> Self {
+        error!("From<cryptoki::error::Error>: {}", value);
+        Self {
+            typ: ErrorResponseType::Internal,
+            message: format!("PKCS#11 token error: {}", value),
+        }
+    }
+}
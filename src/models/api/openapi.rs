@@ -8,10 +8,22 @@ use crate::constants::PUB_URL_WITH_SCHEME;
 use crate::models::api::error_response;
 use crate::models::api::request;
 use crate::models::api::response;
+use crate::models::db::profile;
+use crate::routes::audit;
+use crate::routes::backup;
+use crate::routes::ca;
+use crate::routes::certs_ssh;
+use crate::routes::certs_x509;
 use crate::routes::clients_ssh;
 use crate::routes::clients_x509;
+use crate::routes::enc_keys;
+use crate::routes::metrics;
+use crate::routes::notifications;
+use crate::routes::ocsp;
 use crate::routes::oidc;
+use crate::routes::profiles;
 use crate::routes::sealed;
+use crate::routes::spiffe;
 use crate::routes::unsealed;
 use crate::routes::users;
 use crate::service;
@@ -21,23 +33,72 @@ use crate::VERSION;
 #[derive(OpenApi)]
 #[openapi(
     paths(
+        audit::get_audit,
+        audit::get_verify,
+        backup::post_backup,
+        backup::post_restore,
+        enc_keys::get_enc_keys,
+        enc_keys::post_rotate,
+        enc_keys::post_reencrypt,
+        enc_keys::get_rotation,
+        enc_keys::delete_enc_key,
+        ca::post_ca_ssh_pkcs11,
+        ca::post_ca_x509_pkcs11,
+        ca::get_ca_x509_csr,
+        ca::post_ca_x509_csr,
+        ca::post_ca_x509_cert,
+        ca::get_ca_x509_crl,
+        ca::put_ca_x509_crl,
+        ca::get_crl_der,
+        ca::get_crl_pem,
+        ca::get_ca_x509_ocsp,
+        ca::put_ca_x509_ocsp,
+        ca::get_krl,
+        certs_ssh::get_certs,
+        certs_ssh::get_cert,
+        certs_ssh::get_revoked,
+        certs_ssh::post_revoke_serial,
+        certs_ssh::post_revoke_key_id,
+        certs_ssh::post_revoke_public_key,
+        certs_x509::get_certs,
+        certs_x509::get_cert,
+        certs_x509::get_revoked,
+        certs_x509::post_revoke_serial,
+        certs_x509::post_revoke_client,
+        certs_x509::post_revoke_user,
         clients_ssh::get_clients,
         clients_ssh::post_client,
         clients_ssh::get_client,
         clients_ssh::put_client,
         clients_ssh::get_client_secret,
+        clients_ssh::post_sign_client_pub_key,
         clients_x509::get_clients,
         clients_x509::post_client,
         clients_x509::get_client,
         clients_x509::put_client,
         clients_x509::get_client_secret,
         clients_x509::post_build_client_cert,
+        clients_x509::post_build_client_cert_csr,
+        clients_x509::post_client_jwt_svid,
+        metrics::get_metrics,
+        metrics::get_live,
+        metrics::get_ready,
+        notifications::post_test,
+        ocsp::post_ocsp,
+        ocsp::get_ocsp,
         oidc::get_oidc_exists,
         oidc::get_config_oidc,
         oidc::put_config_oidc,
+        profiles::get_profiles,
+        profiles::post_profile,
+        profiles::put_profile,
+        profiles::delete_profile,
+        spiffe::get_bundle,
         users::get_users,
         users::get_user_group_access,
         users::post_user_group_access,
+        users::post_user_ssh_cert,
+        users::post_user_x509_cert,
         sealed::post_init,
         sealed::post_init_check,
         sealed::post_master_shard,
@@ -48,38 +109,91 @@ use crate::VERSION;
         unsealed::get_login_check,
         unsealed::post_session,
         unsealed::get_status,
+        unsealed::post_master_key_rekey,
     ),
     components(
         schemas(
             certificates::CertFormat,
+            certificates::CertStatus,
             certificates::SshKeyAlg,
             certificates::X509KeyAlg,
             certificates::X509KeyUsages,
             certificates::X509KeyUsagesExt,
+            certificates::X509RevocationReason,
+            certificates::x509::policy::X509Policy,
             error_response::ErrorResponse,
             error_response::ErrorResponseType,
+            profile::SshProfile,
+            profile::X509Profile,
             request::AddMasterShardRequest,
+            request::BackupRestoreRequest,
             request::ClientSshRequest,
             request::InitRequest,
             request::LoginRequest,
+            request::MasterKeyRekeyRequest,
+            request::Pkcs11KeyRequest,
+            request::ProfileRequest,
             request::ConfigOidcEntityRequest,
             request::JwtClaimRequest,
             request::JwtClaimTypRequest,
+            request::JwtSvidRequest,
+            request::SshPublicKeyRequest,
+            request::SshRevokeKeyIdRequest,
+            request::SshRevokePublicKeyRequest,
             request::UnsealRequest,
+            request::X509CaCertRequest,
+            request::X509CaCsrRequest,
+            request::X509CrlConfigRequest,
+            request::X509CsrRequest,
+            request::X509OcspConfigRequest,
+            request::X509RevokeRequest,
+            response::AuditLogEntryResponse,
+            response::AuditLogResponse,
+            response::AuditVerifyError,
+            response::AuditVerifyResponse,
+            response::BackupRestoreResponse,
+            response::EncKeyResponse,
+            response::EncKeyRotationResponse,
             response::CasSshResponse,
             response::CasX509Response,
+            response::X509CaCsrResponse,
+            response::X509CaCsrsResponse,
             response::X509CertificatesInspectResponse,
             response::CertificateInspectResponse,
             response::CertX509Response,
+            response::CertX509CsrResponse,
+            response::CertX509DetailsResponse,
+            response::CertX509InventoryResponse,
+            response::CertsX509Response,
+            response::CertSshDetailsResponse,
+            response::CertSshInventoryResponse,
+            response::CertsSshResponse,
             response::ClientSshResponse,
             response::ClientX509Response,
             response::ClientSecretResponse,
             response::X509ExtensionResponse,
             response::X509ValidityResponse,
+            response::X509RevokedResponse,
+            response::X509CrlResponse,
+            response::X509OcspResponse,
+            response::HealthResponse,
             response::InitResponse,
+            response::JwtSvidResponse,
+            response::MasterKeyRekeyResponse,
+            response::ProfileResponse,
             response::SessionResponse,
+            response::SpiffeBundleKey,
+            response::SpiffeBundleResponse,
             response::SealedStatus,
             response::SshCertificateResponse,
+            response::SshCertificateSignedResponse,
+            response::SshCertRevokedResponse,
+            response::SshKeyIdRevokedResponse,
+            response::SshKeyRevokedResponse,
+            response::SshRevokedResponse,
+            service::audit::AuditAction,
+            service::backup::BackupArchive,
+            service::backup::BackupArchiveBody,
             service::x509::CheckedCerts,
         ),
     ),
@@ -88,6 +202,12 @@ use crate::VERSION;
         (name = "unsealed", description = "Generic routes in unsealed status"),
         (name = "ca", description = "X509 / SSH Certificate Authorities"),
         (name = "clients", description = "Client specific routes"),
+        (name = "certs", description = "Issued certificates and revocation"),
+        (name = "profiles", description = "Reusable X509 / SSH certificate profiles"),
+        (name = "audit", description = "Tamper-evident audit log"),
+        (name = "backup", description = "Encrypted backup and restore"),
+        (name = "enc_keys", description = "Encryption key rotation"),
+        (name = "notifications", description = "Email and webhook notifications"),
         (name = "common", description = "Routes available in both states"),
         (name = "oidc", description = "OIDC config"),
     ),
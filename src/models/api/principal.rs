@@ -16,6 +16,9 @@ use time::OffsetDateTime;
 use tokio::sync::RwLock;
 use uuid::Uuid;
 
+/// The name of the local root user for logging
+pub const LOCAL_ADMIN: &str = "local admin";
+
 /// The AuthorizedUser making requests to the API
 #[derive(Debug, Serialize)]
 #[serde(rename_all = "camelCase")]
@@ -95,6 +98,19 @@ impl Principal {
             "Admin access only".to_string(),
         ))
     }
+
+    /// A short, human readable identifier for logging actions of this principal
+    pub fn name(&self) -> String {
+        if self.local {
+            LOCAL_ADMIN.to_string()
+        } else if let Some(email) = &self.email {
+            email.clone()
+        } else {
+            self.user_id
+                .map(|id| id.to_string())
+                .unwrap_or_else(|| "unknown".to_string())
+        }
+    }
 }
 
 impl Display for Principal {
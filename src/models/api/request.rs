@@ -1,19 +1,30 @@
-use crate::certificates::{SshKeyAlg, X509KeyAlg, X509KeyUsages, X509KeyUsagesExt};
+use crate::certificates::x509::policy::X509Policy;
+use crate::certificates::{
+    CertStatus, SshKeyAlg, X509KeyAlg, X509KeyUsages, X509KeyUsagesExt, X509RevocationReason,
+};
 use crate::constants::{
     RE_CA_NAME, RE_CLIENT_NAME, RE_DNS_SIMPLE, RE_HEX, RE_INIT_KEY, RE_JWT_CLAIM, RE_JWT_SCOPE,
-    RE_LINUX_USER, RE_MASTER_SHARD_KEY, RE_SUBJECT_NAME, RE_SUBJECT_NAME_OPT, RE_XSRF,
+    RE_LINUX_USER, RE_MASTER_SHARD_KEY, RE_SAN_EMAIL, RE_SAN_UPN, RE_SAN_URI,
+    RE_SPIFFE_TRUST_DOMAIN, RE_SUBJECT_NAME, RE_SUBJECT_NAME_OPT, RE_XSRF,
 };
+use crate::models::api::error_response::{ErrorResponse, ErrorResponseType};
 use crate::models::db::client_ssh::SshCertType;
+use crate::models::db::profile::{SshProfile, X509Profile};
+use crate::service::audit::AuditAction;
+use crate::service::backup::BackupArchive;
 use serde::{Deserialize, Serialize};
 use std::net::Ipv4Addr;
 use std::str::FromStr;
-use utoipa::ToSchema;
+use time::OffsetDateTime;
+use utoipa::{IntoParams, ToSchema};
 use uuid::Uuid;
 use validator::{Validate, ValidationError};
 
 #[derive(Debug, Serialize, Deserialize, Validate, ToSchema)]
 pub struct AddMasterShardRequest {
-    #[validate(regex(path = "RE_MASTER_SHARD_KEY", code = "[a-zA-Z0-9]{48}"))]
+    /// A master key share, or one of the 2 shards for instances initialized before Shamir's
+    /// Secret Sharing was supported
+    #[validate(regex(path = "RE_MASTER_SHARD_KEY", code = "[a-fA-F0-9]{130}"))]
     pub key: String,
     #[validate(regex(path = "RE_XSRF", code = "[a-zA-Z0-9]{48}"))]
     pub xsrf: String,
@@ -41,6 +52,11 @@ pub struct ClientSshRequest {
     pub permit_user_rc: Option<bool>,
     #[validate(range(min = 1))]
     pub valid_secs: i32,
+    /// The linked SSH profile. Its values replace the ones above at each issuance.
+    pub profile_id: Option<Uuid>,
+    /// Values, which win over the linked profile
+    #[validate]
+    pub profile_overrides: Option<SshProfile>,
     // #[validate(email)]
     // pub email: String,
 }
@@ -71,12 +87,29 @@ pub struct ClientX509Request {
     pub alt_names_dns: Vec<String>,
     #[validate(custom(function = "validate_vec_ip_simple"))]
     pub alt_names_ip: Vec<String>,
+    /// URI SANs like SPIFFE IDs: `spiffe://example.com/ns/prod/sa/api`
+    #[serde(default)]
+    #[validate(custom(function = "validate_vec_san_uri"))]
+    pub alt_names_uri: Vec<String>,
+    /// RFC822 email SANs
+    #[serde(default)]
+    #[validate(custom(function = "validate_vec_san_email"))]
+    pub alt_names_email: Vec<String>,
+    /// Microsoft User Principal Names, added as otherName SANs: `jdoe@corp.example.com`
+    #[serde(default)]
+    #[validate(custom(function = "validate_vec_san_upn"))]
+    pub alt_names_upn: Vec<String>,
     pub key_usage: Vec<X509KeyUsages>,
     pub key_usage_ext: Vec<X509KeyUsagesExt>,
     #[validate(range(min = 1))]
     pub valid_hours: i32,
     #[validate(email)]
     pub email: String,
+    /// The linked X509 profile. Its values replace the ones above at each issuance.
+    pub profile_id: Option<Uuid>,
+    /// Values, which win over the linked profile
+    #[validate]
+    pub profile_overrides: Option<X509Profile>,
 }
 
 #[derive(Debug, Deserialize, Validate, ToSchema)]
@@ -94,6 +127,20 @@ pub struct GenerateSshKeyRequest {
     #[validate(regex(path = "RE_CA_NAME", code = "[a-zA-Z0-9\\-_.\\s]+"))]
     pub name: Option<String>,
     pub alg: SshKeyAlg,
+    /// Generate the key pair inside the configured PKCS#11 token
+    pub pkcs11: Option<bool>,
+}
+
+/// Moves the private key of a CA into the configured PKCS#11 token
+#[derive(Debug, Deserialize, ToSchema, Validate)]
+pub struct Pkcs11KeyRequest {
+    /// The hex `CKA_ID` of a key, which already exists inside the token, instead of importing the
+    /// current one
+    #[validate(
+        regex(path = "RE_HEX", code = "[a-fA-F0-9]"),
+        length(min = 2, max = 128)
+    )]
+    pub key_id: Option<String>,
 }
 
 #[derive(Debug, Deserialize, ToSchema, Validate)]
@@ -103,6 +150,14 @@ pub struct GroupCreateRequest {
     pub name: String,
     pub ca_ssh: Uuid,
     pub ca_x509: Uuid,
+    /// Enables the ACME directory for this group
+    #[serde(default)]
+    pub acme_enabled: bool,
+    /// Restricts the X509 certificates, which can be issued inside this group
+    pub x509_policy: Option<X509Policy>,
+    /// Binds the group to a SPIFFE trust domain like `example.org`
+    #[validate(regex(path = "RE_SPIFFE_TRUST_DOMAIN", code = "[a-z0-9\\-_.]+"))]
+    pub spiffe_trust_domain: Option<String>,
 }
 
 #[derive(Debug, Deserialize, ToSchema, Validate)]
@@ -113,6 +168,13 @@ pub struct GroupUpdateRequest {
     pub enabled: bool,
     pub ca_ssh: Uuid,
     pub ca_x509: Uuid,
+    /// Enables or disables the ACME directory for this group, stays unchanged if not given
+    pub acme_enabled: Option<bool>,
+    /// Replaces the X509 policy, stays unchanged if not given. An empty policy removes it.
+    pub x509_policy: Option<X509Policy>,
+    /// Replaces the SPIFFE trust domain, stays unchanged if not given. An empty value removes it.
+    #[validate(regex(path = "RE_SPIFFE_TRUST_DOMAIN", code = "[a-z0-9\\-_.]+"))]
+    pub spiffe_trust_domain: Option<String>,
 }
 
 #[derive(Debug, Deserialize, Validate, ToSchema)]
@@ -128,6 +190,52 @@ pub struct InitRequest {
     pub init_key: String,
     #[validate(regex(path = "RE_XSRF", code = "[a-zA-Z0-9]{48}"))]
     pub xsrf_key: String,
+    /// The number of shares needed for unsealing, default: `UNSEAL_SHARES_THRESHOLD`
+    #[validate(range(min = 2, max = 16))]
+    pub shares_threshold: Option<u8>,
+    /// The number of shares the master key is split into, default: the number of
+    /// `custodian_keys` or `UNSEAL_SHARES_TOTAL`
+    #[validate(range(min = 2, max = 16))]
+    pub shares_total: Option<u8>,
+    /// One age X25519 or OpenSSH ed25519 / RSA public key per custodian. Each share is only
+    /// returned encrypted to the key at the same position.
+    #[validate(length(min = 2, max = 16))]
+    pub custodian_keys: Option<Vec<String>>,
+}
+
+#[derive(Debug, Deserialize, Validate, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct MasterKeyRekeyRequest {
+    /// At least as many current shares as the threshold
+    #[validate(custom(function = "validate_vec_master_shares"))]
+    pub master_shares: Vec<String>,
+    /// The new number of shares needed for unsealing, stays unchanged if not given
+    #[validate(range(min = 2, max = 16))]
+    pub shares_threshold: Option<u8>,
+    /// The new number of shares, stays unchanged if not given
+    #[validate(range(min = 2, max = 16))]
+    pub shares_total: Option<u8>,
+    /// One public key per custodian, the new shares are only returned encrypted if given
+    #[validate(length(min = 2, max = 16))]
+    pub custodian_keys: Option<Vec<String>>,
+    /// The local root password is peppered with the master key and must be re-hashed
+    #[validate(length(min = 16, max = 128))]
+    pub local_password: String,
+}
+
+#[derive(Debug, Deserialize, Validate, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct BackupRestoreRequest {
+    pub archive: BackupArchive,
+    /// At least as many shares of the master key the archive has been created with as the
+    /// threshold
+    #[validate(custom(function = "validate_vec_master_shares"))]
+    pub master_shares: Vec<String>,
+    /// The tables to add missing rows to. A full restore is only possible with the CLI.
+    #[validate(length(max = 23))]
+    pub tables: Vec<String>,
+    /// Only validate the archive without writing anything
+    pub dry_run: Option<bool>,
 }
 
 #[derive(Debug, Deserialize, Validate, ToSchema)]
@@ -175,6 +283,14 @@ pub enum JwtClaimTypRequest {
     Groups,
 }
 
+#[derive(Debug, Deserialize, Validate, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SshPublicKeyRequest {
+    /// OpenSSH formatted public key like `ssh-ed25519 AAAA... comment`
+    #[validate(length(max = 16384))]
+    pub pub_key: String,
+}
+
 #[derive(Debug, Clone, Deserialize, Validate, ToSchema)]
 pub struct UnsealRequest {
     #[validate(regex(path = "RE_XSRF", code = "[a-zA-Z0-9]{48}"))]
@@ -207,6 +323,12 @@ pub struct UserGroupAccessX509Request {
     pub key_usage_ext: Vec<X509KeyUsagesExt>,
     #[validate(range(min = 1))]
     pub valid_hours: i32,
+    /// Add the users email as RFC822 SAN to issued certificates
+    #[serde(default)]
+    pub san_email: bool,
+    /// Add the users email as Microsoft UPN otherName SAN to issued certificates
+    #[serde(default)]
+    pub san_upn: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Validate, ToSchema)]
@@ -220,6 +342,22 @@ pub struct UsersGroupAccessRequest {
     pub secret_delete: bool,
     pub access_ssh: UserGroupAccessSshRequest,
     pub access_x509: UserGroupAccessX509Request,
+    /// The linked SSH profile, which replaces the values of `accessSsh` at each issuance
+    pub profile_ssh: Option<Uuid>,
+    /// The linked X509 profile, which replaces the values of `accessX509` at each issuance
+    pub profile_x509: Option<Uuid>,
+}
+
+/// A reusable certificate profile. Exactly one of `x509` or `ssh` must be given.
+#[derive(Debug, Deserialize, Validate, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ProfileRequest {
+    #[validate(regex(path = "RE_CA_NAME", code = "[a-zA-Z0-9\\-_.\\s]+"))]
+    pub name: String,
+    #[validate]
+    pub x509: Option<X509Profile>,
+    #[validate]
+    pub ssh: Option<SshProfile>,
 }
 
 // #[derive(Debug, Deserialize, Validate, ToSchema)]
@@ -248,6 +386,224 @@ pub struct X509CaAddRequest {
     pub it_password: String,
 }
 
+/// Generates the key of a new X509 intermediate CA inside Nioca. The returned CSR must be signed
+/// with the offline root.
+#[derive(Debug, Deserialize, Validate, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct X509CaCsrRequest {
+    #[validate(regex(path = "RE_CA_NAME", code = "[a-zA-Z0-9\\-_.\\s]+"))]
+    pub name: String,
+    pub key_alg: X509KeyAlg,
+    #[validate(regex(path = "RE_CA_NAME", code = "[a-zA-Z0-9\\-_.\\s]+"))]
+    pub common_name: String,
+    #[validate(regex(path = "RE_SUBJECT_NAME_OPT", code = "[a-zA-Z0-9-.*\\s]+"))]
+    pub country: Option<String>,
+    #[validate(regex(path = "RE_SUBJECT_NAME_OPT", code = "[a-zA-Z0-9-.*\\s]+"))]
+    pub locality: Option<String>,
+    #[validate(regex(path = "RE_SUBJECT_NAME_OPT", code = "[a-zA-Z0-9-.*\\s]+"))]
+    pub organizational_unit: Option<String>,
+    #[validate(regex(path = "RE_SUBJECT_NAME_OPT", code = "[a-zA-Z0-9-.*\\s]+"))]
+    pub organization: Option<String>,
+    #[validate(regex(path = "RE_SUBJECT_NAME_OPT", code = "[a-zA-Z0-9-.*\\s]+"))]
+    pub state_or_province: Option<String>,
+    /// Generate the key pair inside the configured PKCS#11 token
+    pub pkcs11: Option<bool>,
+}
+
+/// Completes a pending X509 CA with the certificate signed from its CSR
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct X509CaCertRequest {
+    pub root_pem: String,
+    pub it_pem: String,
+}
+
+#[derive(Debug, Deserialize, Validate, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct X509CsrRequest {
+    /// PKCS#10 certificate signing request in PEM format
+    #[validate(length(max = 16384))]
+    pub csr: String,
+}
+
+#[derive(Debug, Deserialize, Validate, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct JwtSvidRequest {
+    /// The `aud` claim, at least one audience is required
+    #[validate(length(min = 1, max = 16), custom(function = "validate_vec_audience"))]
+    pub audience: Vec<String>,
+    /// Lifetime in seconds, which can only be shorter than `SPIFFE_JWT_SVID_TTL`
+    #[validate(range(min = 1))]
+    pub ttl_secs: Option<i64>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct X509RevokeRequest {
+    pub reason: X509RevocationReason,
+    /// The time the key is known or suspected to be compromised as a UTC timestamp
+    pub invalidity_date: Option<i64>,
+}
+
+#[derive(Debug, Deserialize, Validate, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SshRevokeKeyIdRequest {
+    /// The SSH CA the certificates have been signed with
+    pub ca_id: Uuid,
+    #[validate(length(min = 1, max = 256))]
+    pub key_id: String,
+}
+
+#[derive(Debug, Deserialize, Validate, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SshRevokePublicKeyRequest {
+    /// The public key in OpenSSH format
+    #[validate(length(min = 1, max = 16384))]
+    pub public_key: String,
+}
+
+#[derive(Debug, Deserialize, Validate, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct X509CrlConfigRequest {
+    /// Add a CRL Distribution Points extension to newly issued certificates
+    pub cdp_enabled: bool,
+    /// Custom distribution point URL, if the CRL is published somewhere else than Nioca
+    #[validate(url)]
+    pub cdp_url: Option<String>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct X509OcspConfigRequest {
+    /// Add an Authority Information Access extension to newly issued certificates
+    pub aia_enabled: bool,
+    /// Sign OCSP responses with a delegated responder certificate instead of the CA key
+    pub delegated: bool,
+}
+
+/// Filters for the certificate inventory. All given values must match.
+#[derive(Debug, Deserialize, Validate, IntoParams)]
+#[serde(rename_all = "camelCase")]
+#[into_params(parameter_in = Query)]
+pub struct CertsQuery {
+    pub client_id: Option<Uuid>,
+    pub user_id: Option<Uuid>,
+    pub group_id: Option<Uuid>,
+    pub ca_id: Option<Uuid>,
+    pub serial: Option<i32>,
+    /// x509: SHA256 of the DER certificate as hex, SSH: `SHA256:...` of the public key
+    #[validate(length(max = 128))]
+    pub fingerprint: Option<String>,
+    /// Case-insensitive search in the subject and SANs (x509) or the key ID and principals (SSH)
+    #[validate(length(min = 1, max = 256))]
+    pub name: Option<String>,
+    /// SSH only: certificates containing exactly this principal
+    #[validate(length(min = 1, max = 256))]
+    pub principal: Option<String>,
+    /// unix timestamp in seconds
+    pub created_from: Option<i64>,
+    /// unix timestamp in seconds
+    pub created_until: Option<i64>,
+    /// unix timestamp in seconds
+    pub expires_from: Option<i64>,
+    /// unix timestamp in seconds
+    pub expires_until: Option<i64>,
+    pub status: Option<CertStatus>,
+    /// The `nextCursor` from the previous page
+    pub cursor: Option<i32>,
+    /// Page size, defaults to 50
+    #[validate(range(min = 1, max = 500))]
+    pub limit: Option<i64>,
+}
+
+impl CertsQuery {
+    pub fn limit(&self) -> i64 {
+        self.limit.unwrap_or(50)
+    }
+
+    /// The `cursor` for the next page. Only a full page can be followed by another one.
+    pub fn next_cursor(&self, page_len: usize, last_serial: Option<i32>) -> Option<i32> {
+        if page_len as i64 == self.limit() {
+            last_serial
+        } else {
+            None
+        }
+    }
+
+    /// The `name` as a pattern for `ILIKE`
+    pub fn name_pattern(&self) -> Option<String> {
+        self.name.as_ref().map(|name| {
+            let escaped = name
+                .replace('\\', "\\\\")
+                .replace('%', "\\%")
+                .replace('_', "\\_");
+            format!("%{}%", escaped)
+        })
+    }
+
+    pub fn created_from(&self) -> Result<Option<OffsetDateTime>, ErrorResponse> {
+        timestamp(self.created_from, "createdFrom")
+    }
+
+    pub fn created_until(&self) -> Result<Option<OffsetDateTime>, ErrorResponse> {
+        timestamp(self.created_until, "createdUntil")
+    }
+
+    pub fn expires_from(&self) -> Result<Option<OffsetDateTime>, ErrorResponse> {
+        timestamp(self.expires_from, "expiresFrom")
+    }
+
+    pub fn expires_until(&self) -> Result<Option<OffsetDateTime>, ErrorResponse> {
+        timestamp(self.expires_until, "expiresUntil")
+    }
+}
+
+/// Filters for the audit log. All given values must match.
+#[derive(Debug, Deserialize, Validate, IntoParams)]
+#[serde(rename_all = "camelCase")]
+#[into_params(parameter_in = Query)]
+pub struct AuditQuery {
+    #[validate(length(min = 1, max = 256))]
+    pub actor: Option<String>,
+    pub action: Option<AuditAction>,
+    /// The id of the object the action has been done on
+    #[validate(length(min = 1, max = 256))]
+    pub target: Option<String>,
+    pub success: Option<bool>,
+    /// unix timestamp in seconds
+    pub from: Option<i64>,
+    /// unix timestamp in seconds
+    pub until: Option<i64>,
+    /// The `nextCursor` from the previous page
+    pub cursor: Option<i64>,
+    /// Page size, defaults to 50
+    #[validate(range(min = 1, max = 500))]
+    pub limit: Option<i64>,
+}
+
+impl AuditQuery {
+    pub fn limit(&self) -> i64 {
+        self.limit.unwrap_or(50)
+    }
+
+    pub fn from(&self) -> Result<Option<OffsetDateTime>, ErrorResponse> {
+        timestamp(self.from, "from")
+    }
+
+    pub fn until(&self) -> Result<Option<OffsetDateTime>, ErrorResponse> {
+        timestamp(self.until, "until")
+    }
+}
+
+fn timestamp(ts: Option<i64>, name: &str) -> Result<Option<OffsetDateTime>, ErrorResponse> {
+    ts.map(|ts| {
+        OffsetDateTime::from_unix_timestamp(ts).map_err(|_| {
+            ErrorResponse::new(ErrorResponseType::BadRequest, format!("Invalid '{}'", name))
+        })
+    })
+    .transpose()
+}
+
 fn validate_vec_dns_simple(value: &[String]) -> Result<(), ValidationError> {
     let mut err = None;
     value.iter().for_each(|v| {
@@ -274,7 +630,45 @@ fn validate_vec_ip_simple(value: &[String]) -> Result<(), ValidationError> {
     Ok(())
 }
 
-fn validate_vec_principal(value: &[String]) -> Result<(), ValidationError> {
+fn validate_vec_san_uri(value: &[String]) -> Result<(), ValidationError> {
+    if value.iter().any(|v| !RE_SAN_URI.is_match(v)) {
+        return Err(ValidationError::new("scheme:[!-~]+"));
+    }
+    Ok(())
+}
+
+fn validate_vec_san_email(value: &[String]) -> Result<(), ValidationError> {
+    if value.iter().any(|v| !RE_SAN_EMAIL.is_match(v)) {
+        return Err(ValidationError::new("local@domain.tld"));
+    }
+    Ok(())
+}
+
+fn validate_vec_san_upn(value: &[String]) -> Result<(), ValidationError> {
+    if value.iter().any(|v| !RE_SAN_UPN.is_match(v)) {
+        return Err(ValidationError::new("user@domain"));
+    }
+    Ok(())
+}
+
+fn validate_vec_audience(value: &[String]) -> Result<(), ValidationError> {
+    if value
+        .iter()
+        .any(|v| v.is_empty() || v.len() > 256 || v.chars().any(|c| c.is_control()))
+    {
+        return Err(ValidationError::new("1-256 printable characters"));
+    }
+    Ok(())
+}
+
+fn validate_vec_master_shares(value: &[String]) -> Result<(), ValidationError> {
+    if value.iter().any(|v| !RE_MASTER_SHARD_KEY.is_match(v)) {
+        return Err(ValidationError::new("[a-fA-F0-9]{130}"));
+    }
+    Ok(())
+}
+
+pub(crate) fn validate_vec_principal(value: &[String]) -> Result<(), ValidationError> {
     let mut err = None;
     value.iter().for_each(|v| {
         if !RE_LINUX_USER.is_match(v) {
@@ -286,3 +680,64 @@ fn validate_vec_principal(value: &[String]) -> Result<(), ValidationError> {
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::extract::Query;
+    use axum::http::Uri;
+
+    fn certs_query(query: &str) -> CertsQuery {
+        let uri = format!("http://localhost/api/certs/x509?{}", query)
+            .parse::<Uri>()
+            .unwrap();
+        Query::<CertsQuery>::try_from_uri(&uri).unwrap().0
+    }
+
+    #[test]
+    fn test_certs_query() {
+        let client_id = Uuid::new_v4();
+        let query = certs_query(&format!(
+            "clientId={}&status=Revoked&name=a_b%25c%5C&createdFrom=1700000000&cursor=42&limit=10",
+            client_id
+        ));
+        assert!(query.validate().is_ok());
+        assert_eq!(query.client_id, Some(client_id));
+        assert_eq!(query.status, Some(CertStatus::Revoked));
+        assert_eq!(query.cursor, Some(42));
+        assert_eq!(query.limit(), 10);
+        // `ILIKE` wildcards from the input are matched literally
+        assert_eq!(query.name_pattern().unwrap(), "%a\\_b\\%c\\\\%");
+        assert_eq!(
+            query.created_from().unwrap().unwrap().unix_timestamp(),
+            1700000000
+        );
+        assert_eq!(query.created_until().unwrap(), None);
+
+        let query = certs_query("");
+        assert!(query.validate().is_ok());
+        assert_eq!(query.limit(), 50);
+        assert_eq!(query.name_pattern(), None);
+
+        assert!(certs_query("limit=0").validate().is_err());
+        assert!(certs_query("limit=501").validate().is_err());
+        assert!(certs_query("name=").validate().is_err());
+        assert!(certs_query(&format!("expiresUntil={}", i64::MAX))
+            .expires_until()
+            .is_err());
+
+        let uri = "http://localhost/api/certs/x509?status=unknown"
+            .parse::<Uri>()
+            .unwrap();
+        assert!(Query::<CertsQuery>::try_from_uri(&uri).is_err());
+    }
+
+    #[test]
+    fn test_certs_query_next_cursor() {
+        let query = certs_query("limit=3");
+        assert_eq!(query.next_cursor(3, Some(17)), Some(17));
+        // a page which is not full is the last one
+        assert_eq!(query.next_cursor(2, Some(18)), None);
+        assert_eq!(query.next_cursor(0, None), None);
+    }
+}
@@ -1,18 +1,43 @@
-use crate::certificates::{CertFormat, SshKeyAlg, X509KeyAlg, X509KeyUsages, X509KeyUsagesExt};
+use crate::acme::jws::Jwk;
+use crate::certificates::x509::policy::X509Policy;
+use crate::certificates::x509::verification::x509_der_from_bytes;
+use crate::certificates::{
+    CertFormat, CertStatus, SshKeyAlg, X509KeyAlg, X509KeyUsages, X509KeyUsagesExt,
+    X509RevocationReason,
+};
 use crate::constants::OIDC_CALLBACK_URI;
+use crate::models::api::error_response::{ErrorResponse, ErrorResponseType};
 use crate::models::api::principal::Principal;
+use crate::models::db::acme_eab_key::AcmeEabKeyEntity;
+use crate::models::db::audit_log::AuditLogEntity;
 use crate::models::db::ca_cert_ssh::{CaCertSshEntity, SshKeyPairOpenssh};
+use crate::models::db::cert_ssh::CertSshEntity;
+use crate::models::db::cert_ssh_revoked::{
+    CertSshRevokedEntity, SshRevokedKeyEntity, SshRevokedKeyIdEntity,
+};
+use crate::models::db::cert_x509::CertX509Entity;
+use crate::models::db::cert_x509_revoked::CertX509RevokedEntity;
 use crate::models::db::client_ssh::{ClientSshEntity, SshCertType};
 use crate::models::db::client_x509::ClientX509Entity;
 use crate::models::db::config_oidc::{ConfigOidcEntity, JwtClaim};
+use crate::models::db::crl_x509::CrlX509Entity;
+use crate::models::db::enc_key_rotation::EncKeyRotationEntity;
 use crate::models::db::groups::GroupEntity;
+use crate::models::db::ocsp_x509::OcspX509Entity;
+use crate::models::db::profile::{ProfileEntity, SshProfile, X509Profile};
 use crate::models::db::user::UserEntity;
 use crate::models::db::user_group_access::UsersGroupAccess;
+use der::pem::LineEnding;
 use serde::{Deserialize, Serialize};
+use ssh_key::HashAlg;
+use std::collections::{BTreeMap, HashMap};
+use time::OffsetDateTime;
 use tracing::info;
 use utoipa::ToSchema;
 use uuid::Uuid;
 use x509_parser::certificate::X509Certificate;
+use x509_parser::der_parser::Oid;
+use x509_parser::objects::{oid2sn, oid_registry};
 
 #[derive(Debug, Serialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
@@ -68,6 +93,20 @@ pub struct CasX509Response {
     pub cas_x509: Vec<CertificateInspectResponse>,
 }
 
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct X509CaCsrsResponse {
+    pub csrs: Vec<X509CaCsrResponse>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct X509CaCsrResponse {
+    pub id: Uuid,
+    pub name: String,
+    pub csr_pem: String,
+}
+
 #[derive(Debug, Serialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct X509CertificatesInspectResponse {
@@ -179,6 +218,25 @@ pub struct CertX509Response {
     pub not_after: i64,
 }
 
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CertX509CsrResponse {
+    pub cert: String,
+    pub cert_fingerprint: String,
+    pub cert_chain: String,
+    /// not after as a unix timestamp in seconds in UTC format
+    pub not_after: i64,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct JwtSvidResponse {
+    pub token: String,
+    pub spiffe_id: String,
+    /// not after as a unix timestamp in seconds in UTC format
+    pub not_after: i64,
+}
+
 #[derive(Debug, Serialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct ClientSshResponse {
@@ -208,6 +266,10 @@ pub struct ClientSshResponse {
     pub valid_secs: i32,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub latest_cert: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub profile_id: Option<Uuid>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub profile_overrides: Option<SshProfile>,
     // pub email: String,
 }
 
@@ -223,6 +285,10 @@ impl From<ClientSshEntity> for ClientSshResponse {
         let source_addresses = value
             .source_addresses
             .map(|a| a.split(',').map(String::from).collect::<Vec<String>>());
+        // the overrides have been validated before they were saved
+        let profile_overrides = value
+            .profile_overrides
+            .and_then(|o| serde_json::from_str(&o).ok());
 
         Self {
             id: value.id,
@@ -242,6 +308,8 @@ impl From<ClientSshEntity> for ClientSshResponse {
             permit_user_rc: value.permit_user_rc,
             valid_secs: value.valid_secs,
             latest_cert: value.latest_cert,
+            profile_id: value.profile_id,
+            profile_overrides,
         }
     }
 }
@@ -269,6 +337,9 @@ pub struct ClientX509Response {
     pub state_or_province: Option<String>,
     pub alt_names_dns: Vec<String>,
     pub alt_names_ip: Vec<String>,
+    pub alt_names_uri: Vec<String>,
+    pub alt_names_email: Vec<String>,
+    pub alt_names_upn: Vec<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub key_usage: Option<Vec<X509KeyUsages>>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -277,6 +348,10 @@ pub struct ClientX509Response {
     pub email: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub latest_cert: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub profile_id: Option<Uuid>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub profile_overrides: Option<X509Profile>,
 }
 
 impl From<ClientX509Entity> for ClientX509Response {
@@ -309,6 +384,10 @@ impl From<ClientX509Entity> for ClientX509Response {
             .split(',')
             .map(|n| n.trim().to_string())
             .collect::<Vec<String>>();
+        // the overrides have been validated before they were saved
+        let profile_overrides = value
+            .profile_overrides
+            .and_then(|o| serde_json::from_str(&o).ok());
 
         Self {
             id: value.id,
@@ -325,11 +404,16 @@ impl From<ClientX509Entity> for ClientX509Response {
             state_or_province: value.state_or_province,
             alt_names_dns,
             alt_names_ip,
+            alt_names_uri: value.alt_names_uri,
+            alt_names_email: value.alt_names_email,
+            alt_names_upn: value.alt_names_upn,
             key_usage,
             key_usage_ext,
             valid_hours: value.valid_hours,
             email: value.email,
             latest_cert: value.latest_cert,
+            profile_id: value.profile_id,
+            profile_overrides,
         }
     }
 }
@@ -384,16 +468,79 @@ pub struct GroupResponse {
     pub enabled: bool,
     pub ca_ssh: Option<Uuid>,
     pub ca_x509: Option<Uuid>,
+    pub acme_enabled: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub x509_policy: Option<X509Policy>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub spiffe_trust_domain: Option<String>,
 }
 
 impl From<GroupEntity> for GroupResponse {
     fn from(value: GroupEntity) -> Self {
+        // the policy has been validated before it was saved
+        let x509_policy = value.x509_policy().ok().flatten();
         Self {
             id: value.id,
             name: value.name,
             enabled: value.enabled,
             ca_ssh: value.ca_ssh,
             ca_x509: value.ca_x509,
+            acme_enabled: value.acme_enabled,
+            x509_policy,
+            spiffe_trust_domain: value.spiffe_trust_domain,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AcmeEabKeyResponse {
+    pub kid: String,
+    /// The ACME account this key has been used for
+    pub account_id: Option<Uuid>,
+    pub created: i64,
+}
+
+impl From<AcmeEabKeyEntity> for AcmeEabKeyResponse {
+    fn from(value: AcmeEabKeyEntity) -> Self {
+        Self {
+            kid: value.kid,
+            account_id: value.account_id,
+            created: value.created.unix_timestamp(),
+        }
+    }
+}
+
+/// A new external account key. The HMAC key is only returned once.
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AcmeEabKeyCreatedResponse {
+    pub kid: String,
+    /// The base64 URL safe encoded HMAC key
+    pub hmac_key: String,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ProfileResponse {
+    pub id: Uuid,
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub x509: Option<X509Profile>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ssh: Option<SshProfile>,
+}
+
+impl From<ProfileEntity> for ProfileResponse {
+    fn from(value: ProfileEntity) -> Self {
+        // the profile has been validated before it was saved
+        let x509 = value.x509().ok().flatten();
+        let ssh = value.ssh().ok().flatten();
+        Self {
+            id: value.id,
+            name: value.name,
+            x509,
+            ssh,
         }
     }
 }
@@ -413,6 +560,15 @@ pub struct SshCertificateResponse {
     pub host_key_pair: SshKeyPairOpenssh,
 }
 
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SshCertificateSignedResponse {
+    pub ca_pub: String,
+    pub cert: String,
+    /// not after as a unix timestamp in seconds in UTC format
+    pub not_after: i64,
+}
+
 #[derive(Debug, Serialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct UserResponse {
@@ -448,11 +604,455 @@ pub struct X509ValidityResponse {
     pub not_after: String,
 }
 
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct X509RevokedResponse {
+    pub serial: i32,
+    pub reason: X509RevocationReason,
+    /// revocation time as a unix timestamp in seconds in UTC format
+    pub revoked: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub invalidity_date: Option<i64>,
+    pub revoked_by: String,
+}
+
+impl From<CertX509RevokedEntity> for X509RevokedResponse {
+    fn from(value: CertX509RevokedEntity) -> Self {
+        Self {
+            serial: value.serial,
+            reason: X509RevocationReason::from_value(value.reason),
+            revoked: value.revoked.unix_timestamp(),
+            invalidity_date: value.invalidity_date.map(|d| d.unix_timestamp()),
+            revoked_by: value.revoked_by,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SshCertRevokedResponse {
+    pub serial: i32,
+    pub ca_id: Uuid,
+    /// revocation time as a unix timestamp in seconds in UTC format
+    pub revoked: i64,
+    pub revoked_by: String,
+}
+
+impl From<CertSshRevokedEntity> for SshCertRevokedResponse {
+    fn from(value: CertSshRevokedEntity) -> Self {
+        Self {
+            serial: value.serial,
+            ca_id: value.ca_id,
+            revoked: value.revoked.unix_timestamp(),
+            revoked_by: value.revoked_by,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SshKeyIdRevokedResponse {
+    pub ca_id: Uuid,
+    pub key_id: String,
+    /// revocation time as a unix timestamp in seconds in UTC format
+    pub revoked: i64,
+    pub revoked_by: String,
+}
+
+impl From<SshRevokedKeyIdEntity> for SshKeyIdRevokedResponse {
+    fn from(value: SshRevokedKeyIdEntity) -> Self {
+        Self {
+            ca_id: value.ca_id,
+            key_id: value.key_id,
+            revoked: value.revoked.unix_timestamp(),
+            revoked_by: value.revoked_by,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SshKeyRevokedResponse {
+    /// The SHA256 fingerprint of the public key
+    pub fingerprint: String,
+    pub public_key: String,
+    /// revocation time as a unix timestamp in seconds in UTC format
+    pub revoked: i64,
+    pub revoked_by: String,
+}
+
+impl From<SshRevokedKeyEntity> for SshKeyRevokedResponse {
+    fn from(value: SshRevokedKeyEntity) -> Self {
+        Self {
+            fingerprint: value.fingerprint,
+            public_key: value.public_key,
+            revoked: value.revoked.unix_timestamp(),
+            revoked_by: value.revoked_by,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SshRevokedResponse {
+    pub certs: Vec<SshCertRevokedResponse>,
+    pub key_ids: Vec<SshKeyIdRevokedResponse>,
+    pub public_keys: Vec<SshKeyRevokedResponse>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CertX509InventoryResponse {
+    pub serial: i32,
+    pub id: Uuid,
+    /// unix timestamp in seconds in UTC format
+    pub created: i64,
+    /// unix timestamp in seconds in UTC format
+    pub expires: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<Uuid>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_id: Option<Uuid>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub group_id: Option<Uuid>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ca_id: Option<Uuid>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub subject: Option<String>,
+    pub sans: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fingerprint: Option<String>,
+    pub status: CertStatus,
+    /// revocation time as a unix timestamp in seconds in UTC format
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub revoked: Option<i64>,
+}
+
+impl CertX509InventoryResponse {
+    pub fn new(value: CertX509Entity, revoked: Option<&CertX509RevokedEntity>) -> Self {
+        Self {
+            serial: value.serial,
+            id: value.id,
+            created: value.created.unix_timestamp(),
+            expires: value.expires.unix_timestamp(),
+            client_id: value.client_id,
+            user_id: value.user_id,
+            group_id: value.group_id,
+            ca_id: value.ca_id,
+            subject: value.subject,
+            sans: value.sans.unwrap_or_default(),
+            fingerprint: value.fingerprint,
+            status: CertStatus::new(value.expires, revoked.is_some()),
+            revoked: revoked.map(|r| r.revoked.unix_timestamp()),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CertsX509Response {
+    pub certs: Vec<CertX509InventoryResponse>,
+    /// Pass as `cursor` to get the next page, if there are more certificates
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<i32>,
+}
+
+/// An issued x509 certificate together with the values decoded from its DER
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CertX509DetailsResponse {
+    pub cert: CertX509InventoryResponse,
+    pub issuer: String,
+    /// The serial as hex string like in the certificate
+    pub serial_hex: String,
+    /// unix timestamp in seconds in UTC format
+    pub not_before: i64,
+    /// unix timestamp in seconds in UTC format
+    pub not_after: i64,
+    pub signature_alg: String,
+    pub public_key_alg: String,
+    pub is_ca: bool,
+    pub key_usages: Vec<String>,
+    pub key_usages_ext: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub revocation: Option<X509RevokedResponse>,
+    pub pem: String,
+}
+
+impl CertX509DetailsResponse {
+    pub fn build(
+        value: CertX509Entity,
+        revoked: Option<CertX509RevokedEntity>,
+    ) -> Result<Self, ErrorResponse> {
+        let pem = der::pem::encode_string("CERTIFICATE", LineEnding::LF, &value.data)
+            .map_err(|err| ErrorResponse::new(ErrorResponseType::Internal, err.to_string()))?;
+        let x509 = x509_der_from_bytes(&value.data)?;
+
+        let oid_name = |oid: &Oid| {
+            oid2sn(oid, oid_registry())
+                .map(|name| name.to_string())
+                .unwrap_or_else(|_| oid.to_id_string())
+        };
+
+        let key_usages = match x509.key_usage() {
+            Ok(Some(usage)) => usage
+                .value
+                .to_string()
+                .split(", ")
+                .map(String::from)
+                .collect(),
+            _ => Vec::default(),
+        };
+        let key_usages_ext = match x509.extended_key_usage() {
+            Ok(Some(ext)) => {
+                let eku = &ext.value;
+                [
+                    (eku.any, "anyExtendedKeyUsage"),
+                    (eku.server_auth, "serverAuth"),
+                    (eku.client_auth, "clientAuth"),
+                    (eku.code_signing, "codeSigning"),
+                    (eku.email_protection, "emailProtection"),
+                    (eku.time_stamping, "timeStamping"),
+                    (eku.ocsp_signing, "OCSPSigning"),
+                ]
+                .into_iter()
+                .filter(|(enabled, _)| *enabled)
+                .map(|(_, name)| name.to_string())
+                .chain(eku.other.iter().map(|oid| oid.to_id_string()))
+                .collect()
+            }
+            _ => Vec::default(),
+        };
+
+        Ok(Self {
+            issuer: x509.issuer.to_string(),
+            serial_hex: x509.raw_serial_as_string(),
+            not_before: x509.validity.not_before.timestamp(),
+            not_after: x509.validity.not_after.timestamp(),
+            signature_alg: oid_name(&x509.signature_algorithm.algorithm),
+            public_key_alg: oid_name(&x509.public_key().algorithm.algorithm),
+            is_ca: x509.is_ca(),
+            key_usages,
+            key_usages_ext,
+            revocation: revoked
+                .as_ref()
+                .map(|r| X509RevokedResponse::from(r.clone())),
+            pem,
+            cert: CertX509InventoryResponse::new(value, revoked.as_ref()),
+        })
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CertSshInventoryResponse {
+    pub serial: i32,
+    pub id: Uuid,
+    /// unix timestamp in seconds in UTC format
+    pub created: i64,
+    /// unix timestamp in seconds in UTC format
+    pub expires: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<Uuid>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_id: Option<Uuid>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub group_id: Option<Uuid>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ca_id: Option<Uuid>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key_id: Option<String>,
+    pub principals: Vec<String>,
+    /// The `SHA256:` fingerprint of the certified public key
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fingerprint: Option<String>,
+    pub status: CertStatus,
+    /// revocation time as a unix timestamp in seconds in UTC format
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub revoked: Option<i64>,
+}
+
+impl CertSshInventoryResponse {
+    /// `revoked` is the time of the revocation by serial, key ID or public key
+    pub fn new(value: CertSshEntity, revoked: Option<OffsetDateTime>) -> Self {
+        Self {
+            serial: value.serial,
+            id: value.id,
+            created: value.created.unix_timestamp(),
+            expires: value.expires.unix_timestamp(),
+            client_id: value.client_id,
+            user_id: value.user_id,
+            group_id: value.group_id,
+            ca_id: value.ca_id,
+            key_id: value.key_id,
+            principals: value.principals.unwrap_or_default(),
+            fingerprint: value.fingerprint,
+            status: CertStatus::new(value.expires, revoked.is_some()),
+            revoked: revoked.map(|r| r.unix_timestamp()),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CertsSshResponse {
+    pub certs: Vec<CertSshInventoryResponse>,
+    /// Pass as `cursor` to get the next page, if there are more certificates
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<i32>,
+}
+
+/// An issued SSH certificate together with the values decoded from it
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CertSshDetailsResponse {
+    pub cert: CertSshInventoryResponse,
+    pub typ: SshCertType,
+    /// unix timestamp in seconds in UTC format
+    pub valid_after: u64,
+    /// unix timestamp in seconds in UTC format
+    pub valid_before: u64,
+    pub public_key_alg: String,
+    /// The `SHA256:` fingerprint of the CA key which signed the certificate
+    pub signature_key_fingerprint: String,
+    pub critical_options: HashMap<String, String>,
+    pub extensions: HashMap<String, String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub revocation: Option<SshCertRevokedResponse>,
+    pub openssh: String,
+}
+
+impl CertSshDetailsResponse {
+    /// `revoked_at` is the time of the revocation by serial, key ID or public key,
+    /// `revoked` only exists for a revocation by serial
+    pub fn build(
+        value: CertSshEntity,
+        revoked: Option<CertSshRevokedEntity>,
+        revoked_at: Option<OffsetDateTime>,
+    ) -> Result<Self, ErrorResponse> {
+        let cert = value.certificate()?;
+        let typ = if cert.cert_type().is_host() {
+            SshCertType::Host
+        } else {
+            SshCertType::User
+        };
+
+        Ok(Self {
+            typ,
+            valid_after: cert.valid_after(),
+            valid_before: cert.valid_before(),
+            public_key_alg: cert.public_key().algorithm().to_string(),
+            signature_key_fingerprint: cert
+                .signature_key()
+                .fingerprint(HashAlg::Sha256)
+                .to_string(),
+            critical_options: cert
+                .critical_options()
+                .iter()
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect(),
+            extensions: cert
+                .extensions()
+                .iter()
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect(),
+            revocation: revoked
+                .as_ref()
+                .map(|r| SshCertRevokedResponse::from(r.clone())),
+            openssh: String::from_utf8_lossy(&value.data).trim().to_string(),
+            cert: CertSshInventoryResponse::new(value, revoked_at),
+        })
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct X509CrlResponse {
+    pub ca_id: Uuid,
+    pub cdp_enabled: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cdp_url: Option<String>,
+    /// The public URL of the DER encoded CRL
+    pub url_der: String,
+    /// The public URL of the PEM encoded CRL
+    pub url_pem: String,
+    /// The `CRLNumber` of the current CRL
+    pub number: i64,
+    /// unix timestamp in seconds in UTC format
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub this_update: Option<i64>,
+    /// unix timestamp in seconds in UTC format
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_update: Option<i64>,
+}
+
+impl From<CrlX509Entity> for X509CrlResponse {
+    fn from(value: CrlX509Entity) -> Self {
+        Self {
+            url_der: CrlX509Entity::url_der(&value.ca_id),
+            url_pem: CrlX509Entity::url_pem(&value.ca_id),
+            ca_id: value.ca_id,
+            cdp_enabled: value.cdp_enabled,
+            cdp_url: value.cdp_url,
+            number: value.number,
+            this_update: value.this_update.map(|d| d.unix_timestamp()),
+            next_update: value.next_update.map(|d| d.unix_timestamp()),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct X509OcspResponse {
+    pub ca_id: Uuid,
+    pub aia_enabled: bool,
+    pub delegated: bool,
+    /// The public URL of the OCSP responder
+    pub url: String,
+    /// Expiry of the current delegated responder certificate as a unix timestamp in seconds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signer_expires: Option<i64>,
+}
+
+impl From<OcspX509Entity> for X509OcspResponse {
+    fn from(value: OcspX509Entity) -> Self {
+        Self {
+            ca_id: value.ca_id,
+            aia_enabled: value.aia_enabled,
+            delegated: value.delegated,
+            url: OcspX509Entity::url(),
+            signer_expires: value.signer_expires.map(|d| d.unix_timestamp()),
+        }
+    }
+}
+
 #[derive(Debug, Serialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct InitResponse {
-    pub master_shard_1: String,
-    pub master_shard_2: String,
+    /// Any `shares_threshold` of these are needed for unsealing
+    pub master_shares: Vec<String>,
+    pub shares_threshold: u8,
+    /// If the shares are age encrypted to the given custodian keys
+    pub encrypted: bool,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct MasterKeyRekeyResponse {
+    /// Any `shares_threshold` of these are needed for unsealing
+    pub master_shares: Vec<String>,
+    pub shares_threshold: u8,
+    /// If the shares are age encrypted to the given custodian keys
+    pub encrypted: bool,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct BackupRestoreResponse {
+    /// The number of rows for each table inside the archive
+    pub rows: BTreeMap<String, u64>,
+    /// The number of actually inserted rows, `None` for a dry run
+    pub restored: Option<BTreeMap<String, u64>>,
 }
 
 #[derive(Debug, Serialize, ToSchema)]
@@ -467,8 +1067,10 @@ pub struct SessionResponse {
 pub struct SealedStatus {
     pub is_initialized: bool,
     pub is_sealed: bool,
-    pub master_shard_1: bool,
-    pub master_shard_2: bool,
+    /// The number of valid master key shares added so far
+    pub shares_added: u8,
+    pub shares_threshold: u8,
+    pub shares_total: u8,
     pub is_ready: bool,
     pub key_add_rate_limit: u32,
 }
@@ -496,6 +1098,8 @@ pub struct UserGroupAccessX509Response {
     pub key_usage: Vec<X509KeyUsages>,
     pub key_usage_ext: Vec<X509KeyUsagesExt>,
     pub valid_hours: i32,
+    pub san_email: bool,
+    pub san_upn: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -509,6 +1113,10 @@ pub struct UsersGroupAccessResponse {
     pub secret_delete: bool,
     pub access_ssh: UserGroupAccessSshResponse,
     pub access_x509: UserGroupAccessX509Response,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub profile_ssh: Option<Uuid>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub profile_x509: Option<Uuid>,
 }
 
 impl From<UsersGroupAccess> for UsersGroupAccessResponse {
@@ -538,7 +1146,191 @@ impl From<UsersGroupAccess> for UsersGroupAccessResponse {
                 key_usage: value.access_x509.key_usage,
                 key_usage_ext: value.access_x509.key_usage_ext,
                 valid_hours: value.access_x509.valid_hours,
+                san_email: value.access_x509.san_email,
+                san_upn: value.access_x509.san_upn,
             },
+            profile_ssh: value.profile_ssh,
+            profile_x509: value.profile_x509,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct HealthResponse {
+    pub sealed: bool,
+    /// If the database is reachable
+    pub db: bool,
+}
+
+impl HealthResponse {
+    /// Only an unsealed instance with a reachable database can serve requests
+    pub fn is_ready(&self) -> bool {
+        !self.sealed && self.db
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct EncKeyResponse {
+    pub id: Uuid,
+    pub alg: String,
+    /// unix timestamp, not set for keys created before rotation was supported
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created: Option<i64>,
+    pub active: bool,
+    /// The amount of values which are still encrypted with this key
+    pub usages: i64,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct EncKeyRotationResponse {
+    /// The new key all values are re-encrypted with
+    pub enc_key_id: Uuid,
+    /// unix timestamp
+    pub started: i64,
+    /// unix timestamp
+    pub updated: i64,
+    /// unix timestamp
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub finished: Option<i64>,
+    pub total: i64,
+    pub done: i64,
+    pub failed: i64,
+    /// The latest error, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl From<EncKeyRotationEntity> for EncKeyRotationResponse {
+    fn from(value: EncKeyRotationEntity) -> Self {
+        Self {
+            enc_key_id: value.enc_key_id,
+            started: value.started.unix_timestamp(),
+            updated: value.updated.unix_timestamp(),
+            finished: value.finished.map(|ts| ts.unix_timestamp()),
+            total: value.total,
+            done: value.done,
+            failed: value.failed,
+            error: value.error,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditLogEntryResponse {
+    pub id: i64,
+    /// unix timestamp in microseconds
+    pub timestamp: i64,
+    pub actor: String,
+    pub action: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<String>,
+    pub success: bool,
+    /// hex encoded
+    pub prev_hash: String,
+    /// hex encoded
+    pub hash: String,
+    pub key_id: String,
+}
+
+impl From<AuditLogEntity> for AuditLogEntryResponse {
+    fn from(value: AuditLogEntity) -> Self {
+        Self {
+            id: value.id,
+            timestamp: (value.timestamp.unix_timestamp_nanos() / 1000) as i64,
+            actor: value.actor,
+            action: value.action,
+            target: value.target,
+            details: value.details,
+            success: value.success,
+            prev_hash: hex::encode(value.prev_hash),
+            hash: hex::encode(value.hash),
+            key_id: value.key_id,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditLogResponse {
+    pub entries: Vec<AuditLogEntryResponse>,
+    /// Pass as `cursor` to get the next page, if there are more entries
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<i64>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditVerifyError {
+    pub id: i64,
+    pub reason: String,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditVerifyResponse {
+    pub valid: bool,
+    /// The amount of entries which have been checked
+    pub entries: i64,
+    /// The id of the latest entry
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub head_id: Option<i64>,
+    /// The hex encoded hash of the latest entry. Keep a copy of it outside of Nioca to be able to
+    /// detect a removal of the latest entries.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub head_hash: Option<String>,
+    /// The first entry which failed the verification
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<AuditVerifyError>,
+}
+
+/// A SPIFFE trust bundle in the JWKS format of the SPIFFE Trust Domain and Bundle specification
+#[derive(Debug, Serialize, ToSchema)]
+pub struct SpiffeBundleResponse {
+    pub keys: Vec<SpiffeBundleKey>,
+    pub spiffe_refresh_hint: i64,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct SpiffeBundleKey {
+    /// `x509-svid` or `jwt-svid`
+    #[serde(rename = "use")]
+    pub key_use: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kid: Option<String>,
+    pub kty: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub crv: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub x: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub y: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub n: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub e: Option<String>,
+    /// The base64 DER encoded CA certificate for `x509-svid` keys
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub x5c: Option<Vec<String>>,
+}
+
+impl SpiffeBundleKey {
+    pub fn new(key_use: &'static str, jwk: Jwk) -> Self {
+        Self {
+            key_use,
+            kid: None,
+            kty: jwk.kty,
+            crv: jwk.crv,
+            x: jwk.x,
+            y: jwk.y,
+            n: jwk.n,
+            e: jwk.e,
+            x5c: None,
         }
     }
 }
@@ -0,0 +1,76 @@
+use crate::config::Db;
+use crate::models::api::error_response::ErrorResponse;
+use sqlx::{query, query_as, Postgres, Transaction};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+#[derive(Debug, Clone)]
+pub struct AcmeAccountEntity {
+    pub id: Uuid,
+    pub group_id: Uuid,
+    /// RFC 7638 JWK thumbprint of the account key
+    pub thumbprint: String,
+    /// The account public key as JWK JSON
+    pub jwk: String,
+    /// Comma separated contact URLs
+    pub contact: Option<String>,
+    pub status: String,
+    pub created: OffsetDateTime,
+    /// The external account key the account has been bound with
+    pub eab_kid: Option<String>,
+}
+
+impl AcmeAccountEntity {
+    pub async fn insert(&self, txn: &mut Transaction<'_, Postgres>) -> Result<(), ErrorResponse> {
+        query!(
+            r#"INSERT INTO acme_accounts
+            (id, group_id, thumbprint, jwk, contact, status, created, eab_kid)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)"#,
+            self.id,
+            self.group_id,
+            self.thumbprint,
+            self.jwk,
+            self.contact,
+            self.status,
+            self.created,
+            self.eab_kid,
+        )
+        .execute(&mut **txn)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn find(id: &Uuid) -> Result<Self, ErrorResponse> {
+        let res = query_as!(Self, "SELECT * FROM acme_accounts WHERE id = $1", id)
+            .fetch_one(Db::conn())
+            .await?;
+        Ok(res)
+    }
+
+    pub async fn find_by_thumbprint(
+        group_id: &Uuid,
+        thumbprint: &str,
+    ) -> Result<Option<Self>, ErrorResponse> {
+        let res = query_as!(
+            Self,
+            "SELECT * FROM acme_accounts WHERE group_id = $1 AND thumbprint = $2",
+            group_id,
+            thumbprint,
+        )
+        .fetch_optional(Db::conn())
+        .await?;
+        Ok(res)
+    }
+
+    pub async fn update(&self) -> Result<(), ErrorResponse> {
+        query!(
+            "UPDATE acme_accounts SET contact = $1, status = $2 WHERE id = $3",
+            self.contact,
+            self.status,
+            self.id,
+        )
+        .execute(Db::conn())
+        .await?;
+        Ok(())
+    }
+}
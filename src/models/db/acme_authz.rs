@@ -0,0 +1,128 @@
+use crate::config::Db;
+use crate::models::api::error_response::ErrorResponse;
+use sqlx::{query, query_as, Postgres, Transaction};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+#[derive(Debug, Clone)]
+pub struct AcmeAuthzEntity {
+    pub id: Uuid,
+    pub order_id: Uuid,
+    pub identifier_typ: String,
+    /// The identifier without a possible `*.` wildcard prefix
+    pub identifier_value: String,
+    pub wildcard: bool,
+    pub status: String,
+    pub expires: OffsetDateTime,
+}
+
+impl AcmeAuthzEntity {
+    pub async fn insert(&self, txn: &mut Transaction<'_, Postgres>) -> Result<(), ErrorResponse> {
+        query!(
+            r#"INSERT INTO acme_authorizations
+            (id, order_id, identifier_typ, identifier_value, wildcard, status, expires)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)"#,
+            self.id,
+            self.order_id,
+            self.identifier_typ,
+            self.identifier_value,
+            self.wildcard,
+            self.status,
+            self.expires,
+        )
+        .execute(&mut **txn)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn find(id: &Uuid) -> Result<Self, ErrorResponse> {
+        let res = query_as!(Self, "SELECT * FROM acme_authorizations WHERE id = $1", id)
+            .fetch_one(Db::conn())
+            .await?;
+        Ok(res)
+    }
+
+    pub async fn find_by_order(order_id: &Uuid) -> Result<Vec<Self>, ErrorResponse> {
+        let res = query_as!(
+            Self,
+            "SELECT * FROM acme_authorizations WHERE order_id = $1",
+            order_id
+        )
+        .fetch_all(Db::conn())
+        .await?;
+        Ok(res)
+    }
+
+    pub async fn update_status(&self) -> Result<(), ErrorResponse> {
+        query!(
+            "UPDATE acme_authorizations SET status = $1 WHERE id = $2",
+            self.status,
+            self.id,
+        )
+        .execute(Db::conn())
+        .await?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AcmeChallengeEntity {
+    pub id: Uuid,
+    pub authz_id: Uuid,
+    pub typ: String,
+    pub token: String,
+    pub status: String,
+    pub validated: Option<OffsetDateTime>,
+    /// A problem document as JSON, if the validation failed
+    pub error: Option<String>,
+}
+
+impl AcmeChallengeEntity {
+    pub async fn insert(&self, txn: &mut Transaction<'_, Postgres>) -> Result<(), ErrorResponse> {
+        query!(
+            r#"INSERT INTO acme_challenges (id, authz_id, typ, token, status, validated, error)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)"#,
+            self.id,
+            self.authz_id,
+            self.typ,
+            self.token,
+            self.status,
+            self.validated,
+            self.error,
+        )
+        .execute(&mut **txn)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn find(id: &Uuid) -> Result<Self, ErrorResponse> {
+        let res = query_as!(Self, "SELECT * FROM acme_challenges WHERE id = $1", id)
+            .fetch_one(Db::conn())
+            .await?;
+        Ok(res)
+    }
+
+    pub async fn find_by_authz(authz_id: &Uuid) -> Result<Vec<Self>, ErrorResponse> {
+        let res = query_as!(
+            Self,
+            "SELECT * FROM acme_challenges WHERE authz_id = $1 ORDER BY typ",
+            authz_id
+        )
+        .fetch_all(Db::conn())
+        .await?;
+        Ok(res)
+    }
+
+    pub async fn update(&self) -> Result<(), ErrorResponse> {
+        query!(
+            "UPDATE acme_challenges SET status = $1, validated = $2, error = $3 WHERE id = $4",
+            self.status,
+            self.validated,
+            self.error,
+            self.id,
+        )
+        .execute(Db::conn())
+        .await?;
+        Ok(())
+    }
+}
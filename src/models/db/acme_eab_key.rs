@@ -0,0 +1,147 @@
+use crate::certificates::encryption::{decrypt_by_kid, encrypt};
+use crate::config::{Db, EncKeys};
+use crate::models::api::error_response::{ErrorResponse, ErrorResponseType};
+use crate::util::secure_random;
+use ring::digest;
+use sqlx::{query, query_as, Postgres, Transaction};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+/// An external account binding key from RFC 8555 Section 7.3.4. Admins hand these out to bind
+/// an ACME account to a group, and each key can only bind a single account.
+#[derive(Debug, Clone)]
+pub struct AcmeEabKeyEntity {
+    pub kid: String,
+    pub group_id: Uuid,
+    /// The encrypted HMAC key
+    pub hmac_key: Vec<u8>,
+    pub enc_key_id: Uuid,
+    /// The account this key has been used for
+    pub account_id: Option<Uuid>,
+    pub created: OffsetDateTime,
+}
+
+impl AcmeEabKeyEntity {
+    /// Generates a new key for the group and returns it together with the plain HMAC key
+    pub async fn create(
+        group_id: &Uuid,
+        enc_keys: &EncKeys,
+    ) -> Result<(Self, Vec<u8>), ErrorResponse> {
+        let hmac_key = digest::digest(&digest::SHA256, secure_random(128).as_bytes())
+            .as_ref()
+            .to_vec();
+        let slf = Self {
+            kid: secure_random(24),
+            group_id: *group_id,
+            hmac_key: encrypt(&hmac_key, &enc_keys.enc_key.value)?,
+            enc_key_id: enc_keys.enc_key.id,
+            account_id: None,
+            created: OffsetDateTime::now_utc(),
+        };
+
+        query!(
+            r#"INSERT INTO acme_eab_keys (kid, group_id, hmac_key, enc_key_id, account_id, created)
+            VALUES ($1, $2, $3, $4, $5, $6)"#,
+            slf.kid,
+            slf.group_id,
+            slf.hmac_key,
+            slf.enc_key_id,
+            slf.account_id,
+            slf.created,
+        )
+        .execute(Db::conn())
+        .await?;
+
+        Ok((slf, hmac_key))
+    }
+
+    pub async fn find_all_for_group(group_id: &Uuid) -> Result<Vec<Self>, ErrorResponse> {
+        let res = query_as!(
+            Self,
+            "SELECT * FROM acme_eab_keys WHERE group_id = $1 ORDER BY created",
+            group_id
+        )
+        .fetch_all(Db::conn())
+        .await?;
+        Ok(res)
+    }
+
+    /// Returns the unused key of the group together with the decrypted HMAC key
+    pub async fn find_unused(
+        group_id: &Uuid,
+        kid: &str,
+        enc_keys: &EncKeys,
+    ) -> Result<(Self, Vec<u8>), ErrorResponse> {
+        let slf = query_as!(
+            Self,
+            r#"SELECT * FROM acme_eab_keys
+            WHERE kid = $1 AND group_id = $2 AND account_id IS NULL"#,
+            kid,
+            group_id,
+        )
+        .fetch_optional(Db::conn())
+        .await?
+        .ok_or_else(|| {
+            ErrorResponse::new(
+                ErrorResponseType::Unauthorized,
+                "Unknown or already used external account key",
+            )
+        })?;
+
+        let (hmac_key, _) = decrypt_by_kid(&slf.hmac_key, &slf.enc_key_id, enc_keys).await?;
+        Ok((slf, hmac_key))
+    }
+
+    /// Binds the key to the account. Fails if it has been used in the meantime.
+    pub async fn bind(
+        &self,
+        account_id: &Uuid,
+        txn: &mut Transaction<'_, Postgres>,
+    ) -> Result<(), ErrorResponse> {
+        let res = query!(
+            r#"UPDATE acme_eab_keys SET account_id = $1
+            WHERE kid = $2 AND account_id IS NULL"#,
+            account_id,
+            self.kid,
+        )
+        .execute(&mut **txn)
+        .await?;
+
+        if res.rows_affected() != 1 {
+            return Err(ErrorResponse::new(
+                ErrorResponseType::Unauthorized,
+                "Unknown or already used external account key",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Deletes the key. An account bound with it loses the binding as well.
+    pub async fn delete(group_id: &Uuid, kid: &str) -> Result<(), ErrorResponse> {
+        let mut txn = Db::txn().await?;
+        let res = query!(
+            "DELETE FROM acme_eab_keys WHERE group_id = $1 AND kid = $2",
+            group_id,
+            kid,
+        )
+        .execute(&mut *txn)
+        .await?;
+        if res.rows_affected() == 0 {
+            return Err(ErrorResponse::new(
+                ErrorResponseType::NotFound,
+                "External account key not found",
+            ));
+        }
+
+        query!(
+            "UPDATE acme_accounts SET eab_kid = NULL WHERE group_id = $1 AND eab_kid = $2",
+            group_id,
+            kid,
+        )
+        .execute(&mut *txn)
+        .await?;
+        txn.commit().await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,49 @@
+use crate::config::Db;
+use crate::constants::ACME_NONCE_LIFESPAN;
+use crate::models::api::error_response::ErrorResponse;
+use crate::util::secure_random;
+use sqlx::query;
+use std::ops::Add;
+use time::OffsetDateTime;
+
+/// Replay nonces for the ACME server. Each nonce can only be used exactly once.
+#[derive(Debug)]
+pub struct AcmeNonceEntity;
+
+impl AcmeNonceEntity {
+    /// Generates and saves a fresh nonce
+    pub async fn create() -> Result<String, ErrorResponse> {
+        let nonce = secure_random(32);
+        let expires = OffsetDateTime::now_utc().add(ACME_NONCE_LIFESPAN);
+        query!(
+            "INSERT INTO acme_nonces (nonce, expires) VALUES ($1, $2)",
+            nonce,
+            expires
+        )
+        .execute(Db::conn())
+        .await?;
+        Ok(nonce)
+    }
+
+    /// Deletes the nonce and returns `true` if it existed and was not expired
+    pub async fn consume(nonce: &str) -> Result<bool, ErrorResponse> {
+        let res = query!(
+            "DELETE FROM acme_nonces WHERE nonce = $1 AND expires > $2",
+            nonce,
+            OffsetDateTime::now_utc(),
+        )
+        .execute(Db::conn())
+        .await?;
+        Ok(res.rows_affected() == 1)
+    }
+
+    pub async fn delete_expired() -> Result<(), ErrorResponse> {
+        query!(
+            "DELETE FROM acme_nonces WHERE expires < $1",
+            OffsetDateTime::now_utc()
+        )
+        .execute(Db::conn())
+        .await?;
+        Ok(())
+    }
+}
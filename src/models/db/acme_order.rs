@@ -0,0 +1,199 @@
+use crate::acme::models::{AcmeIdentifier, AcmeStatus};
+use crate::certificates::x509::csr::X509Csr;
+use crate::config::Db;
+use crate::models::api::error_response::{ErrorResponse, ErrorResponseType};
+use crate::models::db::ca_cert_x509::CaCertX509Full;
+use crate::models::db::cert_x509::{CertX509Entity, X509Signed};
+use rcgen::{
+    CertificateParams, DnType, ExtendedKeyUsagePurpose, IsCa, KeyIdMethod, KeyUsagePurpose, SanType,
+};
+use sqlx::{query, query_as, Postgres, Transaction};
+use std::collections::BTreeSet;
+use time::OffsetDateTime;
+use tracing::info;
+use uuid::Uuid;
+
+#[derive(Debug, Clone)]
+pub struct AcmeOrderEntity {
+    pub id: Uuid,
+    pub account_id: Uuid,
+    pub status: String,
+    pub expires: OffsetDateTime,
+    /// The requested identifiers as JSON array
+    pub identifiers: String,
+    /// A problem document as JSON, if the order became invalid
+    pub error: Option<String>,
+    /// The serial from `certs_x509` once the certificate has been issued
+    pub cert_serial: Option<i32>,
+    pub created: OffsetDateTime,
+}
+
+impl AcmeOrderEntity {
+    pub async fn insert(&self, txn: &mut Transaction<'_, Postgres>) -> Result<(), ErrorResponse> {
+        query!(
+            r#"INSERT INTO acme_orders
+            (id, account_id, status, expires, identifiers, error, cert_serial, created)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)"#,
+            self.id,
+            self.account_id,
+            self.status,
+            self.expires,
+            self.identifiers,
+            self.error,
+            self.cert_serial,
+            self.created,
+        )
+        .execute(&mut **txn)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn find(id: &Uuid) -> Result<Self, ErrorResponse> {
+        let res = query_as!(Self, "SELECT * FROM acme_orders WHERE id = $1", id)
+            .fetch_one(Db::conn())
+            .await?;
+        Ok(res)
+    }
+
+    pub async fn find_all_by_account(account_id: &Uuid) -> Result<Vec<Self>, ErrorResponse> {
+        let res = query_as!(
+            Self,
+            "SELECT * FROM acme_orders WHERE account_id = $1 ORDER BY created",
+            account_id
+        )
+        .fetch_all(Db::conn())
+        .await?;
+        Ok(res)
+    }
+
+    /// Moves a `ready` order to `processing`. Returns `false`, if the order is not `ready` anymore,
+    /// for instance because a concurrent request is finalizing it already.
+    pub async fn start_processing(&mut self) -> Result<bool, ErrorResponse> {
+        let res = query!(
+            "UPDATE acme_orders SET status = $1 WHERE id = $2 AND status = $3",
+            AcmeStatus::Processing.as_str(),
+            self.id,
+            AcmeStatus::Ready.as_str(),
+        )
+        .execute(Db::conn())
+        .await?;
+
+        if res.rows_affected() == 1 {
+            self.status = AcmeStatus::Processing.as_str().to_string();
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    pub async fn update(&self) -> Result<(), ErrorResponse> {
+        query!(
+            "UPDATE acme_orders SET status = $1, error = $2, cert_serial = $3 WHERE id = $4",
+            self.status,
+            self.error,
+            self.cert_serial,
+            self.id,
+        )
+        .execute(Db::conn())
+        .await?;
+        Ok(())
+    }
+}
+
+impl AcmeOrderEntity {
+    pub fn identifiers(&self) -> Result<Vec<AcmeIdentifier>, ErrorResponse> {
+        serde_json::from_str(&self.identifiers).map_err(|err| {
+            ErrorResponse::new(
+                ErrorResponseType::Internal,
+                format!("Corrupted identifiers for ACME order {}: {}", self.id, err),
+            )
+        })
+    }
+
+    /// Signs the CSR for this order with the given CA. The CSR must request exactly the
+    /// identifiers from the order.
+    pub async fn build_cert(
+        &self,
+        ca: &CaCertX509Full,
+        group_id: &Uuid,
+        csr: &X509Csr,
+    ) -> Result<(CertX509Entity, X509Signed), ErrorResponse> {
+        let identifiers = self
+            .identifiers()?
+            .into_iter()
+            .map(|i| i.value.to_lowercase())
+            .collect::<BTreeSet<_>>();
+        Self::validate_csr(&identifiers, csr)?;
+
+        let mut params = CertificateParams::default();
+        // the CN is optional and will only be set if the CSR asks for it
+        if let Some(cn) = &csr.common_name {
+            params
+                .distinguished_name
+                .push(DnType::CommonName, cn.to_lowercase());
+        }
+        params.subject_alt_names = identifiers.into_iter().map(SanType::DnsName).collect();
+        params.alg = csr.sig_alg;
+        params.key_pair = Some(csr.key_pair()?);
+        // ACME clients are picky - an explicit `cA: false` is a DER encoded default value
+        params.is_ca = IsCa::NoCa;
+        params.key_usages = vec![
+            KeyUsagePurpose::DigitalSignature,
+            KeyUsagePurpose::KeyEncipherment,
+        ];
+        params.extended_key_usages = vec![
+            ExtendedKeyUsagePurpose::ServerAuth,
+            ExtendedKeyUsagePurpose::ClientAuth,
+        ];
+        params.use_authority_key_identifier_extension = true;
+        params.key_identifier_method = KeyIdMethod::Sha256;
+
+        let entity = CertX509Entity {
+            group_id: Some(*group_id),
+            ..CertX509Entity::from(self)
+        };
+        let (_, cert_entity, signed) = entity.sign(ca, params, &[]).await?;
+        info!(
+            "New certificate with serial {} signed for ACME order {}",
+            cert_entity.serial, self.id
+        );
+
+        Ok((cert_entity, signed))
+    }
+
+    fn validate_csr(identifiers: &BTreeSet<String>, csr: &X509Csr) -> Result<(), ErrorResponse> {
+        if !csr.alt_names_ip.is_empty() {
+            return Err(ErrorResponse::new(
+                ErrorResponseType::BadRequest,
+                "IP addresses are not supported as ACME identifiers",
+            ));
+        }
+        if !csr.alt_names_uri.is_empty()
+            || !csr.alt_names_email.is_empty()
+            || !csr.alt_names_upn.is_empty()
+        {
+            return Err(ErrorResponse::new(
+                ErrorResponseType::BadRequest,
+                "Only DNS names are supported as ACME identifiers",
+            ));
+        }
+
+        let mut requested = csr
+            .alt_names_dns
+            .iter()
+            .map(|name| name.to_lowercase())
+            .collect::<BTreeSet<_>>();
+        if let Some(cn) = &csr.common_name {
+            requested.insert(cn.to_lowercase());
+        }
+
+        if &requested != identifiers {
+            return Err(ErrorResponse::new(
+                ErrorResponseType::BadRequest,
+                "The CSR names do not match the order identifiers",
+            ));
+        }
+
+        Ok(())
+    }
+}
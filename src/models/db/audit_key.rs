@@ -0,0 +1,40 @@
+use crate::config::Db;
+use crate::models::api::error_response::ErrorResponse;
+use sqlx::{query, query_as, Postgres, Transaction};
+
+/// A previous audit log MAC key, encrypted with the current master key
+#[derive(Debug, Clone)]
+pub struct AuditKeyEntity {
+    pub id: String,
+    pub value: Vec<u8>,
+}
+
+impl AuditKeyEntity {
+    pub async fn find_all() -> Result<Vec<Self>, ErrorResponse> {
+        let res = query_as!(Self, "SELECT * FROM audit_keys")
+            .fetch_all(Db::conn())
+            .await?;
+        Ok(res)
+    }
+
+    pub async fn find_all_for_update(
+        txn: &mut Transaction<'_, Postgres>,
+    ) -> Result<Vec<Self>, ErrorResponse> {
+        let res = query_as!(Self, "SELECT * FROM audit_keys FOR UPDATE")
+            .fetch_all(&mut **txn)
+            .await?;
+        Ok(res)
+    }
+
+    pub async fn upsert(&self, txn: &mut Transaction<'_, Postgres>) -> Result<(), ErrorResponse> {
+        query!(
+            r#"INSERT INTO audit_keys (id, value) VALUES ($1, $2)
+            ON CONFLICT (id) DO UPDATE SET value = $2"#,
+            self.id,
+            self.value,
+        )
+        .execute(&mut **txn)
+        .await?;
+        Ok(())
+    }
+}
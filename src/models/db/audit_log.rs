@@ -0,0 +1,118 @@
+use crate::config::Db;
+use crate::models::api::error_response::ErrorResponse;
+use crate::models::api::request::AuditQuery;
+use sqlx::{query, query_as};
+use time::OffsetDateTime;
+
+// random, but static key for the advisory lock, which serializes appending across instances
+const AUDIT_LOG_LOCK: i64 = 0x6e69_6f63_610a;
+
+/// A single, hash-chained entry in the audit log
+#[derive(Debug, Clone)]
+pub struct AuditLogEntity {
+    /// Gapless sequence, starting at 1
+    pub id: i64,
+    pub timestamp: OffsetDateTime,
+    /// The principal, client or ACME account which did the action
+    pub actor: String,
+    pub action: String,
+    /// The id of the object the action has been done on
+    pub target: Option<String>,
+    pub details: Option<String>,
+    pub success: bool,
+    /// The `hash` of the previous entry, all zeros for the very first one
+    pub prev_hash: Vec<u8>,
+    /// SHA256 over the `prev_hash` and all values of this entry
+    pub hash: Vec<u8>,
+    /// HMAC-SHA256 of the `hash` with the audit key derived from the master key
+    pub mac: Vec<u8>,
+    /// Identifies the audit key the `mac` has been built with
+    pub key_id: String,
+}
+
+impl AuditLogEntity {
+    /// Appends a new entry to the chain. The `build` fn gets the next id and the previous hash
+    /// and must return the finished entry.
+    pub async fn append<F>(build: F) -> Result<Self, ErrorResponse>
+    where
+        F: FnOnce(i64, Vec<u8>) -> Result<Self, ErrorResponse>,
+    {
+        let mut txn = Db::txn().await?;
+
+        query!("SELECT pg_advisory_xact_lock($1)", AUDIT_LOG_LOCK)
+            .execute(&mut *txn)
+            .await?;
+
+        let last = query!("SELECT id, hash FROM audit_log ORDER BY id DESC LIMIT 1")
+            .fetch_optional(&mut *txn)
+            .await?;
+        let (id, prev_hash) = match last {
+            Some(row) => (row.id + 1, row.hash),
+            None => (1, vec![0u8; 32]),
+        };
+
+        let entry = build(id, prev_hash)?;
+        query!(
+            r#"INSERT INTO audit_log
+            (id, timestamp, actor, action, target, details, success, prev_hash, hash, mac, key_id)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)"#,
+            entry.id,
+            entry.timestamp,
+            entry.actor,
+            entry.action,
+            entry.target,
+            entry.details,
+            entry.success,
+            entry.prev_hash,
+            entry.hash,
+            entry.mac,
+            entry.key_id,
+        )
+        .execute(&mut *txn)
+        .await?;
+
+        txn.commit().await?;
+        Ok(entry)
+    }
+
+    /// Returns up to `limit` entries with an id greater than `after_id` in ascending order
+    pub async fn find_batch(after_id: i64, limit: i64) -> Result<Vec<Self>, ErrorResponse> {
+        let res = query_as!(
+            Self,
+            "SELECT * FROM audit_log WHERE id > $1 ORDER BY id LIMIT $2",
+            after_id,
+            limit,
+        )
+        .fetch_all(Db::conn())
+        .await?;
+        Ok(res)
+    }
+
+    /// Returns a page of entries matching the query, newest first
+    pub async fn find_filtered(filter: &AuditQuery) -> Result<Vec<Self>, ErrorResponse> {
+        let res = query_as!(
+            Self,
+            r#"SELECT * FROM audit_log
+            WHERE ($1::int8 IS NULL OR id < $1)
+            AND ($2::varchar IS NULL OR actor = $2)
+            AND ($3::varchar IS NULL OR action = $3)
+            AND ($4::varchar IS NULL OR target = $4)
+            AND ($5::bool IS NULL OR success = $5)
+            AND ($6::timestamptz IS NULL OR timestamp >= $6)
+            AND ($7::timestamptz IS NULL OR timestamp <= $7)
+            ORDER BY id DESC
+            LIMIT $8"#,
+            filter.cursor,
+            filter.actor,
+            filter.action.as_ref().map(|a| a.as_str()),
+            filter.target,
+            filter.success,
+            filter.from()?,
+            filter.until()?,
+            filter.limit(),
+        )
+        .fetch_all(Db::conn())
+        .await?;
+        Ok(res)
+    }
+}
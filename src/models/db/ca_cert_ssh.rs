@@ -1,4 +1,6 @@
 use crate::certificates::encryption::{decrypt, encrypt, kdf_danger_static};
+use crate::certificates::key_storage;
+use crate::certificates::key_storage::{CaKeyStorage, CaSshKey};
 use crate::certificates::SshKeyAlg;
 use crate::config::{Db, EncKeys};
 use crate::models::api::error_response::ErrorResponse;
@@ -87,6 +89,22 @@ impl CaCertSshEntity {
         Ok(slf)
     }
 
+    /// Generates a new key pair inside the PKCS#11 token, of which only the reference is saved
+    pub async fn generate_new_pkcs11(
+        name: String,
+        alg: SshKeyAlg,
+        enc_key: &EncKeyEntity,
+    ) -> Result<Self, ErrorResponse> {
+        let (storage, id_pub) = key_storage::ssh_generate_pkcs11(&name, &alg).await?;
+        let kp = SshKeyPairOpenssh {
+            id: storage.value(),
+            id_pub,
+            alg,
+            typ: None,
+        };
+        Self::insert(name, kp, enc_key).await
+    }
+
     pub async fn find_all() -> Result<Vec<Self>, ErrorResponse> {
         let res = query_as!(Self, "SELECT * FROM ca_certs_ssh")
             .fetch_all(Db::conn())
@@ -94,12 +112,12 @@ impl CaCertSshEntity {
         Ok(res)
     }
 
-    // pub async fn find_by_id(id: &Uuid) -> Result<Self, ErrorResponse> {
-    //     query_as!(Self, "select * from ca_certs_ssh where id = $1", id)
-    //         .fetch_one(Db::conn())
-    //         .await
-    //         .map_err(ErrorResponse::from)
-    // }
+    pub async fn find_by_id(id: &Uuid) -> Result<Self, ErrorResponse> {
+        query_as!(Self, "select * from ca_certs_ssh where id = $1", id)
+            .fetch_one(Db::conn())
+            .await
+            .map_err(ErrorResponse::from)
+    }
 
     pub async fn find_by_group(group_id: &Uuid) -> Result<Self, ErrorResponse> {
         let res = query_as!(
@@ -119,12 +137,18 @@ impl CaCertSshEntity {
         Ok(())
     }
 
-    pub async fn get_private_key(&self, enc_keys: &EncKeys) -> Result<PrivateKey, ErrorResponse> {
+    /// Returns the key to sign certificates with, which is either decrypted into memory or a
+    /// handle to the key inside the PKCS#11 token.
+    pub async fn get_signing_key(&self, enc_keys: &EncKeys) -> Result<CaSshKey, ErrorResponse> {
+        let storage = self.get_key_storage(enc_keys).await?;
+        storage.ssh_signing_key(&self.pub_key)
+    }
+
+    pub async fn get_key_storage(&self, enc_keys: &EncKeys) -> Result<CaKeyStorage, ErrorResponse> {
         if self.enc_key_id != enc_keys.enc_key.id {
             let enc_key = EncKeyEntity::find(&self.enc_key_id, &enc_keys.master_key).await?;
             let dec = decrypt(&self.data, &enc_key.value)?;
-            let key_openssh = String::from_utf8_lossy(&dec);
-            let private_key = PrivateKey::from_openssh(key_openssh.as_bytes())?;
+            let storage = CaKeyStorage::parse(&String::from_utf8_lossy(&dec))?;
 
             // re-encrypt with the currently active key for future use
             let enc = encrypt(&dec, &enc_keys.enc_key.value)?;
@@ -137,15 +161,31 @@ impl CaCertSshEntity {
             .execute(Db::conn())
             .await?;
 
-            Ok(private_key)
+            Ok(storage)
         } else {
             let dec = decrypt(&self.data, &enc_keys.enc_key.value)?;
-            let key_openssh = String::from_utf8_lossy(&dec);
-            let private_key = PrivateKey::from_openssh(key_openssh.as_bytes())?;
-
-            Ok(private_key)
+            CaKeyStorage::parse(&String::from_utf8_lossy(&dec))
         }
     }
+
+    /// Replaces the stored private key, encrypted with the currently active enc key
+    pub async fn update_key(
+        &mut self,
+        key: &CaKeyStorage,
+        enc_key: &EncKeyEntity,
+    ) -> Result<(), ErrorResponse> {
+        self.data = encrypt(key.value().as_bytes(), &enc_key.value)?;
+        self.enc_key_id = enc_key.id;
+        query!(
+            "UPDATE ca_certs_ssh SET data = $1, enc_key_id = $2 WHERE id = $3",
+            self.data,
+            self.enc_key_id,
+            self.id,
+        )
+        .execute(Db::conn())
+        .await?;
+        Ok(())
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, ToSchema)]
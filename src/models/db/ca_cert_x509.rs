@@ -1,5 +1,5 @@
 use crate::certificates::encryption::{decrypt_by_kid, encrypt};
-use crate::certificates::x509::cert_from_key_pem;
+use crate::certificates::key_storage::CaKeyStorage;
 use crate::config::{Db, EncKeys};
 use crate::models::api::error_response::{ErrorResponse, ErrorResponseType};
 use crate::util::{fingerprint, pem_to_der};
@@ -94,6 +94,21 @@ impl CaCertX509Entity {
         Ok(slf)
     }
 
+    pub async fn delete(
+        id: &Uuid,
+        typ: CaCertX509Type,
+        txn: &mut Transaction<'_, Postgres>,
+    ) -> Result<(), ErrorResponse> {
+        query!(
+            "DELETE FROM ca_certs_x509 WHERE id = $1 AND typ = $2",
+            id,
+            typ.as_str(),
+        )
+        .execute(&mut **txn)
+        .await?;
+        Ok(())
+    }
+
     pub async fn delete_by_id(id: &Uuid) -> Result<(), ErrorResponse> {
         query!("DELETE FROM ca_certs_x509 WHERE id = $1", id)
             .execute(Db::conn())
@@ -110,6 +125,8 @@ pub enum CaCertX509Type {
     Root,
     Certificate,
     Key,
+    /// The CSR of an intermediate CA, which still waits for the signed certificate
+    Csr,
 }
 
 impl Default for CaCertX509Type {
@@ -130,6 +147,7 @@ impl From<&str> for CaCertX509Type {
             "root" => Self::Root,
             "certificate" => Self::Certificate,
             "key" => Self::Key,
+            "csr" => Self::Csr,
             "unknown" => Self::Unknown,
             _ => unreachable!(),
         }
@@ -142,6 +160,7 @@ impl CaCertX509Type {
             CaCertX509Type::Root => "root",
             CaCertX509Type::Certificate => "certificate",
             CaCertX509Type::Key => "key",
+            CaCertX509Type::Csr => "csr",
             CaCertX509Type::Unknown => "unknown",
         }
     }
@@ -271,34 +290,93 @@ impl CaCertX509Nioca {
         exp: OffsetDateTime,
         txn: &mut Transaction<'_, Postgres>,
     ) -> Result<(), ErrorResponse> {
-        let enc_key = enc_keys.enc_key.value.as_slice();
-        let enc_key_id = enc_keys.enc_key.id;
+        Self::add_cert(enc_keys, id, name.clone(), cert_pem, fingerprint, exp, txn).await?;
+        Self::add_key(enc_keys, id, name, key_plain, txn).await
+    }
 
-        let fingerprint = encrypt(fingerprint.as_bytes(), enc_key)?;
+    /// Saves the key of an intermediate CA generated inside Nioca together with its CSR.
+    ///
+    /// The CA can be used as soon as the certificate signed by the offline root has been added
+    /// with `add_cert()`.
+    pub async fn add_pending(
+        enc_keys: &EncKeys,
+        id: Uuid,
+        name: String,
+        csr_pem: String,
+        key_plain: &str,
+        txn: &mut Transaction<'_, Postgres>,
+    ) -> Result<(), ErrorResponse> {
+        let entity_csr = CaCertX509Entity {
+            id,
+            typ: CaCertX509Type::Csr,
+            name: name.clone(),
+            expires: None,
+            data: csr_pem,
+            fingerprint: None,
+            enc_key_id: enc_keys.enc_key.id,
+        };
+        entity_csr.insert(txn).await?;
+        Self::add_key(enc_keys, id, name, key_plain, txn).await
+    }
+
+    pub async fn add_cert(
+        enc_keys: &EncKeys,
+        id: Uuid,
+        name: String,
+        cert_pem: String,
+        fingerprint: &str,
+        exp: OffsetDateTime,
+        txn: &mut Transaction<'_, Postgres>,
+    ) -> Result<(), ErrorResponse> {
+        let fingerprint = encrypt(fingerprint.as_bytes(), enc_keys.enc_key.value.as_slice())?;
         let entity_cert = CaCertX509Entity {
             id,
             typ: CaCertX509Type::Certificate,
-            name: name.clone(),
+            name,
             expires: Some(exp),
             data: cert_pem,
             fingerprint: Some(fingerprint),
-            enc_key_id,
+            enc_key_id: enc_keys.enc_key.id,
         };
-        entity_cert.insert(txn).await?;
+        entity_cert.insert(txn).await
+    }
 
-        let key_enc = encrypt(key_plain.as_bytes(), enc_key)?;
-        let key_enc_hex = hex::encode(key_enc);
+    async fn add_key(
+        enc_keys: &EncKeys,
+        id: Uuid,
+        name: String,
+        key_plain: &str,
+        txn: &mut Transaction<'_, Postgres>,
+    ) -> Result<(), ErrorResponse> {
+        let key_enc = encrypt(key_plain.as_bytes(), enc_keys.enc_key.value.as_slice())?;
         let entity_key = CaCertX509Entity {
             id,
             typ: CaCertX509Type::Key,
             name,
             expires: None,
-            data: key_enc_hex,
+            data: hex::encode(key_enc),
             fingerprint: None,
-            enc_key_id,
+            enc_key_id: enc_keys.enc_key.id,
         };
-        entity_key.insert(txn).await?;
+        entity_key.insert(txn).await
+    }
 
+    /// Replaces the stored private key, encrypted with the currently active enc key
+    pub async fn update_key(
+        id: &Uuid,
+        key: &CaKeyStorage,
+        enc_keys: &EncKeys,
+    ) -> Result<(), ErrorResponse> {
+        let key_enc = encrypt(key.value().as_bytes(), enc_keys.enc_key.value.as_slice())?;
+        query!(
+            "UPDATE ca_certs_x509 SET data = $1, enc_key_id = $2 WHERE id = $3 AND typ = $4",
+            hex::encode(key_enc),
+            enc_keys.enc_key.id,
+            id,
+            CaCertX509Type::Key.as_str(),
+        )
+        .execute(Db::conn())
+        .await?;
         Ok(())
     }
 
@@ -323,7 +401,7 @@ impl CaCertX509Nioca {
         // private key
         let key_decoded = hex::decode(key_entity.data).expect("Decoding Cert Key from HEX");
         let (key_bytes, key_bytes_new) =
-            decrypt_by_kid(&key_decoded, &cert_entity.enc_key_id, enc_keys).await?;
+            decrypt_by_kid(&key_decoded, &key_entity.enc_key_id, enc_keys).await?;
         let key = match String::from_utf8(key_bytes) {
             Ok(k) => k,
             Err(err) => {
@@ -418,6 +496,8 @@ impl CaCertX509Full {
                 CaCertX509Type::Root => root = Some(entity),
                 CaCertX509Type::Certificate => it = Some(entity),
                 CaCertX509Type::Key => key = Some(entity),
+                // only exists as long as the CA has no certificate
+                CaCertX509Type::Csr => {}
             }
         }
         let root = root.expect("root x509 missing in CaCertX509Full::build_by_id()");
@@ -438,7 +518,6 @@ impl CaCertX509Full {
     }
 
     pub fn signing_cert(&self) -> Result<Certificate, ErrorResponse> {
-        let res = cert_from_key_pem(&self.intermediate.key, &self.intermediate.cert_pem)?;
-        Ok(res)
+        CaKeyStorage::parse(&self.intermediate.key)?.x509_signing_cert(&self.intermediate.cert_pem)
     }
 }
@@ -1,7 +1,10 @@
 use crate::config::Db;
 use crate::models::api::error_response::ErrorResponse;
+use crate::models::api::request::CertsQuery;
 use crate::models::db::client_ssh::ClientSshEntity;
+use crate::models::db::user_group_access::UsersGroupAccess;
 use sqlx::{query, query_as};
+use ssh_key::{Certificate, HashAlg};
 use std::ops::Add;
 use time::OffsetDateTime;
 use uuid::Uuid;
@@ -15,20 +18,42 @@ pub struct CertSshEntity {
     pub client_id: Option<Uuid>,
     pub user_id: Option<Uuid>,
     pub data: Vec<u8>,
+    /// The SSH CA this certificate has been signed with
+    pub ca_id: Option<Uuid>,
+    pub group_id: Option<Uuid>,
+    pub key_id: Option<String>,
+    pub principals: Option<Vec<String>>,
+    /// The `SHA256:` fingerprint of the certified public key
+    pub fingerprint: Option<String>,
+}
+
+/// An issued certificate close to its expiry
+#[derive(Debug)]
+pub struct CertSshExpiring {
+    pub serial: i32,
+    pub group_id: Option<Uuid>,
+    pub key_id: Option<String>,
+    pub principals: Option<Vec<String>>,
+    pub created: OffsetDateTime,
+    pub expires: OffsetDateTime,
+    /// The email of the user the certificate has been issued for
+    pub email: Option<String>,
 }
 
 // CRUD
 impl CertSshEntity {
     pub async fn insert(&self) -> Result<Self, ErrorResponse> {
         query!(
-            "insert into certs_ssh (id, created, expires, client_id, user_id, data) \
-            values ($1, $2, $3, $4, $5, $6)",
+            "insert into certs_ssh (id, created, expires, client_id, user_id, data, ca_id, group_id) \
+            values ($1, $2, $3, $4, $5, $6, $7, $8)",
             self.id,
             self.created,
             self.expires,
             self.client_id,
             self.user_id,
-            self.data
+            self.data,
+            self.ca_id,
+            self.group_id,
         )
         .execute(Db::conn())
         .await?;
@@ -39,12 +64,12 @@ impl CertSshEntity {
         Ok(res)
     }
 
-    // pub async fn find_by_serial(db: DbPool, serial: i32) -> Result<Self, ErrorResponse> {
-    //     query_as!(Self, "select * from certs where serial = $1", serial)
-    //         .fetch_one(&db)
-    //         .await
-    //         .map_err(ErrorResponse::from)
-    // }
+    pub async fn find_by_serial(serial: i32) -> Result<Self, ErrorResponse> {
+        query_as!(Self, "select * from certs_ssh where serial = $1", serial)
+            .fetch_one(Db::conn())
+            .await
+            .map_err(ErrorResponse::from)
+    }
 
     pub async fn find_by_id(uuid: &Uuid) -> Result<Self, ErrorResponse> {
         query_as!(Self, "select * from certs_ssh where id = $1", uuid)
@@ -60,11 +85,20 @@ impl CertSshEntity {
     //         .map_err(ErrorResponse::from)
     // }
 
-    /// Creates a certificate entity with no `data` for getting the serial from the DB.
-    pub async fn update_data(&self) -> Result<(), ErrorResponse> {
+    /// Saves the signed `data` together with the searchable metadata decoded from it.
+    pub async fn update_data(&mut self) -> Result<(), ErrorResponse> {
+        let cert = self.certificate()?;
+        self.key_id = Some(cert.key_id().to_string());
+        self.principals = Some(cert.valid_principals().to_vec());
+        self.fingerprint = Some(cert.public_key().fingerprint(HashAlg::Sha256).to_string());
+
         query!(
-            "update certs_ssh set data = $1 where serial = $2",
+            r#"update certs_ssh set data = $1, key_id = $2, principals = $3, fingerprint = $4
+            where serial = $5"#,
             self.data,
+            self.key_id,
+            self.principals.as_deref(),
+            self.fingerprint,
             self.serial
         )
         .execute(Db::conn())
@@ -72,6 +106,123 @@ impl CertSshEntity {
 
         Ok(())
     }
+
+    /// Certificates issued before the inventory existed have no metadata yet
+    pub async fn find_without_metadata(
+        after_serial: i32,
+        limit: i64,
+    ) -> Result<Vec<Self>, ErrorResponse> {
+        let res = query_as!(
+            Self,
+            r#"select * from certs_ssh
+            where serial > $1 and fingerprint is null and length(data) > 0
+            order by serial
+            limit $2"#,
+            after_serial,
+            limit
+        )
+        .fetch_all(Db::conn())
+        .await?;
+        Ok(res)
+    }
+
+    /// Returns all active certificates expiring until the given time, which have neither been
+    /// revoked nor replaced by a newer one for the same client or user
+    pub async fn find_expiring(
+        until: OffsetDateTime,
+    ) -> Result<Vec<CertSshExpiring>, ErrorResponse> {
+        let res = query_as!(
+            CertSshExpiring,
+            r#"SELECT c.serial, c.group_id, c.key_id, c.principals, c.created, c.expires,
+                u.email AS "email?"
+            FROM certs_ssh c
+            LEFT JOIN users u ON u.id = c.user_id
+            WHERE c.expires > $1 AND c.expires <= $2
+            AND c.group_id IS NOT NULL
+            AND NOT EXISTS (SELECT 1 FROM certs_ssh_revoked r WHERE r.serial = c.serial)
+            AND NOT EXISTS (SELECT 1 FROM ssh_revoked_key_ids k
+                WHERE k.ca_id = c.ca_id AND k.key_id = c.key_id)
+            AND NOT EXISTS (SELECT 1 FROM ssh_revoked_keys p WHERE p.fingerprint = c.fingerprint)
+            AND NOT EXISTS (SELECT 1 FROM certs_ssh n
+                WHERE n.serial > c.serial
+                AND n.group_id = c.group_id
+                AND (n.client_id = c.client_id OR n.user_id = c.user_id))
+            ORDER BY c.expires"#,
+            OffsetDateTime::now_utc(),
+            until,
+        )
+        .fetch_all(Db::conn())
+        .await?;
+        Ok(res)
+    }
+
+    /// Returns a page of issued certificates matching the query, newest first
+    pub async fn find_filtered(filter: &CertsQuery) -> Result<Vec<Self>, ErrorResponse> {
+        let fingerprint = filter.fingerprint.as_deref().map(|f| {
+            let f = f.trim();
+            if f.starts_with("SHA256:") {
+                f.to_string()
+            } else {
+                format!("SHA256:{}", f)
+            }
+        });
+        let res = query_as!(
+            Self,
+            r#"SELECT c.* FROM certs_ssh c
+            WHERE length(c.data) > 0
+            AND ($1::int4 IS NULL OR c.serial < $1)
+            AND ($2::uuid IS NULL OR c.client_id = $2)
+            AND ($3::uuid IS NULL OR c.user_id = $3)
+            AND ($4::uuid IS NULL OR c.group_id = $4)
+            AND ($5::uuid IS NULL OR c.ca_id = $5)
+            AND ($6::int4 IS NULL OR c.serial = $6)
+            AND ($7::varchar IS NULL OR c.fingerprint = $7)
+            AND ($8::varchar IS NULL OR c.key_id ILIKE $8
+                OR EXISTS (SELECT 1 FROM unnest(c.principals) p WHERE p ILIKE $8))
+            AND ($9::varchar IS NULL OR $9 = ANY(c.principals))
+            AND ($10::timestamptz IS NULL OR c.created >= $10)
+            AND ($11::timestamptz IS NULL OR c.created <= $11)
+            AND ($12::timestamptz IS NULL OR c.expires >= $12)
+            AND ($13::timestamptz IS NULL OR c.expires <= $13)
+            AND ($14::varchar IS NULL OR $14 = CASE
+                WHEN EXISTS (SELECT 1 FROM certs_ssh_revoked r WHERE r.serial = c.serial)
+                    OR EXISTS (SELECT 1 FROM ssh_revoked_key_ids k
+                        WHERE k.ca_id = c.ca_id AND k.key_id = c.key_id)
+                    OR EXISTS (SELECT 1 FROM ssh_revoked_keys p
+                        WHERE p.fingerprint = c.fingerprint)
+                    THEN 'revoked'
+                WHEN c.expires <= $15 THEN 'expired'
+                ELSE 'active' END)
+            ORDER BY c.serial DESC
+            LIMIT $16"#,
+            filter.cursor,
+            filter.client_id,
+            filter.user_id,
+            filter.group_id,
+            filter.ca_id,
+            filter.serial,
+            fingerprint,
+            filter.name_pattern(),
+            filter.principal,
+            filter.created_from()?,
+            filter.created_until()?,
+            filter.expires_from()?,
+            filter.expires_until()?,
+            filter.status.as_ref().map(|s| s.as_str()),
+            OffsetDateTime::now_utc(),
+            filter.limit(),
+        )
+        .fetch_all(Db::conn())
+        .await?;
+        Ok(res)
+    }
+
+    /// Decodes the saved OpenSSH certificate
+    pub fn certificate(&self) -> Result<Certificate, ErrorResponse> {
+        let cert_openssh = String::from_utf8_lossy(&self.data);
+        let cert = Certificate::from_openssh(cert_openssh.trim())?;
+        Ok(cert)
+    }
 }
 
 impl From<&ClientSshEntity> for CertSshEntity {
@@ -87,6 +238,33 @@ impl From<&ClientSshEntity> for CertSshEntity {
             client_id: Some(value.id),
             user_id: None,
             data: Vec::default(),
+            ca_id: None,
+            group_id: Some(value.group_id),
+            key_id: None,
+            principals: None,
+            fingerprint: None,
+        }
+    }
+}
+
+impl From<&UsersGroupAccess> for CertSshEntity {
+    fn from(value: &UsersGroupAccess) -> Self {
+        let created = OffsetDateTime::now_utc();
+        let expires = created.add(time::Duration::seconds(value.access_ssh.valid_secs as i64));
+        Self {
+            // Serial will be generated on the DB to have no inconsistencies
+            serial: -1,
+            id: Uuid::new_v4(),
+            created,
+            expires,
+            client_id: None,
+            user_id: Some(value.user_id),
+            data: Vec::default(),
+            ca_id: None,
+            group_id: Some(value.group_id),
+            key_id: None,
+            principals: None,
+            fingerprint: None,
         }
     }
 }
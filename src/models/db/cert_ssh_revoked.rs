@@ -0,0 +1,290 @@
+use crate::certificates::ssh::krl::Krl;
+use crate::config::Db;
+use crate::models::api::error_response::{ErrorResponse, ErrorResponseType};
+use crate::models::db::ca_cert_ssh::CaCertSshEntity;
+use crate::models::db::cert_ssh::CertSshEntity;
+use sqlx::{query, query_as};
+use ssh_key::{HashAlg, PublicKey};
+use std::collections::HashMap;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+/// A revoked SSH certificate, referenced by its serial
+#[derive(Debug, Clone)]
+pub struct CertSshRevokedEntity {
+    pub serial: i32,
+    /// The SSH CA the certificate has been signed with
+    pub ca_id: Uuid,
+    pub revoked: OffsetDateTime,
+    /// The principal who revoked the certificate
+    pub revoked_by: String,
+}
+
+/// A revoked certificate key ID, which applies to all certificates of the CA with this ID
+#[derive(Debug, Clone)]
+pub struct SshRevokedKeyIdEntity {
+    pub ca_id: Uuid,
+    pub key_id: String,
+    pub revoked: OffsetDateTime,
+    pub revoked_by: String,
+}
+
+/// A revoked public key, which applies to the plain key and all certificates for it,
+/// independent of the CA
+#[derive(Debug, Clone)]
+pub struct SshRevokedKeyEntity {
+    /// The SHA256 fingerprint of the public key
+    pub fingerprint: String,
+    /// The public key in OpenSSH format without a comment
+    pub public_key: String,
+    pub revoked: OffsetDateTime,
+    pub revoked_by: String,
+}
+
+// CRUD
+impl CertSshRevokedEntity {
+    pub async fn find(serial: i32) -> Result<Option<Self>, ErrorResponse> {
+        let res = query_as!(
+            Self,
+            "SELECT * FROM certs_ssh_revoked WHERE serial = $1",
+            serial
+        )
+        .fetch_optional(Db::conn())
+        .await?;
+        Ok(res)
+    }
+
+    /// Returns the revocation time for all of the given serials, which have been revoked either
+    /// directly, by their key ID or by their public key
+    pub async fn find_revoked_at(
+        serials: &[i32],
+    ) -> Result<HashMap<i32, OffsetDateTime>, ErrorResponse> {
+        let res = query!(
+            r#"SELECT c.serial, LEAST(r.revoked, k.revoked, p.revoked) AS "revoked!"
+            FROM certs_ssh c
+            LEFT JOIN certs_ssh_revoked r ON r.serial = c.serial
+            LEFT JOIN ssh_revoked_key_ids k ON k.ca_id = c.ca_id AND k.key_id = c.key_id
+            LEFT JOIN ssh_revoked_keys p ON p.fingerprint = c.fingerprint
+            WHERE c.serial = ANY($1)
+            AND (r.serial IS NOT NULL OR k.key_id IS NOT NULL OR p.fingerprint IS NOT NULL)"#,
+            serials
+        )
+        .fetch_all(Db::conn())
+        .await?
+        .into_iter()
+        .map(|row| (row.serial, row.revoked))
+        .collect();
+        Ok(res)
+    }
+
+    pub async fn find_all() -> Result<Vec<Self>, ErrorResponse> {
+        let res = query_as!(
+            Self,
+            "SELECT * FROM certs_ssh_revoked ORDER BY revoked DESC"
+        )
+        .fetch_all(Db::conn())
+        .await?;
+        Ok(res)
+    }
+
+    /// Returns all revoked and not yet expired certificates signed by the given CA
+    pub async fn find_all_by_ca(ca_id: &Uuid) -> Result<Vec<Self>, ErrorResponse> {
+        let res = query_as!(
+            Self,
+            r#"SELECT r.* FROM certs_ssh_revoked r
+            JOIN certs_ssh c ON c.serial = r.serial
+            WHERE r.ca_id = $1 AND c.expires > $2
+            ORDER BY r.serial"#,
+            ca_id,
+            OffsetDateTime::now_utc(),
+        )
+        .fetch_all(Db::conn())
+        .await?;
+        Ok(res)
+    }
+
+    /// Revokes a single certificate
+    pub async fn revoke_serial(serial: i32, revoked_by: String) -> Result<Self, ErrorResponse> {
+        // makes sure we get a proper 404 for unknown serials
+        let cert = CertSshEntity::find_by_serial(serial).await?;
+
+        if Self::find(serial).await?.is_some() {
+            return Err(ErrorResponse::new(
+                ErrorResponseType::BadRequest,
+                format!("The certificate with serial {} is already revoked", serial),
+            ));
+        }
+
+        let ca_id = match cert.ca_id {
+            Some(ca_id) => ca_id,
+            None => Self::find_signing_ca(&cert).await?.id,
+        };
+
+        let res = query_as!(
+            Self,
+            r#"INSERT INTO certs_ssh_revoked (serial, ca_id, revoked, revoked_by)
+            VALUES ($1, $2, $3, $4)
+            RETURNING *"#,
+            serial,
+            ca_id,
+            OffsetDateTime::now_utc(),
+            revoked_by,
+        )
+        .fetch_one(Db::conn())
+        .await?;
+        Ok(res)
+    }
+
+    /// Finds the CA for certificates which have been issued before the `ca_id` has been saved
+    /// by comparing its signature key with all existing SSH CAs
+    async fn find_signing_ca(cert: &CertSshEntity) -> Result<CaCertSshEntity, ErrorResponse> {
+        let signature_key = cert.certificate()?.signature_key().clone();
+
+        for ca in CaCertSshEntity::find_all().await? {
+            if PublicKey::from_openssh(&ca.pub_key)?.key_data() == &signature_key {
+                return Ok(ca);
+            }
+        }
+
+        Err(ErrorResponse::new(
+            ErrorResponseType::NotFound,
+            format!(
+                "The SSH CA for the certificate with serial {} does not exist anymore",
+                cert.serial
+            ),
+        ))
+    }
+}
+
+impl SshRevokedKeyIdEntity {
+    pub async fn find_all() -> Result<Vec<Self>, ErrorResponse> {
+        let res = query_as!(
+            Self,
+            "SELECT * FROM ssh_revoked_key_ids ORDER BY revoked DESC"
+        )
+        .fetch_all(Db::conn())
+        .await?;
+        Ok(res)
+    }
+
+    pub async fn find_all_by_ca(ca_id: &Uuid) -> Result<Vec<Self>, ErrorResponse> {
+        let res = query_as!(
+            Self,
+            "SELECT * FROM ssh_revoked_key_ids WHERE ca_id = $1 ORDER BY key_id",
+            ca_id
+        )
+        .fetch_all(Db::conn())
+        .await?;
+        Ok(res)
+    }
+
+    /// Revokes all certificates of the given CA with this key ID
+    pub async fn revoke(
+        ca_id: &Uuid,
+        key_id: String,
+        revoked_by: String,
+    ) -> Result<Self, ErrorResponse> {
+        // makes sure we get a proper 404 for unknown CAs
+        CaCertSshEntity::find_by_id(ca_id).await?;
+
+        let res = query_as!(
+            Self,
+            r#"INSERT INTO ssh_revoked_key_ids (ca_id, key_id, revoked, revoked_by)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (ca_id, key_id) DO NOTHING
+            RETURNING *"#,
+            ca_id,
+            key_id,
+            OffsetDateTime::now_utc(),
+            revoked_by,
+        )
+        .fetch_optional(Db::conn())
+        .await?;
+
+        res.ok_or_else(|| {
+            ErrorResponse::new(
+                ErrorResponseType::BadRequest,
+                format!("The key ID '{}' is already revoked", key_id),
+            )
+        })
+    }
+}
+
+impl SshRevokedKeyEntity {
+    pub async fn find_all() -> Result<Vec<Self>, ErrorResponse> {
+        let res = query_as!(Self, "SELECT * FROM ssh_revoked_keys ORDER BY revoked DESC")
+            .fetch_all(Db::conn())
+            .await?;
+        Ok(res)
+    }
+
+    /// Revokes the given public key in OpenSSH format for all CAs
+    pub async fn revoke(public_key: &str, revoked_by: String) -> Result<Self, ErrorResponse> {
+        let key = PublicKey::from_openssh(public_key.trim()).map_err(|err| {
+            ErrorResponse::new(
+                ErrorResponseType::BadRequest,
+                format!("Invalid OpenSSH public key: {}", err),
+            )
+        })?;
+        let key = PublicKey::from(key.key_data().clone());
+        let fingerprint = key.fingerprint(HashAlg::Sha256).to_string();
+
+        let res = query_as!(
+            Self,
+            r#"INSERT INTO ssh_revoked_keys (fingerprint, public_key, revoked, revoked_by)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (fingerprint) DO NOTHING
+            RETURNING *"#,
+            fingerprint,
+            key.to_openssh()?,
+            OffsetDateTime::now_utc(),
+            revoked_by,
+        )
+        .fetch_optional(Db::conn())
+        .await?;
+
+        res.ok_or_else(|| {
+            ErrorResponse::new(
+                ErrorResponseType::BadRequest,
+                format!("The public key {} is already revoked", fingerprint),
+            )
+        })
+    }
+}
+
+impl CertSshRevokedEntity {
+    /// Builds the binary OpenSSH KRL for the given CA. Revoked public keys are contained in
+    /// the KRL of every CA.
+    pub async fn build_krl(ca: &CaCertSshEntity) -> Result<Vec<u8>, ErrorResponse> {
+        let certs = Self::find_all_by_ca(&ca.id).await?;
+        let key_ids = SshRevokedKeyIdEntity::find_all_by_ca(&ca.id).await?;
+        let keys = SshRevokedKeyEntity::find_all().await?;
+
+        // the latest revocation as version makes it only change when the content does
+        let version = certs
+            .iter()
+            .map(|r| r.revoked)
+            .chain(key_ids.iter().map(|r| r.revoked))
+            .chain(keys.iter().map(|r| r.revoked))
+            .max()
+            .map(|ts| ts.unix_timestamp() as u64)
+            .unwrap_or_default();
+
+        let public_keys = keys
+            .iter()
+            .map(|k| PublicKey::from_openssh(&k.public_key).map(|key| key.key_data().clone()))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let krl = Krl {
+            version,
+            generated: OffsetDateTime::now_utc().unix_timestamp() as u64,
+            comment: format!("Nioca SSH CA {}", ca.name),
+            serials: certs.iter().map(|r| r.serial as u64).collect(),
+            key_ids: key_ids.into_iter().map(|r| r.key_id).collect(),
+            public_keys,
+        };
+
+        let ca_key = PublicKey::from_openssh(&ca.pub_key)?;
+        krl.to_bytes(ca_key.key_data())
+    }
+}
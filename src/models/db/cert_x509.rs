@@ -1,10 +1,29 @@
+use crate::certificates::spiffe::check_spiffe_ids;
+use crate::certificates::x509::policy::common_name;
+use crate::certificates::x509::san::{add_upns, is_upn, upn_from_other_name};
+use crate::certificates::x509::verification::x509_der_from_bytes;
 use crate::config::Db;
+use crate::constants::ACME_CERT_VALID_HOURS;
 use crate::models::api::error_response::ErrorResponse;
+use crate::models::api::request::CertsQuery;
+use crate::models::db::acme_order::AcmeOrderEntity;
+use crate::models::db::ca_cert_x509::CaCertX509Full;
 use crate::models::db::client_x509::ClientX509Entity;
+use crate::models::db::crl_x509::CrlX509Entity;
+use crate::models::db::groups::GroupEntity;
+use crate::models::db::ocsp_x509::OcspX509Entity;
+use crate::models::db::user_group_access::UsersGroupAccess;
+use crate::notifications::{send_in_background, Notification};
+use crate::util::{fingerprint, pem_to_der};
+use rcgen::{Certificate, CertificateParams, SanType};
 use sqlx::{query, query_as};
-use std::ops::Add;
+use std::net::IpAddr;
+use std::ops::{Add, Sub};
 use time::OffsetDateTime;
+use tracing::warn;
 use uuid::Uuid;
+use x509_parser::certificate::X509Certificate;
+use x509_parser::extensions::GeneralName;
 
 #[derive(Debug, Clone)]
 pub struct CertX509Entity {
@@ -15,20 +34,41 @@ pub struct CertX509Entity {
     pub client_id: Option<Uuid>,
     pub user_id: Option<Uuid>,
     pub data: Vec<u8>,
+    /// The intermediate CA this certificate has been signed with
+    pub ca_id: Option<Uuid>,
+    pub group_id: Option<Uuid>,
+    pub subject: Option<String>,
+    pub sans: Option<Vec<String>>,
+    /// `sha256:` followed by the hex encoded SHA256 of the DER certificate
+    pub fingerprint: Option<String>,
+}
+
+/// An issued certificate close to its expiry
+#[derive(Debug)]
+pub struct CertX509Expiring {
+    pub serial: i32,
+    pub group_id: Option<Uuid>,
+    pub subject: Option<String>,
+    pub created: OffsetDateTime,
+    pub expires: OffsetDateTime,
+    /// The email of the client or user the certificate has been issued for
+    pub email: Option<String>,
 }
 
 // CRUD
 impl CertX509Entity {
     pub async fn insert(&self) -> Result<Self, ErrorResponse> {
         query!(
-            "insert into certs_x509 (id, created, expires, client_id, user_id, data) \
-            values ($1, $2, $3, $4, $5, $6)",
+            "insert into certs_x509 (id, created, expires, client_id, user_id, data, ca_id, group_id) \
+            values ($1, $2, $3, $4, $5, $6, $7, $8)",
             self.id,
             self.created,
             self.expires,
             self.client_id,
             self.user_id,
-            self.data
+            self.data,
+            self.ca_id,
+            self.group_id,
         )
         .execute(Db::conn())
         .await?;
@@ -39,12 +79,12 @@ impl CertX509Entity {
         Ok(res)
     }
 
-    // pub async fn find_by_serial(db: DbPool, serial: i32) -> Result<Self, ErrorResponse> {
-    //     query_as!(Self, "select * from certs where serial = $1", serial)
-    //         .fetch_one(&db)
-    //         .await
-    //         .map_err(ErrorResponse::from)
-    // }
+    pub async fn find_by_serial(serial: i32) -> Result<Self, ErrorResponse> {
+        query_as!(Self, "select * from certs_x509 where serial = $1", serial)
+            .fetch_one(Db::conn())
+            .await
+            .map_err(ErrorResponse::from)
+    }
 
     pub async fn find_by_id(uuid: &Uuid) -> Result<Self, ErrorResponse> {
         query_as!(Self, "select * from certs_x509 where id = $1", uuid)
@@ -53,6 +93,18 @@ impl CertX509Entity {
             .map_err(ErrorResponse::from)
     }
 
+    /// Checks if a certificate with this serial has been signed by the given CA
+    pub async fn exists_for_ca(serial: i32, ca_id: &Uuid) -> Result<bool, ErrorResponse> {
+        let res = query!(
+            "select serial from certs_x509 where serial = $1 and ca_id = $2",
+            serial,
+            ca_id
+        )
+        .fetch_optional(Db::conn())
+        .await?;
+        Ok(res.is_some())
+    }
+
     // pub async fn find_all(db: DbPool) -> Result<Vec<Self>, ErrorResponse> {
     //     query_as!(Self, "select * from certs")
     //         .fetch_all(&db)
@@ -60,11 +112,20 @@ impl CertX509Entity {
     //         .map_err(ErrorResponse::from)
     // }
 
-    /// Creates a certificate entity with no `data` for getting the serial from the DB.
-    pub async fn update_data(&self) -> Result<(), ErrorResponse> {
+    /// Saves the signed `data` together with the searchable metadata decoded from it.
+    pub async fn update_data(&mut self) -> Result<(), ErrorResponse> {
+        let cert = x509_der_from_bytes(&self.data)?;
+        self.subject = Some(cert.subject.to_string());
+        self.sans = Some(x509_sans(&cert));
+        self.fingerprint = Some(fingerprint(&self.data));
+
         query!(
-            "update certs_x509 set data = $1 where serial = $2",
+            r#"update certs_x509 set data = $1, subject = $2, sans = $3, fingerprint = $4
+            where serial = $5"#,
             self.data,
+            self.subject,
+            self.sans.as_deref(),
+            self.fingerprint,
             self.serial
         )
         .execute(Db::conn())
@@ -72,6 +133,209 @@ impl CertX509Entity {
 
         Ok(())
     }
+
+    /// Certificates issued before the inventory existed have no metadata yet
+    pub async fn find_without_metadata(
+        after_serial: i32,
+        limit: i64,
+    ) -> Result<Vec<Self>, ErrorResponse> {
+        let res = query_as!(
+            Self,
+            r#"select * from certs_x509
+            where serial > $1 and fingerprint is null and length(data) > 0
+            order by serial
+            limit $2"#,
+            after_serial,
+            limit
+        )
+        .fetch_all(Db::conn())
+        .await?;
+        Ok(res)
+    }
+
+    /// Returns all active certificates expiring until the given time, which have neither been
+    /// revoked nor replaced by a newer one for the same client, user or ACME subject
+    pub async fn find_expiring(
+        until: OffsetDateTime,
+    ) -> Result<Vec<CertX509Expiring>, ErrorResponse> {
+        let res = query_as!(
+            CertX509Expiring,
+            r#"SELECT c.serial, c.group_id, c.subject, c.created, c.expires,
+                COALESCE(cl.email, u.email) AS "email?"
+            FROM certs_x509 c
+            LEFT JOIN clients_x509 cl ON cl.id = c.client_id
+            LEFT JOIN users u ON u.id = c.user_id
+            WHERE c.expires > $1 AND c.expires <= $2
+            AND c.group_id IS NOT NULL
+            AND NOT EXISTS (SELECT 1 FROM certs_x509_revoked r WHERE r.serial = c.serial)
+            AND NOT EXISTS (SELECT 1 FROM certs_x509 n
+                WHERE n.serial > c.serial
+                AND n.group_id = c.group_id
+                AND (n.client_id = c.client_id
+                    OR n.user_id = c.user_id
+                    OR (c.client_id IS NULL AND c.user_id IS NULL
+                        AND n.client_id IS NULL AND n.user_id IS NULL
+                        AND n.subject = c.subject)))
+            ORDER BY c.expires"#,
+            OffsetDateTime::now_utc(),
+            until,
+        )
+        .fetch_all(Db::conn())
+        .await?;
+        Ok(res)
+    }
+
+    /// Returns a page of issued certificates matching the query, newest first
+    pub async fn find_filtered(filter: &CertsQuery) -> Result<Vec<Self>, ErrorResponse> {
+        let fingerprint = filter.fingerprint.as_deref().map(normalize_fingerprint);
+        let res = query_as!(
+            Self,
+            r#"SELECT c.* FROM certs_x509 c
+            WHERE length(c.data) > 0
+            AND ($1::int4 IS NULL OR c.serial < $1)
+            AND ($2::uuid IS NULL OR c.client_id = $2)
+            AND ($3::uuid IS NULL OR c.user_id = $3)
+            AND ($4::uuid IS NULL OR c.group_id = $4)
+            AND ($5::uuid IS NULL OR c.ca_id = $5)
+            AND ($6::int4 IS NULL OR c.serial = $6)
+            AND ($7::varchar IS NULL OR c.fingerprint = $7)
+            AND ($8::varchar IS NULL OR c.subject ILIKE $8
+                OR EXISTS (SELECT 1 FROM unnest(c.sans) san WHERE san ILIKE $8))
+            AND ($9::timestamptz IS NULL OR c.created >= $9)
+            AND ($10::timestamptz IS NULL OR c.created <= $10)
+            AND ($11::timestamptz IS NULL OR c.expires >= $11)
+            AND ($12::timestamptz IS NULL OR c.expires <= $12)
+            AND ($13::varchar IS NULL OR $13 = CASE
+                WHEN EXISTS (SELECT 1 FROM certs_x509_revoked r WHERE r.serial = c.serial)
+                    THEN 'revoked'
+                WHEN c.expires <= $14 THEN 'expired'
+                ELSE 'active' END)
+            ORDER BY c.serial DESC
+            LIMIT $15"#,
+            filter.cursor,
+            filter.client_id,
+            filter.user_id,
+            filter.group_id,
+            filter.ca_id,
+            filter.serial,
+            fingerprint,
+            filter.name_pattern(),
+            filter.created_from()?,
+            filter.created_until()?,
+            filter.expires_from()?,
+            filter.expires_until()?,
+            filter.status.as_ref().map(|s| s.as_str()),
+            OffsetDateTime::now_utc(),
+            filter.limit(),
+        )
+        .fetch_all(Db::conn())
+        .await?;
+        Ok(res)
+    }
+}
+
+impl CertX509Entity {
+    /// Inserts this entity to get a serial from the DB, signs the certificate built from `params`
+    /// with the given CA and saves the DER afterwards.
+    /// `upns` are added as otherName SANs next to the `subject_alt_names` of the params.
+    pub async fn sign(
+        &self,
+        ca: &CaCertX509Full,
+        mut params: CertificateParams,
+        upns: &[String],
+    ) -> Result<(Certificate, Self, X509Signed), ErrorResponse> {
+        let group = match &self.group_id {
+            Some(group_id) => Some(GroupEntity::find_by_id(group_id).await?),
+            None => None,
+        };
+        let trust_domain = group
+            .as_ref()
+            .and_then(|g| g.spiffe_trust_domain.as_deref());
+        let uris = params.subject_alt_names.iter().filter_map(|san| match san {
+            SanType::URI(uri) => Some(uri),
+            _ => None,
+        });
+        check_spiffe_ids(uris, trust_domain)?;
+        if let Some(policy) = group.map(|g| g.x509_policy()).transpose()?.flatten() {
+            let valid_hours = (self.expires - self.created).whole_hours();
+            policy.check_params(&params, upns, valid_hours)?;
+        }
+        add_upns(&mut params, upns);
+
+        // a certificate must never outlive its issuing CA
+        let mut expires = self.expires;
+        let max_not_after = ca.intermediate.expires.sub(time::Duration::minutes(1));
+        if expires > max_not_after {
+            let name = common_name(&params).unwrap_or_else(|| "Certificate without CN".to_string());
+            warn!(
+                "Capping the validity of '{}' to the expiry of the intermediate CA {}",
+                name, ca.intermediate.id
+            );
+            send_in_background(Notification::ValidityCapped {
+                name,
+                requested: expires.unix_timestamp(),
+                not_after: max_not_after.unix_timestamp(),
+            });
+            expires = max_not_after;
+        }
+
+        // generate a certificate without data to get a serial from the DB
+        let mut cert_entity = Self {
+            ca_id: Some(ca.intermediate.id),
+            expires,
+            ..self.clone()
+        }
+        .insert()
+        .await?;
+        assert!(cert_entity.serial > 0);
+        params.serial_number = Some((cert_entity.serial as u64).into());
+        params.not_before = OffsetDateTime::now_utc().sub(time::Duration::minutes(10));
+        params.not_after = cert_entity.expires;
+        params.crl_distribution_points = CrlX509Entity::find_or_default(&ca.intermediate.id)
+            .await?
+            .distribution_points();
+        if let Some(aia) = OcspX509Entity::find_or_default(&ca.intermediate.id)
+            .await?
+            .aia_extension()
+        {
+            params.custom_extensions.push(aia);
+        }
+
+        let cert = Certificate::from_params(params)?;
+
+        let signed = {
+            // let signing_cert = &state.read().await.nioca_signing_cert;
+            let signing_cert = ca.signing_cert()?;
+            // let cert_der = cert.serialize_der_with_signer(signing_cert)?;
+            let cert_pem = cert
+                .serialize_pem_with_signer(&signing_cert)?
+                // For some reason, this is getting created with CRLF
+                .replace("\r\n", "\n");
+            let cert_der = pem_to_der(&cert_pem).unwrap().to_vec();
+
+            // let ca_chain = &state.read().await.ca_chain_pem;
+            let cert_chain = format!("{}{}", cert_pem, ca.ca_chain_pem);
+            X509Signed {
+                cert_der,
+                cert_pem,
+                cert_chain,
+            }
+        };
+
+        cert_entity.data = signed.cert_der.clone();
+        cert_entity.update_data().await?;
+
+        Ok((cert, cert_entity, signed))
+    }
+}
+
+/// A freshly signed certificate in all the formats we need to build responses
+#[derive(Debug)]
+pub struct X509Signed {
+    pub cert_der: Vec<u8>,
+    pub cert_pem: String,
+    /// The certificate PEM followed by the CA chain
+    pub cert_chain: String,
 }
 
 impl From<&ClientX509Entity> for CertX509Entity {
@@ -87,6 +351,95 @@ impl From<&ClientX509Entity> for CertX509Entity {
             client_id: Some(value.id),
             user_id: None,
             data: Vec::default(),
+            ca_id: None,
+            group_id: Some(value.group_id),
+            subject: None,
+            sans: None,
+            fingerprint: None,
         }
     }
 }
+
+impl From<&UsersGroupAccess> for CertX509Entity {
+    fn from(value: &UsersGroupAccess) -> Self {
+        let created = OffsetDateTime::now_utc();
+        let expires = created.add(time::Duration::hours(value.access_x509.valid_hours as i64));
+        Self {
+            // Serial will be generated on the DB to have no inconsistencies
+            serial: -1,
+            id: Uuid::new_v4(),
+            created,
+            expires,
+            client_id: None,
+            user_id: Some(value.user_id),
+            data: Vec::default(),
+            ca_id: None,
+            group_id: Some(value.group_id),
+            subject: None,
+            sans: None,
+            fingerprint: None,
+        }
+    }
+}
+
+impl From<&AcmeOrderEntity> for CertX509Entity {
+    fn from(_: &AcmeOrderEntity) -> Self {
+        let created = OffsetDateTime::now_utc();
+        let expires = created.add(time::Duration::hours(*ACME_CERT_VALID_HOURS));
+        Self {
+            // Serial will be generated on the DB to have no inconsistencies
+            serial: -1,
+            id: Uuid::new_v4(),
+            created,
+            expires,
+            client_id: None,
+            user_id: None,
+            data: Vec::default(),
+            ca_id: None,
+            group_id: None,
+            subject: None,
+            sans: None,
+            fingerprint: None,
+        }
+    }
+}
+
+/// All SANs of the certificate as plain values
+pub fn x509_sans(cert: &X509Certificate) -> Vec<String> {
+    let alt_names = match cert.subject_alternative_name() {
+        Ok(Some(ext)) => &ext.value.general_names,
+        _ => return Vec::default(),
+    };
+
+    alt_names
+        .iter()
+        .map(|name| match name {
+            GeneralName::DNSName(dns) => dns.to_string(),
+            GeneralName::RFC822Name(email) => email.to_string(),
+            GeneralName::URI(uri) => uri.to_string(),
+            GeneralName::IPAddress(ip) => match ip.len() {
+                4 => IpAddr::from(<[u8; 4]>::try_from(*ip).unwrap()).to_string(),
+                16 => IpAddr::from(<[u8; 16]>::try_from(*ip).unwrap()).to_string(),
+                _ => hex::encode(ip),
+            },
+            GeneralName::OtherName(oid, value) if is_upn(oid) => match upn_from_other_name(value) {
+                Some(upn) => format!("UPN:{}", upn),
+                None => name.to_string(),
+            },
+            name => name.to_string(),
+        })
+        .collect()
+}
+
+/// Accepts the fingerprint in the format Nioca saves it as well as the usual
+/// `AB:CD:...` output from OpenSSL
+fn normalize_fingerprint(value: &str) -> String {
+    let value = value.trim();
+    let hex = value
+        .strip_prefix("sha256:")
+        .or_else(|| value.strip_prefix("SHA256:"))
+        .unwrap_or(value)
+        .replace(':', "")
+        .to_lowercase();
+    format!("sha256:{}", hex)
+}
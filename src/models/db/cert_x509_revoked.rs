@@ -0,0 +1,276 @@
+use crate::certificates::X509RevocationReason;
+use crate::config::Db;
+use crate::models::api::error_response::{ErrorResponse, ErrorResponseType};
+use crate::models::db::cert_x509::CertX509Entity;
+use sqlx::{query, query_as};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+/// A revoked x509 certificate, referenced by its serial
+#[derive(Debug, Clone)]
+pub struct CertX509RevokedEntity {
+    pub serial: i32,
+    /// The RFC 5280 `CRLReason` code
+    pub reason: i16,
+    pub revoked: OffsetDateTime,
+    pub invalidity_date: Option<OffsetDateTime>,
+    /// The principal who revoked the certificate
+    pub revoked_by: String,
+}
+
+// CRUD
+impl CertX509RevokedEntity {
+    pub async fn find(serial: i32) -> Result<Option<Self>, ErrorResponse> {
+        let res = query_as!(
+            Self,
+            "SELECT * FROM certs_x509_revoked WHERE serial = $1",
+            serial
+        )
+        .fetch_optional(Db::conn())
+        .await?;
+        Ok(res)
+    }
+
+    pub async fn find_by_serials(serials: &[i32]) -> Result<Vec<Self>, ErrorResponse> {
+        let res = query_as!(
+            Self,
+            "SELECT * FROM certs_x509_revoked WHERE serial = ANY($1)",
+            serials
+        )
+        .fetch_all(Db::conn())
+        .await?;
+        Ok(res)
+    }
+
+    pub async fn find_all() -> Result<Vec<Self>, ErrorResponse> {
+        let res = query_as!(
+            Self,
+            "SELECT * FROM certs_x509_revoked ORDER BY revoked DESC"
+        )
+        .fetch_all(Db::conn())
+        .await?;
+        Ok(res)
+    }
+
+    /// Returns all revoked and not yet expired certificates signed by the given CA
+    pub async fn find_all_by_ca(ca_id: &Uuid) -> Result<Vec<Self>, ErrorResponse> {
+        let res = query_as!(
+            Self,
+            r#"SELECT r.* FROM certs_x509_revoked r
+            JOIN certs_x509 c ON c.serial = r.serial
+            WHERE c.ca_id = $1 AND c.expires > $2
+            ORDER BY r.serial"#,
+            ca_id,
+            OffsetDateTime::now_utc(),
+        )
+        .fetch_all(Db::conn())
+        .await?;
+        Ok(res)
+    }
+
+    /// Checks if any certificate of the given CA has been revoked at or after `since`
+    pub async fn exists_for_ca_since(
+        ca_id: &Uuid,
+        since: OffsetDateTime,
+    ) -> Result<bool, ErrorResponse> {
+        let res = query!(
+            r#"SELECT EXISTS (
+                SELECT 1 FROM certs_x509_revoked r
+                JOIN certs_x509 c ON c.serial = r.serial
+                WHERE c.ca_id = $1 AND r.revoked >= $2
+            ) AS "exists!""#,
+            ca_id,
+            since,
+        )
+        .fetch_one(Db::conn())
+        .await?;
+        Ok(res.exists)
+    }
+}
+
+impl CertX509RevokedEntity {
+    /// Revokes a single certificate
+    pub async fn revoke_serial(
+        serial: i32,
+        reason: &X509RevocationReason,
+        invalidity_date: Option<OffsetDateTime>,
+        revoked_by: String,
+    ) -> Result<Self, ErrorResponse> {
+        // makes sure we get a proper 404 for unknown serials
+        CertX509Entity::find_by_serial(serial).await?;
+
+        // a concurrent revocation must not end up in a unique violation
+        let res = query_as!(
+            Self,
+            r#"INSERT INTO certs_x509_revoked (serial, reason, revoked, invalidity_date, revoked_by)
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT (serial) DO NOTHING
+            RETURNING *"#,
+            serial,
+            reason.value(),
+            OffsetDateTime::now_utc(),
+            invalidity_date,
+            revoked_by,
+        )
+        .fetch_optional(Db::conn())
+        .await?;
+
+        res.ok_or_else(|| {
+            ErrorResponse::new(
+                ErrorResponseType::BadRequest,
+                format!("The certificate with serial {} is already revoked", serial),
+            )
+        })
+    }
+
+    /// Revokes all not yet expired certificates for the given x509 client.
+    /// Already revoked ones are left untouched.
+    pub async fn revoke_client(
+        client_id: &Uuid,
+        reason: &X509RevocationReason,
+        invalidity_date: Option<OffsetDateTime>,
+        revoked_by: String,
+    ) -> Result<Vec<Self>, ErrorResponse> {
+        let res = query_as!(
+            Self,
+            r#"INSERT INTO certs_x509_revoked (serial, reason, revoked, invalidity_date, revoked_by)
+            SELECT serial, $2::smallint, $3::timestamptz, $4::timestamptz, $5::varchar
+            FROM certs_x509
+            WHERE client_id = $1 AND expires > $3
+            ON CONFLICT (serial) DO NOTHING
+            RETURNING *"#,
+            client_id,
+            reason.value(),
+            OffsetDateTime::now_utc(),
+            invalidity_date,
+            revoked_by,
+        )
+        .fetch_all(Db::conn())
+        .await?;
+        Ok(res)
+    }
+
+    /// Revokes all not yet expired certificates for the given user.
+    /// Already revoked ones are left untouched.
+    pub async fn revoke_user(
+        user_id: &Uuid,
+        reason: &X509RevocationReason,
+        invalidity_date: Option<OffsetDateTime>,
+        revoked_by: String,
+    ) -> Result<Vec<Self>, ErrorResponse> {
+        let res = query_as!(
+            Self,
+            r#"INSERT INTO certs_x509_revoked (serial, reason, revoked, invalidity_date, revoked_by)
+            SELECT serial, $2::smallint, $3::timestamptz, $4::timestamptz, $5::varchar
+            FROM certs_x509
+            WHERE user_id = $1 AND expires > $3
+            ON CONFLICT (serial) DO NOTHING
+            RETURNING *"#,
+            user_id,
+            reason.value(),
+            OffsetDateTime::now_utc(),
+            invalidity_date,
+            revoked_by,
+        )
+        .fetch_all(Db::conn())
+        .await?;
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::db_test;
+    use std::ops::Add;
+
+    #[test]
+    fn test_revocation_reason() {
+        for code in [0, 1, 2, 3, 4, 5, 9] {
+            assert_eq!(X509RevocationReason::from_value(code).value(), code);
+        }
+        // certificateHold (6) and removeFromCRL (8) are not supported
+        assert_eq!(
+            X509RevocationReason::from_value(6),
+            X509RevocationReason::Unspecified
+        );
+        assert_eq!(
+            X509RevocationReason::from_value(8),
+            X509RevocationReason::Unspecified
+        );
+
+        assert!(X509RevocationReason::Unspecified.crl_reason().is_none());
+        assert_eq!(
+            X509RevocationReason::KeyCompromise.crl_reason(),
+            Some(rcgen::RevocationReason::KeyCompromise)
+        );
+    }
+
+    #[test]
+    #[ignore]
+    fn test_revoke_serial() {
+        db_test(async {
+            let now = OffsetDateTime::now_utc();
+            let cert = CertX509Entity {
+                serial: 0,
+                id: Uuid::new_v4(),
+                created: now,
+                expires: now.add(time::Duration::hours(1)),
+                client_id: None,
+                user_id: None,
+                data: vec![],
+                ca_id: None,
+                group_id: None,
+                subject: None,
+                sans: None,
+                fingerprint: None,
+            }
+            .insert()
+            .await
+            .unwrap();
+
+            let revoked = CertX509RevokedEntity::revoke_serial(
+                cert.serial,
+                &X509RevocationReason::KeyCompromise,
+                None,
+                "test".to_string(),
+            )
+            .await
+            .unwrap();
+            assert_eq!(revoked.serial, cert.serial);
+            assert_eq!(revoked.reason, 1);
+
+            // the second one must not overwrite the first revocation
+            let err = CertX509RevokedEntity::revoke_serial(
+                cert.serial,
+                &X509RevocationReason::Superseded,
+                None,
+                "test".to_string(),
+            )
+            .await
+            .unwrap_err();
+            assert_eq!(err.typ, ErrorResponseType::BadRequest);
+            let revoked = CertX509RevokedEntity::find(cert.serial)
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(revoked.reason, 1);
+
+            let err = CertX509RevokedEntity::revoke_serial(
+                i32::MAX,
+                &X509RevocationReason::Unspecified,
+                None,
+                "test".to_string(),
+            )
+            .await
+            .unwrap_err();
+            assert_eq!(err.typ, ErrorResponseType::NotFound);
+
+            sqlx::query("DELETE FROM certs_x509 WHERE serial = $1")
+                .bind(cert.serial)
+                .execute(Db::conn())
+                .await
+                .unwrap();
+        });
+    }
+}
@@ -1,20 +1,22 @@
 use crate::certificates::encryption::{decrypt, encrypt};
+use crate::certificates::ssh::add_permit_extensions;
 use crate::certificates::SshKeyAlg;
 use crate::config::{Db, EncKeys};
 use crate::models::api::error_response::{ErrorResponse, ErrorResponseType};
 use crate::models::api::request::ClientSshRequest;
-use crate::models::api::response::SshCertificateResponse;
+use crate::models::api::response::{SshCertificateResponse, SshCertificateSignedResponse};
 use crate::models::db::ca_cert_ssh::{CaCertSshEntity, SshKeyPairOpenssh};
 use crate::models::db::cert_ssh::CertSshEntity;
 use crate::models::db::enc_key::EncKeyEntity;
 use crate::models::db::groups::GroupEntity;
+use crate::models::db::profile::{ProfileEntity, ProfileType, SshProfile};
 use crate::routes::AppStateExtract;
 use crate::util::secure_random;
 use rand_core::OsRng;
 use serde::{Deserialize, Serialize};
 use sqlx::{query, query_as};
 use ssh_key::certificate::{Builder, CertType};
-use ssh_key::{LineEnding, PrivateKey};
+use ssh_key::{HashAlg, LineEnding, PrivateKey, PublicKey};
 use std::time::{SystemTime, UNIX_EPOCH};
 use time::OffsetDateTime;
 use tracing::{debug, info};
@@ -43,6 +45,9 @@ pub struct ClientSshEntity {
     pub permit_user_rc: Option<bool>,
     pub valid_secs: i32,
     pub latest_cert: Option<i32>,
+    pub profile_id: Option<Uuid>,
+    /// The JSON encoded `SshProfile`, which wins over the linked profile
+    pub profile_overrides: Option<String>,
 }
 
 // CRUD
@@ -55,6 +60,13 @@ impl ClientSshEntity {
         let expires = Self::expires_from_req(client.expires)?;
         let principals = client.principals.join(",");
         let source_addresses = client.source_addresses.map(|a| a.join(","));
+        ProfileEntity::check_link(
+            client.profile_id.as_ref(),
+            ProfileType::Ssh,
+            client.profile_overrides.is_some(),
+        )
+        .await?;
+        let profile_overrides = Self::overrides_value(client.profile_overrides)?;
         let api_key_enc = encrypt(secure_random(48).as_bytes(), enc_key.value.as_bytes())?;
         let enc_key_id = enc_key.id;
 
@@ -64,8 +76,10 @@ impl ClientSshEntity {
             r#"
             INSERT INTO clients_ssh (id, name, expires, enabled, api_key, enc_key_id, key_alg,
             group_id, typ, principals, force_command, source_addresses, permit_x11_forwarding,
-            permit_agent_forwarding, permit_port_forwarding, permit_pty, permit_user_rc, valid_secs)
-            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18)
+            permit_agent_forwarding, permit_port_forwarding, permit_pty, permit_user_rc, valid_secs,
+            profile_id, profile_overrides)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18,
+            $19, $20)
             "#,
             uuid,
             client.name,
@@ -85,6 +99,8 @@ impl ClientSshEntity {
             client.permit_pty,
             client.permit_user_rc,
             client.valid_secs,
+            client.profile_id,
+            profile_overrides,
         )
         .execute(db)
         .await?;
@@ -110,6 +126,13 @@ impl ClientSshEntity {
         let expires = ClientSshEntity::expires_from_req(client.expires)?;
         let principals = client.principals.join(",");
         let source_addresses = client.source_addresses.map(|a| a.join(","));
+        ProfileEntity::check_link(
+            client.profile_id.as_ref(),
+            ProfileType::Ssh,
+            client.profile_overrides.is_some(),
+        )
+        .await?;
+        let profile_overrides = Self::overrides_value(client.profile_overrides)?;
 
         let db = Db::conn();
 
@@ -118,8 +141,9 @@ impl ClientSshEntity {
             UPDATE clients_ssh
             SET name = $1, expires = $2, enabled = $3, key_alg = $4, group_id = $5, typ = $6, principals = $7,
             force_command = $8, source_addresses = $9, permit_x11_forwarding = $10, permit_agent_forwarding = $11,
-            permit_port_forwarding = $12, permit_pty = $13, permit_user_rc = $14, valid_secs = $15
-            WHERE id = $16
+            permit_port_forwarding = $12, permit_pty = $13, permit_user_rc = $14, valid_secs = $15,
+            profile_id = $16, profile_overrides = $17
+            WHERE id = $18
             "#,
             client.name,
             expires,
@@ -136,6 +160,8 @@ impl ClientSshEntity {
             client.permit_pty,
             client.permit_user_rc,
             client.valid_secs,
+            client.profile_id,
+            profile_overrides,
             uuid,
         )
             .execute(db)
@@ -182,7 +208,7 @@ impl ClientSshEntity {
         // If the enc_key is not the currently active one, fetch the old one and re-encrypt with the
         // active key
         let api_key_plain_bytes = if self.enc_key_id != enc_keys.enc_key.id {
-            let enc_key = EncKeyEntity::find(&self.id, &enc_keys.master_key).await?;
+            let enc_key = EncKeyEntity::find(&self.enc_key_id, &enc_keys.master_key).await?;
             let api_key_bytes = decrypt(&self.api_key, &enc_key.value)?;
 
             // re-encrypt with the new key and save it
@@ -234,6 +260,25 @@ impl ClientSshEntity {
 }
 
 impl ClientSshEntity {
+    /// Applies the linked profile and the overrides of this client on top of its own config.
+    /// Must be resolved before each issuance to pick up changes of the profile.
+    pub async fn with_profile(self) -> Result<Self, ErrorResponse> {
+        let Some(profile_id) = self.profile_id else {
+            return Ok(self);
+        };
+        let overrides = match &self.profile_overrides {
+            Some(overrides) => Some(serde_json::from_str(overrides)?),
+            None => None,
+        };
+
+        let mut client = self;
+        ProfileEntity::find_ssh(&profile_id)
+            .await?
+            .merge(overrides)
+            .apply_client(&mut client);
+        Ok(client)
+    }
+
     /// Creates a new SSH certificate for this client and saves the information in the DB
     pub async fn build_cert(
         &self,
@@ -246,19 +291,85 @@ impl ClientSshEntity {
         let key = PrivateKey::random(&mut OsRng, key_alg.as_alg())?;
         let key_openssh = key.to_openssh(LineEnding::LF).unwrap();
 
+        let (ca, _, cert_openssh) = self.sign_public_key(state, group, key.public_key()).await?;
+
+        let kp = SshKeyPairOpenssh {
+            id: key_openssh.to_string(),
+            id_pub: cert_openssh,
+            alg: key_alg,
+            typ: Some(SshCertType::from_str(&self.typ)),
+        };
+
+        info!(
+            "New SSH Certificate generated for client {} - {}",
+            self.id, self.name
+        );
+
+        let resp = SshCertificateResponse {
+            user_ca_pub: ca.pub_key,
+            host_key_pair: kp,
+        };
+
+        Ok(resp)
+    }
+
+    /// Signs an already existing public key, for instance `/etc/ssh/ssh_host_ed25519_key.pub`.
+    /// The private key never leaves the client, which is why only the certificate is returned.
+    pub async fn build_cert_from_pub_key(
+        &self,
+        state: &AppStateExtract,
+        group: &GroupEntity,
+        pub_key: &PublicKey,
+    ) -> Result<SshCertificateSignedResponse, ErrorResponse> {
+        debug!(
+            "Signing SSH public key for client {} with fingerprint {}",
+            self.id,
+            pub_key.fingerprint(HashAlg::Sha256)
+        );
+
+        let key_alg = SshKeyAlg::from_str(&self.key_alg);
+        if !key_alg.matches_key_alg(&pub_key.algorithm()) {
+            return Err(ErrorResponse::new(
+                ErrorResponseType::BadRequest,
+                format!(
+                    "The public key algorithm does not match the configured '{}'",
+                    key_alg.as_str()
+                ),
+            ));
+        }
+
+        let (ca, cert_entity, cert_openssh) = self.sign_public_key(state, group, pub_key).await?;
+
+        info!(
+            "New SSH Certificate signed for client {} - {}",
+            self.id, self.name
+        );
+
+        Ok(SshCertificateSignedResponse {
+            ca_pub: ca.pub_key,
+            cert: cert_openssh,
+            not_after: cert_entity.expires.unix_timestamp(),
+        })
+    }
+
+    /// Builds a certificate for the given public key from the client config, signs it with the
+    /// groups SSH CA and saves it in the DB.
+    async fn sign_public_key(
+        &self,
+        state: &AppStateExtract,
+        group: &GroupEntity,
+        pub_key: &PublicKey,
+    ) -> Result<(CaCertSshEntity, CertSshEntity, String), ErrorResponse> {
         // build the certificate
         let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
         let valid_after = now - 120;
         let valid_before = now + self.valid_secs as u64;
 
-        let mut cert_builder = Builder::new_with_random_nonce(
-            &mut OsRng,
-            key.public_key(),
-            valid_after,
-            valid_before,
-        )?;
+        let mut cert_builder =
+            Builder::new_with_random_nonce(&mut OsRng, pub_key, valid_after, valid_before)?;
 
-        let key_id = format!("nioca-{}", group.name);
+        // unique per client, which makes a revocation by key ID only affect this client
+        let key_id = format!("nioca-{}-{}", group.name, self.id);
         cert_builder.key_id(key_id)?;
         let cert_type = SshCertType::from_str(&self.typ);
         cert_builder.cert_type(cert_type.as_cert_type())?;
@@ -281,77 +392,49 @@ impl ClientSshEntity {
             //     cert_builder.critical_option("source-address", ips).unwrap();
             // }
 
-            if let Some(value) = self.permit_x11_forwarding {
-                if value {
-                    cert_builder.extension("permit-X11-forwarding", "").unwrap();
-                }
-            }
-            if let Some(value) = self.permit_agent_forwarding {
-                if value {
-                    cert_builder
-                        .extension("permit-agent-forwarding", "")
-                        .unwrap();
-                }
-            }
-            if let Some(value) = self.permit_port_forwarding {
-                if value {
-                    cert_builder
-                        .extension("permit-port-forwarding", "")
-                        .unwrap();
-                }
-            }
-            if let Some(value) = self.permit_pty {
-                if value {
-                    cert_builder.extension("permit-pty", "").unwrap();
-                }
-            }
-            if let Some(value) = self.permit_user_rc {
-                if value {
-                    cert_builder.extension("permit-user-rc", "").unwrap();
-                }
-            }
+            add_permit_extensions(
+                &mut cert_builder,
+                self.permit_x11_forwarding,
+                self.permit_agent_forwarding,
+                self.permit_port_forwarding,
+                self.permit_pty,
+                self.permit_user_rc,
+            )?;
         }
 
         let comment = format!("nioca-{}", self.name);
         cert_builder.comment(comment)?;
 
+        let ca = CaCertSshEntity::find_by_group(&self.group_id).await?;
+
         // generate a certificate without data to get a serial from the DB
-        let entity = CertSshEntity::from(self);
+        let entity = CertSshEntity {
+            ca_id: Some(ca.id),
+            ..CertSshEntity::from(self)
+        };
         let mut cert_entity = entity.insert().await?;
         assert!(cert_entity.serial > 0);
         cert_builder.serial(cert_entity.serial as u64)?;
 
-        let ca = CaCertSshEntity::find_by_group(&self.group_id).await?;
         let ca_key = {
             let enc_keys = &state.read().await.enc_keys;
-            ca.get_private_key(enc_keys).await?
+            ca.get_signing_key(enc_keys).await?
         };
         let cert = cert_builder.sign(&ca_key)?;
         let cert_openssh = cert.to_openssh().unwrap();
 
-        let kp = SshKeyPairOpenssh {
-            id: key_openssh.to_string(),
-            id_pub: cert_openssh,
-            alg: key_alg,
-            typ: Some(cert_type),
-        };
-
-        let cert_bytes = kp.id_pub.as_bytes().to_vec();
-        cert_entity.data = cert_bytes;
+        cert_entity.data = cert_openssh.as_bytes().to_vec();
         cert_entity.update_data().await?;
         ClientSshEntity::set_last_cert(&self.id, cert_entity.serial).await?;
 
-        info!(
-            "New SSH Certificate generated for client {} - {}",
-            self.id, self.name
-        );
-
-        let resp = SshCertificateResponse {
-            user_ca_pub: ca.pub_key,
-            host_key_pair: kp,
-        };
+        Ok((ca, cert_entity, cert_openssh))
+    }
 
-        Ok(resp)
+    fn overrides_value(overrides: Option<SshProfile>) -> Result<Option<String>, ErrorResponse> {
+        match overrides {
+            Some(overrides) => Ok(Some(serde_json::to_string(&overrides)?)),
+            None => Ok(None),
+        }
     }
 
     fn expires_from_req(ts: Option<i64>) -> Result<Option<OffsetDateTime>, ErrorResponse> {
@@ -415,7 +498,7 @@ impl ClientSshEntity {
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
 pub enum SshCertType {
     Host,
     User,
@@ -1,26 +1,30 @@
 use crate::certificates::encryption::{decrypt, encrypt};
+use crate::certificates::spiffe::{check_spiffe_ids, jwt_svid_sign, x509_svid, SpiffeId};
+use crate::certificates::x509::csr::X509Csr;
 use crate::certificates::x509::singing::{
     gen_ecdsa_key_pair, gen_ed25519_key_pair, gen_rsa_key_pair,
 };
 use crate::certificates::{CertFormat, X509KeyAlg, X509KeyUsages, X509KeyUsagesExt};
 use crate::config::{Db, EncKeys};
+use crate::constants::SPIFFE_JWT_SVID_TTL;
 use crate::models::api::error_response::{ErrorResponse, ErrorResponseType};
 use crate::models::api::request::ClientX509Request;
-use crate::models::api::response::CertX509Response;
+use crate::models::api::response::{CertX509CsrResponse, CertX509Response, JwtSvidResponse};
 use crate::models::db::ca_cert_x509::CaCertX509Full;
-use crate::models::db::cert_x509::CertX509Entity;
+use crate::models::db::cert_x509::{CertX509Entity, X509Signed};
 use crate::models::db::enc_key::EncKeyEntity;
 use crate::models::db::groups::GroupEntity;
+use crate::models::db::profile::{ProfileEntity, ProfileType, X509Profile};
+use crate::models::db::spiffe_jwt_key::SpiffeJwtKeyEntity;
 use crate::routes::AppStateExtract;
-use crate::util::{b64_encode, csv_to_vec, fingerprint, pem_to_der, secure_random, vec_to_csv};
+use crate::util::{b64_encode, csv_to_vec, fingerprint, secure_random, vec_to_csv};
 use p12::PFX;
 use rcgen::{
-    Certificate, CertificateParams, DistinguishedName, DnType, ExtendedKeyUsagePurpose, IsCa,
-    KeyIdMethod, KeyUsagePurpose, SanType,
+    CertificateParams, DistinguishedName, DnType, ExtendedKeyUsagePurpose, IsCa, KeyIdMethod,
+    KeyUsagePurpose, SanType,
 };
 use sqlx::{query, query_as};
 use std::net::IpAddr;
-use std::ops::Sub;
 use std::str::FromStr;
 use time::OffsetDateTime;
 use tracing::{debug, error, info};
@@ -50,6 +54,13 @@ pub struct ClientX509Entity {
     pub valid_hours: i32,
     pub email: String,
     pub latest_cert: Option<i32>,
+    pub profile_id: Option<Uuid>,
+    /// The JSON encoded `X509Profile`, which wins over the linked profile
+    pub profile_overrides: Option<String>,
+    pub alt_names_uri: Vec<String>,
+    pub alt_names_email: Vec<String>,
+    /// Microsoft User Principal Names, which are added as otherName SANs
+    pub alt_names_upn: Vec<String>,
 }
 
 // CRUD
@@ -65,6 +76,14 @@ impl ClientX509Entity {
         } else {
             GroupEntity::find_default_id().await?
         };
+        Self::check_group_policy(&group_id, &client).await?;
+        ProfileEntity::check_link(
+            client.profile_id.as_ref(),
+            ProfileType::X509,
+            client.profile_overrides.is_some(),
+        )
+        .await?;
+        let profile_overrides = Self::overrides_value(client.profile_overrides)?;
         let dns = vec_to_csv(&client.alt_names_dns);
         let ip = vec_to_csv(&client.alt_names_ip);
         let key_usage: Vec<u8> = client.key_usage.iter().map(|u| u.value()).collect();
@@ -75,8 +94,10 @@ impl ClientX509Entity {
         query!(
             r#"INSERT INTO clients_x509 (id, name, expires, api_key, enabled, group_id, enc_key_id, key_alg,
             common_name, country, locality, organizational_unit, organization, state_or_province,
-            alt_names_dns, alt_names_ip, key_usage, key_usage_ext, valid_hours, email)
-            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19, $20)"#,
+            alt_names_dns, alt_names_ip, key_usage, key_usage_ext, valid_hours, email, profile_id,
+            profile_overrides, alt_names_uri, alt_names_email, alt_names_upn)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19, $20,
+            $21, $22, $23, $24, $25)"#,
             uuid,
             client.name,
             expires,
@@ -96,7 +117,12 @@ impl ClientX509Entity {
             key_usage,
             key_usage_ext,
             client.valid_hours,
-            client.email
+            client.email,
+            client.profile_id,
+            profile_overrides,
+            &client.alt_names_uri,
+            &client.alt_names_email,
+            &client.alt_names_upn,
         )
             .execute(Db::conn())
         .await?;
@@ -125,6 +151,16 @@ impl ClientX509Entity {
 
     pub async fn update(uuid: &Uuid, client: ClientX509Request) -> Result<Self, ErrorResponse> {
         let expires = ClientX509Entity::expires_from_req(client.expires)?;
+        if let Some(group_id) = &client.group_id {
+            Self::check_group_policy(group_id, &client).await?;
+        }
+        ProfileEntity::check_link(
+            client.profile_id.as_ref(),
+            ProfileType::X509,
+            client.profile_overrides.is_some(),
+        )
+        .await?;
+        let profile_overrides = Self::overrides_value(client.profile_overrides)?;
         let dns = vec_to_csv(&client.alt_names_dns);
         let ip = vec_to_csv(&client.alt_names_ip);
         let key_usage: Vec<u8> = client.key_usage.iter().map(|u| u.value()).collect();
@@ -134,8 +170,10 @@ impl ClientX509Entity {
             r#"UPDATE clients_x509
             SET name = $1, expires = $2, enabled = $3, group_id = $4, key_alg = $5, common_name = $6, country = $7,
             locality = $8, organizational_unit = $9, organization = $10, state_or_province = $11, alt_names_dns = $12,
-            alt_names_ip = $13, key_usage = $14, key_usage_ext = $15, valid_hours = $16, email = $17
-            WHERE id = $18"#,
+            alt_names_ip = $13, key_usage = $14, key_usage_ext = $15, valid_hours = $16, email = $17,
+            profile_id = $18, profile_overrides = $19, alt_names_uri = $20, alt_names_email = $21,
+            alt_names_upn = $22
+            WHERE id = $23"#,
             client.name,
             expires,
             client.enabled,
@@ -153,6 +191,11 @@ impl ClientX509Entity {
             key_usage_ext,
             client.valid_hours,
             client.email,
+            client.profile_id,
+            profile_overrides,
+            &client.alt_names_uri,
+            &client.alt_names_email,
+            &client.alt_names_upn,
             uuid,
         )
             .execute(Db::conn())
@@ -180,6 +223,33 @@ impl ClientX509Entity {
             .map_err(ErrorResponse::from)
     }
 
+    /// Finds a client by its name inside a group. The name must be unique inside the group.
+    pub async fn find_by_name(group_id: &Uuid, name: &str) -> Result<Self, ErrorResponse> {
+        let mut res = query_as!(
+            Self,
+            "SELECT * FROM clients_x509 WHERE group_id = $1 AND name = $2",
+            group_id,
+            name
+        )
+        .fetch_all(Db::conn())
+        .await?;
+
+        match res.len() {
+            0 => Err(ErrorResponse::new(
+                ErrorResponseType::NotFound,
+                format!("No x509 client with name '{}' in this group", name),
+            )),
+            1 => Ok(res.remove(0)),
+            _ => Err(ErrorResponse::new(
+                ErrorResponseType::BadRequest,
+                format!(
+                    "The x509 client name '{}' is not unique inside this group, use its ID",
+                    name
+                ),
+            )),
+        }
+    }
+
     pub async fn find_secret(uuid: &Uuid, enc_keys: &EncKeys) -> Result<String, ErrorResponse> {
         let slf = Self::find(uuid).await?;
         slf.decrypt_api_key(enc_keys).await
@@ -199,7 +269,7 @@ impl ClientX509Entity {
         // If the enc_key is not the currently active one, fetch the old one and re-encrypt with the
         // active key
         let api_key_plain_bytes = if self.enc_key_id != enc_keys.enc_key.id {
-            let enc_key = EncKeyEntity::find(&self.id, &enc_keys.master_key).await?;
+            let enc_key = EncKeyEntity::find(&self.enc_key_id, &enc_keys.master_key).await?;
             let api_key_bytes = decrypt(&self.api_key, &enc_key.value)?;
 
             // re-encrypt with the new key and save it
@@ -251,6 +321,51 @@ impl ClientX509Entity {
 }
 
 impl ClientX509Entity {
+    /// Applies the linked profile and the overrides of this client on top of its own config.
+    /// Must be resolved before each issuance to pick up changes of the profile.
+    pub async fn with_profile(self) -> Result<Self, ErrorResponse> {
+        let Some(profile_id) = self.profile_id else {
+            return Ok(self);
+        };
+        let overrides = match &self.profile_overrides {
+            Some(overrides) => Some(serde_json::from_str(overrides)?),
+            None => None,
+        };
+
+        let mut client = self;
+        ProfileEntity::find_x509(&profile_id)
+            .await?
+            .merge(overrides)
+            .apply_client(&mut client);
+        Ok(client)
+    }
+
+    /// Narrows the CN, DNS and IP names of this client down to the requested ones.
+    /// Only names the client is configured with can be requested.
+    pub fn restrict_names(
+        mut self,
+        common_name: &str,
+        alt_names_dns: &[String],
+        alt_names_ip: &[IpAddr],
+    ) -> Result<Self, ErrorResponse> {
+        self.validate_names(Some(common_name), alt_names_dns, alt_names_ip)?;
+
+        self.common_name = common_name.to_string();
+        self.alt_names_dns = vec_to_csv(alt_names_dns);
+        let ips = alt_names_ip
+            .iter()
+            .map(|ip| ip.to_string())
+            .collect::<Vec<String>>();
+        self.alt_names_ip = vec_to_csv(&ips);
+        Ok(self)
+    }
+
+    /// Lowers the validity of the next certificate, it can never be raised above the configured one
+    pub fn restrict_validity(mut self, valid_hours: i32) -> Self {
+        self.valid_hours = self.valid_hours.min(valid_hours);
+        self
+    }
+
     /// Creates a new x509 certificate for this client and saves the information in the DB
     pub async fn build_cert(
         &self,
@@ -260,7 +375,7 @@ impl ClientX509Entity {
         // Optional password in case of CertFormat::PKCS12
         password: Option<&str>,
     ) -> Result<ClientX509EntityCert, ErrorResponse> {
-        let mut params = CertificateParams::default();
+        let mut params = self.cert_params();
 
         // let key_pair = gen_ed25519_key_pair()?;
         // params.alg = &rcgen::PKCS_ED25519;
@@ -279,101 +394,18 @@ impl ClientX509Entity {
             }
         };
         params.key_pair = Some(key_pair);
+        params.subject_alt_names = self.alt_names();
+        self.apply_spiffe(&mut params).await?;
 
-        let mut alt_names = vec![];
-        for name in csv_to_vec(&self.alt_names_dns) {
-            if !name.is_empty() {
-                alt_names.push(SanType::DnsName(name));
-            }
-        }
-        for ip in csv_to_vec(&self.alt_names_ip) {
-            if !ip.is_empty() {
-                if let Ok(ip) = IpAddr::from_str(&ip) {
-                    alt_names.push(SanType::IpAddress(ip));
-                } else {
-                    error!("Error serializing IP alt name for client {}", self.id);
-                    debug!("ip: {}", ip);
-                }
-            }
-        }
-        params.subject_alt_names = alt_names;
-
-        let mut sub = DistinguishedName::new();
-        sub.push(DnType::CommonName, &self.common_name);
-        if let Some(country) = self.country.as_ref() {
-            sub.push(DnType::CountryName, country);
-        }
-        if let Some(loc) = self.locality.as_ref() {
-            sub.push(DnType::LocalityName, loc);
-        }
-        if let Some(ou) = self.organizational_unit.as_ref() {
-            sub.push(DnType::OrganizationalUnitName, ou);
-        }
-        if let Some(org) = self.organization.as_ref() {
-            sub.push(DnType::OrganizationName, org);
-        }
-        if let Some(st) = self.state_or_province.as_ref() {
-            sub.push(DnType::StateOrProvinceName, st);
-        }
-        params.distinguished_name = sub;
-
-        params.is_ca = IsCa::ExplicitNoCa;
-
-        let mut key_usages = vec![];
-        if let Some(mut bytes) = self.key_usage.clone() {
-            for b in bytes.drain(..) {
-                let u = X509KeyUsages::from_value(b);
-                key_usages.push(KeyUsagePurpose::from(u));
-            }
-        }
-        params.key_usages = key_usages;
-
-        let mut key_usage_ext = vec![];
-        if let Some(mut bytes) = self.key_usage_ext.clone() {
-            for b in bytes.drain(..) {
-                let u = X509KeyUsagesExt::from_value(b);
-                key_usage_ext.push(ExtendedKeyUsagePurpose::from(u));
-            }
-        };
-        params.extended_key_usages = key_usage_ext;
-
-        params.name_constraints = None;
-        params.custom_extensions = vec![];
-        params.use_authority_key_identifier_extension = true;
-        params.key_identifier_method = KeyIdMethod::Sha256;
-
-        // generate a certificate without data to get a serial from the DB
-        let entity = CertX509Entity::from(self);
-        let mut cert_entity = entity.insert().await?;
-        assert!(cert_entity.serial > 0);
-        params.serial_number = Some((cert_entity.serial as u64).into());
-        params.not_before = OffsetDateTime::now_utc().sub(time::Duration::minutes(10));
-        params.not_after = cert_entity.expires;
-
-        let cert = Certificate::from_params(params)?;
-
-        let (cert_der, cert_pem, cert_chain) = {
-            // let signing_cert = &state.read().await.nioca_signing_cert;
-            let signing_cert = ca.signing_cert()?;
-            // let cert_der = cert.serialize_der_with_signer(signing_cert)?;
-            let cert_pem = cert
-                .serialize_pem_with_signer(&signing_cert)?
-                // For some reason, this is getting created with CRLF
-                .replace("\r\n", "\n");
-            let cert_der = pem_to_der(&cert_pem).unwrap().to_vec();
-
-            // let ca_chain = &state.read().await.ca_chain_pem;
-            let cert_chain = format!("{}{}", cert_pem, ca.ca_chain_pem);
-            (cert_der, cert_pem, cert_chain)
-        };
-
-        cert_entity.data = cert_der.clone();
-        cert_entity.update_data().await?;
-        ClientX509Entity::set_last_cert(
-            cert_entity.client_id.as_ref().unwrap(),
-            cert_entity.serial,
-        )
-        .await?;
+        let (cert, cert_entity, signed) = CertX509Entity::from(self)
+            .sign(ca, params, &self.alt_names_upn)
+            .await?;
+        ClientX509Entity::set_last_cert(&self.id, cert_entity.serial).await?;
+        let X509Signed {
+            cert_der,
+            cert_pem,
+            cert_chain,
+        } = signed;
 
         match cert_format {
             CertFormat::Pem => {
@@ -455,6 +487,291 @@ impl ClientX509Entity {
         }
     }
 
+    /// Signs a CSR for this client and saves the information in the DB.
+    /// The private key never leaves the client, which is why the response does not contain one.
+    pub async fn build_cert_from_csr(
+        &self,
+        ca: &CaCertX509Full,
+        csr: &X509Csr,
+    ) -> Result<CertX509CsrResponse, ErrorResponse> {
+        self.validate_csr(csr)?;
+
+        let mut params = self.cert_params();
+        if let Some(cn) = &csr.common_name {
+            params
+                .distinguished_name
+                .push(DnType::CommonName, cn.as_str());
+        }
+        params.alg = csr.sig_alg;
+        params.key_pair = Some(csr.key_pair()?);
+        // if the CSR does not request specific SANs, we fall back to the configured ones
+        let (alt_names, upns) = if csr.has_alt_names() {
+            (csr.alt_names(), csr.alt_names_upn.as_slice())
+        } else {
+            (self.alt_names(), self.alt_names_upn.as_slice())
+        };
+        params.subject_alt_names = alt_names;
+        self.apply_spiffe(&mut params).await?;
+
+        let (_, cert_entity, signed) = CertX509Entity::from(self).sign(ca, params, upns).await?;
+        ClientX509Entity::set_last_cert(&self.id, cert_entity.serial).await?;
+        let cert_fingerprint = fingerprint(signed.cert_pem.as_bytes());
+
+        info!(
+            "New certificate signed from CSR for ClientX509Entity: {} with fingerprint {}",
+            self.id, cert_fingerprint
+        );
+
+        Ok(CertX509CsrResponse {
+            cert: signed.cert_pem,
+            cert_fingerprint,
+            cert_chain: signed.cert_chain,
+            not_after: cert_entity.expires.unix_timestamp(),
+        })
+    }
+
+    /// Mints a JWT-SVID for the SPIFFE ID of this client, signed with the key of its group
+    pub async fn build_jwt_svid(
+        &self,
+        enc_keys: &EncKeys,
+        audience: &[String],
+        ttl_secs: Option<i64>,
+    ) -> Result<JwtSvidResponse, ErrorResponse> {
+        let trust_domain = GroupEntity::find_by_id(&self.group_id)
+            .await?
+            .spiffe_trust_domain
+            .ok_or_else(|| {
+                ErrorResponse::new(
+                    ErrorResponseType::BadRequest,
+                    "The group of this client is not bound to a SPIFFE trust domain",
+                )
+            })?;
+        let id = SpiffeId::from_uris(&self.alt_names_uri, &trust_domain)?;
+        let (key, pkcs8) = SpiffeJwtKeyEntity::find_signing_key(&self.group_id, enc_keys).await?;
+
+        let ttl = ttl_secs
+            .unwrap_or(*SPIFFE_JWT_SVID_TTL)
+            .min(*SPIFFE_JWT_SVID_TTL);
+        let iat = OffsetDateTime::now_utc().unix_timestamp();
+        let exp = iat + ttl;
+        let token = jwt_svid_sign(&pkcs8, &key.kid, &id, audience, iat, exp)?;
+
+        info!(
+            "New JWT-SVID for ClientX509Entity: {} with SPIFFE ID {}",
+            self.id, id
+        );
+
+        Ok(JwtSvidResponse {
+            token,
+            spiffe_id: id.to_string(),
+            not_after: exp,
+        })
+    }
+
+    /// Checks that the CSR only requests names this client is allowed to have.
+    fn validate_csr(&self, csr: &X509Csr) -> Result<(), ErrorResponse> {
+        if X509KeyAlg::from_str(&self.key_alg) != csr.key_alg {
+            return Err(ErrorResponse::new(
+                ErrorResponseType::BadRequest,
+                format!(
+                    "The CSR key algorithm does not match the configured '{}'",
+                    self.key_alg
+                ),
+            ));
+        }
+
+        self.validate_names(
+            csr.common_name.as_deref(),
+            &csr.alt_names_dns,
+            &csr.alt_names_ip,
+        )?;
+
+        let checks = [
+            ("URI", &csr.alt_names_uri, &self.alt_names_uri, false),
+            ("Email", &csr.alt_names_email, &self.alt_names_email, true),
+            ("UPN", &csr.alt_names_upn, &self.alt_names_upn, true),
+        ];
+        for (typ, requested, allowed, ignore_case) in checks {
+            for name in requested {
+                let is_allowed = allowed.iter().any(|allowed| {
+                    if ignore_case {
+                        allowed.eq_ignore_ascii_case(name)
+                    } else {
+                        allowed == name
+                    }
+                });
+                if !is_allowed {
+                    return Err(ErrorResponse::new(
+                        ErrorResponseType::Forbidden,
+                        format!("{} alt name '{}' is not allowed for this client", typ, name),
+                    ));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks that the CN, DNS and IP names are configured for this client.
+    fn validate_names(
+        &self,
+        common_name: Option<&str>,
+        alt_names_dns: &[String],
+        alt_names_ip: &[IpAddr],
+    ) -> Result<(), ErrorResponse> {
+        let allowed_dns = csv_to_vec(&self.alt_names_dns);
+        let is_allowed_dns = |name: &str| {
+            allowed_dns
+                .iter()
+                .any(|allowed| allowed.eq_ignore_ascii_case(name))
+        };
+
+        if let Some(cn) = common_name {
+            if !cn.eq_ignore_ascii_case(&self.common_name) && !is_allowed_dns(cn) {
+                return Err(ErrorResponse::new(
+                    ErrorResponseType::Forbidden,
+                    format!("CN '{}' is not allowed for this client", cn),
+                ));
+            }
+        }
+
+        for dns in alt_names_dns {
+            if !is_allowed_dns(dns) {
+                return Err(ErrorResponse::new(
+                    ErrorResponseType::Forbidden,
+                    format!("DNS alt name '{}' is not allowed for this client", dns),
+                ));
+            }
+        }
+
+        let allowed_ips = csv_to_vec(&self.alt_names_ip)
+            .iter()
+            .filter_map(|ip| IpAddr::from_str(ip).ok())
+            .collect::<Vec<IpAddr>>();
+        for ip in alt_names_ip {
+            if !allowed_ips.contains(ip) {
+                return Err(ErrorResponse::new(
+                    ErrorResponseType::Forbidden,
+                    format!("IP alt name '{}' is not allowed for this client", ip),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Builds the basic certificate params from the client config without any key or SANs
+    fn cert_params(&self) -> CertificateParams {
+        let mut params = CertificateParams::default();
+
+        let mut sub = DistinguishedName::new();
+        sub.push(DnType::CommonName, &self.common_name);
+        if let Some(country) = self.country.as_ref() {
+            sub.push(DnType::CountryName, country);
+        }
+        if let Some(loc) = self.locality.as_ref() {
+            sub.push(DnType::LocalityName, loc);
+        }
+        if let Some(ou) = self.organizational_unit.as_ref() {
+            sub.push(DnType::OrganizationalUnitName, ou);
+        }
+        if let Some(org) = self.organization.as_ref() {
+            sub.push(DnType::OrganizationName, org);
+        }
+        if let Some(st) = self.state_or_province.as_ref() {
+            sub.push(DnType::StateOrProvinceName, st);
+        }
+        params.distinguished_name = sub;
+
+        params.is_ca = IsCa::ExplicitNoCa;
+
+        let mut key_usages = vec![];
+        if let Some(mut bytes) = self.key_usage.clone() {
+            for b in bytes.drain(..) {
+                let u = X509KeyUsages::from_value(b);
+                key_usages.push(KeyUsagePurpose::from(u));
+            }
+        }
+        params.key_usages = key_usages;
+
+        let mut key_usage_ext = vec![];
+        if let Some(mut bytes) = self.key_usage_ext.clone() {
+            for b in bytes.drain(..) {
+                let u = X509KeyUsagesExt::from_value(b);
+                key_usage_ext.push(ExtendedKeyUsagePurpose::from(u));
+            }
+        };
+        params.extended_key_usages = key_usage_ext;
+
+        params.name_constraints = None;
+        params.custom_extensions = vec![];
+        params.use_authority_key_identifier_extension = true;
+        params.key_identifier_method = KeyIdMethod::Sha256;
+
+        params
+    }
+
+    /// The configured SANs for this client
+    fn alt_names(&self) -> Vec<SanType> {
+        let mut alt_names = vec![];
+        for name in csv_to_vec(&self.alt_names_dns) {
+            if !name.is_empty() {
+                alt_names.push(SanType::DnsName(name));
+            }
+        }
+        for ip in csv_to_vec(&self.alt_names_ip) {
+            if !ip.is_empty() {
+                if let Ok(ip) = IpAddr::from_str(&ip) {
+                    alt_names.push(SanType::IpAddress(ip));
+                } else {
+                    error!("Error serializing IP alt name for client {}", self.id);
+                    debug!("ip: {}", ip);
+                }
+            }
+        }
+        for uri in &self.alt_names_uri {
+            alt_names.push(SanType::URI(uri.clone()));
+        }
+        for email in &self.alt_names_email {
+            alt_names.push(SanType::Rfc822Name(email.clone()));
+        }
+        alt_names
+    }
+
+    /// Checks the client against the X509 policy and the SPIFFE trust domain of the group
+    async fn check_group_policy(
+        group_id: &Uuid,
+        client: &ClientX509Request,
+    ) -> Result<(), ErrorResponse> {
+        let group = GroupEntity::find_by_id(group_id).await?;
+        match &group.spiffe_trust_domain {
+            Some(trust_domain) => {
+                SpiffeId::from_uris(&client.alt_names_uri, trust_domain)?;
+            }
+            None => check_spiffe_ids(&client.alt_names_uri, None)?,
+        }
+        match group.x509_policy()? {
+            Some(policy) => policy.check_client(client),
+            None => Ok(()),
+        }
+    }
+
+    /// Turns the params into an X.509-SVID, if the group is bound to a SPIFFE trust domain
+    async fn apply_spiffe(&self, params: &mut CertificateParams) -> Result<(), ErrorResponse> {
+        let group = GroupEntity::find_by_id(&self.group_id).await?;
+        if let Some(trust_domain) = group.spiffe_trust_domain {
+            x509_svid(params, &trust_domain)?;
+        }
+        Ok(())
+    }
+
+    fn overrides_value(overrides: Option<X509Profile>) -> Result<Option<String>, ErrorResponse> {
+        match overrides {
+            Some(overrides) => Ok(Some(serde_json::to_string(&overrides)?)),
+            None => Ok(None),
+        }
+    }
+
     fn expires_from_req(ts: Option<i64>) -> Result<Option<OffsetDateTime>, ErrorResponse> {
         if let Some(ts) = ts {
             match OffsetDateTime::from_unix_timestamp(ts) {
@@ -0,0 +1,346 @@
+use crate::certificates::X509RevocationReason;
+use crate::config::{Db, EncKeys};
+use crate::constants::{CRL_VALIDITY_HOURS, PUB_URL_FULL};
+use crate::models::api::error_response::{ErrorResponse, ErrorResponseType};
+use crate::models::db::ca_cert_x509::{CaCertX509Entity, CaCertX509Full, CaCertX509Type};
+use crate::models::db::cert_x509_revoked::CertX509RevokedEntity;
+use der::pem::LineEnding;
+use rcgen::{
+    Certificate, CertificateRevocationList, CertificateRevocationListParams, CrlDistributionPoint,
+    KeyIdMethod, RevokedCertParams,
+};
+use sqlx::{query, query_as};
+use std::ops::Add;
+use time::OffsetDateTime;
+use tracing::{error, info};
+use uuid::Uuid;
+
+/// The CRL of an intermediate CA together with its distribution point config
+#[derive(Debug, Clone)]
+pub struct CrlX509Entity {
+    pub ca_id: Uuid,
+    /// If issued certificates should contain a CRL Distribution Points extension
+    pub cdp_enabled: bool,
+    /// Overwrites the distribution point URL, for instance when the CRL is mirrored somewhere else
+    pub cdp_url: Option<String>,
+    /// The last used `CRLNumber`
+    pub number: i64,
+    pub this_update: Option<OffsetDateTime>,
+    pub next_update: Option<OffsetDateTime>,
+    /// The DER encoded CRL
+    pub data: Option<Vec<u8>>,
+}
+
+// CRUD
+impl CrlX509Entity {
+    pub async fn find(ca_id: &Uuid) -> Result<Option<Self>, ErrorResponse> {
+        let res = query_as!(Self, "SELECT * FROM crls_x509 WHERE ca_id = $1", ca_id)
+            .fetch_optional(Db::conn())
+            .await?;
+        Ok(res)
+    }
+
+    /// Returns the defaults if nothing has been saved for this CA yet
+    pub async fn find_or_default(ca_id: &Uuid) -> Result<Self, ErrorResponse> {
+        let res = Self::find(ca_id).await?.unwrap_or(Self {
+            ca_id: *ca_id,
+            cdp_enabled: true,
+            cdp_url: None,
+            number: 0,
+            this_update: None,
+            next_update: None,
+            data: None,
+        });
+        Ok(res)
+    }
+
+    pub async fn update_config(
+        ca_id: &Uuid,
+        cdp_enabled: bool,
+        cdp_url: Option<String>,
+    ) -> Result<Self, ErrorResponse> {
+        let res = query_as!(
+            Self,
+            r#"INSERT INTO crls_x509 (ca_id, cdp_enabled, cdp_url)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (ca_id) DO UPDATE SET cdp_enabled = $2, cdp_url = $3
+            RETURNING *"#,
+            ca_id,
+            cdp_enabled,
+            cdp_url,
+        )
+        .fetch_one(Db::conn())
+        .await?;
+        Ok(res)
+    }
+
+    pub async fn delete(ca_id: &Uuid) -> Result<(), ErrorResponse> {
+        query!("DELETE FROM crls_x509 WHERE ca_id = $1", ca_id)
+            .execute(Db::conn())
+            .await?;
+        Ok(())
+    }
+
+    /// Increments and returns the `CRLNumber` for the next CRL of this CA
+    async fn next_number(ca_id: &Uuid) -> Result<i64, ErrorResponse> {
+        let res = query!(
+            r#"INSERT INTO crls_x509 (ca_id, number)
+            VALUES ($1, 1)
+            ON CONFLICT (ca_id) DO UPDATE SET number = crls_x509.number + 1
+            RETURNING number"#,
+            ca_id,
+        )
+        .fetch_one(Db::conn())
+        .await?;
+        Ok(res.number)
+    }
+
+    /// Saves the CRL, unless another instance has been faster with a newer one in the meantime
+    async fn save_crl(&self) -> Result<(), ErrorResponse> {
+        query!(
+            r#"UPDATE crls_x509 SET this_update = $1, next_update = $2, data = $3
+            WHERE ca_id = $4 AND number = $5"#,
+            self.this_update,
+            self.next_update,
+            self.data,
+            self.ca_id,
+            self.number,
+        )
+        .execute(Db::conn())
+        .await?;
+        Ok(())
+    }
+}
+
+impl CrlX509Entity {
+    /// The URL Nioca serves the DER encoded CRL for the given CA at
+    pub fn url_der(ca_id: &Uuid) -> String {
+        format!("{}/crl/x509/{}", *PUB_URL_FULL, ca_id)
+    }
+
+    /// The URL Nioca serves the PEM encoded CRL for the given CA at
+    pub fn url_pem(ca_id: &Uuid) -> String {
+        format!("{}/crl/x509/{}/pem", *PUB_URL_FULL, ca_id)
+    }
+
+    /// The distribution points for newly issued certificates
+    pub fn distribution_points(&self) -> Vec<CrlDistributionPoint> {
+        if !self.cdp_enabled {
+            return Vec::default();
+        }
+        let uri = self
+            .cdp_url
+            .clone()
+            .unwrap_or_else(|| Self::url_der(&self.ca_id));
+        vec![CrlDistributionPoint { uris: vec![uri] }]
+    }
+
+    /// Returns `true` if no CRL exists, the current one has passed half of its lifetime,
+    /// or if there have been new revocations since it has been generated.
+    pub async fn needs_rebuild(&self) -> Result<bool, ErrorResponse> {
+        match self.this_update {
+            Some(this_update) if !self.is_due(OffsetDateTime::now_utc()) => {
+                CertX509RevokedEntity::exists_for_ca_since(&self.ca_id, this_update).await
+            }
+            _ => Ok(true),
+        }
+    }
+
+    /// `true` if no CRL exists or the current one has passed half of its lifetime
+    fn is_due(&self, now: OffsetDateTime) -> bool {
+        match (self.this_update, self.next_update, &self.data) {
+            (Some(this_update), Some(next_update), Some(_)) => {
+                now.add((next_update - this_update) / 2) > next_update
+            }
+            _ => true,
+        }
+    }
+
+    /// Generates, signs and saves a new CRL for the given CA
+    pub async fn rebuild(ca: &CaCertX509Full) -> Result<Self, ErrorResponse> {
+        let ca_id = ca.intermediate.id;
+        let mut slf = Self::find_or_default(&ca_id).await?;
+
+        let signing_cert = ca.signing_cert()?;
+        // fetch the revoked certs before taking the timestamp to not miss any in `needs_rebuild`
+        let revoked = CertX509RevokedEntity::find_all_by_ca(&ca_id).await?;
+        let this_update = OffsetDateTime::now_utc();
+        let next_update = this_update.add(time::Duration::hours(*CRL_VALIDITY_HOURS));
+        let number = Self::next_number(&ca_id).await?;
+        let der = Self::build_der(&signing_cert, &revoked, number, this_update, next_update)?;
+
+        slf.number = number;
+        slf.this_update = Some(this_update);
+        slf.next_update = Some(next_update);
+        slf.data = Some(der);
+        slf.save_crl().await?;
+
+        info!(
+            "New CRL {} with {} entries generated for CA {}",
+            number,
+            revoked.len(),
+            ca_id
+        );
+        Ok(slf)
+    }
+
+    /// Builds the DER encoded CRL, signed with the given CA certificate
+    fn build_der(
+        signing_cert: &Certificate,
+        revoked: &[CertX509RevokedEntity],
+        number: i64,
+        this_update: OffsetDateTime,
+        next_update: OffsetDateTime,
+    ) -> Result<Vec<u8>, ErrorResponse> {
+        let revoked_certs = revoked
+            .iter()
+            .map(|revoked| RevokedCertParams {
+                serial_number: (revoked.serial as u64).into(),
+                revocation_time: revoked.revoked,
+                reason_code: X509RevocationReason::from_value(revoked.reason).crl_reason(),
+                // rcgen encodes this as UTCTime while RFC 5280 requires a GeneralizedTime,
+                // which breaks parsing for OpenSSL and others -> only available via the API
+                invalidity_date: None,
+            })
+            .collect::<Vec<_>>();
+
+        let params = CertificateRevocationListParams {
+            this_update,
+            next_update,
+            crl_number: (number as u64).into(),
+            issuing_distribution_point: None,
+            revoked_certs,
+            alg: signing_cert.get_params().alg,
+            key_identifier_method: KeyIdMethod::Sha256,
+        };
+        let crl = CertificateRevocationList::from_params(params)?;
+        Ok(crl.serialize_der_with_signer(signing_cert)?)
+    }
+
+    /// Re-generates the CRLs for all CAs the given serials have been signed with.
+    /// Errors are only logged, the scheduler will catch up on these.
+    pub async fn rebuild_for_serials(serials: &[i32], enc_keys: &EncKeys) {
+        let ca_ids = match query!(
+            r#"SELECT DISTINCT ca_id AS "ca_id!" FROM certs_x509
+            WHERE serial = ANY($1) AND ca_id IS NOT NULL"#,
+            serials,
+        )
+        .fetch_all(Db::conn())
+        .await
+        {
+            Ok(rows) => rows.into_iter().map(|row| row.ca_id).collect::<Vec<_>>(),
+            Err(err) => {
+                error!("Error looking up the CAs for revoked certificates: {}", err);
+                return;
+            }
+        };
+
+        for ca_id in ca_ids {
+            if let Err(err) = Self::rebuild_by_ca_id(&ca_id, enc_keys).await {
+                error!("Error generating the CRL for CA {}: {}", ca_id, err.message);
+            }
+        }
+    }
+
+    /// Re-generates the CRL for the given CA id, if the CA still exists
+    pub async fn rebuild_by_ca_id(ca_id: &Uuid, enc_keys: &EncKeys) -> Result<Self, ErrorResponse> {
+        // makes sure we get a 404 instead of trying to build a CA which does not exist
+        CaCertX509Entity::find_by_id(ca_id, CaCertX509Type::Certificate).await?;
+        let ca = CaCertX509Full::build_by_id(ca_id, enc_keys).await?;
+        Self::rebuild(&ca).await
+    }
+
+    pub fn pem(&self) -> Result<String, ErrorResponse> {
+        let der = self.data.as_deref().ok_or_else(|| {
+            ErrorResponse::new(ErrorResponseType::NotFound, "No CRL generated yet")
+        })?;
+        let pem = der::pem::encode_string("X509 CRL", LineEnding::LF, der)
+            .map_err(|err| ErrorResponse::new(ErrorResponseType::Internal, err.to_string()))?;
+        Ok(pem)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rcgen::{BasicConstraints, CertificateParams, IsCa, KeyUsagePurpose};
+    use std::ops::Sub;
+    use x509_parser::num_bigint::BigUint;
+    use x509_parser::prelude::FromDer;
+    use x509_parser::revocation_list::CertificateRevocationList as ParsedCrl;
+
+    fn crl(this_update: Option<OffsetDateTime>, data: Option<Vec<u8>>) -> CrlX509Entity {
+        CrlX509Entity {
+            ca_id: Uuid::nil(),
+            cdp_enabled: true,
+            cdp_url: None,
+            number: 1,
+            this_update,
+            next_update: this_update.map(|ts| ts.add(time::Duration::hours(24))),
+            data,
+        }
+    }
+
+    #[test]
+    fn test_crl_is_due() {
+        let now = OffsetDateTime::now_utc();
+        assert!(crl(None, None).is_due(now));
+        assert!(crl(Some(now), None).is_due(now));
+
+        let fresh = crl(Some(now), Some(vec![1]));
+        assert!(!fresh.is_due(now));
+        assert!(!fresh.is_due(now.add(time::Duration::hours(11))));
+        assert!(fresh.is_due(now.add(time::Duration::hours(13))));
+    }
+
+    #[test]
+    fn test_crl_build_der() {
+        let mut params = CertificateParams::new(vec![]);
+        params.alg = &rcgen::PKCS_ECDSA_P256_SHA256;
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        params.key_usages = vec![KeyUsagePurpose::KeyCertSign, KeyUsagePurpose::CrlSign];
+        let ca = Certificate::from_params(params).unwrap();
+
+        let revoked_at = OffsetDateTime::now_utc()
+            .sub(time::Duration::hours(1))
+            .replace_nanosecond(0)
+            .unwrap();
+        let revoked = |serial: i32, reason: X509RevocationReason| CertX509RevokedEntity {
+            serial,
+            reason: reason.value(),
+            revoked: revoked_at,
+            invalidity_date: None,
+            revoked_by: "admin".to_string(),
+        };
+        let revoked = vec![
+            revoked(13, X509RevocationReason::KeyCompromise),
+            revoked(37, X509RevocationReason::Unspecified),
+        ];
+
+        let this_update = OffsetDateTime::now_utc().replace_nanosecond(0).unwrap();
+        let next_update = this_update.add(time::Duration::hours(24));
+        let der = CrlX509Entity::build_der(&ca, &revoked, 7, this_update, next_update).unwrap();
+
+        let (_, parsed) = ParsedCrl::from_der(&der).unwrap();
+        let ca_der = ca.serialize_der().unwrap();
+        let (_, ca_cert) = x509_parser::certificate::X509Certificate::from_der(&ca_der).unwrap();
+        parsed.verify_signature(ca_cert.public_key()).unwrap();
+
+        assert_eq!(parsed.crl_number(), Some(&BigUint::from(7u8)));
+        assert_eq!(parsed.last_update().to_datetime(), this_update);
+        assert_eq!(
+            parsed.next_update().map(|ts| ts.to_datetime()),
+            Some(next_update)
+        );
+
+        let entries = parsed.iter_revoked_certificates().collect::<Vec<_>>();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].serial(), &BigUint::from(13u8));
+        assert_eq!(entries[0].revocation_date.to_datetime(), revoked_at);
+        // keyCompromise (1)
+        assert_eq!(entries[0].reason_code().map(|(_, code)| code.0), Some(1));
+        // RFC 5280 wants `unspecified` to be absent
+        assert_eq!(entries[1].serial(), &BigUint::from(37u8));
+        assert!(entries[1].reason_code().is_none());
+    }
+}
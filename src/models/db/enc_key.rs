@@ -1,7 +1,8 @@
 use crate::certificates::encryption::decrypt;
 use crate::config::Db;
 use crate::models::api::error_response::ErrorResponse;
-use sqlx::query_as;
+use sqlx::{query, query_as, Postgres, Transaction};
+use time::OffsetDateTime;
 use uuid::Uuid;
 
 #[derive(Debug, Clone, Default)]
@@ -9,9 +10,25 @@ pub struct EncKeyEntity {
     pub id: Uuid,
     pub alg: String,
     pub value: Vec<u8>,
+    /// `None` for keys created before rotation was supported
+    pub created: Option<OffsetDateTime>,
 }
 
 impl EncKeyEntity {
+    /// The `value` must be encrypted with the master key already
+    pub async fn insert(&self, txn: &mut Transaction<'_, Postgres>) -> Result<(), ErrorResponse> {
+        query!(
+            "INSERT INTO enc_keys (id, alg, value, created) VALUES ($1, $2, $3, $4)",
+            self.id,
+            self.alg,
+            self.value,
+            self.created,
+        )
+        .execute(&mut **txn)
+        .await?;
+        Ok(())
+    }
+
     pub async fn find(uuid: &Uuid, master_key: &[u8]) -> Result<Self, ErrorResponse> {
         let res = query_as!(Self, "select * from enc_keys where id = $1", uuid)
             .fetch_one(Db::conn())
@@ -23,6 +40,88 @@ impl EncKeyEntity {
             id: res.id,
             alg: res.alg,
             value: dec,
+            created: res.created,
         })
     }
+
+    /// Returns all keys with their still encrypted values
+    pub async fn find_all() -> Result<Vec<Self>, ErrorResponse> {
+        let res = query_as!(Self, "select * from enc_keys order by created nulls first")
+            .fetch_all(Db::conn())
+            .await?;
+        Ok(res)
+    }
+
+    pub async fn find_all_for_update(
+        txn: &mut Transaction<'_, Postgres>,
+    ) -> Result<Vec<Self>, ErrorResponse> {
+        let res = query_as!(Self, "select * from enc_keys for update")
+            .fetch_all(&mut **txn)
+            .await?;
+        Ok(res)
+    }
+
+    /// Replaces the encrypted value, used when the master key changes
+    pub async fn update_value(
+        &self,
+        txn: &mut Transaction<'_, Postgres>,
+    ) -> Result<(), ErrorResponse> {
+        query!(
+            "UPDATE enc_keys SET value = $1 WHERE id = $2",
+            self.value,
+            self.id
+        )
+        .execute(&mut **txn)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn delete(id: &Uuid) -> Result<(), ErrorResponse> {
+        query!("DELETE FROM enc_keys WHERE id = $1", id)
+            .execute(Db::conn())
+            .await?;
+        Ok(())
+    }
+
+    /// Counts all values in the database, which are still encrypted with the given key
+    pub async fn count_usages(id: &Uuid) -> Result<i64, ErrorResponse> {
+        let res = query!(
+            r#"SELECT
+            (SELECT count(*) FROM ca_certs_x509 WHERE enc_key_id = $1)
+            + (SELECT count(*) FROM ca_certs_ssh WHERE enc_key_id = $1)
+            + (SELECT count(*) FROM clients_x509 WHERE enc_key_id = $1)
+            + (SELECT count(*) FROM clients_ssh WHERE enc_key_id = $1)
+            + (SELECT count(*) FROM config WHERE enc_key_id = $1)
+            + (SELECT count(*) FROM users_group_access WHERE enc_key_id = $1)
+            + (SELECT count(*) FROM ocsp_x509 WHERE signer_enc_key_id = $1)
+            + (SELECT count(*) FROM spiffe_jwt_keys WHERE enc_key_id = $1)
+            + (SELECT count(*) FROM acme_eab_keys WHERE enc_key_id = $1)
+            AS "count!""#,
+            id,
+        )
+        .fetch_one(Db::conn())
+        .await?;
+        Ok(res.count)
+    }
+
+    /// Counts all values in the database, which are encrypted with any other than the given key
+    pub async fn count_outdated(active_id: &Uuid) -> Result<i64, ErrorResponse> {
+        let res = query!(
+            r#"SELECT
+            (SELECT count(*) FROM ca_certs_x509 WHERE enc_key_id <> $1)
+            + (SELECT count(*) FROM ca_certs_ssh WHERE enc_key_id <> $1)
+            + (SELECT count(*) FROM clients_x509 WHERE enc_key_id <> $1)
+            + (SELECT count(*) FROM clients_ssh WHERE enc_key_id <> $1)
+            + (SELECT count(*) FROM config WHERE enc_key_id <> $1)
+            + (SELECT count(*) FROM users_group_access WHERE enc_key_id <> $1)
+            + (SELECT count(*) FROM ocsp_x509 WHERE signer_enc_key_id <> $1)
+            + (SELECT count(*) FROM spiffe_jwt_keys WHERE enc_key_id <> $1)
+            + (SELECT count(*) FROM acme_eab_keys WHERE enc_key_id <> $1)
+            AS "count!""#,
+            active_id,
+        )
+        .fetch_one(Db::conn())
+        .await?;
+        Ok(res.count)
+    }
 }
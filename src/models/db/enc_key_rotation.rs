@@ -0,0 +1,89 @@
+use crate::config::Db;
+use crate::models::api::error_response::ErrorResponse;
+use sqlx::{query, query_as, Postgres, Transaction};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+/// Progress of the background re-encryption towards a new enc key
+#[derive(Debug, Clone)]
+pub struct EncKeyRotationEntity {
+    /// The new enc key everything is re-encrypted with
+    pub enc_key_id: Uuid,
+    pub started: OffsetDateTime,
+    pub updated: OffsetDateTime,
+    pub finished: Option<OffsetDateTime>,
+    /// Values which used an old key when the job has been started
+    pub total: i64,
+    pub done: i64,
+    pub failed: i64,
+    /// The latest error, if any
+    pub error: Option<String>,
+}
+
+impl EncKeyRotationEntity {
+    pub fn new(enc_key_id: Uuid) -> Self {
+        let now = OffsetDateTime::now_utc();
+        Self {
+            enc_key_id,
+            started: now,
+            updated: now,
+            finished: None,
+            total: 0,
+            done: 0,
+            failed: 0,
+            error: None,
+        }
+    }
+
+    /// Inserts the rotation or resets the progress of an existing one for the same key
+    pub async fn upsert(&self, txn: &mut Transaction<'_, Postgres>) -> Result<(), ErrorResponse> {
+        query!(
+            r#"INSERT INTO enc_key_rotations
+            (enc_key_id, started, updated, finished, total, done, failed, error)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+            ON CONFLICT (enc_key_id) DO UPDATE
+            SET started = $2, updated = $3, finished = $4, total = $5, done = $6, failed = $7,
+            error = $8"#,
+            self.enc_key_id,
+            self.started,
+            self.updated,
+            self.finished,
+            self.total,
+            self.done,
+            self.failed,
+            self.error,
+        )
+        .execute(&mut **txn)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn find_latest() -> Result<Option<Self>, ErrorResponse> {
+        let res = query_as!(
+            Self,
+            "SELECT * FROM enc_key_rotations ORDER BY started DESC LIMIT 1"
+        )
+        .fetch_optional(Db::conn())
+        .await?;
+        Ok(res)
+    }
+
+    pub async fn update_progress(&mut self) -> Result<(), ErrorResponse> {
+        self.updated = OffsetDateTime::now_utc();
+        query!(
+            r#"UPDATE enc_key_rotations
+            SET updated = $1, finished = $2, total = $3, done = $4, failed = $5, error = $6
+            WHERE enc_key_id = $7"#,
+            self.updated,
+            self.finished,
+            self.total,
+            self.done,
+            self.failed,
+            self.error,
+            self.enc_key_id,
+        )
+        .execute(Db::conn())
+        .await?;
+        Ok(())
+    }
+}
@@ -1,3 +1,4 @@
+use crate::certificates::x509::policy::X509Policy;
 use crate::config::Db;
 use crate::models::api::error_response::ErrorResponse;
 use crate::models::api::request::{GroupCreateRequest, GroupUpdateRequest};
@@ -13,6 +14,11 @@ pub struct GroupEntity {
     pub ca_ssh: Option<Uuid>,
     pub ca_x509: Option<Uuid>,
     pub ca_x509_typ: Option<String>,
+    pub acme_enabled: bool,
+    /// The JSON encoded `X509Policy`
+    pub x509_policy: Option<String>,
+    /// Clients of a group with a trust domain receive X.509-SVIDs and can mint JWT-SVIDs
+    pub spiffe_trust_domain: Option<String>,
 }
 
 impl GroupEntity {
@@ -50,12 +56,16 @@ impl GroupEntity {
 
     pub async fn insert(req: GroupCreateRequest) -> Result<(), ErrorResponse> {
         query!(
-            r#"INSERT INTO groups (id, name, enabled, ca_ssh, ca_x509, ca_x509_typ)
-            VALUES ($1, $2, true, $3, $4, 'certificate')"#,
+            r#"INSERT INTO groups (id, name, enabled, ca_ssh, ca_x509, ca_x509_typ, acme_enabled,
+            x509_policy, spiffe_trust_domain)
+            VALUES ($1, $2, true, $3, $4, 'certificate', $5, $6, $7)"#,
             Uuid::new_v4(),
             req.name,
             req.ca_ssh,
             req.ca_x509,
+            req.acme_enabled,
+            Self::policy_value(req.x509_policy)?,
+            req.spiffe_trust_domain.filter(|td| !td.is_empty()),
         )
         .execute(Db::conn())
         .await?;
@@ -72,12 +82,30 @@ impl GroupEntity {
     pub async fn update(id: &Uuid, req: GroupUpdateRequest) -> Result<(), ErrorResponse> {
         // TODO make it impossible to change the 'default' name without fetching the information beforehand
         // -> create more sophisticated query
+        // an empty policy removes any restrictions
+        let set_policy = req.x509_policy.is_some();
+        let policy = match req.x509_policy {
+            Some(policy) if policy == X509Policy::default() => None,
+            policy => Self::policy_value(policy)?,
+        };
+        // an empty trust domain removes it
+        let set_trust_domain = req.spiffe_trust_domain.is_some();
+        let trust_domain = req.spiffe_trust_domain.filter(|td| !td.is_empty());
         query!(
-            "UPDATE groups SET name = $1, enabled = $2, ca_ssh = $3, ca_x509 = $4 WHERE id = $5",
+            r#"UPDATE groups SET name = $1, enabled = $2, ca_ssh = $3, ca_x509 = $4,
+            acme_enabled = COALESCE($5, acme_enabled),
+            x509_policy = CASE WHEN $6 THEN $7 ELSE x509_policy END,
+            spiffe_trust_domain = CASE WHEN $8 THEN $9 ELSE spiffe_trust_domain END
+            WHERE id = $10"#,
             req.name,
             req.enabled,
             req.ca_ssh,
             req.ca_x509,
+            req.acme_enabled,
+            set_policy,
+            policy,
+            set_trust_domain,
+            trust_domain,
             id,
         )
         .execute(Db::conn())
@@ -85,4 +113,22 @@ impl GroupEntity {
 
         Ok(())
     }
+
+    /// The policy certificates inside this group must follow, if any
+    pub fn x509_policy(&self) -> Result<Option<X509Policy>, ErrorResponse> {
+        match &self.x509_policy {
+            Some(policy) => Ok(Some(serde_json::from_str(policy)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn policy_value(policy: Option<X509Policy>) -> Result<Option<String>, ErrorResponse> {
+        match policy {
+            Some(policy) => {
+                policy.validate()?;
+                Ok(Some(serde_json::to_string(&policy)?))
+            }
+            None => Ok(None),
+        }
+    }
 }
@@ -1,13 +1,21 @@
 use crate::config::Db;
 use crate::models::api::error_response::ErrorResponse;
-use sqlx::{query, query_as};
+use crate::service::shamir::MasterShares;
+use serde::Deserialize;
+use sqlx::{query, query_as, Postgres, Transaction};
+use std::collections::BTreeMap;
+use std::str::FromStr;
 use time::OffsetDateTime;
+use uuid::Uuid;
 
 #[derive(Debug, Clone, Default)]
 pub struct MasterKeyEntity {
     pub initialized: Option<OffsetDateTime>,
-    pub check_shard_1: Vec<u8>,
-    pub check_shard_2: Vec<u8>,
+    /// Number of shares needed for unsealing, `None` for the legacy 2 concatenated shards
+    pub shares_threshold: Option<u8>,
+    pub shares_total: Option<u8>,
+    /// The check hashes of all shares by their index
+    pub check_shares: BTreeMap<u8, Vec<u8>>,
     pub check_master: Vec<u8>,
     pub master_key: Vec<u8>,
     pub enc_key_active: Option<String>,
@@ -16,7 +24,11 @@ pub struct MasterKeyEntity {
 impl MasterKeyEntity {
     pub async fn build() -> Result<Self, ErrorResponse> {
         let rows = MasterKeyRow::find_all().await?;
+        Ok(Self::from_rows(rows))
+    }
 
+    /// Builds the entity from already loaded rows, for instance the ones inside a backup archive
+    pub fn from_rows(rows: Vec<MasterKeyRow>) -> Self {
         let mut slf = Self::default();
 
         for entry in rows {
@@ -26,20 +38,43 @@ impl MasterKeyEntity {
                     let dt = OffsetDateTime::from_unix_timestamp(ts).unwrap();
                     slf.initialized = Some(dt);
                 }
-                "check_shard_1" => slf.check_shard_1 = hex::decode(entry.value.unwrap()).unwrap(),
-                "check_shard_2" => slf.check_shard_2 = hex::decode(entry.value.unwrap()).unwrap(),
+                "check_shard_1" => {
+                    slf.check_shares
+                        .insert(1, hex::decode(entry.value.unwrap()).unwrap());
+                }
+                "check_shard_2" => {
+                    slf.check_shares
+                        .insert(2, hex::decode(entry.value.unwrap()).unwrap());
+                }
+                "shares_threshold" => slf.shares_threshold = entry.value.unwrap().parse().ok(),
+                "shares_total" => slf.shares_total = entry.value.unwrap().parse().ok(),
                 "check_master" => slf.check_master = hex::decode(entry.value.unwrap()).unwrap(),
                 "master_key" => slf.master_key = hex::decode(entry.value.unwrap()).unwrap(),
                 "enc_key_active" => slf.enc_key_active = entry.value,
-                _ => {}
+                id => {
+                    if let Some(index) = id.strip_prefix("check_share_") {
+                        let index = index.parse::<u8>().unwrap();
+                        slf.check_shares
+                            .insert(index, hex::decode(entry.value.unwrap()).unwrap());
+                    }
+                }
             }
         }
 
-        Ok(slf)
+        slf
+    }
+
+    /// The number of shares needed to rebuild the master key
+    pub fn threshold(&self) -> u8 {
+        self.shares_threshold.unwrap_or(2)
+    }
+
+    pub fn total(&self) -> u8 {
+        self.shares_total.unwrap_or(2)
     }
 }
 
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, Deserialize)]
 pub struct MasterKeyRow {
     pub id: String,
     pub value: Option<String>,
@@ -53,6 +88,75 @@ impl MasterKeyRow {
             .map_err(ErrorResponse::from)
     }
 
+    pub async fn find_enc_key_active() -> Result<Uuid, ErrorResponse> {
+        let slf = query_as!(Self, "select * from master_key where id = 'enc_key_active'")
+            .fetch_one(Db::conn())
+            .await?;
+
+        let id = slf.value.expect("enc_key_active not set up correctly");
+        Ok(Uuid::from_str(&id).expect("Rebuilding UUID for enc kid"))
+    }
+
+    pub async fn update_enc_key_active(
+        id: &Uuid,
+        txn: &mut Transaction<'_, Postgres>,
+    ) -> Result<(), ErrorResponse> {
+        query!(
+            "update master_key set value = $1 where id = 'enc_key_active'",
+            id.to_string()
+        )
+        .execute(&mut **txn)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn update_value(
+        id: &str,
+        value: &str,
+        txn: &mut Transaction<'_, Postgres>,
+    ) -> Result<(), ErrorResponse> {
+        query!("update master_key set value = $1 where id = $2", value, id)
+            .execute(&mut **txn)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Replaces all existing share check hashes and the shares config
+    pub async fn save_shares(
+        shares: &MasterShares,
+        txn: &mut Transaction<'_, Postgres>,
+    ) -> Result<(), ErrorResponse> {
+        query!(
+            r#"DELETE FROM master_key
+            WHERE id LIKE 'check_shard_%' OR id LIKE 'check_share_%'
+            OR id IN ('shares_threshold', 'shares_total')"#
+        )
+        .execute(&mut **txn)
+        .await?;
+
+        let mut rows = vec![
+            ("shares_threshold".to_string(), shares.threshold.to_string()),
+            ("shares_total".to_string(), shares.total.to_string()),
+        ];
+        for (index, check) in shares.checks.iter() {
+            rows.push((format!("check_share_{}", index), hex::encode(check)));
+        }
+
+        for (id, value) in rows {
+            query!(
+                "INSERT INTO master_key (id, value) VALUES ($1, $2)",
+                id,
+                value
+            )
+            .execute(&mut **txn)
+            .await?;
+        }
+
+        Ok(())
+    }
+
     pub async fn find_local_password() -> Result<String, ErrorResponse> {
         let slf = query_as!(Self, "select * from master_key where id = 'local_password'")
             .fetch_one(Db::conn())
@@ -1,15 +1,30 @@
+pub mod acme_account;
+pub mod acme_authz;
+pub mod acme_eab_key;
+pub mod acme_nonce;
+pub mod acme_order;
+pub mod audit_key;
+pub mod audit_log;
 pub mod ca_cert_ssh;
 pub mod ca_cert_x509;
 pub mod cert_ssh;
+pub mod cert_ssh_revoked;
 pub mod cert_x509;
+pub mod cert_x509_revoked;
 pub mod client_ssh;
 pub mod client_x509;
 pub mod config_oidc;
+pub mod crl_x509;
 pub mod enc_key;
+pub mod enc_key_rotation;
 pub mod groups;
 pub mod key_value_enc;
 pub mod master_key;
+pub mod notification_expiry;
+pub mod ocsp_x509;
+pub mod profile;
 pub mod sealed;
 pub mod session;
+pub mod spiffe_jwt_key;
 pub mod user;
 pub mod user_group_access;
@@ -0,0 +1,73 @@
+use crate::config::Db;
+use crate::models::api::error_response::ErrorResponse;
+use crate::notifications::ExpiryObjectType;
+use sqlx::query;
+use time::OffsetDateTime;
+
+/// An expiry notification which has been sent out already for one of the thresholds
+#[derive(Debug, Clone)]
+pub struct NotificationExpiryEntity {
+    pub typ: String,
+    pub id: String,
+    /// The expiry is part of the key, so a renewed object will be notified about again
+    pub expires: OffsetDateTime,
+    /// The threshold in days
+    pub threshold: i32,
+    pub sent: OffsetDateTime,
+}
+
+impl NotificationExpiryEntity {
+    pub fn new(typ: ExpiryObjectType, id: String, expires: OffsetDateTime, threshold: i64) -> Self {
+        Self {
+            typ: typ.as_str().to_string(),
+            id,
+            expires,
+            threshold: threshold as i32,
+            sent: OffsetDateTime::now_utc(),
+        }
+    }
+
+    /// Claims this notification. Returns `false` if it has been claimed already, which means
+    /// it must not be sent out again. This works across multiple instances.
+    pub async fn claim(&self) -> Result<bool, ErrorResponse> {
+        let res = query!(
+            r#"INSERT INTO notifications_expiry (typ, id, expires, threshold, sent)
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT (typ, id, expires, threshold) DO NOTHING"#,
+            self.typ,
+            self.id,
+            self.expires,
+            self.threshold,
+            self.sent,
+        )
+        .execute(Db::conn())
+        .await?;
+        Ok(res.rows_affected() == 1)
+    }
+
+    /// Releases a claim if the notification could not be sent, so it will be retried
+    pub async fn release(&self) -> Result<(), ErrorResponse> {
+        query!(
+            r#"DELETE FROM notifications_expiry
+            WHERE typ = $1 AND id = $2 AND expires = $3 AND threshold = $4"#,
+            self.typ,
+            self.id,
+            self.expires,
+            self.threshold,
+        )
+        .execute(Db::conn())
+        .await?;
+        Ok(())
+    }
+
+    /// Cleans up the entries for everything which has expired for longer than 30 days
+    pub async fn delete_expired() -> Result<(), ErrorResponse> {
+        query!(
+            "DELETE FROM notifications_expiry WHERE expires < $1",
+            OffsetDateTime::now_utc() - time::Duration::days(30)
+        )
+        .execute(Db::conn())
+        .await?;
+        Ok(())
+    }
+}
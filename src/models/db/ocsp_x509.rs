@@ -0,0 +1,230 @@
+use crate::certificates::encryption::{decrypt_by_kid, encrypt};
+use crate::certificates::key_storage::CaKeyStorage;
+use crate::certificates::x509::ocsp::{aia_extension, nocheck_extension, OcspSigner};
+use crate::config::{Db, EncKeys};
+use crate::constants::{OCSP_SIGNER_CACHE_LIFESPAN, OCSP_SIGNER_LIFESPAN, PUB_URL_FULL};
+use crate::models::api::error_response::ErrorResponse;
+use crate::models::db::ca_cert_x509::CaCertX509Full;
+use crate::models::db::cert_x509::CertX509Entity;
+use once_cell::sync::Lazy;
+use rcgen::{
+    CertificateParams, CustomExtension, DnType, ExtendedKeyUsagePurpose, IsCa, KeyIdMethod,
+    KeyPair, KeyUsagePurpose,
+};
+use sqlx::{query, query_as};
+use std::collections::HashMap;
+use std::ops::Add;
+use std::sync::Arc;
+use time::OffsetDateTime;
+use tokio::sync::RwLock;
+use tracing::info;
+use uuid::Uuid;
+
+type CachedSigner = (OffsetDateTime, Arc<OcspSigner>);
+
+/// Building a signer needs a few DB lookups and decryptions -> cache them for a few minutes
+static SIGNERS: Lazy<RwLock<HashMap<Uuid, CachedSigner>>> =
+    Lazy::new(|| RwLock::new(HashMap::new()));
+
+/// The OCSP config for an intermediate CA together with an optional delegated responder
+#[derive(Debug, Clone)]
+pub struct OcspX509Entity {
+    pub ca_id: Uuid,
+    /// If issued certificates should contain an Authority Information Access extension
+    pub aia_enabled: bool,
+    /// Sign responses with a delegated responder certificate instead of the CA key
+    pub delegated: bool,
+    /// The DER encoded delegated responder certificate
+    pub signer_cert: Option<Vec<u8>>,
+    /// The encrypted PEM private key of the delegated responder
+    pub signer_key: Option<Vec<u8>>,
+    pub signer_enc_key_id: Option<Uuid>,
+    pub signer_expires: Option<OffsetDateTime>,
+}
+
+// CRUD
+impl OcspX509Entity {
+    pub async fn find(ca_id: &Uuid) -> Result<Option<Self>, ErrorResponse> {
+        let res = query_as!(Self, "SELECT * FROM ocsp_x509 WHERE ca_id = $1", ca_id)
+            .fetch_optional(Db::conn())
+            .await?;
+        Ok(res)
+    }
+
+    /// Returns the defaults if nothing has been saved for this CA yet
+    pub async fn find_or_default(ca_id: &Uuid) -> Result<Self, ErrorResponse> {
+        let res = Self::find(ca_id).await?.unwrap_or(Self {
+            ca_id: *ca_id,
+            aia_enabled: true,
+            delegated: false,
+            signer_cert: None,
+            signer_key: None,
+            signer_enc_key_id: None,
+            signer_expires: None,
+        });
+        Ok(res)
+    }
+
+    pub async fn update_config(
+        ca_id: &Uuid,
+        aia_enabled: bool,
+        delegated: bool,
+    ) -> Result<Self, ErrorResponse> {
+        let res = query_as!(
+            Self,
+            r#"INSERT INTO ocsp_x509 (ca_id, aia_enabled, delegated)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (ca_id) DO UPDATE SET aia_enabled = $2, delegated = $3
+            RETURNING *"#,
+            ca_id,
+            aia_enabled,
+            delegated,
+        )
+        .fetch_one(Db::conn())
+        .await?;
+
+        SIGNERS.write().await.remove(ca_id);
+        Ok(res)
+    }
+
+    pub async fn delete(ca_id: &Uuid) -> Result<(), ErrorResponse> {
+        query!("DELETE FROM ocsp_x509 WHERE ca_id = $1", ca_id)
+            .execute(Db::conn())
+            .await?;
+
+        SIGNERS.write().await.remove(ca_id);
+        Ok(())
+    }
+
+    async fn save_signer(&self) -> Result<(), ErrorResponse> {
+        query!(
+            r#"UPDATE ocsp_x509
+            SET signer_cert = $1, signer_key = $2, signer_enc_key_id = $3, signer_expires = $4
+            WHERE ca_id = $5"#,
+            self.signer_cert,
+            self.signer_key,
+            self.signer_enc_key_id,
+            self.signer_expires,
+            self.ca_id,
+        )
+        .execute(Db::conn())
+        .await?;
+        Ok(())
+    }
+}
+
+impl OcspX509Entity {
+    /// The URL of the OCSP responder, which is the same for all CAs
+    pub fn url() -> String {
+        format!("{}/ocsp/x509", *PUB_URL_FULL)
+    }
+
+    /// The Authority Information Access extension for newly issued certificates
+    pub fn aia_extension(&self) -> Option<CustomExtension> {
+        if self.aia_enabled {
+            Some(aia_extension(&Self::url()))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the signer for OCSP responses of the given CA.
+    /// A delegated responder certificate will be issued or renewed when necessary.
+    pub async fn signer(
+        ca_id: &Uuid,
+        enc_keys: &EncKeys,
+    ) -> Result<Arc<OcspSigner>, ErrorResponse> {
+        let now = OffsetDateTime::now_utc();
+        if let Some((created, signer)) = SIGNERS.read().await.get(ca_id) {
+            if created.add(OCSP_SIGNER_CACHE_LIFESPAN) > now {
+                return Ok(signer.clone());
+            }
+        }
+
+        let mut slf = Self::find_or_default(ca_id).await?;
+        let signer = if slf.delegated {
+            slf.delegated_signer(enc_keys).await?
+        } else {
+            let ca = CaCertX509Full::build_by_id(ca_id, enc_keys).await?;
+            CaKeyStorage::parse(&ca.intermediate.key)?.ocsp_signer(&ca.intermediate.cert_pem)?
+        };
+
+        let signer = Arc::new(signer);
+        SIGNERS.write().await.insert(*ca_id, (now, signer.clone()));
+        Ok(signer)
+    }
+
+    async fn delegated_signer(&mut self, enc_keys: &EncKeys) -> Result<OcspSigner, ErrorResponse> {
+        let renew_after = OffsetDateTime::now_utc().add(OCSP_SIGNER_LIFESPAN / 2);
+        if let (Some(cert), Some(key), Some(enc_key_id), Some(expires)) = (
+            &self.signer_cert,
+            &self.signer_key,
+            &self.signer_enc_key_id,
+            &self.signer_expires,
+        ) {
+            if *expires > renew_after {
+                let (key_bytes, key_bytes_new) = decrypt_by_kid(key, enc_key_id, enc_keys).await?;
+                let key_pem = String::from_utf8(key_bytes)?;
+                let signer = OcspSigner::from_key_pem(&key_pem, Some(cert.clone()))?;
+
+                if let Some(bytes_new) = key_bytes_new {
+                    self.signer_key = Some(bytes_new);
+                    self.signer_enc_key_id = Some(enc_keys.enc_key.id);
+                    self.save_signer().await?;
+                }
+                return Ok(signer);
+            }
+        }
+
+        let ca = CaCertX509Full::build_by_id(&self.ca_id, enc_keys).await?;
+        let key_pair = KeyPair::generate(&rcgen::PKCS_ECDSA_P256_SHA256)?;
+        let key_pem = key_pair.serialize_pem();
+
+        let mut params = CertificateParams::default();
+        params
+            .distinguished_name
+            .push(DnType::CommonName, "Nioca OCSP Responder");
+        params.alg = &rcgen::PKCS_ECDSA_P256_SHA256;
+        params.key_pair = Some(key_pair);
+        params.is_ca = IsCa::ExplicitNoCa;
+        params.key_usages = vec![KeyUsagePurpose::DigitalSignature];
+        params.extended_key_usages = vec![ExtendedKeyUsagePurpose::OcspSigning];
+        params.custom_extensions = vec![nocheck_extension()];
+        params.use_authority_key_identifier_extension = true;
+        params.key_identifier_method = KeyIdMethod::Sha256;
+
+        let created = OffsetDateTime::now_utc();
+        let expires = created.add(OCSP_SIGNER_LIFESPAN);
+        let entity = CertX509Entity {
+            // Serial will be generated on the DB to have no inconsistencies
+            serial: -1,
+            id: Uuid::new_v4(),
+            created,
+            expires,
+            client_id: None,
+            user_id: None,
+            data: Vec::default(),
+            ca_id: None,
+            group_id: None,
+            subject: None,
+            sans: None,
+            fingerprint: None,
+        };
+        let (_, cert_entity, signed) = entity.sign(&ca, params, &[]).await?;
+        info!(
+            "New delegated OCSP responder certificate with serial {} for CA {}",
+            cert_entity.serial, self.ca_id
+        );
+
+        self.signer_cert = Some(signed.cert_der.clone());
+        self.signer_key = Some(encrypt(
+            key_pem.as_bytes(),
+            enc_keys.enc_key.value.as_slice(),
+        )?);
+        self.signer_enc_key_id = Some(enc_keys.enc_key.id);
+        self.signer_expires = Some(expires);
+        self.save_signer().await?;
+
+        OcspSigner::from_key_pem(&key_pem, Some(signed.cert_der))
+    }
+}
@@ -0,0 +1,439 @@
+use crate::certificates::{SshKeyAlg, X509KeyAlg, X509KeyUsages, X509KeyUsagesExt};
+use crate::config::Db;
+use crate::constants::RE_SUBJECT_NAME_OPT;
+use crate::models::api::error_response::{ErrorResponse, ErrorResponseType};
+use crate::models::api::request::{validate_vec_principal, ProfileRequest};
+use crate::models::db::client_ssh::{ClientSshEntity, SshCertType};
+use crate::models::db::client_x509::ClientX509Entity;
+use crate::models::db::user_group_access::{UserGroupAccessSsh, UserGroupAccessX509};
+use serde::{Deserialize, Serialize};
+use sqlx::{query, query_as};
+use utoipa::ToSchema;
+use uuid::Uuid;
+use validator::Validate;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ProfileType {
+    X509,
+    Ssh,
+}
+
+impl ProfileType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::X509 => "x509",
+            Self::Ssh => "ssh",
+        }
+    }
+}
+
+/// A named, reusable set of certificate values for X509 / SSH clients and user group access.
+///
+/// Profiles are resolved at each issuance, which means changes are picked up by all linked
+/// clients with their next certificate.
+#[derive(Debug, Clone)]
+pub struct ProfileEntity {
+    pub id: Uuid,
+    pub name: String,
+    pub typ: String,
+    /// The JSON encoded `X509Profile` or `SshProfile`
+    pub profile: String,
+}
+
+impl ProfileEntity {
+    pub async fn find(id: &Uuid) -> Result<Self, ErrorResponse> {
+        let res = query_as!(Self, "SELECT * FROM profiles WHERE id = $1", id)
+            .fetch_one(Db::conn())
+            .await?;
+
+        Ok(res)
+    }
+
+    pub async fn find_all() -> Result<Vec<Self>, ErrorResponse> {
+        let res = query_as!(Self, "SELECT * FROM profiles ORDER BY name")
+            .fetch_all(Db::conn())
+            .await?;
+
+        Ok(res)
+    }
+
+    pub async fn find_x509(id: &Uuid) -> Result<X509Profile, ErrorResponse> {
+        let slf = Self::find(id).await?;
+        slf.check_typ(ProfileType::X509)?;
+        Ok(serde_json::from_str(&slf.profile)?)
+    }
+
+    pub async fn find_ssh(id: &Uuid) -> Result<SshProfile, ErrorResponse> {
+        let slf = Self::find(id).await?;
+        slf.check_typ(ProfileType::Ssh)?;
+        Ok(serde_json::from_str(&slf.profile)?)
+    }
+
+    pub async fn insert(req: ProfileRequest) -> Result<Self, ErrorResponse> {
+        let id = Uuid::new_v4();
+        let (typ, profile) = Self::typ_value(&req)?;
+
+        query!(
+            "INSERT INTO profiles (id, name, typ, profile) VALUES ($1, $2, $3, $4)",
+            id,
+            req.name,
+            typ.as_str(),
+            profile,
+        )
+        .execute(Db::conn())
+        .await?;
+
+        Self::find(&id).await
+    }
+
+    /// Updates the profile. The type cannot be changed, since linked clients depend on it.
+    pub async fn update(id: &Uuid, req: ProfileRequest) -> Result<Self, ErrorResponse> {
+        let (typ, profile) = Self::typ_value(&req)?;
+        Self::find(id).await?.check_typ(typ)?;
+
+        query!(
+            "UPDATE profiles SET name = $1, profile = $2 WHERE id = $3",
+            req.name,
+            profile,
+            id,
+        )
+        .execute(Db::conn())
+        .await?;
+
+        Self::find(id).await
+    }
+
+    pub async fn delete(id: &Uuid) -> Result<(), ErrorResponse> {
+        let in_use = query!(
+            r#"SELECT
+            (SELECT count(*) FROM clients_x509 WHERE profile_id = $1)
+            + (SELECT count(*) FROM clients_ssh WHERE profile_id = $1)
+            + (SELECT count(*) FROM users_group_access WHERE profile_ssh = $1 OR profile_x509 = $1)
+            AS "count!""#,
+            id,
+        )
+        .fetch_one(Db::conn())
+        .await?
+        .count;
+        if in_use > 0 {
+            return Err(ErrorResponse::new(
+                ErrorResponseType::BadRequest,
+                format!(
+                    "Cannot delete a profile which is still linked by {} clients or users",
+                    in_use
+                ),
+            ));
+        }
+
+        query!("DELETE FROM profiles WHERE id = $1", id)
+            .execute(Db::conn())
+            .await?;
+        Ok(())
+    }
+
+    /// Makes sure a profile, which should be linked, exists and has the correct type.
+    /// Overrides are only allowed together with a profile.
+    pub async fn check_link(
+        id: Option<&Uuid>,
+        typ: ProfileType,
+        has_overrides: bool,
+    ) -> Result<(), ErrorResponse> {
+        match id {
+            Some(id) => Self::find(id).await?.check_typ(typ),
+            None if has_overrides => Err(ErrorResponse::new(
+                ErrorResponseType::BadRequest,
+                "'profileOverrides' can only be used together with a 'profileId'",
+            )),
+            None => Ok(()),
+        }
+    }
+
+    pub fn x509(&self) -> Result<Option<X509Profile>, ErrorResponse> {
+        if self.typ == ProfileType::X509.as_str() {
+            Ok(Some(serde_json::from_str(&self.profile)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub fn ssh(&self) -> Result<Option<SshProfile>, ErrorResponse> {
+        if self.typ == ProfileType::Ssh.as_str() {
+            Ok(Some(serde_json::from_str(&self.profile)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn check_typ(&self, typ: ProfileType) -> Result<(), ErrorResponse> {
+        if self.typ == typ.as_str() {
+            Ok(())
+        } else {
+            Err(ErrorResponse::new(
+                ErrorResponseType::BadRequest,
+                format!("Profile '{}' is not of type '{}'", self.name, typ.as_str()),
+            ))
+        }
+    }
+
+    fn typ_value(req: &ProfileRequest) -> Result<(ProfileType, String), ErrorResponse> {
+        match (&req.x509, &req.ssh) {
+            (Some(x509), None) => Ok((ProfileType::X509, serde_json::to_string(x509)?)),
+            (None, Some(ssh)) => Ok((ProfileType::Ssh, serde_json::to_string(ssh)?)),
+            _ => Err(ErrorResponse::new(
+                ErrorResponseType::BadRequest,
+                "Exactly one of 'x509' or 'ssh' must be given",
+            )),
+        }
+    }
+}
+
+/// The values of an X509 profile. Only the given values replace the ones of a linked client.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, Validate, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct X509Profile {
+    pub key_alg: Option<X509KeyAlg>,
+    #[validate(regex(path = "RE_SUBJECT_NAME_OPT", code = "[a-zA-Z0-9-.*\\s]+"))]
+    pub country: Option<String>,
+    #[validate(regex(path = "RE_SUBJECT_NAME_OPT", code = "[a-zA-Z0-9-.*\\s]+"))]
+    pub locality: Option<String>,
+    #[validate(regex(path = "RE_SUBJECT_NAME_OPT", code = "[a-zA-Z0-9-.*\\s]+"))]
+    pub organizational_unit: Option<String>,
+    #[validate(regex(path = "RE_SUBJECT_NAME_OPT", code = "[a-zA-Z0-9-.*\\s]+"))]
+    pub organization: Option<String>,
+    #[validate(regex(path = "RE_SUBJECT_NAME_OPT", code = "[a-zA-Z0-9-.*\\s]+"))]
+    pub state_or_province: Option<String>,
+    pub key_usage: Option<Vec<X509KeyUsages>>,
+    pub key_usage_ext: Option<Vec<X509KeyUsagesExt>>,
+    #[validate(range(min = 1))]
+    pub valid_hours: Option<i32>,
+}
+
+impl X509Profile {
+    /// Each value of the overrides wins over the one from the profile
+    pub fn merge(self, overrides: Option<Self>) -> Self {
+        let Some(o) = overrides else {
+            return self;
+        };
+        Self {
+            key_alg: o.key_alg.or(self.key_alg),
+            country: o.country.or(self.country),
+            locality: o.locality.or(self.locality),
+            organizational_unit: o.organizational_unit.or(self.organizational_unit),
+            organization: o.organization.or(self.organization),
+            state_or_province: o.state_or_province.or(self.state_or_province),
+            key_usage: o.key_usage.or(self.key_usage),
+            key_usage_ext: o.key_usage_ext.or(self.key_usage_ext),
+            valid_hours: o.valid_hours.or(self.valid_hours),
+        }
+    }
+
+    pub fn apply_client(self, client: &mut ClientX509Entity) {
+        if let Some(alg) = self.key_alg {
+            client.key_alg = alg.as_str().to_string();
+        }
+        if self.country.is_some() {
+            client.country = self.country;
+        }
+        if self.locality.is_some() {
+            client.locality = self.locality;
+        }
+        if self.organizational_unit.is_some() {
+            client.organizational_unit = self.organizational_unit;
+        }
+        if self.organization.is_some() {
+            client.organization = self.organization;
+        }
+        if self.state_or_province.is_some() {
+            client.state_or_province = self.state_or_province;
+        }
+        if let Some(usages) = self.key_usage {
+            client.key_usage = Some(usages.iter().map(|u| u.value()).collect());
+        }
+        if let Some(usages) = self.key_usage_ext {
+            client.key_usage_ext = Some(usages.iter().map(|u| u.value()).collect());
+        }
+        if let Some(hours) = self.valid_hours {
+            client.valid_hours = hours;
+        }
+    }
+
+    /// The subject values are ignored, since they are not part of the user access
+    pub fn apply_user(self, access: &mut UserGroupAccessX509) {
+        if let Some(alg) = self.key_alg {
+            access.key_alg = alg;
+        }
+        if let Some(usages) = self.key_usage {
+            access.key_usage = usages;
+        }
+        if let Some(usages) = self.key_usage_ext {
+            access.key_usage_ext = usages;
+        }
+        if let Some(hours) = self.valid_hours {
+            access.valid_hours = hours;
+        }
+    }
+}
+
+/// The values of an SSH profile. Only the given values replace the ones of a linked client.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, Validate, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SshProfile {
+    pub key_alg: Option<SshKeyAlg>,
+    pub typ: Option<SshCertType>,
+    #[validate(custom(function = "validate_vec_principal"))]
+    pub principals: Option<Vec<String>>,
+    pub force_command: Option<String>,
+    pub source_addresses: Option<Vec<String>>,
+    pub permit_x11_forwarding: Option<bool>,
+    pub permit_agent_forwarding: Option<bool>,
+    pub permit_port_forwarding: Option<bool>,
+    pub permit_pty: Option<bool>,
+    pub permit_user_rc: Option<bool>,
+    #[validate(range(min = 1))]
+    pub valid_secs: Option<i32>,
+}
+
+impl SshProfile {
+    /// Each value of the overrides wins over the one from the profile
+    pub fn merge(self, overrides: Option<Self>) -> Self {
+        let Some(o) = overrides else {
+            return self;
+        };
+        Self {
+            key_alg: o.key_alg.or(self.key_alg),
+            typ: o.typ.or(self.typ),
+            principals: o.principals.or(self.principals),
+            force_command: o.force_command.or(self.force_command),
+            source_addresses: o.source_addresses.or(self.source_addresses),
+            permit_x11_forwarding: o.permit_x11_forwarding.or(self.permit_x11_forwarding),
+            permit_agent_forwarding: o.permit_agent_forwarding.or(self.permit_agent_forwarding),
+            permit_port_forwarding: o.permit_port_forwarding.or(self.permit_port_forwarding),
+            permit_pty: o.permit_pty.or(self.permit_pty),
+            permit_user_rc: o.permit_user_rc.or(self.permit_user_rc),
+            valid_secs: o.valid_secs.or(self.valid_secs),
+        }
+    }
+
+    pub fn apply_client(self, client: &mut ClientSshEntity) {
+        self.apply_permits(
+            &mut client.permit_x11_forwarding,
+            &mut client.permit_agent_forwarding,
+            &mut client.permit_port_forwarding,
+            &mut client.permit_pty,
+            &mut client.permit_user_rc,
+        );
+        if let Some(alg) = self.key_alg {
+            client.key_alg = alg.as_str().to_string();
+        }
+        if let Some(typ) = self.typ {
+            client.typ = typ.as_str().to_string();
+        }
+        if let Some(principals) = self.principals {
+            client.principals = principals.join(",");
+        }
+        if self.force_command.is_some() {
+            client.force_command = self.force_command;
+        }
+        if let Some(addresses) = self.source_addresses {
+            client.source_addresses = Some(addresses.join(","));
+        }
+        if let Some(secs) = self.valid_secs {
+            client.valid_secs = secs;
+        }
+    }
+
+    /// The type and source addresses are ignored, since users always get user certificates
+    pub fn apply_user(self, access: &mut UserGroupAccessSsh) {
+        self.apply_permits(
+            &mut access.permit_x11_forwarding,
+            &mut access.permit_agent_forwarding,
+            &mut access.permit_port_forwarding,
+            &mut access.permit_pty,
+            &mut access.permit_user_rc,
+        );
+        if let Some(alg) = self.key_alg {
+            access.key_alg = alg;
+        }
+        if let Some(principals) = self.principals {
+            access.principals = principals;
+        }
+        if self.force_command.is_some() {
+            access.force_command = self.force_command;
+        }
+        if let Some(secs) = self.valid_secs {
+            access.valid_secs = secs;
+        }
+    }
+
+    fn apply_permits(
+        &self,
+        x11_forwarding: &mut Option<bool>,
+        agent_forwarding: &mut Option<bool>,
+        port_forwarding: &mut Option<bool>,
+        pty: &mut Option<bool>,
+        user_rc: &mut Option<bool>,
+    ) {
+        let permits = [
+            (self.permit_x11_forwarding, x11_forwarding),
+            (self.permit_agent_forwarding, agent_forwarding),
+            (self.permit_port_forwarding, port_forwarding),
+            (self.permit_pty, pty),
+            (self.permit_user_rc, user_rc),
+        ];
+        for (value, target) in permits {
+            if value.is_some() {
+                *target = value;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_profile_overrides() {
+        let profile = SshProfile {
+            key_alg: Some(SshKeyAlg::Ed25519),
+            principals: Some(vec!["admin".to_string()]),
+            permit_pty: Some(true),
+            valid_secs: Some(3600),
+            ..Default::default()
+        };
+        let overrides = SshProfile {
+            principals: Some(vec!["root".to_string(), "admin".to_string()]),
+            permit_pty: Some(false),
+            ..Default::default()
+        };
+
+        let merged = profile.clone().merge(Some(overrides));
+        assert_eq!(merged.key_alg, Some(SshKeyAlg::Ed25519));
+        assert_eq!(merged.principals.as_ref().unwrap().len(), 2);
+        assert_eq!(merged.permit_pty, Some(false));
+        assert_eq!(merged.valid_secs, Some(3600));
+        assert_eq!(profile.clone().merge(None), profile);
+
+        let mut access = UserGroupAccessSsh {
+            enabled: true,
+            key_alg: SshKeyAlg::RsaSha512,
+            principals: vec!["nobody".to_string()],
+            force_command: Some("/bin/true".to_string()),
+            permit_x11_forwarding: Some(true),
+            permit_agent_forwarding: None,
+            permit_port_forwarding: None,
+            permit_pty: Some(true),
+            permit_user_rc: None,
+            valid_secs: 60,
+        };
+        merged.apply_user(&mut access);
+        assert!(access.enabled);
+        assert_eq!(access.key_alg, SshKeyAlg::Ed25519);
+        assert_eq!(access.principals, vec!["root", "admin"]);
+        // values, which are not set in the profile, stay untouched
+        assert_eq!(access.force_command.as_deref(), Some("/bin/true"));
+        assert_eq!(access.permit_x11_forwarding, Some(true));
+        assert_eq!(access.permit_pty, Some(false));
+        assert_eq!(access.valid_secs, 3600);
+    }
+}
@@ -1,5 +1,6 @@
 use crate::config::Db;
 use crate::constants::{SESSION_TIMEOUT, SESSION_TIMEOUT_NEW};
+use crate::metrics;
 use crate::models::api::error_response::{ErrorResponse, ErrorResponseType};
 use crate::models::db::config_oidc::JwtClaimTyp;
 use crate::models::db::user::UserEntity;
@@ -53,6 +54,7 @@ impl SessionEntity {
         };
 
         slf.insert().await?;
+        metrics::session_created(true);
 
         Ok((slf, xsrf_plain))
     }
@@ -162,6 +164,7 @@ impl SessionEntity {
         };
 
         slf.insert().await?;
+        metrics::session_created(false);
 
         Ok((slf, xsrf_plain))
     }
@@ -175,6 +178,17 @@ impl SessionEntity {
         Ok(())
     }
 
+    /// Counts all authenticated sessions which have not expired yet
+    pub async fn count_active() -> Result<i64, ErrorResponse> {
+        let res = query!(
+            r#"SELECT COUNT(*) AS "count!" FROM sessions WHERE authenticated = true AND expires > $1"#,
+            OffsetDateTime::now_utc()
+        )
+        .fetch_one(Db::conn())
+        .await?;
+        Ok(res.count)
+    }
+
     pub async fn invalidate(id: Uuid) -> Result<(), ErrorResponse> {
         let now = OffsetDateTime::now_utc().sub(time::Duration::seconds(10));
         query!("UPDATE sessions SET expires = $1 WHERE id = $2", now, id)
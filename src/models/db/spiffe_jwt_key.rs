@@ -0,0 +1,95 @@
+use crate::certificates::encryption::{decrypt_by_kid, encrypt};
+use crate::certificates::spiffe::jwt_key_generate;
+use crate::config::{Db, EncKeys};
+use crate::models::api::error_response::ErrorResponse;
+use crate::util::secure_random;
+use sqlx::{query, query_as};
+use time::OffsetDateTime;
+use tracing::info;
+use uuid::Uuid;
+
+/// A key JWT-SVIDs of a group are signed with
+#[derive(Debug, Clone)]
+pub struct SpiffeJwtKeyEntity {
+    pub kid: String,
+    pub group_id: Uuid,
+    /// The encrypted PKCS#8 private key
+    pub key: Vec<u8>,
+    pub enc_key_id: Uuid,
+    /// The uncompressed EC point of the P-256 public key
+    pub public_key: Vec<u8>,
+    pub created: OffsetDateTime,
+}
+
+impl SpiffeJwtKeyEntity {
+    pub async fn find_all_for_group(group_id: &Uuid) -> Result<Vec<Self>, ErrorResponse> {
+        let res = query_as!(
+            Self,
+            "SELECT * FROM spiffe_jwt_keys WHERE group_id = $1 ORDER BY created",
+            group_id
+        )
+        .fetch_all(Db::conn())
+        .await?;
+        Ok(res)
+    }
+
+    /// Returns the newest key of the group together with the decrypted PKCS#8 private key.
+    /// A key is generated with the first JWT-SVID of a group.
+    pub async fn find_signing_key(
+        group_id: &Uuid,
+        enc_keys: &EncKeys,
+    ) -> Result<(Self, Vec<u8>), ErrorResponse> {
+        let latest = query_as!(
+            Self,
+            "SELECT * FROM spiffe_jwt_keys WHERE group_id = $1 ORDER BY created DESC LIMIT 1",
+            group_id
+        )
+        .fetch_optional(Db::conn())
+        .await?;
+
+        if let Some(mut slf) = latest {
+            let (pkcs8, key_new) = decrypt_by_kid(&slf.key, &slf.enc_key_id, enc_keys).await?;
+            if let Some(key_new) = key_new {
+                query!(
+                    "UPDATE spiffe_jwt_keys SET key = $1, enc_key_id = $2 WHERE kid = $3",
+                    key_new,
+                    enc_keys.enc_key.id,
+                    slf.kid,
+                )
+                .execute(Db::conn())
+                .await?;
+                slf.key = key_new;
+                slf.enc_key_id = enc_keys.enc_key.id;
+            }
+            return Ok((slf, pkcs8));
+        }
+
+        let (pkcs8, public_key) = jwt_key_generate()?;
+        let slf = Self {
+            kid: secure_random(32),
+            group_id: *group_id,
+            key: encrypt(&pkcs8, &enc_keys.enc_key.value)?,
+            enc_key_id: enc_keys.enc_key.id,
+            public_key,
+            created: OffsetDateTime::now_utc(),
+        };
+        query!(
+            r#"INSERT INTO spiffe_jwt_keys (kid, group_id, key, enc_key_id, public_key, created)
+            VALUES ($1, $2, $3, $4, $5, $6)"#,
+            slf.kid,
+            slf.group_id,
+            slf.key,
+            slf.enc_key_id,
+            slf.public_key,
+            slf.created,
+        )
+        .execute(Db::conn())
+        .await?;
+        info!(
+            "New JWT-SVID signing key {} for group {}",
+            slf.kid, slf.group_id
+        );
+
+        Ok((slf, pkcs8))
+    }
+}
@@ -39,13 +39,13 @@ impl UserEntity {
         Ok(slf)
     }
 
-    // pub async fn find(db: DbPool, uuid: &Uuid) -> Result<Option<Self>, ErrorResponse> {
-    //     let slf = query_as!(Self, "select * from users where id = $1", uuid)
-    //         .fetch_optional(&*db)
-    //         .await?;
-    //
-    //     Ok(slf)
-    // }
+    pub async fn find(id: &Uuid) -> Result<Self, ErrorResponse> {
+        let slf = query_as!(Self, "SELECT * FROM users WHERE id = $1", id)
+            .fetch_one(Db::conn())
+            .await?;
+
+        Ok(slf)
+    }
 
     // pub async fn find_by_email(email: &str) -> Result<Option<Self>, ErrorResponse> {
     //     let slf = query_as!(Self, "SELECT * FROM users WHERE email = $1", email)
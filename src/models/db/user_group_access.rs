@@ -1,13 +1,33 @@
 use crate::certificates::encryption::{decrypt, encrypt};
+use crate::certificates::ssh::add_permit_extensions;
+use crate::certificates::x509::csr::X509Csr;
 use crate::certificates::{SshKeyAlg, X509KeyAlg, X509KeyUsages, X509KeyUsagesExt};
 use crate::config::{Db, EncKeys};
-use crate::models::api::error_response::ErrorResponse;
+use crate::models::api::error_response::{ErrorResponse, ErrorResponseType};
 use crate::models::api::request::UsersGroupAccessRequest;
+use crate::models::api::response::{CertX509CsrResponse, SshCertificateSignedResponse};
+use crate::models::db::ca_cert_ssh::CaCertSshEntity;
+use crate::models::db::ca_cert_x509::CaCertX509Full;
+use crate::models::db::cert_ssh::CertSshEntity;
+use crate::models::db::cert_x509::CertX509Entity;
 use crate::models::db::enc_key::EncKeyEntity;
+use crate::models::db::groups::GroupEntity;
+use crate::models::db::profile::ProfileEntity;
+use crate::routes::AppStateExtract;
+use crate::util::fingerprint;
+use rand_core::OsRng;
+use rcgen::{
+    CertificateParams, DistinguishedName, DnType, ExtendedKeyUsagePurpose, IsCa, KeyIdMethod,
+    KeyUsagePurpose, SanType,
+};
 use serde::{Deserialize, Serialize};
 use sqlx::{query, query_as};
+use ssh_key::certificate::{Builder, CertType};
+use ssh_key::PublicKey;
 use std::default::Default;
 use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::info;
 use uuid::Uuid;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -16,6 +36,10 @@ pub struct UsersGroupAccessEntity {
     pub group_id: Uuid,
     pub enc_key_id: Uuid,
     pub group_access: Vec<u8>,
+    pub profile_ssh: Option<Uuid>,
+    pub profile_x509: Option<Uuid>,
+    pub x509_san_email: bool,
+    pub x509_san_upn: bool,
 }
 
 impl UsersGroupAccessEntity {
@@ -48,7 +72,16 @@ impl UsersGroupAccessEntity {
             bincode::deserialize::<UsersGroupAccess>(&dec)?
         };
 
-        Ok(group_access)
+        Ok(UsersGroupAccess {
+            access_x509: UserGroupAccessX509 {
+                san_email: self.x509_san_email,
+                san_upn: self.x509_san_upn,
+                ..group_access.access_x509
+            },
+            profile_ssh: self.profile_ssh,
+            profile_x509: self.profile_x509,
+            ..group_access
+        })
     }
 }
 
@@ -73,6 +106,12 @@ pub struct UserGroupAccessX509 {
     pub key_usage: Vec<X509KeyUsages>,
     pub key_usage_ext: Vec<X509KeyUsagesExt>,
     pub valid_hours: i32,
+    /// Adds the users email as RFC822 SAN. Stored in its own column like the profiles.
+    #[serde(skip)]
+    pub san_email: bool,
+    /// Adds the users email as Microsoft UPN otherName SAN
+    #[serde(skip)]
+    pub san_upn: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -85,6 +124,11 @@ pub struct UsersGroupAccess {
     pub secret_delete: bool,
     pub access_ssh: UserGroupAccessSsh,
     pub access_x509: UserGroupAccessX509,
+    /// Linked profiles are stored in their own columns to be able to reference them
+    #[serde(skip)]
+    pub profile_ssh: Option<Uuid>,
+    #[serde(skip)]
+    pub profile_x509: Option<Uuid>,
 }
 
 impl UsersGroupAccess {
@@ -117,10 +161,22 @@ impl UsersGroupAccess {
         Ok(())
     }
 
-    // pub async fn find_for_group(user_id: Uuid, group_id: Uuid) -> Result<Self, ErrorResponse> {
-    //     // TODO
-    //     Ok(())
-    // }
+    pub async fn find_for_group(
+        enc_keys: &EncKeys,
+        user_id: &Uuid,
+        group_id: &Uuid,
+    ) -> Result<Self, ErrorResponse> {
+        let entity = query_as!(
+            UsersGroupAccessEntity,
+            "SELECT * FROM users_group_access WHERE user_id = $1 AND group_id = $2",
+            user_id,
+            group_id,
+        )
+        .fetch_one(Db::conn())
+        .await?;
+
+        entity.find_group_access(enc_keys).await
+    }
 
     // pub async fn find_groups(user_id: Uuid) -> Result<Vec<Uuid>, ErrorResponse> {
     //     // TODO
@@ -159,10 +215,15 @@ impl UsersGroupAccess {
 
         query!(
             r#"UPDATE users_group_access
-            SET enc_key_id = $1, group_access = $2
-            WHERE user_id = $3 AND group_id = $4"#,
+            SET enc_key_id = $1, group_access = $2, profile_ssh = $3, profile_x509 = $4,
+                x509_san_email = $5, x509_san_upn = $6
+            WHERE user_id = $7 AND group_id = $8"#,
             enc_key_id,
             access_enc,
+            group_access.profile_ssh,
+            group_access.profile_x509,
+            group_access.access_x509.san_email,
+            group_access.access_x509.san_upn,
             user_id,
             group_id,
         )
@@ -184,6 +245,255 @@ impl UsersGroupAccess {
     }
 }
 
+impl UsersGroupAccess {
+    /// Applies the linked profiles on top of the access config.
+    /// Must be resolved before each issuance to pick up changes of the profiles.
+    pub async fn with_profiles(mut self) -> Result<Self, ErrorResponse> {
+        if let Some(id) = &self.profile_ssh {
+            ProfileEntity::find_ssh(id)
+                .await?
+                .apply_user(&mut self.access_ssh);
+        }
+        if let Some(id) = &self.profile_x509 {
+            ProfileEntity::find_x509(id)
+                .await?
+                .apply_user(&mut self.access_x509);
+        }
+        Ok(self)
+    }
+
+    /// Checks the SSH access and returns the unsigned user certificate for the public key
+    fn ssh_cert_builder(
+        &self,
+        group: &GroupEntity,
+        principal_name: &str,
+        pub_key: &PublicKey,
+    ) -> Result<Builder, ErrorResponse> {
+        let access = &self.access_ssh;
+        if !access.enabled {
+            return Err(ErrorResponse::new(
+                ErrorResponseType::Forbidden,
+                "SSH access is not enabled for this group".to_string(),
+            ));
+        }
+        if !group.enabled {
+            return Err(ErrorResponse::new(
+                ErrorResponseType::Forbidden,
+                "Group is disabled".to_string(),
+            ));
+        }
+        if !access.key_alg.matches_key_alg(&pub_key.algorithm()) {
+            return Err(ErrorResponse::new(
+                ErrorResponseType::BadRequest,
+                format!(
+                    "The public key algorithm does not match the configured '{}'",
+                    access.key_alg.as_str()
+                ),
+            ));
+        }
+
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let valid_after = now - 120;
+        let valid_before = now + access.valid_secs as u64;
+
+        let mut cert_builder =
+            Builder::new_with_random_nonce(&mut OsRng, pub_key, valid_after, valid_before)?;
+
+        let key_id = format!("nioca-{}-{}", group.name, principal_name);
+        cert_builder.key_id(key_id)?;
+        cert_builder.cert_type(CertType::User)?;
+
+        for p in &access.principals {
+            cert_builder.valid_principal(p)?;
+        }
+
+        if let Some(cmd) = &access.force_command {
+            cert_builder.critical_option("force-command", cmd)?;
+        }
+
+        add_permit_extensions(
+            &mut cert_builder,
+            access.permit_x11_forwarding,
+            access.permit_agent_forwarding,
+            access.permit_port_forwarding,
+            access.permit_pty,
+            access.permit_user_rc,
+        )?;
+
+        cert_builder.comment(format!("nioca-{}", principal_name))?;
+
+        Ok(cert_builder)
+    }
+
+    /// Signs the users public key with the groups SSH CA, constrained by the SSH access config.
+    pub async fn build_ssh_cert(
+        &self,
+        state: &AppStateExtract,
+        group: &GroupEntity,
+        principal_name: &str,
+        pub_key: &PublicKey,
+    ) -> Result<SshCertificateSignedResponse, ErrorResponse> {
+        let mut cert_builder = self.ssh_cert_builder(group, principal_name, pub_key)?;
+
+        let ca = CaCertSshEntity::find_by_group(&group.id).await?;
+
+        // generate a certificate without data to get a serial from the DB
+        let entity = CertSshEntity {
+            ca_id: Some(ca.id),
+            ..CertSshEntity::from(self)
+        };
+        let mut cert_entity = entity.insert().await?;
+        assert!(cert_entity.serial > 0);
+        cert_builder.serial(cert_entity.serial as u64)?;
+
+        let ca_key = {
+            let enc_keys = &state.read().await.enc_keys;
+            ca.get_signing_key(enc_keys).await?
+        };
+        let cert = cert_builder.sign(&ca_key)?;
+        let cert_openssh = cert.to_openssh()?;
+
+        cert_entity.data = cert_openssh.as_bytes().to_vec();
+        cert_entity.update_data().await?;
+
+        info!(
+            "New SSH Certificate signed for user {} in group {}",
+            self.user_id, group.name
+        );
+
+        Ok(SshCertificateSignedResponse {
+            ca_pub: ca.pub_key,
+            cert: cert_openssh,
+            not_after: cert_entity.expires.unix_timestamp(),
+        })
+    }
+
+    /// Signs the users CSR with the groups X509 CA, constrained by the X509 access config.
+    ///
+    /// The CN is always the email of the user. The only SANs a CSR may request are the email as
+    /// RFC822 or UPN SAN, if they are enabled for the access.
+    pub async fn build_x509_cert(
+        &self,
+        ca: &CaCertX509Full,
+        group: &GroupEntity,
+        email: &str,
+        csr: &X509Csr,
+    ) -> Result<CertX509CsrResponse, ErrorResponse> {
+        let access = &self.access_x509;
+        if !access.enabled {
+            return Err(ErrorResponse::new(
+                ErrorResponseType::Forbidden,
+                "X509 access is not enabled for this group".to_string(),
+            ));
+        }
+        if !group.enabled {
+            return Err(ErrorResponse::new(
+                ErrorResponseType::Forbidden,
+                "Group is disabled".to_string(),
+            ));
+        }
+        if access.key_alg != csr.key_alg {
+            return Err(ErrorResponse::new(
+                ErrorResponseType::BadRequest,
+                format!(
+                    "The CSR key algorithm does not match the configured '{}'",
+                    access.key_alg.as_str()
+                ),
+            ));
+        }
+        Self::validate_csr_names(access, email, csr)?;
+
+        let mut params = CertificateParams::default();
+        let mut sub = DistinguishedName::new();
+        sub.push(DnType::CommonName, email);
+        params.distinguished_name = sub;
+        params.is_ca = IsCa::ExplicitNoCa;
+        params.key_usages = access
+            .key_usage
+            .iter()
+            .cloned()
+            .map(KeyUsagePurpose::from)
+            .collect();
+        params.extended_key_usages = access
+            .key_usage_ext
+            .iter()
+            .cloned()
+            .map(ExtendedKeyUsagePurpose::from)
+            .collect();
+        params.use_authority_key_identifier_extension = true;
+        params.key_identifier_method = KeyIdMethod::Sha256;
+        params.alg = csr.sig_alg;
+        params.key_pair = Some(csr.key_pair()?);
+        if access.san_email {
+            params
+                .subject_alt_names
+                .push(SanType::Rfc822Name(email.to_string()));
+        }
+        let upns = if access.san_upn {
+            vec![email.to_string()]
+        } else {
+            vec![]
+        };
+
+        let (_, cert_entity, signed) = CertX509Entity::from(self).sign(ca, params, &upns).await?;
+        let cert_fingerprint = fingerprint(signed.cert_pem.as_bytes());
+
+        info!(
+            "New X509 Certificate signed for user {} in group {} with fingerprint {}",
+            self.user_id, group.name, cert_fingerprint
+        );
+
+        Ok(CertX509CsrResponse {
+            cert: signed.cert_pem,
+            cert_fingerprint,
+            cert_chain: signed.cert_chain,
+            not_after: cert_entity.expires.unix_timestamp(),
+        })
+    }
+
+    /// Users only get their email as CN and optionally as email / UPN SAN
+    fn validate_csr_names(
+        access: &UserGroupAccessX509,
+        email: &str,
+        csr: &X509Csr,
+    ) -> Result<(), ErrorResponse> {
+        if let Some(cn) = &csr.common_name {
+            if !cn.eq_ignore_ascii_case(email) {
+                return Err(ErrorResponse::new(
+                    ErrorResponseType::Forbidden,
+                    format!("CN '{}' is not allowed, it must be the users email", cn),
+                ));
+            }
+        }
+        if !csr.alt_names_dns.is_empty()
+            || !csr.alt_names_ip.is_empty()
+            || !csr.alt_names_uri.is_empty()
+        {
+            return Err(ErrorResponse::new(
+                ErrorResponseType::Forbidden,
+                "DNS, IP and URI alt names are not allowed for users",
+            ));
+        }
+
+        let checks = [
+            ("Email", &csr.alt_names_email, access.san_email),
+            ("UPN", &csr.alt_names_upn, access.san_upn),
+        ];
+        for (typ, requested, enabled) in checks {
+            for name in requested {
+                if !enabled || !name.eq_ignore_ascii_case(email) {
+                    return Err(ErrorResponse::new(
+                        ErrorResponseType::Forbidden,
+                        format!("{} alt name '{}' is not allowed for this user", typ, name),
+                    ));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
 impl Default for UsersGroupAccess {
     fn default() -> Self {
         Self {
@@ -218,7 +528,11 @@ impl Default for UsersGroupAccess {
                     X509KeyUsagesExt::EmailProtection,
                 ],
                 valid_hours: 720,
+                san_email: false,
+                san_upn: false,
             },
+            profile_ssh: None,
+            profile_x509: None,
         }
     }
 }
@@ -252,7 +566,116 @@ impl TryFrom<UsersGroupAccessRequest> for UsersGroupAccess {
                 key_usage: value.access_x509.key_usage,
                 key_usage_ext: value.access_x509.key_usage_ext,
                 valid_hours: value.access_x509.valid_hours,
+                san_email: value.access_x509.san_email,
+                san_upn: value.access_x509.san_upn,
             },
+            profile_ssh: value.profile_ssh,
+            profile_x509: value.profile_x509,
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::certificates::x509::singing::gen_ecdsa_key_pair;
+    use rcgen::Certificate;
+    use ssh_key::{Algorithm, EcdsaCurve, HashAlg, PrivateKey};
+
+    fn csr(cn: &str, sans: Vec<SanType>) -> X509Csr {
+        let mut params = CertificateParams::default();
+        params.alg = &rcgen::PKCS_ECDSA_P384_SHA384;
+        params.key_pair = Some(gen_ecdsa_key_pair().unwrap());
+        params.distinguished_name.push(DnType::CommonName, cn);
+        params.subject_alt_names = sans;
+        let pem = Certificate::from_params(params)
+            .unwrap()
+            .serialize_request_pem()
+            .unwrap();
+        X509Csr::from_pem(&pem).unwrap()
+    }
+
+    #[test]
+    fn test_user_csr_names() {
+        let email = "jdoe@example.org";
+        let mut access = UsersGroupAccess::default().access_x509;
+
+        let plain = csr(email, vec![]);
+        assert!(UsersGroupAccess::validate_csr_names(&access, email, &plain).is_ok());
+        let other_cn = csr("admin@example.org", vec![]);
+        assert!(UsersGroupAccess::validate_csr_names(&access, email, &other_cn).is_err());
+        let dns = csr(email, vec![SanType::DnsName("example.org".to_string())]);
+        assert!(UsersGroupAccess::validate_csr_names(&access, email, &dns).is_err());
+
+        let san_email = csr(email, vec![SanType::Rfc822Name(email.to_string())]);
+        assert!(UsersGroupAccess::validate_csr_names(&access, email, &san_email).is_err());
+        access.san_email = true;
+        assert!(UsersGroupAccess::validate_csr_names(&access, email, &san_email).is_ok());
+        let other_email = csr(
+            email,
+            vec![SanType::Rfc822Name("admin@example.org".to_string())],
+        );
+        assert!(UsersGroupAccess::validate_csr_names(&access, email, &other_email).is_err());
+    }
+
+    #[test]
+    fn test_user_ssh_cert() {
+        let group = |enabled: bool| GroupEntity {
+            id: Uuid::nil(),
+            name: "admins".to_string(),
+            enabled,
+            ca_ssh: None,
+            ca_x509: None,
+            ca_x509_typ: None,
+            acme_enabled: false,
+            x509_policy: None,
+            spiffe_trust_domain: None,
+        };
+        let err_typ = |res: Result<Builder, ErrorResponse>| res.err().unwrap().typ;
+        let email = "jdoe@example.org";
+        let user_key = PrivateKey::random(&mut OsRng, Algorithm::Ed25519).unwrap();
+        let pub_key = user_key.public_key();
+
+        let mut access = UsersGroupAccess::default();
+        let res = access.ssh_cert_builder(&group(true), email, pub_key);
+        assert_eq!(err_typ(res), ErrorResponseType::Forbidden);
+
+        access.access_ssh.enabled = true;
+        access.access_ssh.principals = vec!["root".to_string(), "jdoe".to_string()];
+        access.access_ssh.force_command = Some("/usr/bin/uptime".to_string());
+        let res = access.ssh_cert_builder(&group(false), email, pub_key);
+        assert_eq!(err_typ(res), ErrorResponseType::Forbidden);
+
+        let ecdsa = PrivateKey::random(
+            &mut OsRng,
+            Algorithm::Ecdsa {
+                curve: EcdsaCurve::NistP256,
+            },
+        )
+        .unwrap();
+        let res = access.ssh_cert_builder(&group(true), email, ecdsa.public_key());
+        assert_eq!(err_typ(res), ErrorResponseType::BadRequest);
+
+        let ca_key = PrivateKey::random(&mut OsRng, Algorithm::Ed25519).unwrap();
+        let cert = access
+            .ssh_cert_builder(&group(true), email, pub_key)
+            .unwrap()
+            .sign(&ca_key)
+            .unwrap();
+        cert.validate(&[ca_key.public_key().fingerprint(HashAlg::Sha256)])
+            .unwrap();
+
+        assert_eq!(cert.cert_type(), CertType::User);
+        assert_eq!(cert.key_id(), "nioca-admins-jdoe@example.org");
+        assert_eq!(cert.valid_principals(), ["root", "jdoe"]);
+        assert_eq!(cert.public_key(), pub_key.key_data());
+        assert_eq!(
+            cert.critical_options().get("force-command").unwrap(),
+            "/usr/bin/uptime"
+        );
+        // only the `permit-*` values set to `true` in the default access
+        let extensions = cert.extensions().keys().collect::<Vec<_>>();
+        assert_eq!(extensions, ["permit-pty", "permit-user-rc"]);
+        assert_eq!(cert.valid_before() - cert.valid_after(), 3600 + 120);
+    }
+}
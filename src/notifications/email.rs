@@ -0,0 +1,38 @@
+use crate::constants::{SMTP_FROM, SMTP_HOST, SMTP_PASSWORD, SMTP_PORT, SMTP_TLS, SMTP_USER};
+use crate::models::api::error_response::{ErrorResponse, ErrorResponseType};
+use lettre::message::header::ContentType;
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+
+pub async fn send(to: &str, subject: &str, text: String) -> Result<(), ErrorResponse> {
+    let msg = Message::builder()
+        .from(SMTP_FROM.parse::<Mailbox>()?)
+        .to(to.parse::<Mailbox>()?)
+        .subject(subject)
+        .header(ContentType::TEXT_PLAIN)
+        .body(text)?;
+
+    mailer()?.send(msg).await?;
+    Ok(())
+}
+
+fn mailer() -> Result<AsyncSmtpTransport<Tokio1Executor>, ErrorResponse> {
+    let host = SMTP_HOST
+        .as_deref()
+        .ok_or_else(|| ErrorResponse::new(ErrorResponseType::Internal, "SMTP_HOST is not set"))?;
+
+    let mut builder = match SMTP_TLS.as_str() {
+        "tls" => AsyncSmtpTransport::<Tokio1Executor>::relay(host)?,
+        "starttls" => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host)?,
+        _ => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(host),
+    };
+    if let Some(port) = *SMTP_PORT {
+        builder = builder.port(port);
+    }
+    if let (Some(user), Some(password)) = (SMTP_USER.as_ref(), SMTP_PASSWORD.as_ref()) {
+        builder = builder.credentials(Credentials::new(user.clone(), password.clone()));
+    }
+
+    Ok(builder.build())
+}
@@ -0,0 +1,218 @@
+use crate::constants::{NOTIFY_EMAILS, NOTIFY_WEBHOOKS, PUB_URL_FULL, SMTP_HOST};
+use crate::models::api::error_response::{ErrorResponse, ErrorResponseType};
+use serde::Serialize;
+use time::format_description::well_known::Rfc2822;
+use time::OffsetDateTime;
+use tracing::error;
+use uuid::Uuid;
+
+mod email;
+mod webhook;
+
+/// The kind of object an expiry notification is about
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum ExpiryObjectType {
+    CaX509,
+    ClientX509,
+    ClientSsh,
+    CertX509,
+    CertSsh,
+}
+
+impl ExpiryObjectType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::CaX509 => "CaX509",
+            Self::ClientX509 => "ClientX509",
+            Self::ClientSsh => "ClientSsh",
+            Self::CertX509 => "CertX509",
+            Self::CertSsh => "CertSsh",
+        }
+    }
+
+    fn description(&self) -> &'static str {
+        match self {
+            Self::CaX509 => "X509 CA",
+            Self::ClientX509 => "X509 client",
+            Self::ClientSsh => "SSH client",
+            Self::CertX509 => "X509 certificate",
+            Self::CertSsh => "SSH certificate",
+        }
+    }
+}
+
+/// An event admins should be informed about. This is the JSON payload for webhooks as well.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "camelCase")]
+pub enum Notification {
+    #[serde(rename_all = "camelCase")]
+    Expiry {
+        object_type: ExpiryObjectType,
+        /// The UUID for CAs and clients, the serial for certificates
+        id: String,
+        name: String,
+        group_id: Option<Uuid>,
+        /// Unix timestamp
+        expires: i64,
+        days_left: i64,
+    },
+    /// A certificate could not be issued for its full duration, because the issuing CA
+    /// expires earlier
+    #[serde(rename_all = "camelCase")]
+    ValidityCapped {
+        name: String,
+        /// Unix timestamp
+        requested: i64,
+        /// Unix timestamp
+        not_after: i64,
+    },
+    Test,
+}
+
+impl Notification {
+    pub fn subject(&self) -> String {
+        match self {
+            Self::Expiry {
+                object_type,
+                name,
+                days_left,
+                ..
+            } => format!(
+                "Nioca: {} '{}' expires in {} days",
+                object_type.description(),
+                name,
+                days_left
+            ),
+            Self::ValidityCapped { name, .. } => {
+                format!(
+                    "Nioca: '{}' has been issued with a shortened validity",
+                    name
+                )
+            }
+            Self::Test => "Nioca: Test Notification".to_string(),
+        }
+    }
+
+    pub fn text(&self) -> String {
+        let details = match self {
+            Self::Expiry {
+                object_type,
+                id,
+                name,
+                group_id,
+                expires,
+                ..
+            } => {
+                let group = group_id
+                    .map(|id| format!("\nGroup: {}", id))
+                    .unwrap_or_default();
+                format!(
+                    "The {} '{}' will expire soon.\n\nID: {}{}\nExpires: {}",
+                    object_type.description(),
+                    name,
+                    id,
+                    group,
+                    fmt_ts(*expires),
+                )
+            }
+            Self::ValidityCapped {
+                name,
+                requested,
+                not_after,
+            } => format!(
+                "The certificate '{}' could not be issued for its full duration, because the \
+                issuing CA expires earlier. Renew the CA to fix this.\n\nRequested: {}\nNot After: {}",
+                name,
+                fmt_ts(*requested),
+                fmt_ts(*not_after),
+            ),
+            Self::Test => "This is a test notification.".to_string(),
+        };
+        format!("{}\n\n-- \nNioca {}\n", details, *PUB_URL_FULL)
+    }
+
+    /// Sends this notification to all configured admin emails and webhooks, and additionally
+    /// to the given `owner` email, if any.
+    ///
+    /// Succeeds as long as at least one of the channels has been reached.
+    pub async fn send(&self, owner: Option<&str>) -> Result<(), ErrorResponse> {
+        let mut recipients = NOTIFY_EMAILS.clone();
+        if let Some(owner) = owner {
+            if !owner.is_empty() && !recipients.iter().any(|r| r == owner) {
+                recipients.push(owner.to_string());
+            }
+        }
+
+        let mut success = 0;
+        let mut last_err = None;
+
+        if SMTP_HOST.is_some() {
+            for rcpt in &recipients {
+                match email::send(rcpt, &self.subject(), self.text()).await {
+                    Ok(_) => success += 1,
+                    Err(err) => {
+                        error!(
+                            "Error sending notification email to {}: {}",
+                            rcpt, err.message
+                        );
+                        last_err = Some(err);
+                    }
+                }
+            }
+        }
+
+        for url in NOTIFY_WEBHOOKS.iter() {
+            match webhook::send(url, self).await {
+                Ok(_) => success += 1,
+                Err(err) => {
+                    error!(
+                        "Error sending notification to webhook {}: {}",
+                        url, err.message
+                    );
+                    last_err = Some(err);
+                }
+            }
+        }
+
+        match last_err {
+            Some(err) if success == 0 => Err(err),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Returns `true` if at least one notification channel has been configured
+pub fn is_configured() -> bool {
+    SMTP_HOST.is_some() || !NOTIFY_WEBHOOKS.is_empty()
+}
+
+/// Sends the notification in the background, if any channel has been configured. Errors are
+/// only logged.
+pub fn send_in_background(notification: Notification) {
+    if !is_configured() {
+        return;
+    }
+    tokio::spawn(async move {
+        if let Err(err) = notification.send(None).await {
+            error!("Error sending notification: {}", err.message);
+        }
+    });
+}
+
+/// Makes sure that a test notification can be sent out with the current config
+pub async fn send_test() -> Result<(), ErrorResponse> {
+    if NOTIFY_EMAILS.is_empty() && NOTIFY_WEBHOOKS.is_empty() {
+        return Err(ErrorResponse::new(
+            ErrorResponseType::BadRequest,
+            "Neither NOTIFY_EMAILS nor NOTIFY_WEBHOOKS have been configured",
+        ));
+    }
+    Notification::Test.send(None).await
+}
+
+fn fmt_ts(ts: i64) -> String {
+    OffsetDateTime::from_unix_timestamp(ts)
+        .ok()
+        .and_then(|dt| dt.format(&Rfc2822).ok())
+        .unwrap_or_else(|| ts.to_string())
+}
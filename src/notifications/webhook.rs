@@ -0,0 +1,51 @@
+use crate::constants::NOTIFY_WEBHOOK_SECRET;
+use crate::models::api::error_response::ErrorResponse;
+use crate::notifications::Notification;
+use once_cell::sync::Lazy;
+use reqwest::header::CONTENT_TYPE;
+use ring::hmac;
+use std::time::Duration;
+
+const SIGNATURE_HEADER: &str = "X-Nioca-Signature";
+
+static CLIENT: Lazy<reqwest::Client> = Lazy::new(|| {
+    reqwest::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .user_agent(format!("Nioca/{}", crate::VERSION))
+        .build()
+        .unwrap()
+});
+
+/// POSTs the notification as JSON. If a `NOTIFY_WEBHOOK_SECRET` is set, the body is signed
+/// and the signature added as `X-Nioca-Signature: sha256=<hex>`.
+pub async fn send(url: &str, notification: &Notification) -> Result<(), ErrorResponse> {
+    let body = serde_json::to_vec(notification)?;
+
+    let mut req = CLIENT.post(url).header(CONTENT_TYPE, "application/json");
+    if let Some(secret) = NOTIFY_WEBHOOK_SECRET.as_ref() {
+        req = req.header(SIGNATURE_HEADER, signature(secret.as_bytes(), &body));
+    }
+
+    req.body(body).send().await?.error_for_status()?;
+    Ok(())
+}
+
+fn signature(secret: &[u8], body: &[u8]) -> String {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, secret);
+    let tag = hmac::sign(&key, body);
+    format!("sha256={}", hex::encode(tag.as_ref()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_webhook_signature() {
+        let sig = signature(b"key", b"The quick brown fox jumps over the lazy dog");
+        assert_eq!(
+            sig,
+            "sha256=f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8"
+        );
+    }
+}
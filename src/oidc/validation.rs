@@ -1,4 +1,5 @@
 use crate::constants::{DEV_MODE, OIDC_CALLBACK_URI};
+use crate::metrics;
 use crate::models::api::error_response::{ErrorResponse, ErrorResponseType};
 use crate::models::db::config_oidc::{ConfigOidcEntity, JwtClaim};
 use crate::oidc::CacheMethod;
@@ -262,7 +263,9 @@ pub async fn init(
         while let Ok(req) = rx.recv_async().await {
             match req.method {
                 CacheMethod::Get => {
-                    if let Some(entry) = cache.cache_get(&req.key) {
+                    let entry = cache.cache_get(&req.key);
+                    metrics::oidc_token_cache(entry.is_some());
+                    if let Some(entry) = entry {
                         debug!(
                             "Found cached token validation response for key {}",
                             &req.key
@@ -0,0 +1,590 @@
+use std::collections::HashMap;
+use std::ops::Add;
+use std::str::FromStr;
+
+use axum::body::Bytes;
+use axum::extract::{Path, Request};
+use axum::http::{header, HeaderValue, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use der::pem::LineEnding;
+use time::OffsetDateTime;
+use tracing::{error, info};
+use uuid::Uuid;
+
+use crate::acme::error::{AcmeError, AcmeErrorType};
+use crate::acme::jws::{AcmeKeyRef, AcmeRequest};
+use crate::acme::models::{
+    AcmeAccountPayload, AcmeAccountResponse, AcmeAuthzResponse, AcmeChallengeResponse,
+    AcmeChallengeType, AcmeDirectory, AcmeFinalizePayload, AcmeIdentifier, AcmeNewOrderPayload,
+    AcmeOrderResponse, AcmeOrdersListResponse, AcmeStatus,
+};
+use crate::acme::validation::process_challenge;
+use crate::acme::{acme_url, b64_url_decode, is_valid_dns_name};
+use crate::certificates::x509::csr::X509Csr;
+use crate::config::Db;
+use crate::constants::ACME_ORDER_LIFESPAN;
+use crate::metrics;
+use crate::metrics::{CertType, IssueFormat};
+use crate::models::api::error_response::ErrorResponse;
+use crate::models::db::acme_account::AcmeAccountEntity;
+use crate::models::db::acme_authz::{AcmeAuthzEntity, AcmeChallengeEntity};
+use crate::models::db::acme_eab_key::AcmeEabKeyEntity;
+use crate::models::db::acme_nonce::AcmeNonceEntity;
+use crate::models::db::acme_order::AcmeOrderEntity;
+use crate::models::db::ca_cert_x509::CaCertX509Full;
+use crate::models::db::cert_x509::CertX509Entity;
+use crate::models::db::groups::GroupEntity;
+use crate::routes::AppStateExtract;
+use crate::service::audit::{AuditAction, AuditEvent};
+use crate::util::secure_random;
+
+/// The maximum amount of identifiers for a single order
+const MAX_IDENTIFIERS: usize = 100;
+
+/// Adds the `Replay-Nonce` and the directory `Link` headers to every ACME response
+pub async fn acme_headers(
+    Path(params): Path<HashMap<String, String>>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let mut resp = next.run(req).await;
+
+    match AcmeNonceEntity::create().await {
+        Ok(nonce) => {
+            if let Ok(value) = HeaderValue::from_str(&nonce) {
+                resp.headers_mut().insert("Replay-Nonce", value);
+            }
+        }
+        Err(err) => error!("Cannot create a new ACME nonce: {:?}", err),
+    }
+
+    if let Some(group_id) = params
+        .get("group_id")
+        .and_then(|id| Uuid::from_str(id).ok())
+    {
+        let link = format!("<{}>;rel=\"index\"", acme_url(&group_id, "directory"));
+        if let Ok(value) = HeaderValue::from_str(&link) {
+            resp.headers_mut().append(header::LINK, value);
+        }
+    }
+
+    resp
+}
+
+/// ACME directory for a group
+pub async fn get_directory(Path(group_id): Path<String>) -> Result<Response, AcmeError> {
+    let group = acme_group(&group_id).await?;
+    Ok(Json(AcmeDirectory::new(&group.id)).into_response())
+}
+
+/// New replay nonce - the nonce itself is added by the `acme_headers` middleware
+pub async fn head_new_nonce(Path(group_id): Path<String>) -> Result<StatusCode, AcmeError> {
+    acme_group(&group_id).await?;
+    Ok(StatusCode::OK)
+}
+
+pub async fn get_new_nonce(Path(group_id): Path<String>) -> Result<StatusCode, AcmeError> {
+    acme_group(&group_id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Creates a new account or looks up an existing one for the JWK.
+/// An account created with an external account binding is bound to the admin issued key.
+pub async fn post_new_account(
+    state: AppStateExtract,
+    Path(group_id): Path<String>,
+    body: Bytes,
+) -> Result<Response, AcmeError> {
+    let group = acme_group(&group_id).await?;
+    let req = AcmeRequest::verify(&group.id, "new-account", AcmeKeyRef::Jwk, &body).await?;
+    let payload = req.payload::<AcmeAccountPayload>()?;
+
+    let thumbprint = req.jwk.thumbprint()?;
+    if let Some(account) = AcmeAccountEntity::find_by_thumbprint(&group.id, &thumbprint).await? {
+        return Ok(account_response(StatusCode::OK, &group.id, &account));
+    }
+    if payload.only_return_existing {
+        return Err(AcmeError::new(
+            AcmeErrorType::AccountDoesNotExist,
+            "No account exists for this key",
+        ));
+    }
+
+    let eab_key = match &payload.external_account_binding {
+        Some(binding) => {
+            let enc_keys = state.read().await.enc_keys.clone();
+            let (key, hmac_key) =
+                AcmeEabKeyEntity::find_unused(&group.id, &binding.kid()?, &enc_keys).await?;
+            binding.verify(&hmac_key, &acme_url(&group.id, "new-account"), &req.jwk)?;
+            Some(key)
+        }
+        None => None,
+    };
+
+    let account = AcmeAccountEntity {
+        id: Uuid::new_v4(),
+        group_id: group.id,
+        thumbprint,
+        jwk: serde_json::to_string(&req.jwk)?,
+        contact: contact_csv(payload.contact)?,
+        status: AcmeStatus::Valid.as_str().to_string(),
+        created: OffsetDateTime::now_utc(),
+        eab_kid: eab_key.as_ref().map(|key| key.kid.clone()),
+    };
+    let mut txn = Db::txn().await?;
+    account.insert(&mut txn).await?;
+    if let Some(key) = &eab_key {
+        key.bind(&account.id, &mut txn).await?;
+    }
+    txn.commit().await.map_err(ErrorResponse::from)?;
+    match &account.eab_kid {
+        Some(kid) => info!(
+            "New ACME account {} in group {} bound with external account key {}",
+            account.id, group.id, kid
+        ),
+        None => info!("New ACME account {} in group {}", account.id, group.id),
+    }
+
+    Ok(account_response(StatusCode::CREATED, &group.id, &account))
+}
+
+/// Returns, updates or deactivates an account
+pub async fn post_account(
+    Path((group_id, id)): Path<(String, String)>,
+    body: Bytes,
+) -> Result<Response, AcmeError> {
+    let group = acme_group(&group_id).await?;
+    let path = format!("account/{}", id);
+    let req = AcmeRequest::verify(&group.id, &path, AcmeKeyRef::Kid, &body).await?;
+    let mut account = req.account()?.clone();
+    if account.id.to_string() != id {
+        return Err(forbidden());
+    }
+
+    if !req.is_post_as_get() {
+        let payload = req.payload::<AcmeAccountPayload>()?;
+        if payload.contact.is_some() {
+            account.contact = contact_csv(payload.contact)?;
+        }
+        match payload.status.as_deref() {
+            None => {}
+            Some("deactivated") => {
+                info!("Deactivating ACME account {}", account.id);
+                account.status = AcmeStatus::Deactivated.as_str().to_string();
+            }
+            Some(_) => {
+                return Err(AcmeError::new(
+                    AcmeErrorType::Malformed,
+                    "An account can only be set to 'deactivated'",
+                ))
+            }
+        }
+        account.update().await?;
+    }
+
+    Ok(account_response(StatusCode::OK, &group.id, &account))
+}
+
+/// Lists all orders of an account
+pub async fn post_account_orders(
+    Path((group_id, id)): Path<(String, String)>,
+    body: Bytes,
+) -> Result<Response, AcmeError> {
+    let group = acme_group(&group_id).await?;
+    let path = format!("account/{}/orders", id);
+    let req = AcmeRequest::verify(&group.id, &path, AcmeKeyRef::Kid, &body).await?;
+    let account = req.account()?;
+    if account.id.to_string() != id {
+        return Err(forbidden());
+    }
+
+    let orders = AcmeOrderEntity::find_all_by_account(&account.id)
+        .await?
+        .into_iter()
+        .map(|o| acme_url(&group.id, &format!("order/{}", o.id)))
+        .collect();
+    Ok(Json(AcmeOrdersListResponse { orders }).into_response())
+}
+
+/// Creates a new order with an authorization for each identifier
+pub async fn post_new_order(
+    Path(group_id): Path<String>,
+    body: Bytes,
+) -> Result<Response, AcmeError> {
+    let group = acme_group(&group_id).await?;
+    let req = AcmeRequest::verify(&group.id, "new-order", AcmeKeyRef::Kid, &body).await?;
+    let account = req.account()?;
+    let payload = req.payload::<AcmeNewOrderPayload>()?;
+
+    if payload.not_before.is_some() || payload.not_after.is_some() {
+        return Err(AcmeError::new(
+            AcmeErrorType::Malformed,
+            "'notBefore' and 'notAfter' are not supported",
+        ));
+    }
+    let identifiers = validate_identifiers(payload.identifiers)?;
+
+    let now = OffsetDateTime::now_utc();
+    let expires = now.add(ACME_ORDER_LIFESPAN);
+    let order = AcmeOrderEntity {
+        id: Uuid::new_v4(),
+        account_id: account.id,
+        status: AcmeStatus::Pending.as_str().to_string(),
+        expires,
+        identifiers: serde_json::to_string(&identifiers)?,
+        error: None,
+        cert_serial: None,
+        created: now,
+    };
+
+    let mut authz_ids = Vec::with_capacity(identifiers.len());
+    let mut txn = Db::txn().await?;
+    order.insert(&mut txn).await?;
+    for identifier in identifiers {
+        // the authorization for a wildcard is for the base domain, RFC 8555 Section 7.1.4
+        let (value, wildcard) = match identifier.value.strip_prefix("*.") {
+            Some(base) => (base.to_string(), true),
+            None => (identifier.value, false),
+        };
+        let authz = AcmeAuthzEntity {
+            id: Uuid::new_v4(),
+            order_id: order.id,
+            identifier_typ: identifier.typ,
+            identifier_value: value,
+            wildcard,
+            status: AcmeStatus::Pending.as_str().to_string(),
+            expires,
+        };
+        authz.insert(&mut txn).await?;
+
+        // a wildcard can only be proven with control over the DNS
+        let challenge_types = if wildcard {
+            vec![AcmeChallengeType::Dns01]
+        } else {
+            vec![AcmeChallengeType::Http01, AcmeChallengeType::Dns01]
+        };
+        for typ in challenge_types {
+            AcmeChallengeEntity {
+                id: Uuid::new_v4(),
+                authz_id: authz.id,
+                typ: typ.as_str().to_string(),
+                token: secure_random(43),
+                status: AcmeStatus::Pending.as_str().to_string(),
+                validated: None,
+                error: None,
+            }
+            .insert(&mut txn)
+            .await?;
+        }
+
+        authz_ids.push(authz.id);
+    }
+    txn.commit().await.map_err(ErrorResponse::from)?;
+
+    let location = acme_url(&group.id, &format!("order/{}", order.id));
+    let resp = AcmeOrderResponse::new(&group.id, &order, &authz_ids);
+    Ok(with_location(StatusCode::CREATED, location, Json(resp)))
+}
+
+/// Returns the current state of an order
+pub async fn post_order(
+    Path((group_id, id)): Path<(String, String)>,
+    body: Bytes,
+) -> Result<Response, AcmeError> {
+    let group = acme_group(&group_id).await?;
+    let path = format!("order/{}", id);
+    let req = AcmeRequest::verify(&group.id, &path, AcmeKeyRef::Kid, &body).await?;
+    let order = find_order(&id, req.account()?).await?;
+
+    order_response(&group.id, &order).await
+}
+
+/// Finalizes a `ready` order with a CSR and issues the certificate
+pub async fn post_order_finalize(
+    state: AppStateExtract,
+    Path((group_id, id)): Path<(String, String)>,
+    body: Bytes,
+) -> Result<Response, AcmeError> {
+    let group = acme_group(&group_id).await?;
+    let path = format!("order/{}/finalize", id);
+    let req = AcmeRequest::verify(&group.id, &path, AcmeKeyRef::Kid, &body).await?;
+    let mut order = find_order(&id, req.account()?).await?;
+
+    if order.status != AcmeStatus::Ready.as_str() {
+        return Err(AcmeError::new(
+            AcmeErrorType::OrderNotReady,
+            format!("The order is '{}' and not 'ready'", order.status),
+        ));
+    }
+    if order.expires < OffsetDateTime::now_utc() {
+        order.status = AcmeStatus::Invalid.as_str().to_string();
+        order.update().await?;
+        return Err(AcmeError::new(
+            AcmeErrorType::OrderNotReady,
+            "The order has expired",
+        ));
+    }
+
+    let payload = req.payload::<AcmeFinalizePayload>()?;
+    let csr = b64_url_decode(&payload.csr)
+        .map_err(AcmeError::from)
+        .and_then(|der| X509Csr::from_der(&der).map_err(AcmeError::from))
+        .map_err(|err| AcmeError::new(AcmeErrorType::BadCsr, err.detail))?;
+
+    let ca_id = group.ca_x509.ok_or_else(|| {
+        AcmeError::new(
+            AcmeErrorType::ServerInternal,
+            "No X509 CA configured for this group",
+        )
+    })?;
+    let enc_keys = state.read().await.enc_keys.clone();
+    let ca = CaCertX509Full::build_by_id(&ca_id, &enc_keys).await?;
+
+    if !order.start_processing().await? {
+        return Err(AcmeError::new(
+            AcmeErrorType::OrderNotReady,
+            "The order is being finalized already",
+        ));
+    }
+
+    match order.build_cert(&ca, &group.id, &csr).await {
+        Ok((cert_entity, _)) => {
+            order.status = AcmeStatus::Valid.as_str().to_string();
+            order.cert_serial = Some(cert_entity.serial);
+            order.update().await?;
+            metrics::cert_issued(CertType::X509, IssueFormat::Acme, &group.id, Some(&ca_id));
+            AuditEvent::new(
+                format!("acme:{}", order.account_id),
+                AuditAction::CertX509Issue,
+            )
+            .target(cert_entity.serial)
+            .details(format!("ACME order {}, group {}", order.id, group.name))
+            .log()
+            .await?;
+        }
+        Err(err) => {
+            metrics::cert_issue_failed(CertType::X509, &err);
+            // a bad CSR leaves the order untouched and the client may try again
+            order.status = AcmeStatus::Ready.as_str().to_string();
+            order.update().await?;
+            return Err(AcmeError::new(AcmeErrorType::BadCsr, err.message));
+        }
+    }
+
+    order_response(&group.id, &order).await
+}
+
+/// Returns an authorization with its challenges
+pub async fn post_authz(
+    Path((group_id, id)): Path<(String, String)>,
+    body: Bytes,
+) -> Result<Response, AcmeError> {
+    let group = acme_group(&group_id).await?;
+    let path = format!("authz/{}", id);
+    let req = AcmeRequest::verify(&group.id, &path, AcmeKeyRef::Kid, &body).await?;
+    let authz = find_authz(&id, req.account()?).await?;
+
+    let challenges = AcmeChallengeEntity::find_by_authz(&authz.id).await?;
+    Ok(Json(AcmeAuthzResponse::new(&group.id, &authz, &challenges)).into_response())
+}
+
+/// Returns a challenge or triggers its validation, if the request is not a POST-as-GET
+pub async fn post_challenge(
+    Path((group_id, id)): Path<(String, String)>,
+    body: Bytes,
+) -> Result<Response, AcmeError> {
+    let group = acme_group(&group_id).await?;
+    let path = format!("chall/{}", id);
+    let req = AcmeRequest::verify(&group.id, &path, AcmeKeyRef::Kid, &body).await?;
+    let account = req.account()?;
+
+    let mut challenge = AcmeChallengeEntity::find(&parse_id(&id)?).await?;
+    let authz = find_authz(&challenge.authz_id.to_string(), account).await?;
+
+    if !req.is_post_as_get()
+        && challenge.status == AcmeStatus::Pending.as_str()
+        && authz.status == AcmeStatus::Pending.as_str()
+    {
+        challenge.status = AcmeStatus::Processing.as_str().to_string();
+        challenge.update().await?;
+        tokio::spawn(process_challenge(
+            challenge.clone(),
+            authz.clone(),
+            account.clone(),
+        ));
+    }
+
+    let mut resp = Json(AcmeChallengeResponse::new(&group.id, &challenge)).into_response();
+    let link = format!(
+        "<{}>;rel=\"up\"",
+        acme_url(&group.id, &format!("authz/{}", authz.id))
+    );
+    if let Ok(value) = HeaderValue::from_str(&link) {
+        resp.headers_mut().append(header::LINK, value);
+    }
+    Ok(resp)
+}
+
+/// Downloads the certificate chain for a `valid` order
+pub async fn post_cert(
+    state: AppStateExtract,
+    Path((group_id, id)): Path<(String, String)>,
+    body: Bytes,
+) -> Result<Response, AcmeError> {
+    let group = acme_group(&group_id).await?;
+    let path = format!("cert/{}", id);
+    let req = AcmeRequest::verify(&group.id, &path, AcmeKeyRef::Kid, &body).await?;
+    let order = find_order(&id, req.account()?).await?;
+
+    let serial = match order.cert_serial {
+        Some(serial) if order.status == AcmeStatus::Valid.as_str() => serial,
+        _ => {
+            return Err(AcmeError::new(
+                AcmeErrorType::Malformed,
+                "No certificate has been issued for this order",
+            ))
+        }
+    };
+    let cert = CertX509Entity::find_by_serial(serial).await?;
+    let cert_pem = der::pem::encode_string("CERTIFICATE", LineEnding::LF, &cert.data)
+        .map_err(|err| AcmeError::new(AcmeErrorType::ServerInternal, err.to_string()))?;
+
+    let ca_id = group.ca_x509.ok_or_else(|| {
+        AcmeError::new(
+            AcmeErrorType::ServerInternal,
+            "No X509 CA configured for this group",
+        )
+    })?;
+    let enc_keys = state.read().await.enc_keys.clone();
+    let ca = CaCertX509Full::build_by_id(&ca_id, &enc_keys).await?;
+
+    Ok((
+        [(header::CONTENT_TYPE, "application/pem-certificate-chain")],
+        format!("{}{}", cert_pem, ca.ca_chain_pem),
+    )
+        .into_response())
+}
+
+/// Returns the group, if it exists and has ACME enabled
+async fn acme_group(group_id: &str) -> Result<GroupEntity, AcmeError> {
+    let not_found = || AcmeError::new(AcmeErrorType::Malformed, "ACME directory does not exist");
+
+    let id = Uuid::from_str(group_id).map_err(|_| not_found())?;
+    let group = GroupEntity::find_by_id(&id)
+        .await
+        .map_err(|_| not_found())?;
+    if !group.enabled || !group.acme_enabled {
+        return Err(not_found());
+    }
+    Ok(group)
+}
+
+async fn find_order(id: &str, account: &AcmeAccountEntity) -> Result<AcmeOrderEntity, AcmeError> {
+    let order = AcmeOrderEntity::find(&parse_id(id)?).await?;
+    if order.account_id != account.id {
+        return Err(forbidden());
+    }
+    Ok(order)
+}
+
+async fn find_authz(id: &str, account: &AcmeAccountEntity) -> Result<AcmeAuthzEntity, AcmeError> {
+    let authz = AcmeAuthzEntity::find(&parse_id(id)?).await?;
+    find_order(&authz.order_id.to_string(), account).await?;
+    Ok(authz)
+}
+
+async fn order_response(group_id: &Uuid, order: &AcmeOrderEntity) -> Result<Response, AcmeError> {
+    let authz_ids = AcmeAuthzEntity::find_by_order(&order.id)
+        .await?
+        .into_iter()
+        .map(|a| a.id)
+        .collect::<Vec<_>>();
+    let location = acme_url(group_id, &format!("order/{}", order.id));
+    let resp = AcmeOrderResponse::new(group_id, order, &authz_ids);
+    Ok(with_location(StatusCode::OK, location, Json(resp)))
+}
+
+fn account_response(status: StatusCode, group_id: &Uuid, account: &AcmeAccountEntity) -> Response {
+    let location = acme_url(group_id, &format!("account/{}", account.id));
+    let resp = AcmeAccountResponse::new(group_id, account);
+    with_location(status, location, Json(resp))
+}
+
+fn with_location(status: StatusCode, location: String, body: impl IntoResponse) -> Response {
+    (status, [(header::LOCATION, location)], body).into_response()
+}
+
+/// Only `mailto:` contacts are supported
+fn contact_csv(contact: Option<Vec<String>>) -> Result<Option<String>, AcmeError> {
+    let contact = match contact {
+        None => return Ok(None),
+        Some(c) if c.is_empty() => return Ok(None),
+        Some(c) => c,
+    };
+    for c in &contact {
+        if !c.starts_with("mailto:") || c.contains(',') {
+            return Err(AcmeError::new(
+                AcmeErrorType::Malformed,
+                format!("Unsupported contact '{}'", c),
+            ));
+        }
+    }
+    Ok(Some(contact.join(",")))
+}
+
+/// Validates and de-duplicates the identifiers for a new order
+fn validate_identifiers(
+    identifiers: Vec<AcmeIdentifier>,
+) -> Result<Vec<AcmeIdentifier>, AcmeError> {
+    if identifiers.is_empty() || identifiers.len() > MAX_IDENTIFIERS {
+        return Err(AcmeError::new(
+            AcmeErrorType::Malformed,
+            format!(
+                "An order needs between 1 and {} identifiers",
+                MAX_IDENTIFIERS
+            ),
+        ));
+    }
+
+    let mut res: Vec<AcmeIdentifier> = Vec::with_capacity(identifiers.len());
+    for identifier in identifiers {
+        if identifier.typ != "dns" {
+            return Err(AcmeError::new(
+                AcmeErrorType::UnsupportedIdentifier,
+                format!("Unsupported identifier type '{}'", identifier.typ),
+            ));
+        }
+
+        let value = identifier.value.to_lowercase();
+        // wildcards are only allowed as the complete left-most label below at least a 2nd level
+        let name = value.strip_prefix("*.").unwrap_or(&value);
+        if !is_valid_dns_name(name) || (name.len() != value.len() && !name.contains('.')) {
+            return Err(AcmeError::new(
+                AcmeErrorType::RejectedIdentifier,
+                format!("Invalid DNS name '{}'", identifier.value),
+            ));
+        }
+
+        if !res.iter().any(|i| i.value == value) {
+            res.push(AcmeIdentifier {
+                typ: identifier.typ,
+                value,
+            });
+        }
+    }
+
+    Ok(res)
+}
+
+fn parse_id(id: &str) -> Result<Uuid, AcmeError> {
+    Uuid::from_str(id).map_err(|_| AcmeError::new(AcmeErrorType::Malformed, "Invalid ID"))
+}
+
+fn forbidden() -> AcmeError {
+    AcmeError::new(
+        AcmeErrorType::Unauthorized,
+        "The resource does not belong to this account",
+    )
+}
@@ -0,0 +1,60 @@
+use crate::models::api::error_response::ErrorResponse;
+use crate::models::api::principal::Principal;
+use crate::models::api::request::AuditQuery;
+use crate::models::api::response::{AuditLogResponse, AuditVerifyResponse};
+use crate::models::db::audit_log::AuditLogEntity;
+use crate::service::audit;
+use axum::extract::Query;
+use axum::Json;
+use validator::Validate;
+
+/// Search the audit log, newest first
+#[utoipa::path(
+    get,
+    tag = "audit",
+    path = "/api/audit",
+    params(AuditQuery),
+    responses(
+        (status = 200, description = "Ok", body = AuditLogResponse),
+        (status = 400, description = "BadRequest", body = ErrorResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+    ),
+)]
+pub async fn get_audit(
+    principal: Principal,
+    Query(params): Query<AuditQuery>,
+) -> Result<Json<AuditLogResponse>, ErrorResponse> {
+    principal.is_admin()?;
+    params.validate()?;
+
+    let entries = AuditLogEntity::find_filtered(&params).await?;
+    let next_cursor = if entries.len() as i64 == params.limit() {
+        entries.last().map(|e| e.id)
+    } else {
+        None
+    };
+
+    Ok(Json(AuditLogResponse {
+        entries: entries.into_iter().map(|e| e.into()).collect(),
+        next_cursor,
+    }))
+}
+
+/// Verifies the whole audit log chain
+///
+/// Checks that no entry is missing, that each one links to the previous one, that no values
+/// have been modified and that all MACs are valid. Keep the returned `headHash` somewhere
+/// outside of Nioca to be able to detect the removal of the latest entries later on.
+#[utoipa::path(
+    get,
+    tag = "audit",
+    path = "/api/audit/verify",
+    responses(
+        (status = 200, description = "Ok", body = AuditVerifyResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+    ),
+)]
+pub async fn get_verify(principal: Principal) -> Result<Json<AuditVerifyResponse>, ErrorResponse> {
+    principal.is_admin()?;
+    Ok(Json(audit::verify().await?))
+}
@@ -0,0 +1,106 @@
+use crate::models::api::error_response::{ErrorResponse, ErrorResponseType};
+use crate::models::api::principal::Principal;
+use crate::models::api::request::BackupRestoreRequest;
+use crate::models::api::response::BackupRestoreResponse;
+use crate::routes::AppStateExtract;
+use crate::service::audit::{AuditAction, AuditEvent};
+use crate::service::backup;
+use crate::service::backup::BackupArchive;
+use axum::Json;
+use validator::Validate;
+
+/// Creates a new backup archive of all tables
+///
+/// The archive stays encrypted with the current enc key and is signed by the Nioca intermediate
+/// CA. It can only be restored with the master key shares.
+#[utoipa::path(
+    post,
+    tag = "backup",
+    path = "/api/backup",
+    responses(
+        (status = 200, description = "Ok", body = BackupArchive),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+    ),
+)]
+pub async fn post_backup(
+    state: AppStateExtract,
+    principal: Principal,
+) -> Result<Json<BackupArchive>, ErrorResponse> {
+    principal.is_admin()?;
+
+    let (enc_keys, nioca_cert) = {
+        let lock = state.read().await;
+        (lock.enc_keys.clone(), lock.nioca_cert.clone())
+    };
+    let archive = backup::create(&enc_keys, &nioca_cert).await?;
+    AuditEvent::new(principal.name(), AuditAction::BackupCreate)
+        .target(&archive.checksum)
+        .log()
+        .await?;
+
+    Ok(Json(archive))
+}
+
+/// Validates a backup archive and adds missing rows to the given tables
+///
+/// The archive must use the same master key as this instance. A full restore must be done with
+/// `nioca restore` while the server is stopped.
+#[utoipa::path(
+    post,
+    tag = "backup",
+    path = "/api/backup/restore",
+    request_body = BackupRestoreRequest,
+    responses(
+        (status = 200, description = "Ok", body = BackupRestoreResponse),
+        (status = 400, description = "BadRequest", body = ErrorResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+    ),
+)]
+pub async fn post_restore(
+    principal: Principal,
+    Json(payload): Json<BackupRestoreRequest>,
+) -> Result<Json<BackupRestoreResponse>, ErrorResponse> {
+    principal.is_admin()?;
+    payload.validate()?;
+    let dry_run = payload.dry_run.unwrap_or(false);
+    if !dry_run && payload.tables.is_empty() {
+        return Err(ErrorResponse::new(
+            ErrorResponseType::BadRequest,
+            "A full restore must be done with 'nioca restore' while the server is stopped",
+        ));
+    }
+
+    let contents = match backup::validate(&payload.archive, &payload.master_shares).await {
+        Ok(contents) => contents,
+        Err(err) => {
+            AuditEvent::new(principal.name(), AuditAction::BackupRestore)
+                .target(&payload.archive.checksum)
+                .details(&err.message)
+                .failed()
+                .log()
+                .await?;
+            return Err(err);
+        }
+    };
+    let rows = contents.row_counts();
+    if dry_run {
+        return Ok(Json(BackupRestoreResponse {
+            rows,
+            restored: None,
+        }));
+    }
+
+    let restored = backup::restore(&contents, &payload.tables).await?;
+    AuditEvent::new(principal.name(), AuditAction::BackupRestore)
+        .target(&payload.archive.checksum)
+        .details(payload.tables.join(", "))
+        .log()
+        .await?;
+
+    Ok(Json(BackupRestoreResponse {
+        rows,
+        restored: Some(restored),
+    }))
+}
@@ -1,20 +1,32 @@
+use crate::certificates::key_storage::CaKeyStorage;
 use crate::certificates::x509::verification::{x509_der_from_bytes, x509_pem_from_bytes};
 use crate::models::api::error_response::{ErrorResponse, ErrorResponseType};
 use crate::models::api::principal::Principal;
-use crate::models::api::request::{ExternalSshKeyRequest, GenerateSshKeyRequest, X509CaAddRequest};
+use crate::models::api::request::{
+    ExternalSshKeyRequest, GenerateSshKeyRequest, Pkcs11KeyRequest, X509CaAddRequest,
+    X509CaCertRequest, X509CaCsrRequest, X509CrlConfigRequest, X509OcspConfigRequest,
+};
 use crate::models::api::response::{
     CaCertSshResponse, CasSshResponse, CasX509Response, CertificateInspectResponse,
-    X509CertificatesOptInspectResponse,
+    X509CaCsrResponse, X509CaCsrsResponse, X509CertificatesOptInspectResponse, X509CrlResponse,
+    X509OcspResponse,
 };
 use crate::models::db::ca_cert_ssh::{CaCertSshEntity, SshKeyPairOpenssh};
-use crate::models::db::ca_cert_x509::{CaCertX509Entity, CaCertX509Type};
+use crate::models::db::ca_cert_x509::{CaCertX509Entity, CaCertX509Nioca, CaCertX509Type};
+use crate::models::db::cert_ssh_revoked::CertSshRevokedEntity;
+use crate::models::db::crl_x509::CrlX509Entity;
 use crate::models::db::groups::GroupEntity;
+use crate::models::db::ocsp_x509::OcspX509Entity;
 use crate::routes::AppStateExtract;
 use crate::service;
+use crate::service::audit::{AuditAction, AuditEvent};
 use axum::extract::Path;
+use axum::http::header;
+use axum::response::IntoResponse;
 use axum::Json;
 use std::collections::HashMap;
 use std::str::FromStr;
+use time::OffsetDateTime;
 use uuid::Uuid;
 use validator::Validate;
 use x509_parser::nom::AsBytes;
@@ -78,7 +90,22 @@ pub async fn post_generate_ca_ssh(
         name
     };
 
-    let entity = CaCertSshEntity::generate_new(ca_name, payload.alg, &enc_key).await?;
+    let pkcs11 = payload.pkcs11.unwrap_or(false);
+    let entity = if pkcs11 {
+        CaCertSshEntity::generate_new_pkcs11(ca_name, payload.alg, &enc_key).await?
+    } else {
+        CaCertSshEntity::generate_new(ca_name, payload.alg, &enc_key).await?
+    };
+    let details = if pkcs11 {
+        format!("{} (pkcs11)", entity.name)
+    } else {
+        entity.name.clone()
+    };
+    AuditEvent::new(principal.name(), AuditAction::CaSshCreate)
+        .target(entity.id)
+        .details(details)
+        .log()
+        .await?;
     let resp = CaCertSshResponse::from(entity);
     Ok(Json(resp))
 }
@@ -125,6 +152,11 @@ pub async fn post_external_ca_ssh(
     };
 
     let entity = CaCertSshEntity::insert(ca_name, kp, &enc_key).await?;
+    AuditEvent::new(principal.name(), AuditAction::CaSshCreate)
+        .target(entity.id)
+        .details(format!("{} (external)", entity.name))
+        .log()
+        .await?;
     let resp = CaCertSshResponse::from(entity);
     Ok(Json(resp))
 }
@@ -146,9 +178,56 @@ pub async fn delete_ca_ssh(
     principal.is_admin()?;
     let id = Uuid::from_str(&id)?;
     CaCertSshEntity::delete_by_id(&id).await?;
+    AuditEvent::new(principal.name(), AuditAction::CaSshDelete)
+        .target(id)
+        .log()
+        .await?;
     Ok(())
 }
 
+/// Move the private key of an SSH CA into the PKCS#11 token
+///
+/// Imports the current key as non-extractable, or links a key which already exists inside the
+/// token with `key_id`. Afterwards, Nioca only keeps a reference to the key.
+#[utoipa::path(
+post,
+tag = "ca",
+path = "/api/ca/ssh/:id/pkcs11",
+request_body = Pkcs11KeyRequest,
+responses(
+(status = 200, description = "Ok"),
+(status = 400, description = "BadRequest", body = ErrorResponse),
+(status = 401, description = "Unauthorized", body = ErrorResponse),
+(status = 404, description = "NotFound", body = ErrorResponse),
+),
+)]
+pub async fn post_ca_ssh_pkcs11(
+    state: AppStateExtract,
+    principal: Principal,
+    Path(id): Path<String>,
+    Json(payload): Json<Pkcs11KeyRequest>,
+) -> Result<(), ErrorResponse> {
+    principal.is_admin()?;
+    payload.validate()?;
+    let id = Uuid::from_str(&id)?;
+    let key_id = payload.key_id.map(hex::decode).transpose()?;
+
+    let enc_keys = state.read().await.enc_keys.clone();
+    let mut ca = CaCertSshEntity::find_by_id(&id).await?;
+    let storage = ca
+        .get_key_storage(&enc_keys)
+        .await?
+        .ssh_to_pkcs11(&ca.name, &ca.pub_key, key_id)
+        .await?;
+    ca.update_key(&storage, &enc_keys.enc_key).await?;
+
+    AuditEvent::new(principal.name(), AuditAction::CaSshKeyPkcs11)
+        .target(id)
+        .details(storage.value())
+        .log()
+        .await
+}
+
 /// Get the X509 intermediate CA's
 #[utoipa::path(
 get,
@@ -279,7 +358,109 @@ pub async fn post_ca_x509(
 ) -> Result<(), ErrorResponse> {
     principal.is_admin()?;
     payload.validate()?;
-    service::x509::add_x509_ca(&state.0, payload).await
+
+    let name = payload.name.clone();
+    service::x509::add_x509_ca(&state.0, payload).await?;
+    AuditEvent::new(principal.name(), AuditAction::CaX509Create)
+        .details(name)
+        .log()
+        .await
+}
+
+/// Get all X509 CAs, which still wait for their signed certificate
+#[utoipa::path(
+get,
+tag = "ca",
+path = "/api/ca/x509/csr",
+responses(
+(status = 200, description = "Ok", body = X509CaCsrsResponse),
+(status = 401, description = "Unauthorized", body = ErrorResponse),
+),
+)]
+pub async fn get_ca_x509_csr(
+    principal: Principal,
+) -> Result<Json<X509CaCsrsResponse>, ErrorResponse> {
+    principal.is_admin()?;
+
+    let csrs = CaCertX509Entity::find_all_by_type(CaCertX509Type::Csr)
+        .await?
+        .drain(..)
+        .map(|entity| X509CaCsrResponse {
+            id: entity.id,
+            name: entity.name,
+            csr_pem: entity.data,
+        })
+        .collect();
+    Ok(Json(X509CaCsrsResponse { csrs }))
+}
+
+/// Generate the key for a new X509 Intermediate CA and return its CSR
+///
+/// The CSR must be signed with the offline root via `nioca x509 --stage sign-intermediate`.
+/// The CA can be used after the signed certificate has been added with
+/// `POST /api/ca/x509/:id/cert`.
+#[utoipa::path(
+post,
+tag = "ca",
+path = "/api/ca/x509/csr",
+request_body = X509CaCsrRequest,
+responses(
+(status = 200, description = "Ok", body = X509CaCsrResponse),
+(status = 400, description = "BadRequest", body = ErrorResponse),
+(status = 401, description = "Unauthorized", body = ErrorResponse),
+),
+)]
+pub async fn post_ca_x509_csr(
+    state: AppStateExtract,
+    principal: Principal,
+    Json(payload): Json<X509CaCsrRequest>,
+) -> Result<Json<X509CaCsrResponse>, ErrorResponse> {
+    principal.is_admin()?;
+    payload.validate()?;
+
+    let pkcs11 = payload.pkcs11.unwrap_or(false);
+    let resp = service::x509::add_x509_ca_csr(&state.0, payload).await?;
+    let details = if pkcs11 {
+        format!("{} (pkcs11)", resp.name)
+    } else {
+        resp.name.clone()
+    };
+    AuditEvent::new(principal.name(), AuditAction::CaX509CsrCreate)
+        .target(resp.id)
+        .details(details)
+        .log()
+        .await?;
+    Ok(Json(resp))
+}
+
+/// Add the certificate signed from the CSR to a pending X509 Intermediate CA
+#[utoipa::path(
+post,
+tag = "ca",
+path = "/api/ca/x509/:id/cert",
+request_body = X509CaCertRequest,
+responses(
+(status = 200, description = "Ok"),
+(status = 400, description = "BadRequest", body = ErrorResponse),
+(status = 401, description = "Unauthorized", body = ErrorResponse),
+(status = 404, description = "NotFound", body = ErrorResponse),
+),
+)]
+pub async fn post_ca_x509_cert(
+    state: AppStateExtract,
+    principal: Principal,
+    Path(id): Path<String>,
+    Json(payload): Json<X509CaCertRequest>,
+) -> Result<(), ErrorResponse> {
+    principal.is_admin()?;
+    let id = Uuid::from_str(&id)?;
+
+    let name = service::x509::add_x509_ca_cert(&state.0, id, payload).await?;
+    AuditEvent::new(principal.name(), AuditAction::CaX509Create)
+        .target(id)
+        .details(name)
+        .log()
+        .await
 }
 
 /// Deletes an unused X509 CA
@@ -299,5 +480,252 @@ pub async fn delete_ca_x509(
     principal.is_admin()?;
     let id = Uuid::from_str(&id)?;
     CaCertX509Entity::delete_by_id(&id).await?;
+    CrlX509Entity::delete(&id).await?;
+    OcspX509Entity::delete(&id).await?;
+    AuditEvent::new(principal.name(), AuditAction::CaX509Delete)
+        .target(id)
+        .log()
+        .await?;
     Ok(())
 }
+
+/// Move the private key of an X509 intermediate CA into the PKCS#11 token
+///
+/// Imports the current key as non-extractable, or links a key which already exists inside the
+/// token with `key_id`. Afterwards, Nioca only keeps a reference to the key.
+#[utoipa::path(
+post,
+tag = "ca",
+path = "/api/ca/x509/:id/pkcs11",
+request_body = Pkcs11KeyRequest,
+responses(
+(status = 200, description = "Ok"),
+(status = 400, description = "BadRequest", body = ErrorResponse),
+(status = 401, description = "Unauthorized", body = ErrorResponse),
+(status = 404, description = "NotFound", body = ErrorResponse),
+),
+)]
+pub async fn post_ca_x509_pkcs11(
+    state: AppStateExtract,
+    principal: Principal,
+    Path(id): Path<String>,
+    Json(payload): Json<Pkcs11KeyRequest>,
+) -> Result<(), ErrorResponse> {
+    principal.is_admin()?;
+    payload.validate()?;
+    let id = Uuid::from_str(&id)?;
+    let key_id = payload.key_id.map(hex::decode).transpose()?;
+
+    let enc_keys = state.read().await.enc_keys.clone();
+    let entity = CaCertX509Entity::find_by_id(&id, CaCertX509Type::Certificate).await?;
+    let ca = CaCertX509Nioca::find(&id, &enc_keys).await?;
+    let storage = CaKeyStorage::parse(&ca.key)?
+        .x509_to_pkcs11(&entity.name, &ca.cert_pem, key_id)
+        .await?;
+    CaCertX509Nioca::update_key(&id, &storage, &enc_keys).await?;
+
+    // the default CA is kept in memory as well
+    {
+        let mut lock = state.write().await;
+        if lock.nioca_cert.id == id {
+            lock.nioca_cert.key = storage.value();
+        }
+    }
+
+    AuditEvent::new(principal.name(), AuditAction::CaX509KeyPkcs11)
+        .target(id)
+        .details(storage.value())
+        .log()
+        .await
+}
+
+/// Get the CRL distribution config and status for an X509 CA
+#[utoipa::path(
+get,
+tag = "ca",
+path = "/api/ca/x509/:id/crl",
+responses(
+(status = 200, description = "Ok", body = X509CrlResponse),
+(status = 401, description = "Unauthorized", body = ErrorResponse),
+(status = 404, description = "NotFound", body = ErrorResponse),
+),
+)]
+pub async fn get_ca_x509_crl(
+    principal: Principal,
+    Path(id): Path<String>,
+) -> Result<Json<X509CrlResponse>, ErrorResponse> {
+    principal.is_admin()?;
+    let id = Uuid::from_str(&id)?;
+    CaCertX509Entity::find_by_id(&id, CaCertX509Type::Certificate).await?;
+
+    let crl = CrlX509Entity::find_or_default(&id).await?;
+    Ok(Json(X509CrlResponse::from(crl)))
+}
+
+/// Update the CRL distribution config for an X509 CA
+///
+/// Only affects certificates issued afterwards.
+#[utoipa::path(
+put,
+tag = "ca",
+path = "/api/ca/x509/:id/crl",
+request_body = X509CrlConfigRequest,
+responses(
+(status = 200, description = "Ok", body = X509CrlResponse),
+(status = 400, description = "BadRequest", body = ErrorResponse),
+(status = 401, description = "Unauthorized", body = ErrorResponse),
+(status = 404, description = "NotFound", body = ErrorResponse),
+),
+)]
+pub async fn put_ca_x509_crl(
+    principal: Principal,
+    Path(id): Path<String>,
+    Json(payload): Json<X509CrlConfigRequest>,
+) -> Result<Json<X509CrlResponse>, ErrorResponse> {
+    principal.is_admin()?;
+    payload.validate()?;
+    let id = Uuid::from_str(&id)?;
+    CaCertX509Entity::find_by_id(&id, CaCertX509Type::Certificate).await?;
+
+    let crl = CrlX509Entity::update_config(&id, payload.cdp_enabled, payload.cdp_url).await?;
+    AuditEvent::new(principal.name(), AuditAction::CaX509CrlConfig)
+        .target(id)
+        .details(format!("cdp_enabled: {}", crl.cdp_enabled))
+        .log()
+        .await?;
+    Ok(Json(X509CrlResponse::from(crl)))
+}
+
+/// Get the OCSP config for an X509 CA
+#[utoipa::path(
+get,
+tag = "ca",
+path = "/api/ca/x509/:id/ocsp",
+responses(
+(status = 200, description = "Ok", body = X509OcspResponse),
+(status = 401, description = "Unauthorized", body = ErrorResponse),
+(status = 404, description = "NotFound", body = ErrorResponse),
+),
+)]
+pub async fn get_ca_x509_ocsp(
+    principal: Principal,
+    Path(id): Path<String>,
+) -> Result<Json<X509OcspResponse>, ErrorResponse> {
+    principal.is_admin()?;
+    let id = Uuid::from_str(&id)?;
+    CaCertX509Entity::find_by_id(&id, CaCertX509Type::Certificate).await?;
+
+    let ocsp = OcspX509Entity::find_or_default(&id).await?;
+    Ok(Json(X509OcspResponse::from(ocsp)))
+}
+
+/// Update the OCSP config for an X509 CA
+///
+/// The AIA extension only affects certificates issued afterwards.
+#[utoipa::path(
+put,
+tag = "ca",
+path = "/api/ca/x509/:id/ocsp",
+request_body = X509OcspConfigRequest,
+responses(
+(status = 200, description = "Ok", body = X509OcspResponse),
+(status = 401, description = "Unauthorized", body = ErrorResponse),
+(status = 404, description = "NotFound", body = ErrorResponse),
+),
+)]
+pub async fn put_ca_x509_ocsp(
+    principal: Principal,
+    Path(id): Path<String>,
+    Json(payload): Json<X509OcspConfigRequest>,
+) -> Result<Json<X509OcspResponse>, ErrorResponse> {
+    principal.is_admin()?;
+    let id = Uuid::from_str(&id)?;
+    CaCertX509Entity::find_by_id(&id, CaCertX509Type::Certificate).await?;
+
+    let ocsp = OcspX509Entity::update_config(&id, payload.aia_enabled, payload.delegated).await?;
+    AuditEvent::new(principal.name(), AuditAction::CaX509OcspConfig)
+        .target(id)
+        .details(format!(
+            "aia_enabled: {}, delegated: {}",
+            ocsp.aia_enabled, ocsp.delegated
+        ))
+        .log()
+        .await?;
+    Ok(Json(X509OcspResponse::from(ocsp)))
+}
+
+/// The DER encoded CRL of an X509 CA
+#[utoipa::path(
+get,
+tag = "common",
+path = "/crl/x509/:id",
+responses(
+(status = 200, description = "Ok", content_type = "application/pkix-crl"),
+(status = 404, description = "NotFound", body = ErrorResponse),
+),
+)]
+pub async fn get_crl_der(
+    state: AppStateExtract,
+    Path(id): Path<String>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    let crl = current_crl(&state, &id).await?;
+    Ok((
+        [(header::CONTENT_TYPE, "application/pkix-crl")],
+        crl.data.unwrap_or_default(),
+    ))
+}
+
+/// The PEM encoded CRL of an X509 CA
+#[utoipa::path(
+get,
+tag = "common",
+path = "/crl/x509/:id/pem",
+responses(
+(status = 200, description = "Ok", content_type = "application/x-pem-file"),
+(status = 404, description = "NotFound", body = ErrorResponse),
+),
+)]
+pub async fn get_crl_pem(
+    state: AppStateExtract,
+    Path(id): Path<String>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    let crl = current_crl(&state, &id).await?;
+    Ok((
+        [(header::CONTENT_TYPE, "application/x-pem-file")],
+        crl.pem()?,
+    ))
+}
+
+/// The binary OpenSSH Key Revocation List of an SSH CA, usable for `RevokedKeys` in `sshd_config`
+#[utoipa::path(
+get,
+tag = "common",
+path = "/krl/ssh/:id",
+responses(
+(status = 200, description = "Ok", content_type = "application/octet-stream"),
+(status = 404, description = "NotFound", body = ErrorResponse),
+),
+)]
+pub async fn get_krl(Path(id): Path<String>) -> Result<impl IntoResponse, ErrorResponse> {
+    let id = Uuid::from_str(&id)?;
+    let ca = CaCertSshEntity::find_by_id(&id).await?;
+    let krl = CertSshRevokedEntity::build_krl(&ca).await?;
+    Ok(([(header::CONTENT_TYPE, "application/octet-stream")], krl))
+}
+
+/// Returns the saved CRL or generates a new one, if none exists yet or the scheduler is behind
+pub(crate) async fn current_crl(
+    state: &AppStateExtract,
+    id: &str,
+) -> Result<CrlX509Entity, ErrorResponse> {
+    let id = Uuid::from_str(id)?;
+    let crl = CrlX509Entity::find_or_default(&id).await?;
+
+    let now = OffsetDateTime::now_utc();
+    if crl.data.is_some() && crl.next_update.map(|next| next > now).unwrap_or(false) {
+        return Ok(crl);
+    }
+
+    let enc_keys = state.read().await.enc_keys.clone();
+    CrlX509Entity::rebuild_by_ca_id(&id, &enc_keys).await
+}
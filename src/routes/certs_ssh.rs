@@ -0,0 +1,212 @@
+use crate::models::api::error_response::ErrorResponse;
+use crate::models::api::principal::Principal;
+use crate::models::api::request::{CertsQuery, SshRevokeKeyIdRequest, SshRevokePublicKeyRequest};
+use crate::models::api::response::{
+    CertSshDetailsResponse, CertSshInventoryResponse, CertsSshResponse, SshCertRevokedResponse,
+    SshKeyIdRevokedResponse, SshKeyRevokedResponse, SshRevokedResponse,
+};
+use crate::models::db::cert_ssh::CertSshEntity;
+use crate::models::db::cert_ssh_revoked::{
+    CertSshRevokedEntity, SshRevokedKeyEntity, SshRevokedKeyIdEntity,
+};
+use crate::service::audit::{AuditAction, AuditEvent};
+use axum::extract::{Path, Query};
+use axum::Json;
+use tracing::info;
+use validator::Validate;
+
+/// Search issued SSH certificates, newest first
+#[utoipa::path(
+    get,
+    tag = "certs",
+    path = "/api/certs/ssh",
+    params(CertsQuery),
+    responses(
+        (status = 200, description = "Ok", body = CertsSshResponse),
+        (status = 400, description = "BadRequest", body = ErrorResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+    ),
+)]
+pub async fn get_certs(
+    principal: Principal,
+    Query(params): Query<CertsQuery>,
+) -> Result<Json<CertsSshResponse>, ErrorResponse> {
+    principal.is_admin()?;
+    params.validate()?;
+
+    let certs = CertSshEntity::find_filtered(&params).await?;
+    let next_cursor = params.next_cursor(certs.len(), certs.last().map(|c| c.serial));
+
+    let serials = certs.iter().map(|c| c.serial).collect::<Vec<_>>();
+    let revoked = CertSshRevokedEntity::find_revoked_at(&serials).await?;
+
+    let certs = certs
+        .into_iter()
+        .map(|c| {
+            let revoked = revoked.get(&c.serial).copied();
+            CertSshInventoryResponse::new(c, revoked)
+        })
+        .collect();
+    Ok(Json(CertsSshResponse { certs, next_cursor }))
+}
+
+/// Get a single issued SSH certificate with its decoded details
+#[utoipa::path(
+    get,
+    tag = "certs",
+    path = "/api/certs/ssh/:serial",
+    responses(
+        (status = 200, description = "Ok", body = CertSshDetailsResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 404, description = "NotFound", body = ErrorResponse),
+    ),
+)]
+pub async fn get_cert(
+    Path(serial): Path<i32>,
+    principal: Principal,
+) -> Result<Json<CertSshDetailsResponse>, ErrorResponse> {
+    principal.is_admin()?;
+
+    let cert = CertSshEntity::find_by_serial(serial).await?;
+    let revoked = CertSshRevokedEntity::find(serial).await?;
+    let revoked_at = CertSshRevokedEntity::find_revoked_at(&[serial])
+        .await?
+        .remove(&serial);
+    Ok(Json(CertSshDetailsResponse::build(
+        cert, revoked, revoked_at,
+    )?))
+}
+
+/// Get all revoked SSH certificates, key IDs and public keys
+#[utoipa::path(
+    get,
+    tag = "certs",
+    path = "/api/certs/ssh/revoked",
+    responses(
+        (status = 200, description = "Ok", body = SshRevokedResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+    ),
+)]
+pub async fn get_revoked(principal: Principal) -> Result<Json<SshRevokedResponse>, ErrorResponse> {
+    principal.is_admin()?;
+
+    let certs = CertSshRevokedEntity::find_all()
+        .await?
+        .into_iter()
+        .map(SshCertRevokedResponse::from)
+        .collect();
+    let key_ids = SshRevokedKeyIdEntity::find_all()
+        .await?
+        .into_iter()
+        .map(SshKeyIdRevokedResponse::from)
+        .collect();
+    let public_keys = SshRevokedKeyEntity::find_all()
+        .await?
+        .into_iter()
+        .map(SshKeyRevokedResponse::from)
+        .collect();
+
+    Ok(Json(SshRevokedResponse {
+        certs,
+        key_ids,
+        public_keys,
+    }))
+}
+
+/// Revoke a single SSH certificate by its serial
+#[utoipa::path(
+    post,
+    tag = "certs",
+    path = "/api/certs/ssh/:serial/revoke",
+    responses(
+        (status = 200, description = "Ok", body = SshCertRevokedResponse),
+        (status = 400, description = "BadRequest", body = ErrorResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 404, description = "NotFound", body = ErrorResponse),
+    ),
+)]
+pub async fn post_revoke_serial(
+    Path(serial): Path<i32>,
+    principal: Principal,
+) -> Result<Json<SshCertRevokedResponse>, ErrorResponse> {
+    principal.is_admin()?;
+
+    let revoked = CertSshRevokedEntity::revoke_serial(serial, principal.name()).await?;
+    info!("SSH certificate {} revoked by {}", serial, principal.name());
+    AuditEvent::new(principal.name(), AuditAction::CertSshRevoke)
+        .target(serial)
+        .log()
+        .await?;
+
+    Ok(Json(SshCertRevokedResponse::from(revoked)))
+}
+
+/// Revoke all SSH certificates of a CA with the given key ID
+#[utoipa::path(
+    post,
+    tag = "certs",
+    path = "/api/certs/ssh/revoke_key_id",
+    request_body = SshRevokeKeyIdRequest,
+    responses(
+        (status = 200, description = "Ok", body = SshKeyIdRevokedResponse),
+        (status = 400, description = "BadRequest", body = ErrorResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 404, description = "NotFound", body = ErrorResponse),
+    ),
+)]
+pub async fn post_revoke_key_id(
+    principal: Principal,
+    Json(payload): Json<SshRevokeKeyIdRequest>,
+) -> Result<Json<SshKeyIdRevokedResponse>, ErrorResponse> {
+    principal.is_admin()?;
+    payload.validate()?;
+
+    let revoked =
+        SshRevokedKeyIdEntity::revoke(&payload.ca_id, payload.key_id, principal.name()).await?;
+    info!(
+        "SSH key ID '{}' for CA {} revoked by {}",
+        revoked.key_id,
+        revoked.ca_id,
+        principal.name()
+    );
+    AuditEvent::new(principal.name(), AuditAction::SshKeyIdRevoke)
+        .target(revoked.ca_id)
+        .details(format!("key id: {}", revoked.key_id))
+        .log()
+        .await?;
+
+    Ok(Json(SshKeyIdRevokedResponse::from(revoked)))
+}
+
+/// Revoke an SSH public key together with all certificates for it
+#[utoipa::path(
+    post,
+    tag = "certs",
+    path = "/api/certs/ssh/revoke_public_key",
+    request_body = SshRevokePublicKeyRequest,
+    responses(
+        (status = 200, description = "Ok", body = SshKeyRevokedResponse),
+        (status = 400, description = "BadRequest", body = ErrorResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+    ),
+)]
+pub async fn post_revoke_public_key(
+    principal: Principal,
+    Json(payload): Json<SshRevokePublicKeyRequest>,
+) -> Result<Json<SshKeyRevokedResponse>, ErrorResponse> {
+    principal.is_admin()?;
+    payload.validate()?;
+
+    let revoked = SshRevokedKeyEntity::revoke(&payload.public_key, principal.name()).await?;
+    info!(
+        "SSH public key {} revoked by {}",
+        revoked.fingerprint,
+        principal.name()
+    );
+    AuditEvent::new(principal.name(), AuditAction::SshPublicKeyRevoke)
+        .target(&revoked.fingerprint)
+        .log()
+        .await?;
+
+    Ok(Json(SshKeyRevokedResponse::from(revoked)))
+}
@@ -0,0 +1,284 @@
+use crate::models::api::error_response::{ErrorResponse, ErrorResponseType};
+use crate::models::api::principal::Principal;
+use crate::models::api::request::{CertsQuery, X509RevokeRequest};
+use crate::models::api::response::{
+    CertX509DetailsResponse, CertX509InventoryResponse, CertsX509Response, X509RevokedResponse,
+};
+use crate::models::db::cert_x509::CertX509Entity;
+use crate::models::db::cert_x509_revoked::CertX509RevokedEntity;
+use crate::models::db::client_x509::ClientX509Entity;
+use crate::models::db::crl_x509::CrlX509Entity;
+use crate::models::db::user::UserEntity;
+use crate::routes::AppStateExtract;
+use crate::service::audit::{AuditAction, AuditEvent};
+use axum::extract::{Path, Query};
+use axum::Json;
+use std::collections::HashMap;
+use std::str::FromStr;
+use time::OffsetDateTime;
+use tracing::info;
+use uuid::Uuid;
+use validator::Validate;
+
+/// Search issued x509 certificates, newest first
+#[utoipa::path(
+    get,
+    tag = "certs",
+    path = "/api/certs/x509",
+    params(CertsQuery),
+    responses(
+        (status = 200, description = "Ok", body = CertsX509Response),
+        (status = 400, description = "BadRequest", body = ErrorResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+    ),
+)]
+pub async fn get_certs(
+    principal: Principal,
+    Query(params): Query<CertsQuery>,
+) -> Result<Json<CertsX509Response>, ErrorResponse> {
+    principal.is_admin()?;
+    params.validate()?;
+
+    let certs = CertX509Entity::find_filtered(&params).await?;
+    let next_cursor = params.next_cursor(certs.len(), certs.last().map(|c| c.serial));
+
+    let serials = certs.iter().map(|c| c.serial).collect::<Vec<_>>();
+    let revoked = CertX509RevokedEntity::find_by_serials(&serials)
+        .await?
+        .into_iter()
+        .map(|r| (r.serial, r))
+        .collect::<HashMap<_, _>>();
+
+    let certs = certs
+        .into_iter()
+        .map(|c| {
+            let revoked = revoked.get(&c.serial);
+            CertX509InventoryResponse::new(c, revoked)
+        })
+        .collect();
+    Ok(Json(CertsX509Response { certs, next_cursor }))
+}
+
+/// Get a single issued x509 certificate with its decoded details
+#[utoipa::path(
+    get,
+    tag = "certs",
+    path = "/api/certs/x509/:serial",
+    responses(
+        (status = 200, description = "Ok", body = CertX509DetailsResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 404, description = "NotFound", body = ErrorResponse),
+    ),
+)]
+pub async fn get_cert(
+    Path(serial): Path<i32>,
+    principal: Principal,
+) -> Result<Json<CertX509DetailsResponse>, ErrorResponse> {
+    principal.is_admin()?;
+
+    let cert = CertX509Entity::find_by_serial(serial).await?;
+    let revoked = CertX509RevokedEntity::find(serial).await?;
+    Ok(Json(CertX509DetailsResponse::build(cert, revoked)?))
+}
+
+/// Get all revoked x509 certificates
+#[utoipa::path(
+    get,
+    tag = "certs",
+    path = "/api/certs/x509/revoked",
+    responses(
+        (status = 200, description = "Ok", body = Vec<X509RevokedResponse>),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+    ),
+)]
+pub async fn get_revoked(
+    principal: Principal,
+) -> Result<Json<Vec<X509RevokedResponse>>, ErrorResponse> {
+    principal.is_admin()?;
+
+    let res = CertX509RevokedEntity::find_all()
+        .await?
+        .into_iter()
+        .map(X509RevokedResponse::from)
+        .collect();
+    Ok(Json(res))
+}
+
+/// Revoke a single x509 certificate by its serial
+#[utoipa::path(
+    post,
+    tag = "certs",
+    path = "/api/certs/x509/:serial/revoke",
+    request_body = X509RevokeRequest,
+    responses(
+        (status = 200, description = "Ok", body = X509RevokedResponse),
+        (status = 400, description = "BadRequest", body = ErrorResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 404, description = "NotFound", body = ErrorResponse),
+    ),
+)]
+pub async fn post_revoke_serial(
+    state: AppStateExtract,
+    Path(serial): Path<i32>,
+    principal: Principal,
+    Json(payload): Json<X509RevokeRequest>,
+) -> Result<Json<X509RevokedResponse>, ErrorResponse> {
+    principal.is_admin()?;
+
+    let invalidity_date = invalidity_date(&payload)?;
+    let revoked = CertX509RevokedEntity::revoke_serial(
+        serial,
+        &payload.reason,
+        invalidity_date,
+        principal.name(),
+    )
+    .await?;
+    info!(
+        "x509 certificate {} revoked by {}: {:?}",
+        serial,
+        principal.name(),
+        payload.reason
+    );
+    AuditEvent::new(principal.name(), AuditAction::CertX509Revoke)
+        .target(serial)
+        .details(format!("{:?}", payload.reason))
+        .log()
+        .await?;
+
+    let enc_keys = state.read().await.enc_keys.clone();
+    CrlX509Entity::rebuild_for_serials(&[revoked.serial], &enc_keys).await;
+
+    Ok(Json(X509RevokedResponse::from(revoked)))
+}
+
+/// Revoke all valid certificates of an x509 client
+#[utoipa::path(
+    post,
+    tag = "certs",
+    path = "/api/clients/x509/:id/revoke",
+    request_body = X509RevokeRequest,
+    responses(
+        (status = 200, description = "Ok", body = Vec<X509RevokedResponse>),
+        (status = 400, description = "BadRequest", body = ErrorResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 404, description = "NotFound", body = ErrorResponse),
+    ),
+)]
+pub async fn post_revoke_client(
+    state: AppStateExtract,
+    Path(id): Path<String>,
+    principal: Principal,
+    Json(payload): Json<X509RevokeRequest>,
+) -> Result<Json<Vec<X509RevokedResponse>>, ErrorResponse> {
+    principal.is_admin()?;
+
+    let uuid = Uuid::from_str(&id)?;
+    let client = ClientX509Entity::find(&uuid).await?;
+
+    let invalidity_date = invalidity_date(&payload)?;
+    let revoked = CertX509RevokedEntity::revoke_client(
+        &client.id,
+        &payload.reason,
+        invalidity_date,
+        principal.name(),
+    )
+    .await?;
+    info!(
+        "{} x509 certificates for client {} revoked by {}: {:?}",
+        revoked.len(),
+        client.id,
+        principal.name(),
+        payload.reason
+    );
+    AuditEvent::new(principal.name(), AuditAction::CertX509Revoke)
+        .target(client.id)
+        .details(format!(
+            "{} certificates of client: {:?}",
+            revoked.len(),
+            payload.reason
+        ))
+        .log()
+        .await?;
+
+    let serials = revoked.iter().map(|r| r.serial).collect::<Vec<_>>();
+    let enc_keys = state.read().await.enc_keys.clone();
+    CrlX509Entity::rebuild_for_serials(&serials, &enc_keys).await;
+
+    let res = revoked.into_iter().map(X509RevokedResponse::from).collect();
+    Ok(Json(res))
+}
+
+/// Revoke all valid x509 certificates of a user
+#[utoipa::path(
+    post,
+    tag = "certs",
+    path = "/api/users/:id/x509/revoke",
+    request_body = X509RevokeRequest,
+    responses(
+        (status = 200, description = "Ok", body = Vec<X509RevokedResponse>),
+        (status = 400, description = "BadRequest", body = ErrorResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 404, description = "NotFound", body = ErrorResponse),
+    ),
+)]
+pub async fn post_revoke_user(
+    state: AppStateExtract,
+    Path(id): Path<String>,
+    principal: Principal,
+    Json(payload): Json<X509RevokeRequest>,
+) -> Result<Json<Vec<X509RevokedResponse>>, ErrorResponse> {
+    principal.is_admin()?;
+
+    let uuid = Uuid::from_str(&id)?;
+    let user = UserEntity::find(&uuid).await?;
+
+    let invalidity_date = invalidity_date(&payload)?;
+    let revoked = CertX509RevokedEntity::revoke_user(
+        &user.id,
+        &payload.reason,
+        invalidity_date,
+        principal.name(),
+    )
+    .await?;
+    info!(
+        "{} x509 certificates for user {} revoked by {}: {:?}",
+        revoked.len(),
+        user.email,
+        principal.name(),
+        payload.reason
+    );
+    AuditEvent::new(principal.name(), AuditAction::CertX509Revoke)
+        .target(user.id)
+        .details(format!(
+            "{} certificates of user: {:?}",
+            revoked.len(),
+            payload.reason
+        ))
+        .log()
+        .await?;
+
+    let serials = revoked.iter().map(|r| r.serial).collect::<Vec<_>>();
+    let enc_keys = state.read().await.enc_keys.clone();
+    CrlX509Entity::rebuild_for_serials(&serials, &enc_keys).await;
+
+    let res = revoked.into_iter().map(X509RevokedResponse::from).collect();
+    Ok(Json(res))
+}
+
+fn invalidity_date(payload: &X509RevokeRequest) -> Result<Option<OffsetDateTime>, ErrorResponse> {
+    let ts = match payload.invalidity_date {
+        None => return Ok(None),
+        Some(ts) => ts,
+    };
+
+    let date = OffsetDateTime::from_unix_timestamp(ts).map_err(|_| {
+        ErrorResponse::new(ErrorResponseType::BadRequest, "Invalid 'invalidityDate'")
+    })?;
+    if date > OffsetDateTime::now_utc() {
+        return Err(ErrorResponse::new(
+            ErrorResponseType::BadRequest,
+            "'invalidityDate' cannot be in the future",
+        ));
+    }
+    Ok(Some(date))
+}
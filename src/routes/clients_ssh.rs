@@ -1,16 +1,22 @@
+use crate::metrics;
+use crate::metrics::{CertType, IssueFormat};
 use crate::models::api::error_response::ErrorResponse;
 use crate::models::api::principal::Principal;
-use crate::models::api::request::ClientSshRequest;
+use crate::models::api::request::{ClientSshRequest, SshPublicKeyRequest};
 use crate::models::api::response::{
-    ClientSecretResponse, ClientSshResponse, SshCertificateResponse,
+    ClientSecretResponse, ClientSshResponse, SshCertificateResponse, SshCertificateSignedResponse,
 };
 use crate::models::db::client_ssh::ClientSshEntity;
+use crate::models::db::groups::GroupEntity;
 use crate::routes::AppStateExtract;
+use crate::service::audit;
+use crate::service::audit::{AuditAction, AuditEvent};
 use axum::extract::Path;
 use axum::Json;
 use axum_extra::{headers, TypedHeader};
 use headers::authorization::Bearer;
 use headers::Authorization;
+use ssh_key::{HashAlg, PublicKey};
 use std::str::FromStr;
 use uuid::Uuid;
 use validator::Validate;
@@ -60,6 +66,11 @@ pub async fn post_client(
 
     let enc_key = state.read().await.enc_keys.enc_key.clone();
     let client = ClientSshEntity::create(payload, &enc_key).await?;
+    AuditEvent::new(principal.name(), AuditAction::ClientSshCreate)
+        .target(client.id)
+        .details(&client.name)
+        .log()
+        .await?;
 
     Ok(Json(ClientSshResponse::from(client)))
 }
@@ -109,6 +120,11 @@ pub async fn put_client(
 
     let uuid = Uuid::from_str(&id)?;
     let client = ClientSshEntity::update(&uuid, payload).await?;
+    AuditEvent::new(principal.name(), AuditAction::ClientSshUpdate)
+        .target(client.id)
+        .details(&client.name)
+        .log()
+        .await?;
 
     let resp = ClientSshResponse::from(client);
     Ok(Json(resp))
@@ -132,6 +148,10 @@ pub async fn delete_client(
 
     let uuid = Uuid::from_str(&id)?;
     ClientSshEntity::delete(&uuid).await?;
+    AuditEvent::new(principal.name(), AuditAction::ClientSshDelete)
+        .target(uuid)
+        .log()
+        .await?;
 
     Ok(())
 }
@@ -157,10 +177,75 @@ pub async fn post_build_client_cert(
     let uuid = Uuid::from_str(&id)?;
     let client = ClientSshEntity::find(&uuid).await?;
 
-    let group = client
-        .validate_active_enabled(&state, api_key.token())
+    let group = validate_client(&client, &state, api_key.token()).await?;
+    let client = client.with_profile().await?;
+    let resp = client
+        .build_cert(&state, &group)
+        .await
+        .inspect_err(|err| metrics::cert_issue_failed(CertType::Ssh, err))?;
+    metrics::cert_issued(
+        CertType::Ssh,
+        IssueFormat::KeyPair,
+        &group.id,
+        group.ca_ssh.as_ref(),
+    );
+    AuditEvent::new(audit::client_actor(&client.id), AuditAction::CertSshIssue)
+        .target(client.id)
+        .log()
+        .await?;
+    Ok(Json(resp))
+}
+
+/// Sign an existing SSH public key for an SSH client
+///
+/// Requests the clients API key given as `Bearer` token in the `Authorization` header.
+/// The public key algorithm must match the clients configured `keyAlg`. The private key never
+/// leaves the client and only the signed certificate is returned.
+#[utoipa::path(
+    post,
+    tag = "clients",
+    path = "/api/clients/ssh/:id/sign",
+    request_body = SshPublicKeyRequest,
+    responses(
+        (status = 200, description = "Ok", body = SshCertificateSignedResponse),
+        (status = 400, description = "BadRequest", body = ErrorResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+    ),
+)]
+pub async fn post_sign_client_pub_key(
+    state: AppStateExtract,
+    TypedHeader(api_key): TypedHeader<Authorization<Bearer>>,
+    Path(id): Path<String>,
+    Json(payload): Json<SshPublicKeyRequest>,
+) -> Result<Json<SshCertificateSignedResponse>, ErrorResponse> {
+    payload.validate()?;
+
+    let uuid = Uuid::from_str(&id)?;
+    let client = ClientSshEntity::find(&uuid).await?;
+
+    let group = validate_client(&client, &state, api_key.token()).await?;
+    let client = client.with_profile().await?;
+    let pub_key = PublicKey::from_openssh(payload.pub_key.trim())
+        .map_err(ErrorResponse::from)
+        .inspect_err(|err| metrics::cert_issue_failed(CertType::Ssh, err))?;
+    let resp = client
+        .build_cert_from_pub_key(&state, &group, &pub_key)
+        .await
+        .inspect_err(|err| metrics::cert_issue_failed(CertType::Ssh, err))?;
+    metrics::cert_issued(
+        CertType::Ssh,
+        IssueFormat::PublicKey,
+        &group.id,
+        group.ca_ssh.as_ref(),
+    );
+    AuditEvent::new(audit::client_actor(&client.id), AuditAction::CertSshIssue)
+        .target(client.id)
+        .details(format!(
+            "public key: {}",
+            pub_key.fingerprint(HashAlg::Sha256)
+        ))
+        .log()
         .await?;
-    let resp = client.build_cert(&state, &group).await?;
     Ok(Json(resp))
 }
 
@@ -185,6 +270,10 @@ pub async fn get_client_secret(
 
     let uuid = Uuid::from_str(&id)?;
     let secret = ClientSshEntity::find_secret(&uuid, &enc_keys).await?;
+    AuditEvent::new(principal.name(), AuditAction::ClientSshSecretRead)
+        .target(uuid)
+        .log()
+        .await?;
     let resp = ClientSecretResponse { secret };
     Ok(Json(resp))
 }
@@ -209,7 +298,32 @@ pub async fn put_client_secret(
     let enc_keys = state.read().await.enc_keys.clone();
     let uuid = Uuid::from_str(&id)?;
     let secret = ClientSshEntity::new_secret(&uuid, &enc_keys).await?;
+    AuditEvent::new(principal.name(), AuditAction::ClientSshSecretRotate)
+        .target(uuid)
+        .log()
+        .await?;
 
     let resp = ClientSecretResponse { secret };
     Ok(Json(resp))
 }
+
+/// Validates the API key and logs failed attempts
+async fn validate_client(
+    client: &ClientSshEntity,
+    state: &AppStateExtract,
+    api_key: &str,
+) -> Result<GroupEntity, ErrorResponse> {
+    match client.validate_active_enabled(state, api_key).await {
+        Ok(group) => Ok(group),
+        Err(err) => {
+            metrics::cert_issue_failed(CertType::Ssh, &err);
+            AuditEvent::new(audit::client_actor(&client.id), AuditAction::CertSshIssue)
+                .target(client.id)
+                .details(&err.message)
+                .failed()
+                .log()
+                .await?;
+            Err(err)
+        }
+    }
+}
@@ -1,12 +1,20 @@
+use crate::certificates::x509::csr::X509Csr;
 use crate::certificates::CertFormat;
 use crate::constants::HEADER_OCTET_STREAM;
+use crate::metrics;
+use crate::metrics::{CertType, IssueFormat};
 use crate::models::api::error_response::ErrorResponse;
 use crate::models::api::principal::Principal;
-use crate::models::api::request::ClientX509Request;
-use crate::models::api::response::{CertX509Response, ClientSecretResponse, ClientX509Response};
+use crate::models::api::request::{ClientX509Request, JwtSvidRequest, X509CsrRequest};
+use crate::models::api::response::{
+    CertX509CsrResponse, CertX509Response, ClientSecretResponse, ClientX509Response,
+    JwtSvidResponse,
+};
 use crate::models::db::ca_cert_x509::CaCertX509Full;
 use crate::models::db::client_x509::{ClientX509Entity, ClientX509EntityCert};
 use crate::routes::AppStateExtract;
+use crate::service::audit;
+use crate::service::audit::{AuditAction, AuditEvent};
 use axum::extract::Path;
 use axum::response::{IntoResponse, Response};
 use axum::Json;
@@ -62,6 +70,11 @@ pub async fn post_client(
 
     let enc_key = state.read().await.enc_keys.enc_key.clone();
     let client = ClientX509Entity::create(payload, &enc_key).await?;
+    AuditEvent::new(principal.name(), AuditAction::ClientX509Create)
+        .target(client.id)
+        .details(&client.name)
+        .log()
+        .await?;
 
     let resp = ClientX509Response::from(client);
     Ok(Json(resp))
@@ -111,6 +124,11 @@ pub async fn put_client(
 
     let uuid = Uuid::from_str(&id)?;
     let client = ClientX509Entity::update(&uuid, payload).await?;
+    AuditEvent::new(principal.name(), AuditAction::ClientX509Update)
+        .target(client.id)
+        .details(&client.name)
+        .log()
+        .await?;
 
     let resp = ClientX509Response::from(client);
     Ok(Json(resp))
@@ -134,6 +152,10 @@ pub async fn delete_client(
 
     let uuid = Uuid::from_str(&id)?;
     ClientX509Entity::delete(&uuid).await?;
+    AuditEvent::new(principal.name(), AuditAction::ClientX509Delete)
+        .target(uuid)
+        .log()
+        .await?;
 
     Ok(())
 }
@@ -159,16 +181,29 @@ pub async fn post_build_client_cert(
     let uuid = Uuid::from_str(&id)?;
     let client = ClientX509Entity::find(&uuid).await?;
 
-    let ca_id = client
-        .validate_active_enabled(state.clone(), api_key.token())
-        .await?;
+    let ca_id = validate_client(&client, &state, api_key.token()).await?;
+    let client = client.with_profile().await?;
     let enc_keys = state.read().await.enc_keys.clone();
     let ca = CaCertX509Full::build_by_id(&ca_id, &enc_keys).await?;
-    let resp = match client.build_cert(&ca, CertFormat::Pem, None).await? {
-        // let resp = match client.build_cert(state, CertFormat::Pem, None).await? {
+    let resp = match client
+        .build_cert(&ca, CertFormat::Pem, None)
+        .await
+        .inspect_err(|err| metrics::cert_issue_failed(CertType::X509, err))?
+    {
         ClientX509EntityCert::Pem(resp) => resp,
         _ => unreachable!(),
     };
+    metrics::cert_issued(
+        CertType::X509,
+        IssueFormat::Pem,
+        &client.group_id,
+        Some(&ca_id),
+    );
+    AuditEvent::new(audit::client_actor(&client.id), AuditAction::CertX509Issue)
+        .target(client.id)
+        .details(format!("fingerprint: {}", resp.cert_fingerprint))
+        .log()
+        .await?;
     Ok(Json(resp))
 }
 
@@ -193,18 +228,29 @@ pub async fn post_build_client_cert_p12(
     let uuid = Uuid::from_str(&id)?;
     let client = ClientX509Entity::find(&uuid).await?;
 
-    let ca_id = client
-        .validate_active_enabled(state.clone(), api_key.token())
-        .await?;
+    let ca_id = validate_client(&client, &state, api_key.token()).await?;
+    let client = client.with_profile().await?;
     let enc_keys = state.read().await.enc_keys.clone();
     let ca = CaCertX509Full::build_by_id(&ca_id, &enc_keys).await?;
     let pkcs12 = match client
         .build_cert(&ca, CertFormat::PKCS12, Some(api_key.token()))
-        .await?
+        .await
+        .inspect_err(|err| metrics::cert_issue_failed(CertType::X509, err))?
     {
         ClientX509EntityCert::PKCS12(pkcs12) => pkcs12,
         _ => unreachable!(),
     };
+    metrics::cert_issued(
+        CertType::X509,
+        IssueFormat::Pkcs12,
+        &client.group_id,
+        Some(&ca_id),
+    );
+    AuditEvent::new(audit::client_actor(&client.id), AuditAction::CertX509Issue)
+        .target(client.id)
+        .details("PKCS12")
+        .log()
+        .await?;
 
     // This template is used for bigger files from disk only - not needed in this case
     // --> https://github.com/tokio-rs/axum/discussions/608
@@ -216,6 +262,103 @@ pub async fn post_build_client_cert_p12(
     Ok((HEADER_OCTET_STREAM, pkcs12).into_response())
 }
 
+/// Sign a CSR for an x509 client
+///
+/// Requests the clients API key given as `Bearer` token in the `Authorization` header.
+/// The CSR must be PEM encoded and may only request the CN and SANs the client is configured
+/// with. The private key never leaves the client and is therefore not part of the response.
+#[utoipa::path(
+    post,
+    tag = "clients",
+    path = "/api/clients/x509/:id/csr",
+    request_body = X509CsrRequest,
+    responses(
+        (status = 200, description = "Ok", body = CertX509CsrResponse),
+        (status = 400, description = "BadRequest", body = ErrorResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+    ),
+)]
+pub async fn post_build_client_cert_csr(
+    state: AppStateExtract,
+    TypedHeader(api_key): TypedHeader<Authorization<Bearer>>,
+    Path(id): Path<String>,
+    Json(payload): Json<X509CsrRequest>,
+) -> Result<Json<CertX509CsrResponse>, ErrorResponse> {
+    payload.validate()?;
+
+    let uuid = Uuid::from_str(&id)?;
+    let client = ClientX509Entity::find(&uuid).await?;
+
+    let ca_id = validate_client(&client, &state, api_key.token()).await?;
+    let client = client.with_profile().await?;
+    let csr = X509Csr::from_pem(&payload.csr)
+        .inspect_err(|err| metrics::cert_issue_failed(CertType::X509, err))?;
+    let enc_keys = state.read().await.enc_keys.clone();
+    let ca = CaCertX509Full::build_by_id(&ca_id, &enc_keys).await?;
+    let resp = client
+        .build_cert_from_csr(&ca, &csr)
+        .await
+        .inspect_err(|err| metrics::cert_issue_failed(CertType::X509, err))?;
+    metrics::cert_issued(
+        CertType::X509,
+        IssueFormat::Csr,
+        &client.group_id,
+        Some(&ca_id),
+    );
+    AuditEvent::new(audit::client_actor(&client.id), AuditAction::CertX509Issue)
+        .target(client.id)
+        .details(format!("CSR, fingerprint: {}", resp.cert_fingerprint))
+        .log()
+        .await?;
+    Ok(Json(resp))
+}
+
+/// Mint a JWT-SVID for an x509 client
+///
+/// Requests the clients API key given as `Bearer` token in the `Authorization` header.
+/// The group of the client must be bound to a SPIFFE trust domain, and the SPIFFE ID is the
+/// single URI alt name of the client.
+#[utoipa::path(
+    post,
+    tag = "clients",
+    path = "/api/clients/x509/:id/svid/jwt",
+    request_body = JwtSvidRequest,
+    responses(
+        (status = 200, description = "Ok", body = JwtSvidResponse),
+        (status = 400, description = "BadRequest", body = ErrorResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+    ),
+)]
+pub async fn post_client_jwt_svid(
+    state: AppStateExtract,
+    TypedHeader(api_key): TypedHeader<Authorization<Bearer>>,
+    Path(id): Path<String>,
+    Json(payload): Json<JwtSvidRequest>,
+) -> Result<Json<JwtSvidResponse>, ErrorResponse> {
+    payload.validate()?;
+
+    let uuid = Uuid::from_str(&id)?;
+    let client = ClientX509Entity::find(&uuid).await?;
+
+    validate_client(&client, &state, api_key.token()).await?;
+    let client = client.with_profile().await?;
+    let enc_keys = state.read().await.enc_keys.clone();
+    let resp = client
+        .build_jwt_svid(&enc_keys, &payload.audience, payload.ttl_secs)
+        .await?;
+    AuditEvent::new(audit::client_actor(&client.id), AuditAction::JwtSvidIssue)
+        .target(client.id)
+        .details(format!(
+            "{}, audience: {}",
+            resp.spiffe_id,
+            payload.audience.join(", ")
+        ))
+        .log()
+        .await?;
+    Ok(Json(resp))
+}
+
 /// Get x509 client secret in cleartext
 #[utoipa::path(
     get,
@@ -237,6 +380,10 @@ pub async fn get_client_secret(
 
     let uuid = Uuid::from_str(&id)?;
     let secret = ClientX509Entity::find_secret(&uuid, &enc_keys).await?;
+    AuditEvent::new(principal.name(), AuditAction::ClientX509SecretRead)
+        .target(uuid)
+        .log()
+        .await?;
     let resp = ClientSecretResponse { secret };
     Ok(Json(resp))
 }
@@ -261,6 +408,31 @@ pub async fn put_client_secret(
     let enc_keys = state.read().await.enc_keys.clone();
     let uuid = Uuid::from_str(&id)?;
     let secret = ClientX509Entity::new_secret(&uuid, &enc_keys).await?;
+    AuditEvent::new(principal.name(), AuditAction::ClientX509SecretRotate)
+        .target(uuid)
+        .log()
+        .await?;
     let resp = ClientSecretResponse { secret };
     Ok(Json(resp))
 }
+
+/// Validates the API key and logs failed attempts
+pub(crate) async fn validate_client(
+    client: &ClientX509Entity,
+    state: &AppStateExtract,
+    api_key: &str,
+) -> Result<Uuid, ErrorResponse> {
+    match client.validate_active_enabled(state.clone(), api_key).await {
+        Ok(ca_id) => Ok(ca_id),
+        Err(err) => {
+            metrics::cert_issue_failed(CertType::X509, &err);
+            AuditEvent::new(audit::client_actor(&client.id), AuditAction::CertX509Issue)
+                .target(client.id)
+                .details(&err.message)
+                .failed()
+                .log()
+                .await?;
+            Err(err)
+        }
+    }
+}
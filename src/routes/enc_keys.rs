@@ -0,0 +1,159 @@
+use crate::models::api::error_response::{ErrorResponse, ErrorResponseType};
+use crate::models::api::principal::Principal;
+use crate::models::api::response::{EncKeyResponse, EncKeyRotationResponse};
+use crate::models::db::enc_key::EncKeyEntity;
+use crate::models::db::enc_key_rotation::EncKeyRotationEntity;
+use crate::routes::AppStateExtract;
+use crate::service::audit::{AuditAction, AuditEvent};
+use crate::service::enc_keys;
+use axum::extract::Path;
+use axum::http::StatusCode;
+use axum::Json;
+use std::str::FromStr;
+use uuid::Uuid;
+
+/// Lists all enc keys and how many values are still encrypted with each of them
+#[utoipa::path(
+    get,
+    tag = "enc_keys",
+    path = "/api/enc_keys",
+    responses(
+        (status = 200, description = "Ok", body = [EncKeyResponse]),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+    ),
+)]
+pub async fn get_enc_keys(
+    state: AppStateExtract,
+    principal: Principal,
+) -> Result<Json<Vec<EncKeyResponse>>, ErrorResponse> {
+    principal.is_admin()?;
+
+    let active = state.read().await.enc_keys.enc_key.id;
+    let mut res = Vec::new();
+    for key in EncKeyEntity::find_all().await? {
+        res.push(EncKeyResponse {
+            usages: EncKeyEntity::count_usages(&key.id).await?,
+            active: key.id == active,
+            id: key.id,
+            alg: key.alg,
+            created: key.created.map(|ts| ts.unix_timestamp()),
+        });
+    }
+
+    Ok(Json(res))
+}
+
+/// Generates a new enc key and makes it the active one
+///
+/// All values encrypted with older keys will be re-encrypted in the background. The progress
+/// can be checked with `GET /api/enc_keys/rotation`.
+#[utoipa::path(
+    post,
+    tag = "enc_keys",
+    path = "/api/enc_keys/rotate",
+    responses(
+        (status = 202, description = "Accepted", body = EncKeyRotationResponse),
+        (status = 400, description = "BadRequest", body = ErrorResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+    ),
+)]
+pub async fn post_rotate(
+    state: AppStateExtract,
+    principal: Principal,
+) -> Result<(StatusCode, Json<EncKeyRotationResponse>), ErrorResponse> {
+    principal.is_admin()?;
+
+    let rotation = enc_keys::rotate(&state.0).await?;
+    AuditEvent::new(principal.name(), AuditAction::EncKeyRotate)
+        .target(rotation.enc_key_id)
+        .log()
+        .await?;
+
+    Ok((StatusCode::ACCEPTED, Json(rotation.into())))
+}
+
+/// Re-encrypts all values, which do not use the active enc key yet
+///
+/// Only needed if the re-encryption after a rotation could not finish, for instance because
+/// the instance running it has been stopped.
+#[utoipa::path(
+    post,
+    tag = "enc_keys",
+    path = "/api/enc_keys/reencrypt",
+    responses(
+        (status = 202, description = "Accepted", body = EncKeyRotationResponse),
+        (status = 400, description = "BadRequest", body = ErrorResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+    ),
+)]
+pub async fn post_reencrypt(
+    state: AppStateExtract,
+    principal: Principal,
+) -> Result<(StatusCode, Json<EncKeyRotationResponse>), ErrorResponse> {
+    principal.is_admin()?;
+
+    let rotation = enc_keys::reencrypt(&state.0).await?;
+    AuditEvent::new(principal.name(), AuditAction::EncKeyReEncrypt)
+        .target(rotation.enc_key_id)
+        .log()
+        .await?;
+
+    Ok((StatusCode::ACCEPTED, Json(rotation.into())))
+}
+
+/// The progress of the latest re-encryption
+#[utoipa::path(
+    get,
+    tag = "enc_keys",
+    path = "/api/enc_keys/rotation",
+    responses(
+        (status = 200, description = "Ok", body = EncKeyRotationResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+        (status = 404, description = "NotFound", body = ErrorResponse),
+    ),
+)]
+pub async fn get_rotation(
+    principal: Principal,
+) -> Result<Json<EncKeyRotationResponse>, ErrorResponse> {
+    principal.is_admin()?;
+
+    match EncKeyRotationEntity::find_latest().await? {
+        Some(rotation) => Ok(Json(rotation.into())),
+        None => Err(ErrorResponse::new(
+            ErrorResponseType::NotFound,
+            "No enc key rotation has been done yet",
+        )),
+    }
+}
+
+/// Retires an old enc key
+///
+/// The active key and keys, which are still in use, cannot be retired.
+#[utoipa::path(
+    delete,
+    tag = "enc_keys",
+    path = "/api/enc_keys/:id",
+    responses(
+        (status = 200, description = "Ok"),
+        (status = 400, description = "BadRequest", body = ErrorResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+    ),
+)]
+pub async fn delete_enc_key(
+    principal: Principal,
+    Path(id): Path<String>,
+) -> Result<(), ErrorResponse> {
+    principal.is_admin()?;
+    let id = Uuid::from_str(&id)?;
+
+    enc_keys::retire(&id).await?;
+    AuditEvent::new(principal.name(), AuditAction::EncKeyRetire)
+        .target(id)
+        .log()
+        .await
+}
@@ -6,13 +6,17 @@ use tracing::{error, warn};
 use uuid::Uuid;
 use validator::Validate;
 
+use crate::acme::b64_url_encode;
 use crate::models::api::error_response::{ErrorResponse, ErrorResponseType};
 use crate::models::api::principal::Principal;
 use crate::models::api::request::{GroupCreateRequest, GroupUpdateRequest};
-use crate::models::api::response::GroupResponse;
+use crate::models::api::response::{AcmeEabKeyCreatedResponse, AcmeEabKeyResponse, GroupResponse};
+use crate::models::db::acme_eab_key::AcmeEabKeyEntity;
 use crate::models::db::client_ssh::ClientSshEntity;
 use crate::models::db::client_x509::ClientX509Entity;
 use crate::models::db::groups::GroupEntity;
+use crate::routes::AppStateExtract;
+use crate::service::audit::{AuditAction, AuditEvent};
 
 #[utoipa::path(
     get,
@@ -52,8 +56,12 @@ pub async fn post_group(
     principal.is_admin()?;
     payload.validate()?;
 
+    let name = payload.name.clone();
     GroupEntity::insert(payload).await?;
-    Ok(())
+    AuditEvent::new(principal.name(), AuditAction::GroupCreate)
+        .details(name)
+        .log()
+        .await
 }
 
 #[utoipa::path(
@@ -76,8 +84,13 @@ pub async fn put_group(
     payload.validate()?;
 
     let id = Uuid::from_str(&id)?;
+    let name = payload.name.clone();
     GroupEntity::update(&id, payload).await?;
-    Ok(())
+    AuditEvent::new(principal.name(), AuditAction::GroupUpdate)
+        .target(id)
+        .details(name)
+        .log()
+        .await
 }
 
 #[utoipa::path(
@@ -127,5 +140,96 @@ pub async fn delete_group(
         };
     }
 
-    Ok(())
+    AuditEvent::new(principal.name(), AuditAction::GroupDelete)
+        .target(id)
+        .log()
+        .await
+}
+
+#[utoipa::path(
+    get,
+    tag = "unsealed",
+    path = "/api/groups/:id/acme/eab",
+    responses(
+        (status = 200, description = "Ok", body = [AcmeEabKeyResponse]),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+    ),
+)]
+pub async fn get_acme_eab_keys(
+    principal: Principal,
+    Path(id): Path<String>,
+) -> Result<Json<Vec<AcmeEabKeyResponse>>, ErrorResponse> {
+    principal.is_admin()?;
+
+    let id = Uuid::from_str(&id)?;
+    let keys = AcmeEabKeyEntity::find_all_for_group(&id)
+        .await?
+        .into_iter()
+        .map(AcmeEabKeyResponse::from)
+        .collect();
+    Ok(Json(keys))
+}
+
+/// Creates an external account key for ACME clients of the group.
+/// The HMAC key is only returned once and each key can only bind a single ACME account.
+#[utoipa::path(
+    post,
+    tag = "unsealed",
+    path = "/api/groups/:id/acme/eab",
+    responses(
+        (status = 200, description = "Ok", body = AcmeEabKeyCreatedResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+        (status = 404, description = "NotFound", body = ErrorResponse),
+    ),
+)]
+pub async fn post_acme_eab_key(
+    state: AppStateExtract,
+    principal: Principal,
+    Path(id): Path<String>,
+) -> Result<Json<AcmeEabKeyCreatedResponse>, ErrorResponse> {
+    principal.is_admin()?;
+
+    let group = GroupEntity::find_by_id(&Uuid::from_str(&id)?).await?;
+    let enc_keys = state.read().await.enc_keys.clone();
+    let (key, hmac_key) = AcmeEabKeyEntity::create(&group.id, &enc_keys).await?;
+
+    AuditEvent::new(principal.name(), AuditAction::AcmeEabKeyCreate)
+        .target(group.id)
+        .details(key.kid.clone())
+        .log()
+        .await?;
+
+    Ok(Json(AcmeEabKeyCreatedResponse {
+        kid: key.kid,
+        hmac_key: b64_url_encode(&hmac_key),
+    }))
+}
+
+/// Deletes an external account key. An ACME account bound with it loses the binding.
+#[utoipa::path(
+    delete,
+    tag = "unsealed",
+    path = "/api/groups/:id/acme/eab/:kid",
+    responses(
+        (status = 200, description = "Ok"),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+        (status = 404, description = "NotFound", body = ErrorResponse),
+    ),
+)]
+pub async fn delete_acme_eab_key(
+    principal: Principal,
+    Path((id, kid)): Path<(String, String)>,
+) -> Result<(), ErrorResponse> {
+    principal.is_admin()?;
+
+    let id = Uuid::from_str(&id)?;
+    AcmeEabKeyEntity::delete(&id, &kid).await?;
+    AuditEvent::new(principal.name(), AuditAction::AcmeEabKeyDelete)
+        .target(id)
+        .details(kid)
+        .log()
+        .await
 }
@@ -0,0 +1,79 @@
+use crate::config::Db;
+use crate::metrics;
+use crate::models::api::response::HealthResponse;
+use axum::http::{header, StatusCode};
+use axum::response::IntoResponse;
+use axum::Json;
+
+/// Prometheus metrics
+#[utoipa::path(
+    get,
+    tag = "common",
+    path = "/metrics",
+    responses(
+        (status = 200, description = "Ok"),
+    ),
+)]
+pub async fn get_metrics() -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        metrics::render(),
+    )
+}
+
+/// Liveness check
+///
+/// Always succeeds as long as the server is running, even if it is sealed.
+#[utoipa::path(
+    get,
+    tag = "common",
+    path = "/health/live",
+    responses(
+        (status = 200, description = "Ok", body = HealthResponse),
+    ),
+)]
+pub async fn get_live() -> Json<HealthResponse> {
+    Json(HealthResponse {
+        sealed: metrics::is_sealed(),
+        db: Db::ping().await,
+    })
+}
+
+/// Readiness check
+///
+/// Only succeeds if this instance is unsealed and the database is reachable.
+#[utoipa::path(
+    get,
+    tag = "common",
+    path = "/health/ready",
+    responses(
+        (status = 200, description = "Ok", body = HealthResponse),
+        (status = 503, description = "ServiceUnavailable", body = HealthResponse),
+    ),
+)]
+pub async fn get_ready() -> impl IntoResponse {
+    let health = HealthResponse {
+        sealed: metrics::is_sealed(),
+        db: Db::ping().await,
+    };
+    let status = if health.is_ready() {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(health))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_readiness() {
+        let health = |sealed: bool, db: bool| HealthResponse { sealed, db };
+        assert!(health(false, true).is_ready());
+        assert!(!health(true, true).is_ready());
+        assert!(!health(false, false).is_ready());
+        assert!(!health(true, false).is_ready());
+    }
+}
@@ -1,13 +1,25 @@
 use crate::config::{AppState, AppStateSealed};
 
+pub mod acme;
+pub mod audit;
+pub mod backup;
 pub mod ca;
+pub mod certs_ssh;
+pub mod certs_x509;
 pub mod clients_ssh;
 pub mod clients_x509;
+pub mod enc_keys;
 pub mod groups;
+pub mod metrics;
+pub mod notifications;
+pub mod ocsp;
 pub mod oidc;
+pub mod profiles;
 pub mod sealed;
+pub mod spiffe;
 pub mod unsealed;
 pub mod users;
+pub mod vault;
 
 pub type AppStateExtract = axum::extract::State<AppState>;
 pub type AppStateSealedExtract = axum::extract::State<AppStateSealed>;
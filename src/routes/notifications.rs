@@ -0,0 +1,20 @@
+use crate::models::api::error_response::ErrorResponse;
+use crate::models::api::principal::Principal;
+use crate::notifications;
+
+/// Sends a test notification to all configured admin emails and webhooks
+#[utoipa::path(
+    post,
+    tag = "notifications",
+    path = "/api/notifications/test",
+    responses(
+        (status = 200, description = "Ok"),
+        (status = 400, description = "BadRequest", body = ErrorResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 503, description = "ServiceUnavailable", body = ErrorResponse),
+    ),
+)]
+pub async fn post_test(principal: Principal) -> Result<(), ErrorResponse> {
+    principal.is_admin()?;
+    notifications::send_test().await
+}
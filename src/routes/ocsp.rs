@@ -0,0 +1,113 @@
+use crate::certificates::x509::ocsp::{
+    build_error_response, build_response, OcspCertStatus, OcspRequest, OcspResponseStatus,
+};
+use crate::certificates::X509RevocationReason;
+use crate::constants::OCSP_VALIDITY_MINUTES;
+use crate::models::db::ca_cert_x509::{CaCertX509Entity, CaCertX509Type};
+use crate::models::db::cert_x509::CertX509Entity;
+use crate::models::db::cert_x509_revoked::CertX509RevokedEntity;
+use crate::models::db::ocsp_x509::OcspX509Entity;
+use crate::routes::AppStateExtract;
+use crate::util::pem_to_der;
+use axum::body::Bytes;
+use axum::extract::Path;
+use axum::http::header;
+use axum::response::IntoResponse;
+use base64::{engine::general_purpose, Engine as _};
+use std::ops::Add;
+use time::OffsetDateTime;
+use tracing::error;
+
+/// OCSP responder for all X509 CAs via POST
+#[utoipa::path(
+    post,
+    tag = "common",
+    path = "/ocsp/x509",
+    request_body(content = Vec<u8>, content_type = "application/ocsp-request"),
+    responses(
+        (status = 200, description = "Ok", content_type = "application/ocsp-response"),
+    ),
+)]
+pub async fn post_ocsp(state: AppStateExtract, body: Bytes) -> impl IntoResponse {
+    ocsp_response(respond(&state, &body).await)
+}
+
+/// OCSP responder for all X509 CAs via GET with the base64 encoded request in the path
+#[utoipa::path(
+    get,
+    tag = "common",
+    path = "/ocsp/x509/:request",
+    responses(
+        (status = 200, description = "Ok", content_type = "application/ocsp-response"),
+    ),
+)]
+pub async fn get_ocsp(state: AppStateExtract, Path(request): Path<String>) -> impl IntoResponse {
+    let res = match general_purpose::STANDARD.decode(request.trim_start_matches('/')) {
+        Ok(der) => respond(&state, &der).await,
+        Err(_) => Err(OcspResponseStatus::MalformedRequest),
+    };
+    ocsp_response(res)
+}
+
+fn ocsp_response(res: Result<Vec<u8>, OcspResponseStatus>) -> impl IntoResponse {
+    let body = res.unwrap_or_else(build_error_response);
+    ([(header::CONTENT_TYPE, "application/ocsp-response")], body)
+}
+
+async fn respond(state: &AppStateExtract, der: &[u8]) -> Result<Vec<u8>, OcspResponseStatus> {
+    let req = OcspRequest::from_der(der)?;
+
+    // all requested certificates are expected to be issued by the same CA, which will sign the
+    // response -> the first one decides
+    let mut ca = None;
+    for entity in CaCertX509Entity::find_all_by_type(CaCertX509Type::Certificate).await? {
+        let der = pem_to_der(&entity.data)?;
+        if req.cert_ids[0].is_issued_by(der.as_bytes()) {
+            ca = Some((entity.id, der));
+            break;
+        }
+    }
+    let (ca_id, ca_der) = ca.ok_or(OcspResponseStatus::Unauthorized)?;
+
+    let mut responses = Vec::with_capacity(req.cert_ids.len());
+    for cert_id in &req.cert_ids {
+        let status = match cert_id.serial() {
+            Some(serial) if cert_id.is_issued_by(ca_der.as_bytes()) => {
+                if !CertX509Entity::exists_for_ca(serial, &ca_id).await? {
+                    OcspCertStatus::Unknown
+                } else if let Some(revoked) = CertX509RevokedEntity::find(serial).await? {
+                    OcspCertStatus::Revoked {
+                        time: revoked.revoked,
+                        reason: X509RevocationReason::from_value(revoked.reason).crl_reason(),
+                    }
+                } else {
+                    OcspCertStatus::Good
+                }
+            }
+            _ => OcspCertStatus::Unknown,
+        };
+        responses.push((cert_id, status));
+    }
+
+    let enc_keys = state.read().await.enc_keys.clone();
+    let signer = OcspX509Entity::signer(&ca_id, &enc_keys)
+        .await
+        .map_err(|err| {
+            error!(
+                "Error building the OCSP signer for CA {}: {}",
+                ca_id, err.message
+            );
+            OcspResponseStatus::InternalError
+        })?;
+
+    let this_update = OffsetDateTime::now_utc();
+    let next_update = this_update.add(time::Duration::minutes(*OCSP_VALIDITY_MINUTES));
+    let res = build_response(
+        &signer,
+        req.nonce.as_deref(),
+        &responses,
+        this_update,
+        next_update,
+    )?;
+    Ok(res)
+}
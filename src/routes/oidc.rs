@@ -12,6 +12,7 @@ use crate::oidc::principal::PrincipalOidc;
 use crate::oidc::validation::{OidcConfig, TokenCacheReq};
 use crate::oidc::{validation, CacheMethod};
 use crate::routes::AppStateExtract;
+use crate::service::audit::{AuditAction, AuditEvent};
 use crate::util::{build_session_cookie, build_session_cookie_xsrf};
 use axum::body::Body;
 use axum::extract::Query;
@@ -112,13 +113,33 @@ pub async fn get_oidc_callback(
 ) -> Result<Response<Body>, ErrorResponse> {
     let enc_key_entity = state.read().await.enc_keys.enc_key.clone();
 
-    let (jar, _token_set, id_claims) = if *DEV_MODE {
-        oidc_handler::oidc_callback(&jar, params, &enc_key_entity.value, true).await?
-    } else {
-        oidc_handler::oidc_callback(&jar, params, &enc_key_entity.value, false).await?
+    let res =
+        match oidc_handler::oidc_callback(&jar, params, &enc_key_entity.value, *DEV_MODE).await {
+            Ok((jar, _token_set, id_claims)) => SessionEntity::from_id_claims(id_claims)
+                .await
+                .map(|session| (jar, session)),
+            Err(err) => Err(err),
+        };
+    let (jar, (session, xsrf)) = match res {
+        Ok(res) => res,
+        Err(err) => {
+            AuditEvent::new("oidc", AuditAction::LoginOidc)
+                .details(&err.message)
+                .failed()
+                .log()
+                .await?;
+            return Err(err);
+        }
     };
+    let mut event = AuditEvent::new(
+        session.email.as_deref().unwrap_or("oidc"),
+        AuditAction::LoginOidc,
+    );
+    if let Some(user_id) = session.user_id {
+        event = event.target(user_id);
+    }
+    event.log().await?;
 
-    let (session, xsrf) = SessionEntity::from_id_claims(id_claims).await?;
     tracing::warn!("\n\nxsrf in oidc callback: {}\n", xsrf);
     let session_cookie = build_session_cookie(session.id.to_string());
     let session_cookie_xsrf = build_session_cookie_xsrf(xsrf);
@@ -199,6 +220,10 @@ pub async fn put_config_oidc(
     // Persist the new config
     let enc_keys = state.read().await.enc_keys.clone();
     entity.save(&enc_keys).await?;
+    AuditEvent::new(principal.name(), AuditAction::OidcConfigUpdate)
+        .target(&entity.iss)
+        .log()
+        .await?;
 
     // stop any possibly running token cache
     if let Some(tx) = &state.read().await.tx_token_cache {
@@ -0,0 +1,127 @@
+use crate::models::api::error_response::ErrorResponse;
+use crate::models::api::principal::Principal;
+use crate::models::api::request::ProfileRequest;
+use crate::models::api::response::ProfileResponse;
+use crate::models::db::profile::ProfileEntity;
+use crate::service::audit::{AuditAction, AuditEvent};
+use axum::extract::Path;
+use axum::Json;
+use std::str::FromStr;
+use uuid::Uuid;
+use validator::Validate;
+
+/// All X509 and SSH certificate profiles
+#[utoipa::path(
+    get,
+    tag = "profiles",
+    path = "/api/profiles",
+    responses(
+        (status = 200, description = "Ok", body = [ProfileResponse]),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+    ),
+)]
+pub async fn get_profiles(
+    principal: Principal,
+) -> Result<Json<Vec<ProfileResponse>>, ErrorResponse> {
+    principal.is_admin()?;
+
+    let profiles = ProfileEntity::find_all()
+        .await?
+        .into_iter()
+        .map(ProfileResponse::from)
+        .collect();
+    Ok(Json(profiles))
+}
+
+/// Creates a new certificate profile
+#[utoipa::path(
+    post,
+    tag = "profiles",
+    path = "/api/profiles",
+    request_body = ProfileRequest,
+    responses(
+        (status = 200, description = "Ok", body = ProfileResponse),
+        (status = 400, description = "BadRequest", body = ErrorResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+    ),
+)]
+pub async fn post_profile(
+    principal: Principal,
+    Json(payload): Json<ProfileRequest>,
+) -> Result<Json<ProfileResponse>, ErrorResponse> {
+    principal.is_admin()?;
+    payload.validate()?;
+
+    let profile = ProfileEntity::insert(payload).await?;
+    AuditEvent::new(principal.name(), AuditAction::ProfileCreate)
+        .target(profile.id)
+        .details(&profile.name)
+        .log()
+        .await?;
+
+    Ok(Json(ProfileResponse::from(profile)))
+}
+
+/// Updates a certificate profile
+///
+/// All linked clients and user group access entries pick up the changes with their next
+/// certificate. The type of a profile cannot be changed.
+#[utoipa::path(
+    put,
+    tag = "profiles",
+    path = "/api/profiles/:id",
+    request_body = ProfileRequest,
+    responses(
+        (status = 200, description = "Ok", body = ProfileResponse),
+        (status = 400, description = "BadRequest", body = ErrorResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+        (status = 404, description = "NotFound", body = ErrorResponse),
+    ),
+)]
+pub async fn put_profile(
+    principal: Principal,
+    Path(id): Path<String>,
+    Json(payload): Json<ProfileRequest>,
+) -> Result<Json<ProfileResponse>, ErrorResponse> {
+    principal.is_admin()?;
+    payload.validate()?;
+
+    let id = Uuid::from_str(&id)?;
+    let profile = ProfileEntity::update(&id, payload).await?;
+    AuditEvent::new(principal.name(), AuditAction::ProfileUpdate)
+        .target(id)
+        .details(&profile.name)
+        .log()
+        .await?;
+
+    Ok(Json(ProfileResponse::from(profile)))
+}
+
+/// Deletes a certificate profile, which is not linked anymore
+#[utoipa::path(
+    delete,
+    tag = "profiles",
+    path = "/api/profiles/:id",
+    responses(
+        (status = 200, description = "Ok"),
+        (status = 400, description = "BadRequest", body = ErrorResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+    ),
+)]
+pub async fn delete_profile(
+    principal: Principal,
+    Path(id): Path<String>,
+) -> Result<(), ErrorResponse> {
+    principal.is_admin()?;
+
+    let id = Uuid::from_str(&id)?;
+    ProfileEntity::delete(&id).await?;
+    AuditEvent::new(principal.name(), AuditAction::ProfileDelete)
+        .target(id)
+        .log()
+        .await
+}
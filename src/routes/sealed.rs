@@ -1,12 +1,16 @@
-use crate::constants::UNSEAL_RATE_LIMIT;
+use crate::metrics;
 use crate::models::api::error_response::{ErrorResponse, ErrorResponseType};
 use crate::models::api::request::{AddMasterShardRequest, InitRequest, UnsealRequest};
 use crate::models::api::response::{InitResponse, SealedStatus, X509CertificatesInspectResponse};
 use crate::routes::AppStateSealedExtract;
-use crate::service::sealed::{add_unseal_shard, init, init_values_check, unseal};
+use crate::service::audit::{AuditAction, AuditEvent};
+use crate::service::sealed::{add_unseal_shard, init, init_values_check, sealed_status, unseal};
 use axum::Json;
 use validator::Validate;
 
+/// There is no session in the sealed state
+const SEALED_ACTOR: &str = "sealed";
+
 /// Initialize Nioca with a fully empty database
 #[utoipa::path(
     tag = "sealed",
@@ -26,6 +30,9 @@ pub async fn post_init(
     check_init_state(&state).await?;
 
     let init_resp = init(state, payload).await?;
+    AuditEvent::new(SEALED_ACTOR, AuditAction::Init)
+        .log()
+        .await?;
     Ok(Json(init_resp))
 }
 
@@ -70,8 +77,24 @@ pub async fn post_master_shard(
 ) -> Result<Json<SealedStatus>, ErrorResponse> {
     payload.validate()?;
 
-    let status = add_unseal_shard(state, payload).await?;
-    Ok(Json(status))
+    match add_unseal_shard(state, payload).await {
+        Ok(status) => {
+            metrics::unseal_attempt("shard", true);
+            AuditEvent::new(SEALED_ACTOR, AuditAction::UnsealShard)
+                .log()
+                .await?;
+            Ok(Json(status))
+        }
+        Err(err) => {
+            metrics::unseal_attempt("shard", false);
+            AuditEvent::new(SEALED_ACTOR, AuditAction::UnsealShard)
+                .details(&err.message)
+                .failed()
+                .log()
+                .await?;
+            Err(err)
+        }
+    }
 }
 
 /// Returns the current sealed status and the added keys
@@ -85,16 +108,7 @@ pub async fn post_master_shard(
 )]
 pub async fn get_status(state: AppStateSealedExtract) -> Result<Json<SealedStatus>, ErrorResponse> {
     let config = state.read().await;
-    let status = SealedStatus {
-        is_initialized: config.init_key.is_none(),
-        is_sealed: true,
-        master_shard_1: config.enc_keys.master_shard_1.is_some(),
-        master_shard_2: config.enc_keys.master_shard_2.is_some(),
-        is_ready: config.enc_keys.master_shard_1.is_some()
-            && config.enc_keys.master_shard_2.is_some(),
-        key_add_rate_limit: *UNSEAL_RATE_LIMIT,
-    };
-    Ok(Json(status))
+    Ok(Json(sealed_status(&config)))
 }
 
 /// Unseal Nioca if all the keys have been added
@@ -113,7 +127,15 @@ pub async fn post_unseal(
     Json(payload): Json<UnsealRequest>,
 ) -> Result<(), ErrorResponse> {
     payload.validate()?;
-    unseal(state, payload).await
+
+    let res = unseal(state, payload).await;
+    metrics::unseal_attempt("unseal", res.is_ok());
+    let event = AuditEvent::new(SEALED_ACTOR, AuditAction::Unseal);
+    match &res {
+        Ok(_) => event.log().await?,
+        Err(err) => event.details(&err.message).failed().log().await?,
+    }
+    res
 }
 
 /// Get the XSRF token for an unsealing operation
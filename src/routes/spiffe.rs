@@ -0,0 +1,61 @@
+use crate::certificates::spiffe::{jwt_key_jwk, x509_authority_jwk};
+use crate::certificates::x509::verification::x509_der_from_bytes;
+use crate::constants::SPIFFE_BUNDLE_REFRESH_HINT;
+use crate::models::api::error_response::{ErrorResponse, ErrorResponseType};
+use crate::models::api::response::{SpiffeBundleKey, SpiffeBundleResponse};
+use crate::models::db::ca_cert_x509::{CaCertX509Entity, CaCertX509Type};
+use crate::models::db::groups::GroupEntity;
+use crate::models::db::spiffe_jwt_key::SpiffeJwtKeyEntity;
+use crate::util::{b64_encode, pem_to_der};
+use axum::extract::Path;
+use axum::Json;
+use std::str::FromStr;
+use uuid::Uuid;
+
+/// The SPIFFE trust bundle of a group
+///
+/// Contains the root CA of the group as `x509-svid` authority and all keys JWT-SVIDs of the
+/// group are signed with as `jwt-svid` authorities.
+#[utoipa::path(
+    get,
+    tag = "common",
+    path = "/spiffe/:group_id/bundle",
+    responses(
+        (status = 200, description = "Ok", body = SpiffeBundleResponse),
+        (status = 404, description = "NotFound", body = ErrorResponse),
+    ),
+)]
+pub async fn get_bundle(
+    Path(group_id): Path<String>,
+) -> Result<Json<SpiffeBundleResponse>, ErrorResponse> {
+    let group_id = Uuid::from_str(&group_id)?;
+    let group = GroupEntity::find_by_id(&group_id).await?;
+    let not_found = || {
+        ErrorResponse::new(
+            ErrorResponseType::NotFound,
+            "This group is not bound to a SPIFFE trust domain",
+        )
+    };
+    if group.spiffe_trust_domain.is_none() {
+        return Err(not_found());
+    }
+    let ca_id = group.ca_x509.ok_or_else(not_found)?;
+
+    let root = CaCertX509Entity::find_by_id(&ca_id, CaCertX509Type::Root).await?;
+    let root_der = pem_to_der(&root.data)?;
+    let cert = x509_der_from_bytes(root_der.as_bytes())?;
+    let mut x509_key = SpiffeBundleKey::new("x509-svid", x509_authority_jwk(cert.public_key())?);
+    x509_key.x5c = Some(vec![b64_encode(root_der.as_bytes())]);
+
+    let mut keys = vec![x509_key];
+    for jwt_key in SpiffeJwtKeyEntity::find_all_for_group(&group_id).await? {
+        let mut key = SpiffeBundleKey::new("jwt-svid", jwt_key_jwk(&jwt_key.public_key));
+        key.kid = Some(jwt_key.kid);
+        keys.push(key);
+    }
+
+    Ok(Json(SpiffeBundleResponse {
+        keys,
+        spiffe_refresh_hint: SPIFFE_BUNDLE_REFRESH_HINT,
+    }))
+}
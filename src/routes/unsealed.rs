@@ -1,11 +1,15 @@
 use crate::constants::{PUB_URL, SESSION_COOKIE, SESSION_COOKIE_XSRF, UNSEAL_RATE_LIMIT};
 use crate::models::api::error_response::{ErrorResponse, ErrorResponseType};
-use crate::models::api::principal::Principal;
-use crate::models::api::request::{LoginRequest, PasswordChangeRequest};
-use crate::models::api::response::{AuthCheckResponse, SealedStatus, SessionResponse};
-use crate::models::db::master_key::MasterKeyRow;
+use crate::models::api::principal::{Principal, LOCAL_ADMIN};
+use crate::models::api::request::{LoginRequest, MasterKeyRekeyRequest, PasswordChangeRequest};
+use crate::models::api::response::{
+    AuthCheckResponse, MasterKeyRekeyResponse, SealedStatus, SessionResponse,
+};
+use crate::models::db::master_key::{MasterKeyEntity, MasterKeyRow};
 use crate::models::db::session::SessionEntity;
 use crate::routes::AppStateExtract;
+use crate::service::audit::{AuditAction, AuditEvent};
+use crate::service::master_key;
 use crate::service::password_hasher::{ComparePasswords, HashPassword};
 use crate::util::{build_session_cookie, delete_session_cookie_xsrf, get_session_cookie};
 use axum::Json;
@@ -47,6 +51,10 @@ pub async fn post_login(
     let password_match =
         ComparePasswords::is_match(&payload.password, password_hash, &pepper).await?;
     if !password_match {
+        AuditEvent::new(LOCAL_ADMIN, AuditAction::Login)
+            .failed()
+            .log()
+            .await?;
         return Err(ErrorResponse::new(
             ErrorResponseType::Unauthorized,
             "Bad Credentials".to_string(),
@@ -55,6 +63,9 @@ pub async fn post_login(
 
     // expand the session lifetime
     session.set_authenticated().await?;
+    AuditEvent::new(LOCAL_ADMIN, AuditAction::Login)
+        .log()
+        .await?;
 
     Ok(())
 }
@@ -160,6 +171,10 @@ pub async fn put_password_change(
     let password_match =
         ComparePasswords::is_match(&payload.current_password, password_hash, &pepper).await?;
     if !password_match {
+        AuditEvent::new(principal.name(), AuditAction::PasswordChange)
+            .failed()
+            .log()
+            .await?;
         return Err(ErrorResponse::new(
             ErrorResponseType::Forbidden,
             "Bad Credentials".to_string(),
@@ -169,10 +184,62 @@ pub async fn put_password_change(
     // hash and save the new password
     let new_hash = HashPassword::hash_password(&payload.new_password, &pepper).await?;
     MasterKeyRow::update_local_password(&new_hash).await?;
+    AuditEvent::new(principal.name(), AuditAction::PasswordChange)
+        .log()
+        .await?;
 
     Ok(())
 }
 
+/// Re-keys the master key
+///
+/// Generates new master shards and re-encrypts everything depending on the master key. The old
+/// shards will not work anymore afterwards. Other unsealed instances will exit within 30 seconds
+/// and must be unsealed with the new shards again.
+#[utoipa::path(
+    post,
+    tag = "unsealed",
+    path = "/api/master_key/rekey",
+    request_body = MasterKeyRekeyRequest,
+    responses(
+        (status = 200, description = "Ok", body = MasterKeyRekeyResponse),
+        (status = 400, description = "BadRequest", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+    ),
+)]
+pub async fn post_master_key_rekey(
+    principal: Principal,
+    state: AppStateExtract,
+    Json(payload): Json<MasterKeyRekeyRequest>,
+) -> Result<Json<MasterKeyRekeyResponse>, ErrorResponse> {
+    payload.validate()?;
+
+    // only the local root user knows the shards -> not allowed from SSO admin
+    if !principal.local || principal.is_admin.is_none() || !principal.is_admin.unwrap() {
+        return Err(ErrorResponse::new(
+            ErrorResponseType::Forbidden,
+            "Only the local root user can re-key the master key",
+        ));
+    }
+
+    match master_key::rekey(&state.0, payload).await {
+        Ok(res) => {
+            AuditEvent::new(principal.name(), AuditAction::MasterKeyRekey)
+                .log()
+                .await?;
+            Ok(Json(res))
+        }
+        Err(err) => {
+            AuditEvent::new(principal.name(), AuditAction::MasterKeyRekey)
+                .details(&err.message)
+                .failed()
+                .log()
+                .await?;
+            Err(err)
+        }
+    }
+}
+
 /// Create and get a new session
 ///
 /// Sets a session cookie, which must be provided with every request.<br>
@@ -209,11 +276,13 @@ pub async fn post_session() -> Result<(CookieJar, Json<SessionResponse>), ErrorR
     ),
 )]
 pub async fn get_status() -> Result<Json<SealedStatus>, ErrorResponse> {
+    let mk_entity = MasterKeyEntity::build().await?;
     let status = SealedStatus {
         is_initialized: true,
         is_sealed: false,
-        master_shard_1: true,
-        master_shard_2: true,
+        shares_added: mk_entity.threshold(),
+        shares_threshold: mk_entity.threshold(),
+        shares_total: mk_entity.total(),
         is_ready: true,
         key_add_rate_limit: *UNSEAL_RATE_LIMIT,
     };
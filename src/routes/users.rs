@@ -1,12 +1,22 @@
+use crate::certificates::x509::csr::X509Csr;
+use crate::metrics;
+use crate::metrics::{CertType, IssueFormat};
 use crate::models::api::error_response::{ErrorResponse, ErrorResponseType};
 use crate::models::api::principal::Principal;
-use crate::models::api::request::UsersGroupAccessRequest;
-use crate::models::api::response::{UserResponse, UsersGroupAccessResponse};
+use crate::models::api::request::{SshPublicKeyRequest, UsersGroupAccessRequest, X509CsrRequest};
+use crate::models::api::response::{
+    CertX509CsrResponse, SshCertificateSignedResponse, UserResponse, UsersGroupAccessResponse,
+};
+use crate::models::db::ca_cert_x509::CaCertX509Full;
+use crate::models::db::groups::GroupEntity;
+use crate::models::db::profile::{ProfileEntity, ProfileType};
 use crate::models::db::user::UserEntity;
 use crate::models::db::user_group_access::UsersGroupAccess;
 use crate::routes::AppStateExtract;
+use crate::service::audit::{AuditAction, AuditEvent};
 use axum::extract::Path;
 use axum::Json;
+use ssh_key::{HashAlg, PublicKey};
 use std::str::FromStr;
 use uuid::Uuid;
 use validator::Validate;
@@ -88,8 +98,11 @@ pub async fn post_user_group_access(
     let group_id = Uuid::from_str(&group_id)?;
 
     UsersGroupAccess::create(user_id, group_id, &enc_key).await?;
-
-    Ok(())
+    AuditEvent::new(principal.name(), AuditAction::UserAccessCreate)
+        .target(user_id)
+        .details(format!("group: {}", group_id))
+        .log()
+        .await
 }
 
 #[utoipa::path(
@@ -122,10 +135,16 @@ pub async fn put_user_group_access(
         ));
     }
 
+    ProfileEntity::check_link(group_access.profile_ssh.as_ref(), ProfileType::Ssh, false).await?;
+    ProfileEntity::check_link(group_access.profile_x509.as_ref(), ProfileType::X509, false).await?;
+
     let enc_key = state.read().await.enc_keys.enc_key.clone();
     UsersGroupAccess::update(&enc_key, user_id, group_id, &group_access).await?;
-
-    Ok(())
+    AuditEvent::new(principal.name(), AuditAction::UserAccessUpdate)
+        .target(user_id)
+        .details(format!("group: {}", group_id))
+        .log()
+        .await
 }
 
 #[utoipa::path(
@@ -148,6 +167,146 @@ pub async fn delete_user_group_access(
     let group_id = Uuid::from_str(&group_id)?;
 
     UsersGroupAccess::delete(user_id, group_id).await?;
+    AuditEvent::new(principal.name(), AuditAction::UserAccessDelete)
+        .target(user_id)
+        .details(format!("group: {}", group_id))
+        .log()
+        .await
+}
+
+/// Sign an SSH public key for the logged in user
+///
+/// The certificate is constrained by the users SSH access config for the given group.
+#[utoipa::path(
+    post,
+    tag = "unsealed",
+    path = "/api/users/me/groups/:group_id/ssh",
+    request_body = SshPublicKeyRequest,
+    responses(
+        (status = 200, description = "Ok", body = SshCertificateSignedResponse),
+        (status = 400, description = "BadRequest", body = ErrorResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+    ),
+)]
+pub async fn post_user_ssh_cert(
+    state: AppStateExtract,
+    Path(group_id): Path<String>,
+    principal: Principal,
+    Json(payload): Json<SshPublicKeyRequest>,
+) -> Result<Json<SshCertificateSignedResponse>, ErrorResponse> {
+    payload.validate()?;
+
+    let user_id = principal.user_id.ok_or_else(|| {
+        ErrorResponse::new(
+            ErrorResponseType::Forbidden,
+            "Only available for OIDC users".to_string(),
+        )
+    })?;
+    let group_id = Uuid::from_str(&group_id)?;
+
+    let enc_keys = state.read().await.enc_keys.clone();
+    let access = UsersGroupAccess::find_for_group(&enc_keys, &user_id, &group_id)
+        .await?
+        .with_profiles()
+        .await?;
+    let group = GroupEntity::find_by_id(&group_id).await?;
+
+    let pub_key = PublicKey::from_openssh(payload.pub_key.trim())?;
+    let principal_name = principal
+        .email
+        .clone()
+        .unwrap_or_else(|| user_id.to_string());
+    let resp = access
+        .build_ssh_cert(&state, &group, &principal_name, &pub_key)
+        .await
+        .inspect_err(|err| metrics::cert_issue_failed(CertType::Ssh, err))?;
+    metrics::cert_issued(
+        CertType::Ssh,
+        IssueFormat::PublicKey,
+        &group.id,
+        group.ca_ssh.as_ref(),
+    );
+    AuditEvent::new(principal.name(), AuditAction::CertSshIssue)
+        .target(user_id)
+        .details(format!(
+            "group: {}, public key: {}",
+            group.name,
+            pub_key.fingerprint(HashAlg::Sha256)
+        ))
+        .log()
+        .await?;
+
+    Ok(Json(resp))
+}
+
+/// Sign an X509 CSR for the logged in user
+///
+/// The certificate is constrained by the users X509 access config for the given group.
+/// The CN is the email of the user, and the CSR may only request it as email or UPN SAN.
+#[utoipa::path(
+    post,
+    tag = "unsealed",
+    path = "/api/users/me/groups/:group_id/x509",
+    request_body = X509CsrRequest,
+    responses(
+        (status = 200, description = "Ok", body = CertX509CsrResponse),
+        (status = 400, description = "BadRequest", body = ErrorResponse),
+        (status = 401, description = "Unauthorized", body = ErrorResponse),
+        (status = 403, description = "Forbidden", body = ErrorResponse),
+    ),
+)]
+pub async fn post_user_x509_cert(
+    state: AppStateExtract,
+    Path(group_id): Path<String>,
+    principal: Principal,
+    Json(payload): Json<X509CsrRequest>,
+) -> Result<Json<CertX509CsrResponse>, ErrorResponse> {
+    payload.validate()?;
+
+    let user_id = principal.user_id.ok_or_else(|| {
+        ErrorResponse::new(
+            ErrorResponseType::Forbidden,
+            "Only available for OIDC users".to_string(),
+        )
+    })?;
+    let email = principal.email.clone().ok_or_else(|| {
+        ErrorResponse::new(
+            ErrorResponseType::Forbidden,
+            "X509 certificates need the email of the user".to_string(),
+        )
+    })?;
+    let group_id = Uuid::from_str(&group_id)?;
+
+    let enc_keys = state.read().await.enc_keys.clone();
+    let access = UsersGroupAccess::find_for_group(&enc_keys, &user_id, &group_id)
+        .await?
+        .with_profiles()
+        .await?;
+    let group = GroupEntity::find_by_id(&group_id).await?;
+    let ca_id = group.ca_x509.ok_or_else(|| {
+        ErrorResponse::new(
+            ErrorResponseType::Internal,
+            "This groups has no linked CA".to_string(),
+        )
+    })?;
+
+    let csr = X509Csr::from_pem(&payload.csr)
+        .inspect_err(|err| metrics::cert_issue_failed(CertType::X509, err))?;
+    let ca = CaCertX509Full::build_by_id(&ca_id, &enc_keys).await?;
+    let resp = access
+        .build_x509_cert(&ca, &group, &email, &csr)
+        .await
+        .inspect_err(|err| metrics::cert_issue_failed(CertType::X509, err))?;
+    metrics::cert_issued(CertType::X509, IssueFormat::Csr, &group.id, Some(&ca_id));
+    AuditEvent::new(principal.name(), AuditAction::CertX509Issue)
+        .target(user_id)
+        .details(format!(
+            "group: {}, CSR, fingerprint: {}",
+            group.name, resp.cert_fingerprint
+        ))
+        .log()
+        .await?;
 
-    Ok(())
+    Ok(Json(resp))
 }
@@ -0,0 +1,340 @@
+use crate::certificates::x509::csr::X509Csr;
+use crate::certificates::x509::verification::x509_der_from_bytes;
+use crate::certificates::{CertFormat, X509KeyAlg, X509RevocationReason};
+use crate::metrics;
+use crate::metrics::{CertType, IssueFormat};
+use crate::models::api::error_response::{ErrorResponse, ErrorResponseType};
+use crate::models::db::ca_cert_x509::{CaCertX509Entity, CaCertX509Full, CaCertX509Type};
+use crate::models::db::cert_x509::CertX509Entity;
+use crate::models::db::cert_x509_revoked::CertX509RevokedEntity;
+use crate::models::db::client_x509::{ClientX509Entity, ClientX509EntityCert};
+use crate::models::db::crl_x509::CrlX509Entity;
+use crate::models::db::groups::GroupEntity;
+use crate::routes::ca::current_crl;
+use crate::routes::clients_x509::validate_client;
+use crate::routes::AppStateExtract;
+use crate::service::audit;
+use crate::service::audit::{AuditAction, AuditEvent};
+use crate::util::{b64_decode, b64_encode, csv_to_vec, pem_to_der};
+use crate::vault::error::VaultError;
+use crate::vault::models::{
+    VaultCertData, VaultFormat, VaultIssueRequest, VaultResponse, VaultRevokeData,
+    VaultRevokeRequest, VaultSignRequest,
+};
+use crate::vault::{mount_group, serial_from_hex, serial_to_hex, vault_token};
+use axum::extract::Path;
+use axum::http::{header, HeaderMap};
+use axum::response::IntoResponse;
+use axum::Json;
+use std::net::IpAddr;
+use std::str::FromStr;
+use time::format_description::well_known::Rfc3339;
+use tracing::info;
+use uuid::Uuid;
+
+/// Vault PKI `issue`: a new certificate and private key for the x509 client given as role
+///
+/// Only the CN and the DNS / IP names of the client can be requested, and the `ttl` can only
+/// lower its configured validity.
+pub async fn post_issue(
+    state: AppStateExtract,
+    Path((mount, role)): Path<(String, String)>,
+    headers: HeaderMap,
+    Json(payload): Json<VaultIssueRequest>,
+) -> Result<Json<VaultResponse<VaultCertData>>, VaultError> {
+    let (client, ca_id) = vault_client(&state, &mount, &role, &headers).await?;
+
+    let alt_names_ip = payload
+        .ip_sans
+        .iter()
+        .map(|ip| {
+            IpAddr::from_str(ip).map_err(|_| {
+                ErrorResponse::new(
+                    ErrorResponseType::BadRequest,
+                    format!("Invalid IP SAN '{}'", ip),
+                )
+            })
+        })
+        .collect::<Result<Vec<IpAddr>, ErrorResponse>>()?;
+    // like Vault, the CN is added to the DNS SANs if it is a valid one for this client
+    let cn = &payload.common_name;
+    let mut alt_names_dns = payload.alt_names.clone();
+    if !payload.exclude_cn_from_sans
+        && !alt_names_dns.iter().any(|dns| dns.eq_ignore_ascii_case(cn))
+        && csv_to_vec(&client.alt_names_dns)
+            .iter()
+            .any(|dns| dns.eq_ignore_ascii_case(cn))
+    {
+        alt_names_dns.push(cn.clone());
+    }
+
+    let mut client =
+        client
+            .with_profile()
+            .await?
+            .restrict_names(cn, &alt_names_dns, &alt_names_ip)?;
+    if let Some(ttl) = &payload.ttl {
+        client = client.restrict_validity(ttl.hours()?);
+    }
+
+    let enc_keys = state.read().await.enc_keys.clone();
+    let ca = CaCertX509Full::build_by_id(&ca_id, &enc_keys).await?;
+    let cert_format = if payload.format == VaultFormat::Der {
+        CertFormat::Der
+    } else {
+        CertFormat::Pem
+    };
+    let resp = match client
+        .build_cert(&ca, cert_format, None)
+        .await
+        .inspect_err(|err| metrics::cert_issue_failed(CertType::X509, err))?
+    {
+        ClientX509EntityCert::Pem(resp) | ClientX509EntityCert::Der(resp) => resp,
+        ClientX509EntityCert::PKCS12(_) => unreachable!(),
+    };
+    metrics::cert_issued(
+        CertType::X509,
+        IssueFormat::Vault,
+        &client.group_id,
+        Some(&ca_id),
+    );
+    AuditEvent::new(audit::client_actor(&client.id), AuditAction::CertX509Issue)
+        .target(client.id)
+        .details(format!(
+            "Vault issue, fingerprint: {}",
+            resp.cert_fingerprint
+        ))
+        .log()
+        .await?;
+
+    let cert_der = match payload.format {
+        VaultFormat::Der => b64_decode(&resp.cert).map_err(ErrorResponse::from)?,
+        _ => pem_to_der(&resp.cert)?.to_vec(),
+    };
+    let private_key_type = match X509KeyAlg::from_str(&client.key_alg) {
+        X509KeyAlg::RSA => "rsa",
+        X509KeyAlg::ECDSA => "ec",
+        X509KeyAlg::EdDSA => "ed25519",
+    };
+    let (certificate, private_key) = match payload.format {
+        VaultFormat::PemBundle => (
+            pem_bundle(&[&resp.key, &resp.cert, &ca.intermediate.cert_pem]),
+            resp.key,
+        ),
+        _ => (resp.cert, resp.key),
+    };
+    let data = VaultCertData {
+        certificate,
+        private_key: Some(private_key),
+        private_key_type: Some(private_key_type),
+        serial_number: serial_to_hex(x509_der_from_bytes(&cert_der)?.raw_serial()),
+        expiration: resp.not_after,
+        ..ca_data(&ca, &payload.format)
+    };
+    Ok(Json(VaultResponse::new(data)))
+}
+
+/// Vault PKI `sign`: signs a CSR for the x509 client given as role
+///
+/// The CSR may only request names the client is configured with.
+pub async fn post_sign(
+    state: AppStateExtract,
+    Path((mount, role)): Path<(String, String)>,
+    headers: HeaderMap,
+    Json(payload): Json<VaultSignRequest>,
+) -> Result<Json<VaultResponse<VaultCertData>>, VaultError> {
+    let (client, ca_id) = vault_client(&state, &mount, &role, &headers).await?;
+
+    let mut client = client.with_profile().await?;
+    if let Some(ttl) = &payload.ttl {
+        client = client.restrict_validity(ttl.hours()?);
+    }
+    let csr = X509Csr::from_pem(&payload.csr)
+        .inspect_err(|err| metrics::cert_issue_failed(CertType::X509, err))?;
+    let enc_keys = state.read().await.enc_keys.clone();
+    let ca = CaCertX509Full::build_by_id(&ca_id, &enc_keys).await?;
+    let resp = client
+        .build_cert_from_csr(&ca, &csr)
+        .await
+        .inspect_err(|err| metrics::cert_issue_failed(CertType::X509, err))?;
+    metrics::cert_issued(
+        CertType::X509,
+        IssueFormat::Vault,
+        &client.group_id,
+        Some(&ca_id),
+    );
+    AuditEvent::new(audit::client_actor(&client.id), AuditAction::CertX509Issue)
+        .target(client.id)
+        .details(format!(
+            "Vault sign CSR, fingerprint: {}",
+            resp.cert_fingerprint
+        ))
+        .log()
+        .await?;
+
+    let cert_der = pem_to_der(&resp.cert)?;
+    let certificate = match payload.format {
+        VaultFormat::Pem => resp.cert,
+        VaultFormat::Der => b64_encode(cert_der.as_bytes()),
+        VaultFormat::PemBundle => pem_bundle(&[&resp.cert, &ca.intermediate.cert_pem]),
+    };
+    let data = VaultCertData {
+        certificate,
+        serial_number: serial_to_hex(x509_der_from_bytes(cert_der.as_bytes())?.raw_serial()),
+        expiration: resp.not_after,
+        ..ca_data(&ca, &payload.format)
+    };
+    Ok(Json(VaultResponse::new(data)))
+}
+
+/// Vault PKI `ca/pem`: the PEM encoded issuing CA of the group
+pub async fn get_ca_pem(Path(mount): Path<String>) -> Result<impl IntoResponse, VaultError> {
+    let group = mount_group(&mount).await?;
+    let ca_id = group_ca_id(&group)?;
+    let ca = CaCertX509Entity::find_by_id(&ca_id, CaCertX509Type::Certificate).await?;
+    Ok(([(header::CONTENT_TYPE, "application/pem-file")], ca.data))
+}
+
+/// Vault PKI `crl`: the DER encoded CRL of the issuing CA of the group
+pub async fn get_crl(
+    state: AppStateExtract,
+    Path(mount): Path<String>,
+) -> Result<impl IntoResponse, VaultError> {
+    let group = mount_group(&mount).await?;
+    let ca_id = group_ca_id(&group)?;
+    let crl = current_crl(&state, &ca_id.to_string()).await?;
+    Ok((
+        [(header::CONTENT_TYPE, "application/pkix-crl")],
+        crl.data.unwrap_or_default(),
+    ))
+}
+
+/// Vault PKI `revoke`: revokes a certificate by its serial
+///
+/// A client can only revoke certificates which have been issued for itself.
+pub async fn post_revoke(
+    state: AppStateExtract,
+    Path(mount): Path<String>,
+    headers: HeaderMap,
+    Json(payload): Json<VaultRevokeRequest>,
+) -> Result<Json<VaultResponse<VaultRevokeData>>, VaultError> {
+    let token = vault_token(&headers)?;
+    let group = mount_group(&mount).await?;
+    let serial = serial_from_hex(&payload.serial_number)?;
+
+    let cert = CertX509Entity::find_by_serial(serial).await?;
+    let client_id = match cert.client_id {
+        Some(client_id) if cert.group_id == Some(group.id) => client_id,
+        _ => {
+            return Err(ErrorResponse::new(
+                ErrorResponseType::Forbidden,
+                "This certificate has not been issued for a client of this mount",
+            )
+            .into())
+        }
+    };
+    let client = ClientX509Entity::find(&client_id).await?;
+    if let Err(err) = client.validate_active_enabled(state.clone(), token).await {
+        AuditEvent::new(audit::client_actor(&client.id), AuditAction::CertX509Revoke)
+            .target(serial)
+            .details(&err.message)
+            .failed()
+            .log()
+            .await?;
+        return Err(err.into());
+    }
+
+    let revoked = CertX509RevokedEntity::revoke_serial(
+        serial,
+        &X509RevocationReason::Unspecified,
+        None,
+        audit::client_actor(&client.id),
+    )
+    .await?;
+    info!(
+        "x509 certificate {} revoked by client {} via the Vault API",
+        serial, client.id
+    );
+    AuditEvent::new(audit::client_actor(&client.id), AuditAction::CertX509Revoke)
+        .target(serial)
+        .details("Vault revoke")
+        .log()
+        .await?;
+
+    let enc_keys = state.read().await.enc_keys.clone();
+    CrlX509Entity::rebuild_for_serials(&[revoked.serial], &enc_keys).await;
+
+    let data = VaultRevokeData {
+        revocation_time: revoked.revoked.unix_timestamp(),
+        revocation_time_rfc3339: revoked
+            .revoked
+            .format(&Rfc3339)
+            .map_err(|err| ErrorResponse::new(ErrorResponseType::Internal, err.to_string()))?,
+    };
+    Ok(Json(VaultResponse::new(data)))
+}
+
+/// Resolves the role to an x509 client of the mount and validates the token as its API key.
+/// The role is either the ID or the name of the client.
+async fn vault_client(
+    state: &AppStateExtract,
+    mount: &str,
+    role: &str,
+    headers: &HeaderMap,
+) -> Result<(ClientX509Entity, Uuid), ErrorResponse> {
+    let token = vault_token(headers)?;
+    let group = mount_group(mount).await?;
+
+    let client = match Uuid::from_str(role) {
+        Ok(id) => ClientX509Entity::find(&id).await?,
+        Err(_) => ClientX509Entity::find_by_name(&group.id, role).await?,
+    };
+    if client.group_id != group.id {
+        return Err(ErrorResponse::new(
+            ErrorResponseType::NotFound,
+            format!("Unknown role '{}'", role),
+        ));
+    }
+
+    let ca_id = validate_client(&client, state, token).await?;
+    Ok((client, ca_id))
+}
+
+fn group_ca_id(group: &GroupEntity) -> Result<Uuid, ErrorResponse> {
+    group.ca_x509.ok_or_else(|| {
+        ErrorResponse::new(
+            ErrorResponseType::NotFound,
+            "This mount has no X509 CA configured",
+        )
+    })
+}
+
+/// The issuing CA and the chain in the requested format, without any certificate
+fn ca_data(ca: &CaCertX509Full, format: &VaultFormat) -> VaultCertData {
+    let (issuing_ca, root) = match format {
+        VaultFormat::Der => (
+            b64_encode(ca.intermediate.cert_der.as_bytes()),
+            b64_encode(ca.root.cert_der.as_bytes()),
+        ),
+        _ => (ca.intermediate.cert_pem.clone(), ca.root.cert_pem.clone()),
+    };
+    VaultCertData {
+        certificate: String::default(),
+        ca_chain: vec![issuing_ca.clone(), root],
+        issuing_ca,
+        private_key: None,
+        private_key_type: None,
+        serial_number: String::default(),
+        expiration: 0,
+    }
+}
+
+fn pem_bundle(pems: &[&str]) -> String {
+    let mut bundle = pems
+        .iter()
+        .map(|pem| pem.trim_end())
+        .collect::<Vec<&str>>()
+        .join("\n");
+    bundle.push('\n');
+    bundle
+}
@@ -0,0 +1,18 @@
+use crate::models::db::acme_nonce::AcmeNonceEntity;
+use std::time::Duration;
+use tokio::time;
+use tracing::{debug, error};
+
+/// Cleans up expired ACME replay nonces from the database
+pub async fn acme_nonces_cleanup() {
+    let mut interval = time::interval(Duration::from_secs(600));
+
+    loop {
+        interval.tick().await;
+        debug!("Running acme_nonces_cleanup scheduler");
+
+        if let Err(err) = AcmeNonceEntity::delete_expired().await {
+            error!("acme_nonces_cleanup scheduler error: {:?}", err);
+        }
+    }
+}
@@ -0,0 +1,36 @@
+use crate::config::AppState;
+use crate::models::api::error_response::ErrorResponse;
+use crate::models::db::ca_cert_x509::{CaCertX509Entity, CaCertX509Type};
+use crate::models::db::crl_x509::CrlX509Entity;
+use std::time::Duration;
+use tokio::time;
+use tracing::{debug, error};
+
+/// Re-generates the CRLs of all intermediate CAs before they expire or when new revocations exist
+pub async fn crl_rebuild(state: AppState) {
+    let mut interval = time::interval(Duration::from_secs(300));
+
+    loop {
+        interval.tick().await;
+        debug!("Running crl_rebuild scheduler");
+
+        if let Err(err) = rebuild_all(&state).await {
+            error!("crl_rebuild scheduler error: {:?}", err);
+        }
+    }
+}
+
+async fn rebuild_all(state: &AppState) -> Result<(), ErrorResponse> {
+    let enc_keys = state.read().await.enc_keys.clone();
+
+    for ca in CaCertX509Entity::find_all_by_type(CaCertX509Type::Certificate).await? {
+        let crl = CrlX509Entity::find_or_default(&ca.id).await?;
+        if crl.needs_rebuild().await? {
+            if let Err(err) = CrlX509Entity::rebuild_by_ca_id(&ca.id, &enc_keys).await {
+                error!("Error generating the CRL for CA {}: {}", ca.id, err.message);
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,18 @@
+use crate::config::AppState;
+use crate::service::enc_keys;
+use std::time::Duration;
+use tracing::{debug, error};
+
+/// Picks up enc key rotations and master key re-keys, which have been done by other instances
+pub async fn enc_key_refresh(state: AppState) {
+    let mut interval = tokio::time::interval(Duration::from_secs(30));
+
+    loop {
+        interval.tick().await;
+        debug!("Running enc_key_refresh scheduler");
+
+        if let Err(err) = enc_keys::refresh_active(&state).await {
+            error!("enc_key_refresh scheduler error: {:?}", err);
+        }
+    }
+}
@@ -0,0 +1,246 @@
+use crate::constants::{NOTIFY_EXPIRY_DAYS, NOTIFY_EXPIRY_OWNERS};
+use crate::models::api::error_response::ErrorResponse;
+use crate::models::db::ca_cert_x509::{CaCertX509Entity, CaCertX509Type};
+use crate::models::db::cert_ssh::CertSshEntity;
+use crate::models::db::cert_x509::CertX509Entity;
+use crate::models::db::client_ssh::ClientSshEntity;
+use crate::models::db::client_x509::ClientX509Entity;
+use crate::models::db::notification_expiry::NotificationExpiryEntity;
+use crate::notifications;
+use crate::notifications::{ExpiryObjectType, Notification};
+use std::time::Duration;
+use time::OffsetDateTime;
+use tracing::{debug, error, info};
+use uuid::Uuid;
+
+/// Something which will expire and should be notified about
+struct Expiring {
+    typ: ExpiryObjectType,
+    id: String,
+    name: String,
+    group_id: Option<Uuid>,
+    /// Only set for certificates. Thresholds longer than the full lifetime will be skipped,
+    /// so short-lived certificates do not create any noise.
+    created: Option<OffsetDateTime>,
+    expires: OffsetDateTime,
+    owner: Option<String>,
+}
+
+/// Sends out notifications for CAs, clients and certificates at each of the
+/// `NOTIFY_EXPIRY_DAYS` before they expire
+pub async fn expiry_notifications() {
+    if !notifications::is_configured() || NOTIFY_EXPIRY_DAYS.is_empty() {
+        info!("No notification channel configured - expiry notifications are disabled");
+        return;
+    }
+
+    let mut interval = tokio::time::interval(Duration::from_secs(3600));
+
+    loop {
+        interval.tick().await;
+        debug!("Running expiry_notifications scheduler");
+
+        if let Err(err) = notify_all().await {
+            error!("expiry_notifications scheduler error: {:?}", err);
+        }
+    }
+}
+
+async fn notify_all() -> Result<(), ErrorResponse> {
+    let now = OffsetDateTime::now_utc();
+    let max_days = *NOTIFY_EXPIRY_DAYS.last().unwrap();
+    let until = now + time::Duration::days(max_days);
+
+    let mut expiring = Vec::new();
+
+    for ca in CaCertX509Entity::find_all_certs().await? {
+        if let Some(expires) = ca.expires {
+            // the root and its intermediate share the same id, which would make them share the
+            // notification claims as well
+            let (id, name) = match ca.typ {
+                CaCertX509Type::Root => (format!("{}/root", ca.id), format!("{} Root", ca.name)),
+                _ => (
+                    format!("{}/intermediate", ca.id),
+                    format!("{} Intermediate", ca.name),
+                ),
+            };
+            expiring.push(Expiring {
+                typ: ExpiryObjectType::CaX509,
+                id,
+                name,
+                group_id: None,
+                created: None,
+                expires,
+                owner: None,
+            });
+        }
+    }
+
+    for client in ClientX509Entity::find_all().await? {
+        if let (true, Some(expires)) = (client.enabled, client.expires) {
+            expiring.push(Expiring {
+                typ: ExpiryObjectType::ClientX509,
+                id: client.id.to_string(),
+                name: client.name,
+                group_id: Some(client.group_id),
+                created: None,
+                expires,
+                owner: Some(client.email),
+            });
+        }
+    }
+
+    for client in ClientSshEntity::find_all().await? {
+        if let (true, Some(expires)) = (client.enabled, client.expires) {
+            expiring.push(Expiring {
+                typ: ExpiryObjectType::ClientSsh,
+                id: client.id.to_string(),
+                name: client.name,
+                group_id: Some(client.group_id),
+                created: None,
+                expires,
+                owner: None,
+            });
+        }
+    }
+
+    for cert in CertX509Entity::find_expiring(until).await? {
+        expiring.push(Expiring {
+            typ: ExpiryObjectType::CertX509,
+            id: cert.serial.to_string(),
+            name: cert
+                .subject
+                .unwrap_or_else(|| format!("serial {}", cert.serial)),
+            group_id: cert.group_id,
+            created: Some(cert.created),
+            expires: cert.expires,
+            owner: cert.email,
+        });
+    }
+
+    for cert in CertSshEntity::find_expiring(until).await? {
+        let key_id = cert
+            .key_id
+            .unwrap_or_else(|| format!("serial {}", cert.serial));
+        let name = match cert.principals {
+            Some(principals) if !principals.is_empty() => {
+                format!("{} ({})", key_id, principals.join(", "))
+            }
+            _ => key_id,
+        };
+        expiring.push(Expiring {
+            typ: ExpiryObjectType::CertSsh,
+            id: cert.serial.to_string(),
+            name,
+            group_id: cert.group_id,
+            created: Some(cert.created),
+            expires: cert.expires,
+            owner: cert.email,
+        });
+    }
+
+    let mut sent = 0;
+    for obj in expiring {
+        let Some(threshold) = threshold(&obj, now, &NOTIFY_EXPIRY_DAYS) else {
+            continue;
+        };
+
+        let claim = NotificationExpiryEntity::new(obj.typ, obj.id.clone(), obj.expires, threshold);
+        if !claim.claim().await? {
+            continue;
+        }
+
+        let owner = if *NOTIFY_EXPIRY_OWNERS {
+            obj.owner.as_deref()
+        } else {
+            None
+        };
+        let notification = Notification::Expiry {
+            object_type: obj.typ,
+            id: obj.id,
+            name: obj.name,
+            group_id: obj.group_id,
+            expires: obj.expires.unix_timestamp(),
+            days_left: (obj.expires - now).whole_days(),
+        };
+
+        if let Err(err) = notification.send(owner).await {
+            error!("Error sending expiry notification: {}", err.message);
+            // will be retried with the next run
+            claim.release().await?;
+        } else {
+            sent += 1;
+        }
+    }
+
+    if sent > 0 {
+        info!("Sent {} expiry notifications", sent);
+    }
+    NotificationExpiryEntity::delete_expired().await?;
+
+    Ok(())
+}
+
+/// Returns the smallest threshold out of the sorted `days` the object has crossed already, if any
+fn threshold(obj: &Expiring, now: OffsetDateTime, days: &[i64]) -> Option<i64> {
+    if obj.expires <= now {
+        return None;
+    }
+    let left = obj.expires - now;
+
+    let days = *days.iter().find(|d| left <= time::Duration::days(**d))?;
+    if let Some(created) = obj.created {
+        if obj.expires - created <= time::Duration::days(days) {
+            return None;
+        }
+    }
+
+    Some(days)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_threshold() {
+        let now = OffsetDateTime::now_utc();
+        let days = [1, 7, 14, 30];
+        let expiring = |expires_in: time::Duration, lifetime: Option<time::Duration>| Expiring {
+            typ: ExpiryObjectType::CertX509,
+            id: "1".to_string(),
+            name: "test".to_string(),
+            group_id: None,
+            created: lifetime.map(|lifetime| now + expires_in - lifetime),
+            expires: now + expires_in,
+            owner: None,
+        };
+        let threshold_in =
+            |expires_in, lifetime| threshold(&expiring(expires_in, lifetime), now, &days);
+
+        // the smallest crossed threshold
+        assert_eq!(threshold_in(time::Duration::days(31), None), None);
+        assert_eq!(threshold_in(time::Duration::days(30), None), Some(30));
+        assert_eq!(threshold_in(time::Duration::days(20), None), Some(30));
+        assert_eq!(threshold_in(time::Duration::days(10), None), Some(14));
+        assert_eq!(threshold_in(time::Duration::days(3), None), Some(7));
+        assert_eq!(threshold_in(time::Duration::hours(5), None), Some(1));
+        assert_eq!(threshold_in(time::Duration::ZERO, None), None);
+        assert_eq!(threshold_in(time::Duration::days(-1), None), None);
+
+        // thresholds longer than the lifetime are skipped
+        let lifetime = Some(time::Duration::days(90));
+        assert_eq!(threshold_in(time::Duration::days(10), lifetime), Some(14));
+        let lifetime = Some(time::Duration::days(10));
+        assert_eq!(threshold_in(time::Duration::days(9), lifetime), None);
+        assert_eq!(threshold_in(time::Duration::days(5), lifetime), Some(7));
+        assert_eq!(threshold_in(time::Duration::hours(5), lifetime), Some(1));
+        let lifetime = Some(time::Duration::hours(24));
+        assert_eq!(threshold_in(time::Duration::hours(5), lifetime), None);
+
+        assert_eq!(
+            threshold(&expiring(time::Duration::days(3), None), now, &[]),
+            None
+        );
+    }
+}
@@ -0,0 +1,68 @@
+use crate::models::db::cert_ssh::CertSshEntity;
+use crate::models::db::cert_x509::CertX509Entity;
+use tracing::{error, info};
+
+const BATCH_SIZE: i64 = 100;
+
+/// Fills the searchable metadata for certificates which have been issued before the
+/// inventory existed. Runs only once at startup.
+pub async fn certs_metadata_backfill() {
+    let mut count = 0;
+
+    let mut last_serial = 0;
+    loop {
+        let certs = match CertX509Entity::find_without_metadata(last_serial, BATCH_SIZE).await {
+            Ok(certs) => certs,
+            Err(err) => {
+                error!("certs_metadata_backfill scheduler error: {:?}", err);
+                break;
+            }
+        };
+        if certs.is_empty() {
+            break;
+        }
+
+        for mut cert in certs {
+            last_serial = cert.serial;
+            match cert.update_data().await {
+                Ok(_) => count += 1,
+                Err(err) => error!(
+                    "Cannot decode the x509 certificate with serial {}: {}",
+                    cert.serial, err.message
+                ),
+            }
+        }
+    }
+
+    let mut last_serial = 0;
+    loop {
+        let certs = match CertSshEntity::find_without_metadata(last_serial, BATCH_SIZE).await {
+            Ok(certs) => certs,
+            Err(err) => {
+                error!("certs_metadata_backfill scheduler error: {:?}", err);
+                break;
+            }
+        };
+        if certs.is_empty() {
+            break;
+        }
+
+        for mut cert in certs {
+            last_serial = cert.serial;
+            match cert.update_data().await {
+                Ok(_) => count += 1,
+                Err(err) => error!(
+                    "Cannot decode the SSH certificate with serial {}: {}",
+                    cert.serial, err.message
+                ),
+            }
+        }
+    }
+
+    if count > 0 {
+        info!(
+            "Inventory metadata added for {} existing certificates",
+            count
+        );
+    }
+}
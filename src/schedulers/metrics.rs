@@ -0,0 +1,37 @@
+use crate::metrics;
+use crate::models::api::error_response::ErrorResponse;
+use crate::models::db::ca_cert_x509::{CaCertX509Entity, CaCertX509Type};
+use crate::models::db::session::SessionEntity;
+use std::time::Duration;
+use time::OffsetDateTime;
+use tracing::{debug, error};
+
+/// Updates the gauges, which cannot be tracked at the time something happens
+pub async fn metrics_gauges() {
+    let mut interval = tokio::time::interval(Duration::from_secs(300));
+
+    loop {
+        interval.tick().await;
+        debug!("Running metrics_gauges scheduler");
+
+        if let Err(err) = update_gauges().await {
+            error!("metrics_gauges scheduler error: {:?}", err);
+        }
+    }
+}
+
+async fn update_gauges() -> Result<(), ErrorResponse> {
+    let now = OffsetDateTime::now_utc();
+    for ca in CaCertX509Entity::find_all_certs().await? {
+        if let Some(expires) = ca.expires {
+            let typ = match ca.typ {
+                CaCertX509Type::Root => "root",
+                _ => "intermediate",
+            };
+            metrics::ca_expiry(&ca.id, &ca.name, typ, (expires - now).whole_days());
+        }
+    }
+
+    metrics::sessions_active(SessionEntity::count_active().await?);
+    Ok(())
+}
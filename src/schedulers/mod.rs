@@ -1,16 +1,34 @@
 use crate::config::AppState;
+use crate::schedulers::acme::acme_nonces_cleanup;
+use crate::schedulers::crl::crl_rebuild;
+use crate::schedulers::enc_keys::enc_key_refresh;
+use crate::schedulers::expiry::expiry_notifications;
+use crate::schedulers::inventory::certs_metadata_backfill;
+use crate::schedulers::metrics::metrics_gauges;
 use crate::schedulers::remote_auto_unseal::auto_unseal_task;
 use crate::schedulers::sessions::sessions_cleanup;
 use std::thread;
 use tracing::debug;
 
+mod acme;
+mod crl;
+mod enc_keys;
+mod expiry;
+mod inventory;
+mod metrics;
 mod remote_auto_unseal;
 mod sessions;
 
 pub async fn scheduler_main(state: AppState) {
     debug!("Schedulers started on {:?}", thread::current().id());
 
+    tokio::spawn(acme_nonces_cleanup());
     tokio::spawn(sessions_cleanup());
+    tokio::spawn(crl_rebuild(state.clone()));
+    tokio::spawn(enc_key_refresh(state.clone()));
+    tokio::spawn(certs_metadata_backfill());
+    tokio::spawn(expiry_notifications());
+    tokio::spawn(metrics_gauges());
     tokio::spawn(auto_unseal_task(state));
 }
 
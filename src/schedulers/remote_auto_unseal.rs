@@ -21,8 +21,6 @@ pub async fn auto_unseal_task(state: AppState) {
     let mut interval = time::interval(Duration::from_secs(sec));
     let rate_limit = Duration::from_secs(*UNSEAL_RATE_LIMIT as u64);
 
-    let shard_1 = state.read().await.enc_keys.master_shard_1.clone();
-    let shard_2 = state.read().await.enc_keys.master_shard_2.clone();
     let ca_chain = state.read().await.ca_chain_pem.clone();
 
     debug!("Running auto_unseal_task scheduler");
@@ -30,21 +28,18 @@ pub async fn auto_unseal_task(state: AppState) {
     loop {
         interval.tick().await;
 
-        // try push first key
-        if let Some(key) = &shard_1 {
-            if let Err(err) = push_shard_to_remotes(key.clone(), ca_chain.as_bytes()).await {
-                error!("{:?}", err);
-            }
-        }
+        // the shares may change with a master key re-key
+        let shares = state.read().await.enc_keys.master_shares.clone();
 
-        // sleep for the rate limiter
-        time::sleep(rate_limit).await;
-        // small security margin
-        time::sleep(Duration::from_millis(100)).await;
+        for (i, key) in shares.into_values().enumerate() {
+            if i > 0 {
+                // sleep for the rate limiter
+                time::sleep(rate_limit).await;
+                // small security margin
+                time::sleep(Duration::from_millis(100)).await;
+            }
 
-        // try push the second key
-        if let Some(key) = &shard_2 {
-            if let Err(err) = push_shard_to_remotes(key.clone(), ca_chain.as_bytes()).await {
+            if let Err(err) = push_shard_to_remotes(key, ca_chain.as_bytes()).await {
                 error!("{:?}", err);
             }
         }
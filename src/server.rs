@@ -1,3 +1,4 @@
+use std::collections::BTreeMap;
 use std::env;
 use std::net::SocketAddr;
 use std::str::FromStr;
@@ -8,8 +9,8 @@ use axum::body::Bytes;
 use axum::handler::HandlerWithoutStateExt;
 use axum::http::{header, HeaderName, StatusCode, Uri};
 use axum::response::Redirect;
-use axum::routing::{delete, get, post, put};
-use axum::{extract, BoxError, Router};
+use axum::routing::{delete, get, head, post, put};
+use axum::{extract, middleware, BoxError, Router};
 use axum_extra::headers::HeaderValue;
 use axum_server::tls_rustls::RustlsConfig;
 use axum_server::Handle;
@@ -25,17 +26,26 @@ use utoipa_swagger_ui::SwaggerUi;
 use uuid::Uuid;
 use x509_parser::nom::AsBytes;
 
+use crate::acme::dns::DNS_UPDATER;
 use crate::certificates::encryption::{kdf_danger_static, EncAlg};
+use crate::certificates::pkcs11;
 use crate::certificates::x509::end_entity::nioca_server_cert;
 use crate::config::{Config, ConfigSealed, Db, EncKeys};
-use crate::constants::{AUTO_UNSEAL, DEV_MODE, INSTANCE_UUID, XSRF_HEADER};
+use crate::constants::{AUTO_UNSEAL, DEV_MODE, INSTANCE_UUID, METRICS_PORT, XSRF_HEADER};
+use crate::metrics;
 use crate::models::api::openapi::ApiDoc;
 use crate::models::db::enc_key::EncKeyEntity;
-use crate::routes::{ca, groups, unsealed, users};
+use crate::models::db::master_key::{MasterKeyEntity, MasterKeyRow};
+use crate::routes;
+use crate::routes::{
+    acme, audit, backup, ca, certs_ssh, certs_x509, enc_keys, groups, notifications, ocsp,
+    profiles, spiffe, unsealed, users, vault,
+};
 use crate::routes::{clients_ssh, sealed};
 use crate::routes::{clients_x509, oidc};
 use crate::schedulers::scheduler_main;
 use crate::service::password_hasher;
+use crate::service::shamir;
 use crate::VERSION;
 
 #[derive(Debug, Clone, Copy)]
@@ -72,8 +82,13 @@ pub async fn run_server(level: &str) -> Result<(), anyhow::Error> {
     info!("Log Level set to {}", level);
 
     info!("Nioca instance uuid: {}", *INSTANCE_UUID);
+    if let Some(updater) = &*DNS_UPDATER {
+        info!("ACME DNS-01 updates enabled for zones: {}", updater.zones());
+    }
 
     Db::init().await?;
+    metrics::init();
+    pkcs11::init().map_err(|err| anyhow::Error::msg(err.message))?;
 
     // build middleware
     let sensitive_headers: Arc<[_]> = vec![
@@ -142,6 +157,10 @@ pub async fn run_server(level: &str) -> Result<(), anyhow::Error> {
     // run the password hasher
     tokio::spawn(password_hasher::run());
 
+    if let Some(port) = *METRICS_PORT {
+        tokio::spawn(metrics_server(port));
+    }
+
     // TLS config unseal
     let tls_config_unseal = {
         let cert = env::var("UNSEAL_CERT_B64").expect("UNSEAL_CERT_B64 is missing");
@@ -169,7 +188,7 @@ pub async fn run_server(level: &str) -> Result<(), anyhow::Error> {
     }
 
     let config_sealed = ConfigSealed::new(tx_enc_key, tx_exit).await?;
-    let routes_sealed = Router::new()
+    let routes_sealed = with_metrics(Router::new())
         .route("/api/status", get(sealed::get_status))
         .nest(
             "/unseal",
@@ -183,6 +202,7 @@ pub async fn run_server(level: &str) -> Result<(), anyhow::Error> {
         )
         .nest_service("/", ServeDir::new("static"))
         .layer(middleware.clone().into_inner())
+        .layer(axum::middleware::from_fn(metrics::track_http))
         .with_state(config_sealed);
     debug!("Router created");
 
@@ -239,7 +259,7 @@ pub async fn run_server(level: &str) -> Result<(), anyhow::Error> {
     };
 
     // main routes
-    let routes = Router::new()
+    let routes = with_metrics(Router::new())
         .nest(
             "/api",
             Router::new()
@@ -247,9 +267,24 @@ pub async fn run_server(level: &str) -> Result<(), anyhow::Error> {
                 .route("/ca/ssh/external", post(ca::post_external_ca_ssh))
                 .route("/ca/ssh/generate", post(ca::post_generate_ca_ssh))
                 .route("/ca/ssh/:id", delete(ca::delete_ca_ssh))
+                .route("/ca/ssh/:id/pkcs11", post(ca::post_ca_ssh_pkcs11))
                 .route("/ca/x509", get(ca::get_ca_x509).post(ca::post_ca_x509))
                 .route("/ca/x509/inspect", get(ca::get_ca_x509_inspect))
+                .route(
+                    "/ca/x509/csr",
+                    get(ca::get_ca_x509_csr).post(ca::post_ca_x509_csr),
+                )
                 .route("/ca/x509/:id", delete(ca::delete_ca_x509))
+                .route("/ca/x509/:id/cert", post(ca::post_ca_x509_cert))
+                .route("/ca/x509/:id/pkcs11", post(ca::post_ca_x509_pkcs11))
+                .route(
+                    "/ca/x509/:id/crl",
+                    get(ca::get_ca_x509_crl).put(ca::put_ca_x509_crl),
+                )
+                .route(
+                    "/ca/x509/:id/ocsp",
+                    get(ca::get_ca_x509_ocsp).put(ca::put_ca_x509_ocsp),
+                )
                 .route(
                     "/clients/ssh",
                     get(clients_ssh::get_clients).post(clients_ssh::post_client),
@@ -264,6 +299,10 @@ pub async fn run_server(level: &str) -> Result<(), anyhow::Error> {
                     "/clients/ssh/:id/cert",
                     post(clients_ssh::post_build_client_cert),
                 )
+                .route(
+                    "/clients/ssh/:id/sign",
+                    post(clients_ssh::post_sign_client_pub_key),
+                )
                 .route(
                     "/clients/ssh/:id/secret",
                     get(clients_ssh::get_client_secret).put(clients_ssh::put_client_secret),
@@ -286,19 +325,80 @@ pub async fn run_server(level: &str) -> Result<(), anyhow::Error> {
                     "/clients/x509/:id/cert/p12",
                     post(clients_x509::post_build_client_cert_p12),
                 )
+                .route(
+                    "/clients/x509/:id/csr",
+                    post(clients_x509::post_build_client_cert_csr),
+                )
+                .route(
+                    "/clients/x509/:id/svid/jwt",
+                    post(clients_x509::post_client_jwt_svid),
+                )
                 .route(
                     "/clients/x509/:id/secret",
                     get(clients_x509::get_client_secret).put(clients_x509::put_client_secret),
                 )
+                .route(
+                    "/clients/x509/:id/revoke",
+                    post(certs_x509::post_revoke_client),
+                )
+                .route("/certs/x509", get(certs_x509::get_certs))
+                .route("/certs/x509/:serial", get(certs_x509::get_cert))
+                .route("/certs/x509/revoked", get(certs_x509::get_revoked))
+                .route(
+                    "/certs/x509/:serial/revoke",
+                    post(certs_x509::post_revoke_serial),
+                )
+                .route("/certs/ssh", get(certs_ssh::get_certs))
+                .route("/certs/ssh/:serial", get(certs_ssh::get_cert))
+                .route("/certs/ssh/revoked", get(certs_ssh::get_revoked))
+                .route(
+                    "/certs/ssh/revoke_key_id",
+                    post(certs_ssh::post_revoke_key_id),
+                )
+                .route(
+                    "/certs/ssh/revoke_public_key",
+                    post(certs_ssh::post_revoke_public_key),
+                )
+                .route(
+                    "/certs/ssh/:serial/revoke",
+                    post(certs_ssh::post_revoke_serial),
+                )
+                .route("/audit", get(audit::get_audit))
+                .route("/audit/verify", get(audit::get_verify))
+                .route("/backup", post(backup::post_backup))
+                .route("/backup/restore", post(backup::post_restore))
+                .route("/enc_keys", get(enc_keys::get_enc_keys))
+                .route("/enc_keys/rotate", post(enc_keys::post_rotate))
+                .route("/enc_keys/reencrypt", post(enc_keys::post_reencrypt))
+                .route("/enc_keys/rotation", get(enc_keys::get_rotation))
+                .route("/enc_keys/:id", delete(enc_keys::delete_enc_key))
                 .route("/groups", get(groups::get_groups).post(groups::post_group))
                 .route(
                     "/groups/:id",
                     put(groups::put_group).delete(groups::delete_group),
                 )
+                .route(
+                    "/groups/:id/acme/eab",
+                    get(groups::get_acme_eab_keys).post(groups::post_acme_eab_key),
+                )
+                .route(
+                    "/groups/:id/acme/eab/:kid",
+                    delete(groups::delete_acme_eab_key),
+                )
+                .route("/notifications/test", post(notifications::post_test))
+                .route(
+                    "/profiles",
+                    get(profiles::get_profiles).post(profiles::post_profile),
+                )
+                .route(
+                    "/profiles/:id",
+                    put(profiles::put_profile).delete(profiles::delete_profile),
+                )
                 .route("/login", post(unsealed::post_login))
                 .route("/login/check", get(unsealed::get_login_check))
                 .route("/logout", post(unsealed::post_logout))
                 .route("/password_change", put(unsealed::put_password_change))
+                .route("/master_key/rekey", post(unsealed::post_master_key_rekey))
                 .route("/sessions", post(unsealed::post_session))
                 .route("/oidc/auth", get(oidc::get_oidc_auth))
                 .route("/oidc/auth/redirect", get(oidc::get_oidc_auth_redirect))
@@ -311,19 +411,66 @@ pub async fn run_server(level: &str) -> Result<(), anyhow::Error> {
                 .route("/status", get(unsealed::get_status))
                 .route("/users", get(users::get_users))
                 .route("/users/:id/access", get(users::get_user_group_access))
+                .route("/users/:id/x509/revoke", post(certs_x509::post_revoke_user))
                 .route(
                     "/users/:user_id/access/:group_id",
                     post(users::post_user_group_access)
                         .put(users::put_user_group_access)
                         .delete(users::delete_user_group_access),
+                )
+                .route(
+                    "/users/me/groups/:group_id/ssh",
+                    post(users::post_user_ssh_cert),
+                )
+                .route(
+                    "/users/me/groups/:group_id/x509",
+                    post(users::post_user_x509_cert),
                 ),
         )
+        .nest(
+            "/acme/:group_id",
+            Router::new()
+                .route("/directory", get(acme::get_directory))
+                .route(
+                    "/new-nonce",
+                    head(acme::head_new_nonce).get(acme::get_new_nonce),
+                )
+                .route("/new-account", post(acme::post_new_account))
+                .route("/new-order", post(acme::post_new_order))
+                .route("/account/:id", post(acme::post_account))
+                .route("/account/:id/orders", post(acme::post_account_orders))
+                .route("/order/:id", post(acme::post_order))
+                .route("/order/:id/finalize", post(acme::post_order_finalize))
+                .route("/authz/:id", post(acme::post_authz))
+                .route("/chall/:id", post(acme::post_challenge))
+                .route("/cert/:id", post(acme::post_cert))
+                .route_layer(middleware::from_fn(acme::acme_headers)),
+        )
+        .nest(
+            "/v1/:mount",
+            Router::new()
+                .route(
+                    "/issue/:role",
+                    post(vault::post_issue).put(vault::post_issue),
+                )
+                .route("/sign/:role", post(vault::post_sign).put(vault::post_sign))
+                .route("/ca/pem", get(vault::get_ca_pem))
+                .route("/crl", get(vault::get_crl))
+                .route("/revoke", post(vault::post_revoke).put(vault::post_revoke)),
+        )
+        .route("/crl/x509/:id", get(ca::get_crl_der))
+        .route("/crl/x509/:id/pem", get(ca::get_crl_pem))
+        .route("/krl/ssh/:id", get(ca::get_krl))
+        .route("/ocsp/x509", post(ocsp::post_ocsp))
+        .route("/spiffe/:group_id/bundle", get(spiffe::get_bundle))
+        .route("/ocsp/x509/*request", get(ocsp::get_ocsp))
         .route("/unseal/status", get(unsealed::get_status))
         .route("/root.fingerprint", get(unsealed::get_root_fingerprint))
         .route("/root.pem", get(unsealed::get_root_pem))
         .merge(SwaggerUi::new("/docs/swagger-ui").url("/docs/openapi.json", ApiDoc::build()))
         .nest_service("/", ServeDir::new("static"))
         .layer(middleware.into_inner())
+        .layer(axum::middleware::from_fn(metrics::track_http))
         .with_state(app_state);
 
     if *DEV_MODE {
@@ -347,6 +494,39 @@ pub async fn run_server(level: &str) -> Result<(), anyhow::Error> {
     Ok(())
 }
 
+/// Adds the health checks and `/metrics`, if it is not served on its own port
+fn with_metrics<S>(router: Router<S>) -> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    let router = router
+        .route("/health/live", get(routes::metrics::get_live))
+        .route("/health/ready", get(routes::metrics::get_ready));
+    if METRICS_PORT.is_none() {
+        router.route("/metrics", get(routes::metrics::get_metrics))
+    } else {
+        router
+    }
+}
+
+/// Serves `/metrics` and the health checks via plain HTTP on the `METRICS_PORT`
+async fn metrics_server(port: u16) {
+    let router = Router::new()
+        .route("/metrics", get(routes::metrics::get_metrics))
+        .route("/health/live", get(routes::metrics::get_live))
+        .route("/health/ready", get(routes::metrics::get_ready));
+
+    let addr = SocketAddr::from(([0, 0, 0, 0], port));
+    info!("Metrics listening on {}", addr);
+
+    let listener = TcpListener::bind(addr)
+        .await
+        .expect("Cannot bind to METRICS_PORT");
+    axum::serve(listener, router.into_make_service())
+        .await
+        .expect("Starting the metrics server");
+}
+
 async fn redirect_http_to_https(ports: Ports) {
     fn make_https(host: String, uri: Uri, ports: Ports) -> Result<Uri, BoxError> {
         let mut parts = uri.into_parts();
@@ -392,9 +572,31 @@ async fn auto_unseal(tx: flume::Sender<EncKeys>, tx_exit: flume::Sender<()>) {
         "#
     );
 
-    let master_shard_1 = env::var("AUTO_UNSEAL_SHARD_1").expect("AUTO_UNSEAL_SHARD_1");
-    let master_shard_2 = env::var("AUTO_UNSEAL_SHARD_2").expect("AUTO_UNSEAL_SHARD_2");
-    let master_full = format!("{}{}", master_shard_1, master_shard_2);
+    // instances initialized before Shamir's Secret Sharing was supported use 2 shards
+    let master_shares: BTreeMap<u8, String> = match env::var("AUTO_UNSEAL_SHARES") {
+        Ok(shares) => shares
+            .split(',')
+            .map(|share| {
+                let index = hex::decode(share.trim()).expect("AUTO_UNSEAL_SHARES decoding")[0];
+                (index, share.trim().to_lowercase())
+            })
+            .collect(),
+        Err(_) => BTreeMap::from([
+            (
+                1,
+                env::var("AUTO_UNSEAL_SHARD_1").expect("AUTO_UNSEAL_SHARD_1"),
+            ),
+            (
+                2,
+                env::var("AUTO_UNSEAL_SHARD_2").expect("AUTO_UNSEAL_SHARD_2"),
+            ),
+        ]),
+    };
+    let mk_entity = MasterKeyEntity::build()
+        .await
+        .expect("Reading the master key config");
+    let master_full =
+        shamir::combine(&mk_entity, &master_shares).expect("Bad DEV AUTO_UNSEAL setup");
     // let master_key_digest = digest::digest(&digest::SHA256, master_full.as_bytes());
     let master_key = kdf_danger_static(master_full.as_bytes())
         .await
@@ -406,16 +608,31 @@ async fn auto_unseal(tx: flume::Sender<EncKeys>, tx_exit: flume::Sender<()>) {
     let enc_hex = env::var("AUTO_UNSEAL_ENC_VALUE").expect("AUTO_UNSEAL_ENC_VALUE");
     let enc_bytes = hex::decode(enc_hex).expect("AUTO_UNSEAL_ENC_VALUE decoding");
 
+    let mut enc_key = EncKeyEntity {
+        id: Uuid::from_str(&enc_id).expect("AUTO_UNSEAL_ENC_UUID to uuid"),
+        alg: EncAlg::ChaCha20Poly1305.to_string(),
+        value: enc_bytes,
+        created: None,
+    };
+    // the enc key may have been rotated since the auto unseal values have been set up
+    let active = MasterKeyRow::find_enc_key_active()
+        .await
+        .expect("Reading the active enc key");
+    if active != enc_key.id {
+        warn!(
+            "AUTO_UNSEAL_ENC_UUID is not the active enc key anymore: {}",
+            active
+        );
+        enc_key = EncKeyEntity::find(&active, &master_key)
+            .await
+            .expect("Reading the active enc key");
+    }
+
     let enc_keys = EncKeys {
-        master_shard_1: None,
-        master_shard_2: None,
+        master_shares,
         master_key,
         pepper: master_full,
-        enc_key: EncKeyEntity {
-            id: Uuid::from_str(&enc_id).expect("AUTO_UNSEAL_ENC_UUID to uuid"),
-            alg: EncAlg::ChaCha20Poly1305.to_string(),
-            value: enc_bytes,
-        },
+        enc_key,
     };
 
     tx.send_async(enc_keys).await.unwrap();
@@ -0,0 +1,522 @@
+use crate::certificates::encryption::{decrypt, encrypt};
+use crate::config::EncKeys;
+use crate::models::api::error_response::{ErrorResponse, ErrorResponseType};
+use crate::models::api::response::{AuditVerifyError, AuditVerifyResponse};
+use crate::models::db::audit_key::AuditKeyEntity;
+use crate::models::db::audit_log::AuditLogEntity;
+use ring::{digest, hkdf, hmac};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::{Mutex, RwLock};
+use time::OffsetDateTime;
+use tracing::{error, info, warn};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+/// Upper limit for events which are buffered in memory while Nioca is sealed
+const PENDING_MAX: usize = 1000;
+const VERIFY_BATCH: i64 = 1000;
+
+static AUDIT_KEY: RwLock<Option<AuditKey>> = RwLock::new(None);
+/// Keys derived from previous master keys, only used for verification
+static PREVIOUS_KEYS: RwLock<Vec<AuditKey>> = RwLock::new(Vec::new());
+static PENDING: Mutex<VecDeque<AuditEvent>> = Mutex::new(VecDeque::new());
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub enum AuditAction {
+    Init,
+    UnsealShard,
+    Unseal,
+    Login,
+    LoginOidc,
+    PasswordChange,
+    CaSshCreate,
+    CaSshDelete,
+    CaSshKeyPkcs11,
+    CaX509Create,
+    CaX509CsrCreate,
+    CaX509Delete,
+    CaX509KeyPkcs11,
+    CaX509CrlConfig,
+    CaX509OcspConfig,
+    ClientX509Create,
+    ClientX509Update,
+    ClientX509Delete,
+    ClientX509SecretRead,
+    ClientX509SecretRotate,
+    ClientSshCreate,
+    ClientSshUpdate,
+    ClientSshDelete,
+    ClientSshSecretRead,
+    ClientSshSecretRotate,
+    CertX509Issue,
+    CertSshIssue,
+    JwtSvidIssue,
+    CertX509Revoke,
+    CertSshRevoke,
+    SshKeyIdRevoke,
+    SshPublicKeyRevoke,
+    GroupCreate,
+    GroupUpdate,
+    GroupDelete,
+    AcmeEabKeyCreate,
+    AcmeEabKeyDelete,
+    ProfileCreate,
+    ProfileUpdate,
+    ProfileDelete,
+    UserAccessCreate,
+    UserAccessUpdate,
+    UserAccessDelete,
+    OidcConfigUpdate,
+    MasterKeyRekey,
+    EncKeyRotate,
+    EncKeyReEncrypt,
+    EncKeyRetire,
+    BackupCreate,
+    BackupRestore,
+}
+
+impl AuditAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Init => "Init",
+            Self::UnsealShard => "UnsealShard",
+            Self::Unseal => "Unseal",
+            Self::Login => "Login",
+            Self::LoginOidc => "LoginOidc",
+            Self::PasswordChange => "PasswordChange",
+            Self::CaSshCreate => "CaSshCreate",
+            Self::CaSshDelete => "CaSshDelete",
+            Self::CaSshKeyPkcs11 => "CaSshKeyPkcs11",
+            Self::CaX509Create => "CaX509Create",
+            Self::CaX509CsrCreate => "CaX509CsrCreate",
+            Self::CaX509Delete => "CaX509Delete",
+            Self::CaX509KeyPkcs11 => "CaX509KeyPkcs11",
+            Self::CaX509CrlConfig => "CaX509CrlConfig",
+            Self::CaX509OcspConfig => "CaX509OcspConfig",
+            Self::ClientX509Create => "ClientX509Create",
+            Self::ClientX509Update => "ClientX509Update",
+            Self::ClientX509Delete => "ClientX509Delete",
+            Self::ClientX509SecretRead => "ClientX509SecretRead",
+            Self::ClientX509SecretRotate => "ClientX509SecretRotate",
+            Self::ClientSshCreate => "ClientSshCreate",
+            Self::ClientSshUpdate => "ClientSshUpdate",
+            Self::ClientSshDelete => "ClientSshDelete",
+            Self::ClientSshSecretRead => "ClientSshSecretRead",
+            Self::ClientSshSecretRotate => "ClientSshSecretRotate",
+            Self::CertX509Issue => "CertX509Issue",
+            Self::CertSshIssue => "CertSshIssue",
+            Self::JwtSvidIssue => "JwtSvidIssue",
+            Self::CertX509Revoke => "CertX509Revoke",
+            Self::CertSshRevoke => "CertSshRevoke",
+            Self::SshKeyIdRevoke => "SshKeyIdRevoke",
+            Self::SshPublicKeyRevoke => "SshPublicKeyRevoke",
+            Self::GroupCreate => "GroupCreate",
+            Self::GroupUpdate => "GroupUpdate",
+            Self::GroupDelete => "GroupDelete",
+            Self::AcmeEabKeyCreate => "AcmeEabKeyCreate",
+            Self::AcmeEabKeyDelete => "AcmeEabKeyDelete",
+            Self::ProfileCreate => "ProfileCreate",
+            Self::ProfileUpdate => "ProfileUpdate",
+            Self::ProfileDelete => "ProfileDelete",
+            Self::UserAccessCreate => "UserAccessCreate",
+            Self::UserAccessUpdate => "UserAccessUpdate",
+            Self::UserAccessDelete => "UserAccessDelete",
+            Self::OidcConfigUpdate => "OidcConfigUpdate",
+            Self::MasterKeyRekey => "MasterKeyRekey",
+            Self::EncKeyRotate => "EncKeyRotate",
+            Self::EncKeyReEncrypt => "EncKeyReEncrypt",
+            Self::EncKeyRetire => "EncKeyRetire",
+            Self::BackupCreate => "BackupCreate",
+            Self::BackupRestore => "BackupRestore",
+        }
+    }
+}
+
+/// The MAC key for the audit log, derived from the master key
+#[derive(Clone)]
+pub struct AuditKey {
+    pub id: String,
+    raw: Vec<u8>,
+    key: hmac::Key,
+}
+
+impl AuditKey {
+    pub fn derive(master_key: &[u8]) -> Self {
+        struct Len;
+        impl hkdf::KeyType for Len {
+            fn len(&self) -> usize {
+                32
+            }
+        }
+
+        let mut raw = [0u8; 32];
+        hkdf::Salt::new(hkdf::HKDF_SHA256, b"nioca-audit-log")
+            .extract(master_key)
+            .expand(&[b"audit-log-mac"], Len)
+            .expect("32 bytes to be a valid HKDF-SHA256 length")
+            .fill(&mut raw)
+            .expect("32 bytes to be a valid HKDF-SHA256 length");
+
+        Self::from_raw(&raw)
+    }
+
+    fn from_raw(raw: &[u8]) -> Self {
+        let id = hex::encode(&digest::digest(&digest::SHA256, raw).as_ref()[..8]);
+        Self {
+            id,
+            raw: raw.to_vec(),
+            key: hmac::Key::new(hmac::HMAC_SHA256, raw),
+        }
+    }
+
+    /// Encrypts this key with the given master key to be able to keep it after a re-key
+    pub fn encrypt(&self, master_key: &[u8]) -> Result<AuditKeyEntity, ErrorResponse> {
+        Ok(AuditKeyEntity {
+            id: self.id.clone(),
+            value: encrypt(&self.raw, master_key)?,
+        })
+    }
+
+    pub fn decrypt(entity: &AuditKeyEntity, master_key: &[u8]) -> Result<Self, ErrorResponse> {
+        Ok(Self::from_raw(&decrypt(&entity.value, master_key)?))
+    }
+
+    fn mac(&self, hash: &[u8]) -> Vec<u8> {
+        hmac::sign(&self.key, hash).as_ref().to_vec()
+    }
+
+    fn verify(&self, hash: &[u8], mac: &[u8]) -> bool {
+        hmac::verify(&self.key, hash, mac).is_ok()
+    }
+}
+
+/// Sets the audit key after a successful unseal and writes all events which have been
+/// buffered while Nioca was sealed.
+pub async fn init(enc_keys: &EncKeys) {
+    let key = AuditKey::derive(&enc_keys.master_key);
+    info!("Audit log key id: {}", key.id);
+    *AUDIT_KEY.write().unwrap() = Some(key);
+
+    match AuditKeyEntity::find_all().await {
+        Ok(entities) => {
+            let mut previous = Vec::with_capacity(entities.len());
+            for entity in entities {
+                match AuditKey::decrypt(&entity, &enc_keys.master_key) {
+                    Ok(key) => previous.push(key),
+                    Err(err) => error!("Cannot decrypt audit key {}: {}", entity.id, err.message),
+                }
+            }
+            *PREVIOUS_KEYS.write().unwrap() = previous;
+        }
+        Err(err) => error!("Error loading previous audit keys: {}", err.message),
+    }
+
+    let pending = PENDING.lock().unwrap().drain(..).collect::<Vec<_>>();
+    for event in pending {
+        if let Err(err) = event.log().await {
+            error!("Error writing buffered audit event: {}", err.message);
+        }
+    }
+}
+
+/// Switches to the audit key for a new master key after a re-key. The current one is kept for
+/// verifying older entries.
+pub fn rekey(new_master_key: &[u8]) {
+    let key = AuditKey::derive(new_master_key);
+    info!("New audit log key id: {}", key.id);
+    if let Some(old) = AUDIT_KEY.write().unwrap().replace(key) {
+        PREVIOUS_KEYS.write().unwrap().push(old);
+    }
+}
+
+/// The actor for requests authenticated with a clients API key
+pub fn client_actor(id: &Uuid) -> String {
+    format!("client:{}", id)
+}
+
+fn audit_key() -> Option<AuditKey> {
+    AUDIT_KEY.read().unwrap().clone()
+}
+
+/// A single event for the audit log.
+///
+/// `AuditEvent::new(actor, action).target(id).log().await?`
+#[derive(Debug, Clone)]
+pub struct AuditEvent {
+    timestamp: OffsetDateTime,
+    actor: String,
+    action: AuditAction,
+    target: Option<String>,
+    details: Option<String>,
+    success: bool,
+}
+
+impl AuditEvent {
+    pub fn new(actor: impl Into<String>, action: AuditAction) -> Self {
+        let now = OffsetDateTime::now_utc();
+        // Postgres only stores microseconds - the hash must be reproducible from the db
+        let timestamp = now
+            .replace_nanosecond(now.nanosecond() / 1000 * 1000)
+            .unwrap_or(now);
+
+        Self {
+            timestamp,
+            actor: actor.into(),
+            action,
+            target: None,
+            details: None,
+            success: true,
+        }
+    }
+
+    pub fn target(mut self, target: impl ToString) -> Self {
+        self.target = Some(target.to_string());
+        self
+    }
+
+    pub fn details(mut self, details: impl ToString) -> Self {
+        self.details = Some(details.to_string());
+        self
+    }
+
+    pub fn failed(mut self) -> Self {
+        self.success = false;
+        self
+    }
+
+    /// Appends the event to the audit log. While Nioca is sealed, it will be buffered in
+    /// memory and written right after the unseal.
+    pub async fn log(self) -> Result<(), ErrorResponse> {
+        let Some(key) = audit_key() else {
+            let mut pending = PENDING.lock().unwrap();
+            if pending.len() >= PENDING_MAX {
+                warn!("Too many buffered audit events while sealed - dropping the oldest one");
+                pending.pop_front();
+            }
+            pending.push_back(self);
+            return Ok(());
+        };
+
+        AuditLogEntity::append(|id, prev_hash| {
+            let hash = hash_entry(
+                &prev_hash,
+                id,
+                self.timestamp,
+                &self.actor,
+                self.action.as_str(),
+                self.target.as_deref(),
+                self.details.as_deref(),
+                self.success,
+            );
+            let mac = key.mac(&hash);
+
+            Ok(AuditLogEntity {
+                id,
+                timestamp: self.timestamp,
+                actor: self.actor,
+                action: self.action.as_str().to_string(),
+                target: self.target,
+                details: self.details,
+                success: self.success,
+                prev_hash,
+                hash,
+                mac,
+                key_id: key.id,
+            })
+        })
+        .await?;
+
+        Ok(())
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn hash_entry(
+    prev_hash: &[u8],
+    id: i64,
+    timestamp: OffsetDateTime,
+    actor: &str,
+    action: &str,
+    target: Option<&str>,
+    details: Option<&str>,
+    success: bool,
+) -> Vec<u8> {
+    fn update_str(ctx: &mut digest::Context, value: &str) {
+        ctx.update(&(value.len() as u64).to_be_bytes());
+        ctx.update(value.as_bytes());
+    }
+
+    fn update_opt(ctx: &mut digest::Context, value: Option<&str>) {
+        match value {
+            None => ctx.update(&[0]),
+            Some(v) => {
+                ctx.update(&[1]);
+                update_str(ctx, v);
+            }
+        }
+    }
+
+    let micros = (timestamp.unix_timestamp_nanos() / 1000) as i64;
+
+    let mut ctx = digest::Context::new(&digest::SHA256);
+    ctx.update(prev_hash);
+    ctx.update(&id.to_be_bytes());
+    ctx.update(&micros.to_be_bytes());
+    update_str(&mut ctx, actor);
+    update_str(&mut ctx, action);
+    update_opt(&mut ctx, target);
+    update_opt(&mut ctx, details);
+    ctx.update(&[success as u8]);
+    ctx.finish().as_ref().to_vec()
+}
+
+fn hash_of(entry: &AuditLogEntity) -> Vec<u8> {
+    hash_entry(
+        &entry.prev_hash,
+        entry.id,
+        entry.timestamp,
+        &entry.actor,
+        &entry.action,
+        entry.target.as_deref(),
+        entry.details.as_deref(),
+        entry.success,
+    )
+}
+
+/// Walks the whole chain and checks the sequence, the links, the hashes and the MACs
+pub async fn verify() -> Result<AuditVerifyResponse, ErrorResponse> {
+    let key = audit_key().ok_or_else(|| {
+        ErrorResponse::new(
+            ErrorResponseType::ServiceUnavailable,
+            "The audit key is not available",
+        )
+    })?;
+    let mut keys = PREVIOUS_KEYS.read().unwrap().clone();
+    keys.push(key);
+
+    let mut res = AuditVerifyResponse {
+        valid: true,
+        entries: 0,
+        head_id: None,
+        head_hash: None,
+        error: None,
+    };
+
+    let mut last_id = 0;
+    let mut last_hash = vec![0u8; 32];
+    loop {
+        let batch = AuditLogEntity::find_batch(last_id, VERIFY_BATCH).await?;
+        if batch.is_empty() {
+            break;
+        }
+
+        for entry in batch {
+            if let Some(reason) = check_entry(&keys, &entry, last_id, &last_hash) {
+                res.valid = false;
+                res.error = Some(AuditVerifyError {
+                    id: entry.id,
+                    reason: reason.to_string(),
+                });
+                return Ok(res);
+            }
+
+            res.entries += 1;
+            last_id = entry.id;
+            last_hash = entry.hash;
+        }
+    }
+
+    if last_id > 0 {
+        res.head_id = Some(last_id);
+        res.head_hash = Some(hex::encode(last_hash));
+    }
+    Ok(res)
+}
+
+fn check_entry(
+    keys: &[AuditKey],
+    entry: &AuditLogEntity,
+    last_id: i64,
+    last_hash: &[u8],
+) -> Option<&'static str> {
+    if entry.id != last_id + 1 {
+        Some("missing entries before this one")
+    } else if entry.prev_hash != last_hash {
+        Some("broken link to the previous entry")
+    } else if hash_of(entry) != entry.hash {
+        Some("hash mismatch - the entry has been modified")
+    } else {
+        match keys.iter().find(|k| k.id == entry.key_id) {
+            None => Some("unknown audit key"),
+            Some(key) if !key.verify(&entry.hash, &entry.mac) => Some("invalid MAC"),
+            Some(_) => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_audit_chain() {
+        let key = AuditKey::derive(b"some master key");
+        assert_eq!(key.id, AuditKey::derive(b"some master key").id);
+        assert_ne!(key.id, AuditKey::derive(b"another master key").id);
+
+        let event = AuditEvent::new("admin", AuditAction::ClientX509Delete).target("123");
+        let prev_hash = vec![0u8; 32];
+        let hash = hash_entry(
+            &prev_hash,
+            1,
+            event.timestamp,
+            &event.actor,
+            event.action.as_str(),
+            event.target.as_deref(),
+            None,
+            true,
+        );
+        let mut entry = AuditLogEntity {
+            id: 1,
+            timestamp: event.timestamp,
+            actor: event.actor,
+            action: event.action.as_str().to_string(),
+            target: event.target,
+            details: None,
+            success: true,
+            prev_hash: prev_hash.clone(),
+            mac: key.mac(&hash),
+            hash,
+            key_id: key.id.clone(),
+        };
+        assert_eq!(
+            check_entry(std::slice::from_ref(&key), &entry, 0, &prev_hash),
+            None
+        );
+        assert!(check_entry(std::slice::from_ref(&key), &entry, 1, &prev_hash).is_some());
+
+        // a moved target must not produce the same hash
+        entry.target = None;
+        entry.details = Some("123".to_string());
+        assert!(check_entry(std::slice::from_ref(&key), &entry, 0, &prev_hash).is_some());
+        entry.details = None;
+        entry.target = Some("123".to_string());
+
+        entry.success = false;
+        assert!(check_entry(std::slice::from_ref(&key), &entry, 0, &prev_hash).is_some());
+        entry.success = true;
+
+        // entries from before a re-key must stay verifiable
+        let other = AuditKey::derive(b"another master key");
+        assert!(check_entry(std::slice::from_ref(&other), &entry, 0, &prev_hash).is_some());
+        let keys = [other.clone(), key.clone()];
+        assert_eq!(check_entry(&keys, &entry, 0, &prev_hash), None);
+        let master_key = [7u8; 32];
+        let restored = AuditKey::decrypt(&key.encrypt(&master_key).unwrap(), &master_key).unwrap();
+        assert_eq!(check_entry(&[restored], &entry, 0, &prev_hash), None);
+
+        entry.key_id = other.id.clone();
+        assert!(check_entry(&[other], &entry, 0, &prev_hash).is_some());
+    }
+}
@@ -0,0 +1,510 @@
+use crate::certificates::encryption::{decrypt, encrypt, kdf_danger_static, prompt_password};
+use crate::certificates::key_storage;
+use crate::certificates::key_storage::CaKeyStorage;
+use crate::certificates::pkcs11;
+use crate::cli::{BackupOptions, RestoreOptions};
+use crate::config::{Db, EncKeys};
+use crate::models::api::error_response::{ErrorResponse, ErrorResponseType};
+use crate::models::db::ca_cert_x509::CaCertX509Nioca;
+use crate::models::db::enc_key::EncKeyEntity;
+use crate::models::db::master_key::{MasterKeyEntity, MasterKeyRow};
+use crate::service::shamir;
+use crate::VERSION;
+use base64::{engine::general_purpose, Engine as _};
+use ring::digest;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::{Postgres, Transaction};
+use std::collections::BTreeMap;
+use time::OffsetDateTime;
+use tokio::fs;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+/// The version of the archive format, which is increased with each breaking change
+const ARCHIVE_VERSION: u16 = 1;
+
+/// All tables inside the encrypted payload in insert order, parents before children.
+///
+/// `master_key` and `enc_keys` are kept outside the payload, since they are needed to decrypt it.
+pub const BACKUP_TABLES: [&str; 26] = [
+    "audit_keys",
+    "config",
+    "ca_certs_x509",
+    "ca_certs_ssh",
+    "groups",
+    "profiles",
+    "spiffe_jwt_keys",
+    "users",
+    "users_group_access",
+    "clients_ssh",
+    "certs_ssh",
+    "certs_x509",
+    "clients_x509",
+    "certs_x509_revoked",
+    "certs_ssh_revoked",
+    "ssh_revoked_keys",
+    "ssh_revoked_key_ids",
+    "crls_x509",
+    "ocsp_x509",
+    "acme_accounts",
+    "acme_eab_keys",
+    "acme_orders",
+    "acme_authorizations",
+    "acme_challenges",
+    "notifications_expiry",
+    "audit_log",
+];
+
+/// Never cleared during a full restore. Only the missing entries are added, which keeps all
+/// audit entries created after the backup verifiable.
+const APPEND_ONLY_TABLES: [&str; 2] = ["audit_keys", "audit_log"];
+
+/// Runtime state, which is not part of the archive and only cleared during a full restore
+const TRANSIENT_TABLES: [&str; 4] = ["sessions", "sealed", "enc_key_rotations", "acme_nonces"];
+
+/// Sequences which must be moved past the restored values
+const SERIAL_COLUMNS: [(&str, &str); 2] = [("certs_x509", "serial"), ("certs_ssh", "serial")];
+
+/// The signed content of a backup archive
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct BackupArchiveBody {
+    pub version: u16,
+    pub nioca_version: String,
+    /// Unix timestamp of the creation
+    pub created: i64,
+    /// The rows of the `master_key` table with the share check hashes
+    #[schema(value_type = Object)]
+    pub master_key: Value,
+    /// The rows of the `enc_keys` table, still encrypted with the master key
+    #[schema(value_type = Object)]
+    pub enc_keys: Value,
+    /// The enc key the payload is encrypted with
+    pub enc_key_id: Uuid,
+    /// All other tables as base64 encoded, encrypted JSON
+    pub payload: String,
+}
+
+impl BackupArchiveBody {
+    fn checksum(&self) -> Result<Vec<u8>, ErrorResponse> {
+        let bytes = serde_json::to_vec(self)?;
+        Ok(digest::digest(&digest::SHA256, &bytes).as_ref().to_vec())
+    }
+}
+
+/// A full export of a Nioca instance, which can only be restored with the master key shares
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct BackupArchive {
+    pub body: BackupArchiveBody,
+    /// Hex encoded SHA256 of the body
+    pub checksum: String,
+    /// Base64 encoded signature of the checksum
+    pub signature: String,
+    /// The Nioca intermediate certificate, which created the signature
+    pub signer_cert: String,
+}
+
+impl BackupArchive {
+    pub fn master_key_entity(&self) -> Result<MasterKeyEntity, ErrorResponse> {
+        let rows: Vec<MasterKeyRow> = serde_json::from_value(self.body.master_key.clone())?;
+        Ok(MasterKeyEntity::from_rows(rows))
+    }
+}
+
+/// The decrypted and validated content of a backup archive
+#[derive(Debug)]
+pub struct BackupContents {
+    pub check_master: Vec<u8>,
+    pub master_key: Value,
+    pub enc_keys: Value,
+    pub tables: BTreeMap<String, Value>,
+}
+
+impl BackupContents {
+    /// The number of rows for each table inside the archive
+    pub fn row_counts(&self) -> BTreeMap<String, u64> {
+        self.tables
+            .iter()
+            .map(|(table, rows)| (table.clone(), row_count(rows)))
+            .collect()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct EncKeyRow {
+    id: Uuid,
+    value: String,
+}
+
+/// Exports all tables from a single snapshot into a new archive, signed by the Nioca
+/// intermediate CA.
+pub async fn create(
+    enc_keys: &EncKeys,
+    nioca_cert: &CaCertX509Nioca,
+) -> Result<BackupArchive, ErrorResponse> {
+    let mut txn = Db::txn().await?;
+    sqlx::query("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ READ ONLY")
+        .execute(&mut *txn)
+        .await?;
+
+    let master_key = dump_table("master_key", &mut txn).await?;
+    let enc_key_rows = dump_table("enc_keys", &mut txn).await?;
+    let mut tables = BTreeMap::new();
+    for table in BACKUP_TABLES {
+        tables.insert(table.to_string(), dump_table(table, &mut txn).await?);
+    }
+    txn.commit().await?;
+
+    let payload = encrypt(&serde_json::to_vec(&tables)?, &enc_keys.enc_key.value)?;
+    let body = BackupArchiveBody {
+        version: ARCHIVE_VERSION,
+        nioca_version: VERSION.to_string(),
+        created: OffsetDateTime::now_utc().unix_timestamp(),
+        master_key,
+        enc_keys: enc_key_rows,
+        enc_key_id: enc_keys.enc_key.id,
+        payload: general_purpose::STANDARD.encode(payload),
+    };
+
+    let checksum = body.checksum()?;
+    let signature =
+        CaKeyStorage::parse(&nioca_cert.key)?.x509_sign(&nioca_cert.cert_pem, &checksum)?;
+
+    Ok(BackupArchive {
+        body,
+        checksum: hex::encode(checksum),
+        signature: general_purpose::STANDARD.encode(signature),
+        signer_cert: nioca_cert.cert_pem.clone(),
+    })
+}
+
+/// Checks the signature of the archive and decrypts it with the given master key shares.
+///
+/// Nothing is written to the database.
+pub async fn validate(
+    archive: &BackupArchive,
+    master_shares: &[String],
+) -> Result<BackupContents, ErrorResponse> {
+    if archive.body.version != ARCHIVE_VERSION {
+        return Err(ErrorResponse::new(
+            ErrorResponseType::BadRequest,
+            format!(
+                "Unsupported backup archive version {}",
+                archive.body.version
+            ),
+        ));
+    }
+
+    let checksum = archive.body.checksum()?;
+    if hex::encode(&checksum) != archive.checksum {
+        return Err(ErrorResponse::new(
+            ErrorResponseType::BadRequest,
+            "The backup archive checksum does not match its content",
+        ));
+    }
+    let signature = general_purpose::STANDARD.decode(&archive.signature)?;
+    key_storage::x509_verify(&archive.signer_cert, &checksum, &signature).map_err(|_| {
+        ErrorResponse::new(
+            ErrorResponseType::BadRequest,
+            "The backup archive signature is invalid",
+        )
+    })?;
+
+    let mk_entity = archive.master_key_entity()?;
+    let (_, master_key) = master_key_from_shares(&mk_entity, master_shares).await?;
+
+    let enc_key_rows: Vec<EncKeyRow> = serde_json::from_value(archive.body.enc_keys.clone())?;
+    let enc_key = enc_key_rows
+        .iter()
+        .find(|row| row.id == archive.body.enc_key_id)
+        .ok_or_else(|| {
+            ErrorResponse::new(
+                ErrorResponseType::BadRequest,
+                "The enc key of the payload is missing in the backup archive",
+            )
+        })?;
+    let enc_key = decrypt(&bytea_from_json(&enc_key.value)?, &master_key)?;
+
+    let payload = general_purpose::STANDARD.decode(&archive.body.payload)?;
+    let tables: BTreeMap<String, Value> = serde_json::from_slice(&decrypt(&payload, &enc_key)?)?;
+
+    // the signer must be the intermediate of the exported instance itself
+    let is_own_signer = tables
+        .get("ca_certs_x509")
+        .and_then(Value::as_array)
+        .map(|rows| {
+            rows.iter()
+                .any(|row| row["typ"] == "certificate" && row["data"] == archive.signer_cert)
+        })
+        .unwrap_or(false);
+    if !is_own_signer {
+        return Err(ErrorResponse::new(
+            ErrorResponseType::BadRequest,
+            "The backup archive has not been signed by one of its own intermediate CAs",
+        ));
+    }
+
+    Ok(BackupContents {
+        check_master: mk_entity.check_master,
+        master_key: archive.body.master_key.clone(),
+        enc_keys: archive.body.enc_keys.clone(),
+        tables,
+    })
+}
+
+/// Writes a validated archive into the database inside a single transaction and returns the
+/// number of inserted rows for each table.
+///
+/// Without any `tables`, everything but the append-only audit tables is replaced, which must
+/// only be done while no Nioca server is running. Otherwise, only rows missing in the given
+/// tables are added, which requires the archive to use the same master key as this instance.
+pub async fn restore(
+    contents: &BackupContents,
+    tables: &[String],
+) -> Result<BTreeMap<String, u64>, ErrorResponse> {
+    let full = tables.is_empty();
+    if let Some(table) = tables
+        .iter()
+        .find(|table| !BACKUP_TABLES.contains(&table.as_str()))
+    {
+        return Err(ErrorResponse::new(
+            ErrorResponseType::BadRequest,
+            format!(
+                "Unknown table '{}' - valid tables are: {}",
+                table,
+                BACKUP_TABLES.join(", ")
+            ),
+        ));
+    }
+    if !full && MasterKeyEntity::build().await?.check_master != contents.check_master {
+        return Err(ErrorResponse::new(
+            ErrorResponseType::BadRequest,
+            "A partial restore needs the archive to use the same master key as this instance",
+        ));
+    }
+
+    let mut txn = Db::txn().await?;
+    let mut res = BTreeMap::new();
+
+    if full {
+        let truncate = ["master_key", "enc_keys"]
+            .into_iter()
+            .chain(
+                BACKUP_TABLES
+                    .into_iter()
+                    .filter(|table| !APPEND_ONLY_TABLES.contains(table)),
+            )
+            .chain(TRANSIENT_TABLES)
+            .collect::<Vec<_>>()
+            .join(", ");
+        sqlx::query(&format!("TRUNCATE {}", truncate))
+            .execute(&mut *txn)
+            .await?;
+
+        let count = insert_rows("master_key", &contents.master_key, &mut txn).await?;
+        res.insert("master_key".to_string(), count);
+    }
+    // restored rows may reference enc keys which have been retired in the meantime
+    let count = insert_rows("enc_keys", &contents.enc_keys, &mut txn).await?;
+    res.insert("enc_keys".to_string(), count);
+
+    let empty = Value::Array(Vec::new());
+    for table in BACKUP_TABLES {
+        if !full && !tables.iter().any(|t| t == table) {
+            continue;
+        }
+        let rows = contents.tables.get(table).unwrap_or(&empty);
+
+        // clients_ssh and certs_ssh reference each other -> link the latest cert afterwards
+        let count = if table == "clients_ssh" {
+            let mut rows = rows.clone();
+            if let Some(rows) = rows.as_array_mut() {
+                for row in rows {
+                    row["latest_cert"] = Value::Null;
+                }
+            }
+            insert_rows(table, &rows, &mut txn).await?
+        } else {
+            insert_rows(table, rows, &mut txn).await?
+        };
+        res.insert(table.to_string(), count);
+    }
+
+    if let Some(rows) = contents.tables.get("clients_ssh") {
+        sqlx::query(
+            r#"UPDATE clients_ssh c SET latest_cert = r.latest_cert
+            FROM json_populate_recordset(null::clients_ssh, $1::json) r
+            WHERE c.id = r.id AND c.latest_cert IS NULL
+            AND r.latest_cert IN (SELECT serial FROM certs_ssh)"#,
+        )
+        .bind(rows.to_string())
+        .execute(&mut *txn)
+        .await?;
+    }
+
+    for (table, column) in SERIAL_COLUMNS {
+        if res.contains_key(table) {
+            sqlx::query(&format!(
+                r#"SELECT setval(pg_get_serial_sequence('{table}', '{column}'),
+                greatest(max({column}), (SELECT last_value FROM {table}_{column}_seq)))
+                FROM {table} HAVING max({column}) IS NOT NULL"#,
+            ))
+            .execute(&mut *txn)
+            .await?;
+        }
+    }
+
+    txn.commit().await?;
+    Ok(res)
+}
+
+/// Rebuilds the master key from the shares and checks it against the master key entity.
+///
+/// Returns the full secret and the derived master key.
+async fn master_key_from_shares(
+    mk_entity: &MasterKeyEntity,
+    master_shares: &[String],
+) -> Result<(String, Vec<u8>), ErrorResponse> {
+    let shares = shamir::check_shares(mk_entity, master_shares).await?;
+    let master_full = shamir::combine(mk_entity, &shares)?;
+    let master_key = kdf_danger_static(master_full.as_bytes()).await?;
+    if kdf_danger_static(&master_key).await? != mk_entity.check_master {
+        return Err(ErrorResponse::new(
+            ErrorResponseType::BadRequest,
+            "Master Key Checksum failed",
+        ));
+    }
+    Ok((master_full, master_key))
+}
+
+async fn dump_table(
+    table: &str,
+    txn: &mut Transaction<'_, Postgres>,
+) -> Result<Value, ErrorResponse> {
+    let rows: String = sqlx::query_scalar(&format!(
+        "SELECT coalesce(json_agg(t), '[]')::text FROM {} t",
+        table
+    ))
+    .fetch_one(&mut **txn)
+    .await?;
+    Ok(serde_json::from_str(&rows)?)
+}
+
+async fn insert_rows(
+    table: &str,
+    rows: &Value,
+    txn: &mut Transaction<'_, Postgres>,
+) -> Result<u64, ErrorResponse> {
+    if row_count(rows) == 0 {
+        return Ok(0);
+    }
+    let res = sqlx::query(&format!(
+        r#"INSERT INTO {table} SELECT * FROM json_populate_recordset(null::{table}, $1::json)
+        ON CONFLICT DO NOTHING"#
+    ))
+    .bind(rows.to_string())
+    .execute(&mut **txn)
+    .await?;
+    Ok(res.rows_affected())
+}
+
+fn row_count(rows: &Value) -> u64 {
+    rows.as_array().map(|rows| rows.len() as u64).unwrap_or(0)
+}
+
+/// Postgres exports `bytea` as hex with a `\x` prefix into JSON
+fn bytea_from_json(value: &str) -> Result<Vec<u8>, ErrorResponse> {
+    match value.strip_prefix("\\x") {
+        Some(value) => Ok(hex::decode(value)?),
+        None => Err(ErrorResponse::new(
+            ErrorResponseType::BadRequest,
+            "Malformed bytea value in the backup archive",
+        )),
+    }
+}
+
+fn prompt_shares(threshold: u8) -> Result<Vec<String>, anyhow::Error> {
+    (1..=threshold)
+        .map(|i| prompt_password(format!("Master key share {}/{}: ", i, threshold)))
+        .collect()
+}
+
+async fn create_with_shares(
+    mk_entity: &MasterKeyEntity,
+    master_shares: &[String],
+) -> Result<BackupArchive, ErrorResponse> {
+    let (pepper, master_key) = master_key_from_shares(mk_entity, master_shares).await?;
+    let enc_key_id = MasterKeyRow::find_enc_key_active().await?;
+    let enc_keys = EncKeys {
+        master_shares: BTreeMap::new(),
+        enc_key: EncKeyEntity::find(&enc_key_id, &master_key).await?,
+        master_key,
+        pepper,
+    };
+    let nioca_cert = CaCertX509Nioca::find_default(&enc_keys).await?;
+    create(&enc_keys, &nioca_cert).await
+}
+
+/// Creates a new backup archive directly from the database. The master key shares are prompted
+/// for, since the intermediate CA must sign the archive.
+pub async fn backup_cli(opt: BackupOptions) -> Result<(), anyhow::Error> {
+    Db::init().await?;
+    pkcs11::init().map_err(|err| anyhow::Error::msg(err.message))?;
+
+    let mk_entity = MasterKeyEntity::build()
+        .await
+        .map_err(|err| anyhow::Error::msg(err.message))?;
+    if mk_entity.initialized.is_none() {
+        return Err(anyhow::Error::msg("This Nioca instance is not initialized"));
+    }
+    let shares = prompt_shares(mk_entity.threshold())?;
+    let res = create_with_shares(&mk_entity, &shares)
+        .await
+        .map_err(|err| anyhow::Error::msg(err.message))?;
+
+    fs::write(&opt.file, serde_json::to_vec(&res)?).await?;
+    eprintln!("Backup archive written to {}", opt.file);
+
+    Ok(())
+}
+
+/// Validates a backup archive against the prompted master key shares and restores it.
+pub async fn restore_cli(opt: RestoreOptions) -> Result<(), anyhow::Error> {
+    let archive: BackupArchive = serde_json::from_slice(&fs::read(&opt.file).await?)?;
+    let threshold = archive
+        .master_key_entity()
+        .map_err(|err| anyhow::Error::msg(err.message))?
+        .threshold();
+    let shares = prompt_shares(threshold)?;
+    let contents = validate(&archive, &shares)
+        .await
+        .map_err(|err| anyhow::Error::msg(err.message))?;
+
+    let rows = if opt.dry_run {
+        eprintln!("The backup archive is valid, nothing has been restored");
+        contents.row_counts()
+    } else {
+        Db::init().await?;
+        restore(&contents, &opt.table)
+            .await
+            .map_err(|err| anyhow::Error::msg(err.message))?
+    };
+    for (table, count) in rows {
+        println!("{}: {}", table, count);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bytea_from_json() {
+        assert_eq!(bytea_from_json("\\x0aff").unwrap(), vec![10, 255]);
+        assert!(bytea_from_json("0aff").is_err());
+    }
+}
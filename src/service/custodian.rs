@@ -0,0 +1,172 @@
+use crate::certificates::encryption::prompt_password;
+use crate::cli::DecryptShareOptions;
+use crate::models::api::error_response::{ErrorResponse, ErrorResponseType};
+use age::armor::ArmoredReader;
+use age::secrecy::SecretString;
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::str::FromStr;
+use tokio::fs;
+
+/// A custodian public key, which a master key share is encrypted to
+pub enum CustodianKey {
+    Age(age::x25519::Recipient),
+    Ssh(age::ssh::Recipient),
+}
+
+impl FromStr for CustodianKey {
+    type Err = ErrorResponse;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.starts_with("age1") {
+            let key = age::x25519::Recipient::from_str(s).map_err(|err| {
+                ErrorResponse::new(
+                    ErrorResponseType::BadRequest,
+                    format!("Invalid age public key: {}", err),
+                )
+            })?;
+            Ok(Self::Age(key))
+        } else {
+            // only ssh-ed25519 and ssh-rsa keys are supported
+            let key = age::ssh::Recipient::from_str(s).map_err(|_| {
+                ErrorResponse::new(
+                    ErrorResponseType::BadRequest,
+                    "Custodian keys must be age X25519 or OpenSSH ed25519 / RSA public keys",
+                )
+            })?;
+            Ok(Self::Ssh(key))
+        }
+    }
+}
+
+impl CustodianKey {
+    /// Parses the custodian keys and makes sure there is exactly one for each share
+    pub fn parse_all(keys: &[String], shares_total: u8) -> Result<Vec<Self>, ErrorResponse> {
+        if keys.len() != shares_total as usize {
+            return Err(ErrorResponse::new(
+                ErrorResponseType::BadRequest,
+                format!(
+                    "{} custodian keys given for {} master key shares",
+                    keys.len(),
+                    shares_total
+                ),
+            ));
+        }
+        keys.iter().map(|key| Self::from_str(key)).collect()
+    }
+
+    /// Encrypts a share to this key and returns it as an ASCII armored age file
+    pub fn encrypt(&self, share: &str) -> Result<String, ErrorResponse> {
+        let res = match self {
+            Self::Age(key) => age::encrypt_and_armor(key, share.as_bytes()),
+            Self::Ssh(key) => age::encrypt_and_armor(key, share.as_bytes()),
+        };
+        res.map_err(|err| {
+            ErrorResponse::new(
+                ErrorResponseType::Internal,
+                format!("Encrypting a master key share: {}", err),
+            )
+        })
+    }
+}
+
+/// Returns the shares in order, each one encrypted to the custodian key at the same position,
+/// if any are given.
+pub fn deliver_shares(
+    shares: &BTreeMap<u8, String>,
+    keys: Option<&[CustodianKey]>,
+) -> Result<Vec<String>, ErrorResponse> {
+    match keys {
+        None => Ok(shares.values().cloned().collect()),
+        Some(keys) => shares
+            .values()
+            .zip(keys)
+            .map(|(share, key)| key.encrypt(share))
+            .collect(),
+    }
+}
+
+/// Prompts for the passphrase of an encrypted OpenSSH private key
+#[derive(Debug, Clone)]
+struct PassphrasePrompt;
+
+impl age::Callbacks for PassphrasePrompt {
+    fn display_message(&self, message: &str) {
+        eprintln!("{}", message);
+    }
+
+    fn confirm(&self, _: &str, _: &str, _: Option<&str>) -> Option<bool> {
+        None
+    }
+
+    fn request_public_string(&self, _: &str) -> Option<String> {
+        None
+    }
+
+    fn request_passphrase(&self, description: &str) -> Option<SecretString> {
+        prompt_password(format!("{}: ", description))
+            .ok()
+            .map(SecretString::from)
+    }
+}
+
+/// Decrypts a master key share with a custodian identity and prints it to stdout, ready to be
+/// submitted to `/unseal/key`.
+pub async fn decrypt_share(opt: DecryptShareOptions) -> Result<(), anyhow::Error> {
+    let identity = fs::read_to_string(&opt.identity).await?;
+    let identities: Vec<Box<dyn age::Identity>> = if identity.contains("PRIVATE KEY-----") {
+        let key = age::ssh::Identity::from_buffer(identity.as_bytes(), Some(opt.identity.clone()))?;
+        if let age::ssh::Identity::Unsupported(_) = key {
+            return Err(anyhow::Error::msg(
+                "Only OpenSSH ed25519 and RSA keys are supported",
+            ));
+        }
+        vec![Box::new(key.with_callbacks(PassphrasePrompt))]
+    } else {
+        age::IdentityFile::from_buffer(identity.as_bytes())?.into_identities()?
+    };
+
+    let ciphertext = match &opt.file {
+        Some(path) => fs::read(path).await?,
+        None => {
+            let mut buf = Vec::new();
+            std::io::stdin().read_to_end(&mut buf)?;
+            buf
+        }
+    };
+
+    let decryptor = age::Decryptor::new_buffered(ArmoredReader::new(ciphertext.as_slice()))?;
+    let mut reader = decryptor.decrypt(identities.iter().map(|i| i.as_ref()))?;
+    let mut share = String::new();
+    reader.read_to_string(&mut share)?;
+
+    println!("{}", share);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deliver_shares() {
+        let identity = age::x25519::Identity::generate();
+        let public = identity.to_public().to_string();
+        let shares = BTreeMap::from([(1, "share1".to_string()), (2, "share2".to_string())]);
+
+        assert!(CustodianKey::parse_all(std::slice::from_ref(&public), 2).is_err());
+        assert!(CustodianKey::parse_all(&[public.clone(), "ssh-dss AAAA".to_string()], 2).is_err());
+
+        let other = age::x25519::Identity::generate().to_public().to_string();
+        let keys = CustodianKey::parse_all(&[public, other], 2).unwrap();
+        let delivered = deliver_shares(&shares, Some(&keys)).unwrap();
+        assert_eq!(delivered.len(), 2);
+        assert!(delivered[0].starts_with("-----BEGIN AGE ENCRYPTED FILE-----"));
+
+        // only the first custodian can read the first share
+        let plain = age::decrypt(&identity, delivered[0].as_bytes()).unwrap();
+        assert_eq!(plain, b"share1");
+        assert!(age::decrypt(&identity, delivered[1].as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,666 @@
+use crate::certificates::encryption::{decrypt, encrypt, kdf_danger_static, EncAlg};
+use crate::config::{AppState, Db, EncKeys};
+use crate::models::api::error_response::{ErrorResponse, ErrorResponseType};
+use crate::models::db::enc_key::EncKeyEntity;
+use crate::models::db::enc_key_rotation::EncKeyRotationEntity;
+use crate::models::db::master_key::{MasterKeyEntity, MasterKeyRow};
+use crate::util::secure_random;
+use ring::digest;
+use sqlx::{query, PgConnection};
+use std::collections::HashMap;
+use time::OffsetDateTime;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+// random, but static key for the advisory lock, which makes sure that only a single
+// re-encryption is running across all instances
+const ENC_KEY_ROTATION_LOCK: i64 = 0x6e69_6f63_610b;
+
+/// Progress is written to the database after this many values
+const PROGRESS_INTERVAL: i64 = 100;
+
+/// Holds the advisory lock until it is released. The connection is detached from the pool, which
+/// makes sure the lock is released on drop as well, even if the job panics.
+pub struct RotationLock(PgConnection);
+
+impl RotationLock {
+    pub async fn try_acquire() -> Result<Self, ErrorResponse> {
+        let mut conn = Db::conn().acquire().await?.detach();
+        let locked = query!(
+            r#"SELECT pg_try_advisory_lock($1) AS "locked!""#,
+            ENC_KEY_ROTATION_LOCK
+        )
+        .fetch_one(&mut conn)
+        .await?
+        .locked;
+
+        if locked {
+            Ok(Self(conn))
+        } else {
+            Err(ErrorResponse::new(
+                ErrorResponseType::BadRequest,
+                "An enc key rotation is running already",
+            ))
+        }
+    }
+
+    pub async fn release(mut self) {
+        if let Err(err) = query!("SELECT pg_advisory_unlock($1)", ENC_KEY_ROTATION_LOCK)
+            .fetch_one(&mut self.0)
+            .await
+        {
+            error!("Error releasing the enc key rotation lock: {}", err);
+        }
+    }
+}
+
+/// Generates a new enc key and makes it the active one. All values encrypted with older keys
+/// will be re-encrypted in the background.
+pub async fn rotate(state: &AppState) -> Result<EncKeyRotationEntity, ErrorResponse> {
+    let lock = RotationLock::try_acquire().await?;
+    let master_key = state.read().await.enc_keys.master_key.clone();
+
+    let key_hash = digest::digest(&digest::SHA256, secure_random(128).as_bytes());
+    let key_plain = key_hash.as_ref().to_vec();
+    let mut enc_key = EncKeyEntity {
+        id: Uuid::new_v4(),
+        alg: EncAlg::default().to_string(),
+        value: encrypt(&key_plain, &master_key)?,
+        created: Some(OffsetDateTime::now_utc()),
+    };
+    let rotation = EncKeyRotationEntity::new(enc_key.id);
+
+    // the new key must never be active without existing
+    let mut txn = Db::txn().await?;
+    enc_key.insert(&mut txn).await?;
+    MasterKeyRow::update_enc_key_active(&enc_key.id, &mut txn).await?;
+    rotation.upsert(&mut txn).await?;
+    txn.commit().await?;
+    info!("New enc key {} is active now", enc_key.id);
+
+    enc_key.value = key_plain;
+    let enc_keys = {
+        let mut lock = state.write().await;
+        lock.enc_keys.enc_key = enc_key;
+        lock.enc_keys.clone()
+    };
+
+    tokio::spawn(reencrypt_all(lock, enc_keys, rotation.clone()));
+    Ok(rotation)
+}
+
+/// Starts the re-encryption with the currently active key again, for instance if the instance
+/// running it has been stopped in between.
+pub async fn reencrypt(state: &AppState) -> Result<EncKeyRotationEntity, ErrorResponse> {
+    let lock = RotationLock::try_acquire().await?;
+    let enc_keys = state.read().await.enc_keys.clone();
+    let rotation = EncKeyRotationEntity::new(enc_keys.enc_key.id);
+
+    let mut txn = Db::txn().await?;
+    rotation.upsert(&mut txn).await?;
+    txn.commit().await?;
+
+    tokio::spawn(reencrypt_all(lock, enc_keys, rotation.clone()));
+    Ok(rotation)
+}
+
+/// Deletes an old enc key. This is only possible, if no value is encrypted with it anymore.
+pub async fn retire(id: &Uuid) -> Result<(), ErrorResponse> {
+    // the db is the source of truth, another instance might have rotated in between
+    if &MasterKeyRow::find_enc_key_active().await? == id {
+        return Err(ErrorResponse::new(
+            ErrorResponseType::BadRequest,
+            "The active enc key cannot be retired",
+        ));
+    }
+
+    // must not delete a key a running job may still decrypt with
+    let lock = RotationLock::try_acquire().await?;
+
+    let usages = EncKeyEntity::count_usages(id).await?;
+    if usages > 0 {
+        lock.release().await;
+        return Err(ErrorResponse::new(
+            ErrorResponseType::BadRequest,
+            format!(
+                "The enc key is still used by {} values - re-encrypt them first",
+                usages
+            ),
+        ));
+    }
+
+    let res = EncKeyEntity::delete(id).await;
+    lock.release().await;
+    res?;
+    info!("Enc key {} has been retired", id);
+    Ok(())
+}
+
+/// Switches to the active enc key from the database, if another instance has rotated it.
+///
+/// If the active key cannot be decrypted anymore and the master key check from the database does
+/// not match our master key, it has been re-keyed by another instance. This instance will exit in
+/// that case, to be unsealed with the new shards again. Any other error, like a database which is
+/// unreachable for a moment, is just returned.
+pub async fn refresh_active(state: &AppState) -> Result<(), ErrorResponse> {
+    let active = MasterKeyRow::find_enc_key_active().await?;
+    let master_key = state.read().await.enc_keys.master_key.clone();
+
+    let enc_key = match EncKeyEntity::find(&active, &master_key).await {
+        Ok(key) => key,
+        Err(err) => {
+            if is_rekeyed(&master_key).await? {
+                error!("The master key has been re-keyed by another instance - exiting");
+                std::process::exit(1);
+            }
+            return Err(err);
+        }
+    };
+
+    if state.read().await.enc_keys.enc_key.id != active {
+        state.write().await.enc_keys.enc_key = enc_key;
+        info!("Switched to the new active enc key {}", active);
+    }
+
+    Ok(())
+}
+
+/// Checks the master key against the `check_master` from the database
+async fn is_rekeyed(master_key: &[u8]) -> Result<bool, ErrorResponse> {
+    let check_master = MasterKeyEntity::build().await?.check_master;
+    Ok(kdf_danger_static(master_key).await? != check_master)
+}
+
+async fn reencrypt_all(lock: RotationLock, enc_keys: EncKeys, rotation: EncKeyRotationEntity) {
+    info!(
+        "Re-encrypting all values with enc key {}",
+        enc_keys.enc_key.id
+    );
+
+    let mut job = ReEncrypt {
+        enc_keys,
+        old_keys: HashMap::default(),
+        rotation,
+    };
+    if let Err(err) = job.run().await {
+        error!("Error during re-encryption: {}", err.message);
+        job.rotation.error = Some(err.message);
+    }
+
+    job.rotation.finished = Some(OffsetDateTime::now_utc());
+    if let Err(err) = job.rotation.update_progress().await {
+        error!("Error saving the re-encryption progress: {}", err.message);
+    }
+    lock.release().await;
+
+    info!(
+        "Re-encryption finished - {} / {} values done, {} failed",
+        job.rotation.done, job.rotation.total, job.rotation.failed
+    );
+}
+
+struct ReEncrypt {
+    enc_keys: EncKeys,
+    /// decrypted old keys by id
+    old_keys: HashMap<Uuid, Vec<u8>>,
+    rotation: EncKeyRotationEntity,
+}
+
+impl ReEncrypt {
+    async fn run(&mut self) -> Result<(), ErrorResponse> {
+        self.rotation.total = EncKeyEntity::count_outdated(&self.enc_keys.enc_key.id).await?;
+        self.rotation.update_progress().await?;
+
+        self.clients_x509().await?;
+        self.clients_ssh().await?;
+        self.ca_certs_x509().await?;
+        self.ca_certs_ssh().await?;
+        self.config().await?;
+        self.users_group_access().await?;
+        self.ocsp_x509().await?;
+        self.spiffe_jwt_keys().await?;
+        self.acme_eab_keys().await?;
+
+        Ok(())
+    }
+
+    /// Decrypts the value with the given old key and encrypts it with the active one
+    async fn reencrypt(
+        &mut self,
+        value: &[u8],
+        enc_key_id: &Uuid,
+    ) -> Result<Vec<u8>, ErrorResponse> {
+        if !self.old_keys.contains_key(enc_key_id) {
+            let key = EncKeyEntity::find(enc_key_id, &self.enc_keys.master_key).await?;
+            self.old_keys.insert(*enc_key_id, key.value);
+        }
+        let plain = decrypt(value, self.old_keys.get(enc_key_id).unwrap())?;
+        encrypt(&plain, &self.enc_keys.enc_key.value)
+    }
+
+    async fn progress(&mut self, res: Result<(), ErrorResponse>, what: &str) {
+        match res {
+            Ok(_) => self.rotation.done += 1,
+            Err(err) => {
+                warn!("Cannot re-encrypt {}: {}", what, err.message);
+                self.rotation.failed += 1;
+                self.rotation.error = Some(format!("{}: {}", what, err.message));
+            }
+        }
+
+        if (self.rotation.done + self.rotation.failed) % PROGRESS_INTERVAL == 0 {
+            if let Err(err) = self.rotation.update_progress().await {
+                error!("Error saving the re-encryption progress: {}", err.message);
+            }
+        }
+    }
+
+    // The updates only apply if the value has not been changed in the meantime. If it has been,
+    // it has been encrypted with the active key already and there is nothing left to do.
+
+    async fn clients_x509(&mut self) -> Result<(), ErrorResponse> {
+        let active = self.enc_keys.enc_key.id;
+        let rows = query!(
+            "SELECT id, api_key, enc_key_id FROM clients_x509 WHERE enc_key_id <> $1",
+            active
+        )
+        .fetch_all(Db::conn())
+        .await?;
+
+        for row in rows {
+            let res = async {
+                let api_key = self.reencrypt(&row.api_key, &row.enc_key_id).await?;
+                query!(
+                    r#"UPDATE clients_x509 SET api_key = $1, enc_key_id = $2
+                    WHERE id = $3 AND api_key = $4"#,
+                    api_key,
+                    active,
+                    row.id,
+                    row.api_key,
+                )
+                .execute(Db::conn())
+                .await?;
+                Ok::<(), ErrorResponse>(())
+            }
+            .await;
+            self.progress(res, &format!("clients_x509 {}", row.id))
+                .await;
+        }
+
+        Ok(())
+    }
+
+    async fn clients_ssh(&mut self) -> Result<(), ErrorResponse> {
+        let active = self.enc_keys.enc_key.id;
+        let rows = query!(
+            "SELECT id, api_key, enc_key_id FROM clients_ssh WHERE enc_key_id <> $1",
+            active
+        )
+        .fetch_all(Db::conn())
+        .await?;
+
+        for row in rows {
+            let res = async {
+                let api_key = self.reencrypt(&row.api_key, &row.enc_key_id).await?;
+                query!(
+                    r#"UPDATE clients_ssh SET api_key = $1, enc_key_id = $2
+                    WHERE id = $3 AND api_key = $4"#,
+                    api_key,
+                    active,
+                    row.id,
+                    row.api_key,
+                )
+                .execute(Db::conn())
+                .await?;
+                Ok::<(), ErrorResponse>(())
+            }
+            .await;
+            self.progress(res, &format!("clients_ssh {}", row.id)).await;
+        }
+
+        Ok(())
+    }
+
+    async fn ca_certs_x509(&mut self) -> Result<(), ErrorResponse> {
+        let active = self.enc_keys.enc_key.id;
+        let rows = query!(
+            r#"SELECT id, typ, data, fingerprint, enc_key_id FROM ca_certs_x509
+            WHERE enc_key_id <> $1"#,
+            active
+        )
+        .fetch_all(Db::conn())
+        .await?;
+
+        for row in rows {
+            let res = async {
+                // the private key is hex encoded in `data`, the certificates only encrypt
+                // their fingerprint
+                let (data, fingerprint) = if row.typ == "key" {
+                    let key = hex::decode(&row.data).map_err(|_| {
+                        ErrorResponse::new(ErrorResponseType::Internal, "Invalid HEX data")
+                    })?;
+                    let data = hex::encode(self.reencrypt(&key, &row.enc_key_id).await?);
+                    (data, row.fingerprint.clone())
+                } else {
+                    let fingerprint = match &row.fingerprint {
+                        Some(f) => Some(self.reencrypt(f, &row.enc_key_id).await?),
+                        None => None,
+                    };
+                    (row.data.clone(), fingerprint)
+                };
+
+                query!(
+                    r#"UPDATE ca_certs_x509 SET data = $1, fingerprint = $2, enc_key_id = $3
+                    WHERE id = $4 AND typ = $5 AND enc_key_id = $6"#,
+                    data,
+                    fingerprint,
+                    active,
+                    row.id,
+                    row.typ,
+                    row.enc_key_id,
+                )
+                .execute(Db::conn())
+                .await?;
+                Ok::<(), ErrorResponse>(())
+            }
+            .await;
+            self.progress(res, &format!("ca_certs_x509 {} {}", row.id, row.typ))
+                .await;
+        }
+
+        Ok(())
+    }
+
+    async fn ca_certs_ssh(&mut self) -> Result<(), ErrorResponse> {
+        let active = self.enc_keys.enc_key.id;
+        let rows = query!(
+            "SELECT id, data, enc_key_id FROM ca_certs_ssh WHERE enc_key_id <> $1",
+            active
+        )
+        .fetch_all(Db::conn())
+        .await?;
+
+        for row in rows {
+            let res = async {
+                let data = self.reencrypt(&row.data, &row.enc_key_id).await?;
+                query!(
+                    r#"UPDATE ca_certs_ssh SET data = $1, enc_key_id = $2
+                    WHERE id = $3 AND data = $4"#,
+                    data,
+                    active,
+                    row.id,
+                    row.data,
+                )
+                .execute(Db::conn())
+                .await?;
+                Ok::<(), ErrorResponse>(())
+            }
+            .await;
+            self.progress(res, &format!("ca_certs_ssh {}", row.id))
+                .await;
+        }
+
+        Ok(())
+    }
+
+    async fn config(&mut self) -> Result<(), ErrorResponse> {
+        let active = self.enc_keys.enc_key.id;
+        let rows = query!(
+            "SELECT key, value, enc_key_id FROM config WHERE enc_key_id <> $1",
+            active
+        )
+        .fetch_all(Db::conn())
+        .await?;
+
+        for row in rows {
+            let res = async {
+                let value = self.reencrypt(&row.value, &row.enc_key_id).await?;
+                query!(
+                    r#"UPDATE config SET value = $1, enc_key_id = $2
+                    WHERE key = $3 AND value = $4"#,
+                    value,
+                    active,
+                    row.key,
+                    row.value,
+                )
+                .execute(Db::conn())
+                .await?;
+                Ok::<(), ErrorResponse>(())
+            }
+            .await;
+            self.progress(res, &format!("config {}", row.key)).await;
+        }
+
+        Ok(())
+    }
+
+    async fn users_group_access(&mut self) -> Result<(), ErrorResponse> {
+        let active = self.enc_keys.enc_key.id;
+        let rows = query!(
+            r#"SELECT user_id, group_id, group_access, enc_key_id FROM users_group_access
+            WHERE enc_key_id <> $1"#,
+            active
+        )
+        .fetch_all(Db::conn())
+        .await?;
+
+        for row in rows {
+            let res = async {
+                let access = self.reencrypt(&row.group_access, &row.enc_key_id).await?;
+                query!(
+                    r#"UPDATE users_group_access SET group_access = $1, enc_key_id = $2
+                    WHERE user_id = $3 AND group_id = $4 AND group_access = $5"#,
+                    access,
+                    active,
+                    row.user_id,
+                    row.group_id,
+                    row.group_access,
+                )
+                .execute(Db::conn())
+                .await?;
+                Ok::<(), ErrorResponse>(())
+            }
+            .await;
+            self.progress(
+                res,
+                &format!("users_group_access {} {}", row.user_id, row.group_id),
+            )
+            .await;
+        }
+
+        Ok(())
+    }
+
+    async fn ocsp_x509(&mut self) -> Result<(), ErrorResponse> {
+        let active = self.enc_keys.enc_key.id;
+        let rows = query!(
+            r#"SELECT ca_id, signer_key AS "signer_key!", signer_enc_key_id AS "signer_enc_key_id!"
+            FROM ocsp_x509
+            WHERE signer_enc_key_id <> $1 AND signer_key IS NOT NULL"#,
+            active
+        )
+        .fetch_all(Db::conn())
+        .await?;
+
+        for row in rows {
+            let res = async {
+                let key = self
+                    .reencrypt(&row.signer_key, &row.signer_enc_key_id)
+                    .await?;
+                query!(
+                    r#"UPDATE ocsp_x509 SET signer_key = $1, signer_enc_key_id = $2
+                    WHERE ca_id = $3 AND signer_key = $4"#,
+                    key,
+                    active,
+                    row.ca_id,
+                    row.signer_key,
+                )
+                .execute(Db::conn())
+                .await?;
+                Ok::<(), ErrorResponse>(())
+            }
+            .await;
+            self.progress(res, &format!("ocsp_x509 {}", row.ca_id))
+                .await;
+        }
+
+        Ok(())
+    }
+
+    async fn spiffe_jwt_keys(&mut self) -> Result<(), ErrorResponse> {
+        let active = self.enc_keys.enc_key.id;
+        let rows = query!(
+            "SELECT kid, key, enc_key_id FROM spiffe_jwt_keys WHERE enc_key_id <> $1",
+            active
+        )
+        .fetch_all(Db::conn())
+        .await?;
+
+        for row in rows {
+            let res = async {
+                let key = self.reencrypt(&row.key, &row.enc_key_id).await?;
+                query!(
+                    r#"UPDATE spiffe_jwt_keys SET key = $1, enc_key_id = $2
+                    WHERE kid = $3 AND key = $4"#,
+                    key,
+                    active,
+                    row.kid,
+                    row.key,
+                )
+                .execute(Db::conn())
+                .await?;
+                Ok::<(), ErrorResponse>(())
+            }
+            .await;
+            self.progress(res, &format!("spiffe_jwt_keys {}", row.kid))
+                .await;
+        }
+
+        Ok(())
+    }
+
+    async fn acme_eab_keys(&mut self) -> Result<(), ErrorResponse> {
+        let active = self.enc_keys.enc_key.id;
+        let rows = query!(
+            "SELECT kid, hmac_key, enc_key_id FROM acme_eab_keys WHERE enc_key_id <> $1",
+            active
+        )
+        .fetch_all(Db::conn())
+        .await?;
+
+        for row in rows {
+            let res = async {
+                let key = self.reencrypt(&row.hmac_key, &row.enc_key_id).await?;
+                query!(
+                    r#"UPDATE acme_eab_keys SET hmac_key = $1, enc_key_id = $2
+                    WHERE kid = $3 AND hmac_key = $4"#,
+                    key,
+                    active,
+                    row.kid,
+                    row.hmac_key,
+                )
+                .execute(Db::conn())
+                .await?;
+                Ok::<(), ErrorResponse>(())
+            }
+            .await;
+            self.progress(res, &format!("acme_eab_keys {}", row.kid))
+                .await;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::db_test;
+
+    fn enc_keys(key: Vec<u8>) -> EncKeys {
+        EncKeys {
+            master_shares: Default::default(),
+            master_key: vec![0; 32],
+            pepper: String::default(),
+            enc_key: EncKeyEntity {
+                id: Uuid::new_v4(),
+                alg: EncAlg::default().to_string(),
+                value: key,
+                created: None,
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reencrypt() {
+        let key_old = digest::digest(&digest::SHA256, b"old").as_ref().to_vec();
+        let key_new = digest::digest(&digest::SHA256, b"new").as_ref().to_vec();
+        let id_old = Uuid::new_v4();
+
+        let enc_keys = enc_keys(key_new.clone());
+        let mut job = ReEncrypt {
+            rotation: EncKeyRotationEntity::new(enc_keys.enc_key.id),
+            enc_keys,
+            old_keys: HashMap::from([(id_old, key_old.clone())]),
+        };
+
+        let value = encrypt(b"my secret value", &key_old).unwrap();
+        let reencrypted = job.reencrypt(&value, &id_old).await.unwrap();
+        assert_ne!(value, reencrypted);
+        assert!(decrypt(&reencrypted, &key_old).is_err());
+        assert_eq!(
+            decrypt(&reencrypted, &key_new).unwrap(),
+            b"my secret value".to_vec()
+        );
+
+        // a value which has not been encrypted with the given key must never be overwritten
+        let value = encrypt(b"my secret value", &key_new).unwrap();
+        assert!(job.reencrypt(&value, &id_old).await.is_err());
+    }
+
+    #[test]
+    #[ignore]
+    fn test_retire() {
+        db_test(async {
+            let active = MasterKeyRow::find_enc_key_active().await.unwrap();
+            let err = retire(&active).await.unwrap_err();
+            assert_eq!(err.typ, ErrorResponseType::BadRequest);
+
+            let old = EncKeyEntity {
+                id: Uuid::new_v4(),
+                alg: EncAlg::default().to_string(),
+                value: vec![0; 32],
+                created: Some(OffsetDateTime::now_utc()),
+            };
+            let mut txn = Db::txn().await.unwrap();
+            old.insert(&mut txn).await.unwrap();
+            txn.commit().await.unwrap();
+
+            // still in use
+            let key = format!("test_retire_{}", old.id);
+            sqlx::query("INSERT INTO config (key, enc_key_id, value) VALUES ($1, $2, $3)")
+                .bind(&key)
+                .bind(old.id)
+                .bind(vec![0u8; 8])
+                .execute(Db::conn())
+                .await
+                .unwrap();
+            let err = retire(&old.id).await.unwrap_err();
+            assert_eq!(err.typ, ErrorResponseType::BadRequest);
+            assert_eq!(EncKeyEntity::count_usages(&old.id).await.unwrap(), 1);
+
+            sqlx::query("DELETE FROM config WHERE key = $1")
+                .bind(&key)
+                .execute(Db::conn())
+                .await
+                .unwrap();
+            retire(&old.id).await.unwrap();
+            let exists = EncKeyEntity::find_all()
+                .await
+                .unwrap()
+                .iter()
+                .any(|k| k.id == old.id);
+            assert!(!exists);
+        });
+    }
+}
@@ -0,0 +1,219 @@
+use crate::certificates::encryption::{decrypt, encrypt, kdf_danger_static};
+use crate::config::{AppState, Db};
+use crate::constants::{DEV_MODE, UNSEAL_SHARES_THRESHOLD, UNSEAL_SHARES_TOTAL};
+use crate::models::api::error_response::{ErrorResponse, ErrorResponseType};
+use crate::models::api::request::MasterKeyRekeyRequest;
+use crate::models::api::response::MasterKeyRekeyResponse;
+use crate::models::db::audit_key::AuditKeyEntity;
+use crate::models::db::enc_key::EncKeyEntity;
+use crate::models::db::master_key::{MasterKeyEntity, MasterKeyRow};
+use crate::service::audit;
+use crate::service::audit::AuditKey;
+use crate::service::custodian;
+use crate::service::custodian::CustodianKey;
+use crate::service::enc_keys::RotationLock;
+use crate::service::password_hasher::{ComparePasswords, HashPassword};
+use crate::service::sealed::log_dev_shares;
+use crate::service::shamir;
+use crate::service::shamir::MasterShares;
+use tracing::info;
+
+/// Replaces the master key with a new one built from freshly generated shares.
+///
+/// Instances still using the legacy 2 shards are switched to Shamir's Secret Sharing with the
+/// default config, unless a new one is given.
+///
+/// All enc keys and previous audit keys are re-encrypted with the new master key and the local
+/// root password is re-hashed with the new pepper, all inside a single transaction.
+pub async fn rekey(
+    state: &AppState,
+    req: MasterKeyRekeyRequest,
+) -> Result<MasterKeyRekeyResponse, ErrorResponse> {
+    let mk_entity = MasterKeyEntity::build().await?;
+    let threshold = req
+        .shares_threshold
+        .or(mk_entity.shares_threshold)
+        .unwrap_or(*UNSEAL_SHARES_THRESHOLD);
+    let total = req
+        .shares_total
+        .or(req.custodian_keys.as_ref().map(|keys| keys.len() as u8))
+        .or(mk_entity.shares_total)
+        .unwrap_or(*UNSEAL_SHARES_TOTAL);
+    shamir::validate_config(threshold, total)?;
+    let custodian_keys = req
+        .custodian_keys
+        .as_ref()
+        .map(|keys| CustodianKey::parse_all(keys, total))
+        .transpose()?;
+
+    // check the given shares against the current ones
+    let shares = shamir::check_shares(&mk_entity, &req.master_shares).await?;
+
+    let master_full_old = shamir::combine(&mk_entity, &shares)?;
+    let master_key_old = kdf_danger_static(master_full_old.as_bytes()).await?;
+    if master_key_old != state.read().await.enc_keys.master_key {
+        return Err(ErrorResponse::new(
+            ErrorResponseType::BadRequest,
+            "The shards do not match the master key of this instance",
+        ));
+    }
+
+    // the password is needed in plain text to re-hash it with the new pepper
+    let password_hash = MasterKeyRow::find_local_password().await?;
+    if !ComparePasswords::is_match(&req.local_password, password_hash, &master_full_old).await? {
+        return Err(ErrorResponse::new(
+            ErrorResponseType::Forbidden,
+            "Bad Credentials",
+        ));
+    }
+
+    // no new enc key must be created while the existing ones are re-encrypted
+    let lock = RotationLock::try_acquire().await?;
+    let res = rekey_locked(
+        state,
+        &req.local_password,
+        &master_key_old,
+        threshold,
+        total,
+        custodian_keys.as_deref(),
+    )
+    .await;
+    lock.release().await;
+    res
+}
+
+async fn rekey_locked(
+    state: &AppState,
+    local_password: &str,
+    master_key_old: &[u8],
+    threshold: u8,
+    total: u8,
+    custodian_keys: Option<&[CustodianKey]>,
+) -> Result<MasterKeyRekeyResponse, ErrorResponse> {
+    let shares = MasterShares::generate(threshold, total).await?;
+    let master_shares = custodian::deliver_shares(&shares.shares, custodian_keys)?;
+    let master_key = shares.master_key.clone();
+
+    let local_password_hash =
+        HashPassword::hash_password(local_password, &shares.master_full).await?;
+
+    store_rekey(master_key_old, &shares, &local_password_hash).await?;
+
+    {
+        let mut lock = state.write().await;
+        lock.enc_keys.master_key = master_key.clone();
+        lock.enc_keys.pepper = shares.master_full;
+        lock.enc_keys.master_shares = shares.shares.clone();
+    }
+    audit::rekey(&master_key);
+    info!("The master key has been re-keyed");
+
+    // When running in DEV_MODE, log these values into the console to have them for the .env file
+    // with auto unsealing
+    if *DEV_MODE {
+        info!("######################################################");
+        log_dev_shares(&shares.shares, &MasterKeyEntity::build().await?);
+        info!("######################################################");
+    }
+
+    Ok(MasterKeyRekeyResponse {
+        master_shares,
+        shares_threshold: shares.threshold,
+        encrypted: custodian_keys.is_some(),
+    })
+}
+
+/// Re-encrypts all enc keys and audit keys with the new master key and saves the new shares and
+/// password hash. Nothing is changed at all, if any step fails.
+async fn store_rekey(
+    master_key_old: &[u8],
+    shares: &MasterShares,
+    local_password_hash: &str,
+) -> Result<(), ErrorResponse> {
+    let mut txn = Db::txn().await?;
+
+    for mut enc_key in EncKeyEntity::find_all_for_update(&mut txn).await? {
+        let plain = decrypt(&enc_key.value, master_key_old)?;
+        enc_key.value = encrypt(&plain, &shares.master_key)?;
+        enc_key.update_value(&mut txn).await?;
+    }
+
+    // keep all audit keys to be able to verify older entries
+    for entity in AuditKeyEntity::find_all_for_update(&mut txn).await? {
+        AuditKey::decrypt(&entity, master_key_old)?
+            .encrypt(&shares.master_key)?
+            .upsert(&mut txn)
+            .await?;
+    }
+    AuditKey::derive(master_key_old)
+        .encrypt(&shares.master_key)?
+        .upsert(&mut txn)
+        .await?;
+
+    MasterKeyRow::save_shares(shares, &mut txn).await?;
+    MasterKeyRow::update_value(
+        "check_master",
+        &hex::encode(&shares.master_key_check),
+        &mut txn,
+    )
+    .await?;
+    MasterKeyRow::update_value("local_password", local_password_hash, &mut txn).await?;
+
+    txn.commit().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::db_test;
+    use uuid::Uuid;
+
+    type Rows<K> = Vec<(K, Vec<u8>)>;
+
+    async fn snapshot() -> (Rows<Uuid>, Rows<String>, Vec<(String, Option<String>)>) {
+        let enc_keys = sqlx::query_as("SELECT id, value FROM enc_keys ORDER BY id")
+            .fetch_all(Db::conn())
+            .await
+            .unwrap();
+        let audit_keys = sqlx::query_as("SELECT id, value FROM audit_keys ORDER BY id")
+            .fetch_all(Db::conn())
+            .await
+            .unwrap();
+        let master_key = sqlx::query_as("SELECT id, value FROM master_key ORDER BY id")
+            .fetch_all(Db::conn())
+            .await
+            .unwrap();
+        (enc_keys, audit_keys, master_key)
+    }
+
+    #[test]
+    #[ignore]
+    fn test_rekey_failure_keeps_keys() {
+        db_test(async {
+            // an audit key which can never be decrypted makes the re-key fail with any data
+            let broken = AuditKeyEntity {
+                id: format!("test_rekey_{}", Uuid::new_v4()),
+                value: vec![0; 64],
+            };
+            let mut txn = Db::txn().await.unwrap();
+            broken.upsert(&mut txn).await.unwrap();
+            txn.commit().await.unwrap();
+
+            let before = snapshot().await;
+            let shares = MasterShares::generate(2, 3).await.unwrap();
+            let res = store_rekey(&[0; 32], &shares, "hash").await;
+            let after = snapshot().await;
+
+            sqlx::query("DELETE FROM audit_keys WHERE id = $1")
+                .bind(&broken.id)
+                .execute(Db::conn())
+                .await
+                .unwrap();
+
+            assert!(res.is_err());
+            // enc_keys, audit_keys, the share checks, check_master and local_password
+            assert_eq!(before, after);
+        });
+    }
+}
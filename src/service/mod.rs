@@ -1,3 +1,9 @@
+pub mod audit;
+pub mod backup;
+pub mod custodian;
+pub mod enc_keys;
+pub mod master_key;
 pub mod password_hasher;
 pub mod sealed;
+pub mod shamir;
 pub mod x509;
@@ -1,3 +1,4 @@
+use crate::metrics;
 use crate::models::api::error_response::{ErrorResponse, ErrorResponseType};
 use argon2::password_hash::SaltString;
 use argon2::{Algorithm, Argon2, PasswordHash, PasswordHasher, PasswordVerifier, Version};
@@ -136,6 +137,8 @@ pub async fn run() {
 
 #[inline]
 fn check_await_threshold(instant: &Instant) {
+    metrics::password_hash_wait(instant.elapsed());
+
     // This cast from u128 -> u64 is "unsafe", but in reality, this threshold can never be reached
     // in this context. Having the HASH_AWAIT_WARN_TIME as u64 is a small bonus though.
     if instant.elapsed().as_millis() as u64 > *HASH_AWAIT_WARN_TIME {
@@ -1,20 +1,27 @@
 use crate::certificates::encryption::{encrypt, kdf_danger_static, EncAlg};
 use crate::config::{ConfigSealed, Db, EncKeys};
-use crate::constants::{DEV_MODE, INSTANCE_UUID, UNSEAL_RATE_LIMIT};
+use crate::constants::{
+    DEV_MODE, INSTANCE_UUID, UNSEAL_RATE_LIMIT, UNSEAL_SHARES_THRESHOLD, UNSEAL_SHARES_TOTAL,
+};
 use crate::models::api::error_response::{ErrorResponse, ErrorResponseType};
 use crate::models::api::request::{AddMasterShardRequest, InitRequest, UnsealRequest};
 use crate::models::api::response::{InitResponse, SealedStatus, X509CertificatesInspectResponse};
 use crate::models::db::ca_cert_x509::{CaCertX509Nioca, CaCertX509Root, CaCertX509Type};
 use crate::models::db::enc_key::EncKeyEntity;
-use crate::models::db::master_key::MasterKeyEntity;
+use crate::models::db::master_key::{MasterKeyEntity, MasterKeyRow};
 use crate::models::db::sealed::SealedEntity;
 use crate::routes::AppStateSealedExtract;
 use crate::service;
+use crate::service::custodian;
+use crate::service::custodian::CustodianKey;
 use crate::service::password_hasher::HashPassword;
+use crate::service::shamir;
+use crate::service::shamir::MasterShares;
 use crate::service::x509::CheckedCerts;
 use crate::util::secure_random;
 use ring::digest;
 use sqlx::query;
+use std::collections::{btree_map, BTreeMap};
 use std::env;
 use std::ops::Add;
 use std::str::FromStr;
@@ -22,7 +29,6 @@ use std::time::Duration;
 use time::OffsetDateTime;
 use tracing::{debug, error, info};
 use uuid::Uuid;
-use x509_parser::nom::AsBytes;
 
 /// Initialized the database with the given init values.
 pub async fn init(
@@ -36,20 +42,16 @@ pub async fn init(
     let (checked_certs, _) = init_values_check(&config, &req).await?;
 
     // create keys and secrets
-    let master_shard_1 = secure_random(48);
-    let master_shard_1_check = kdf_danger_static(master_shard_1.as_bytes()).await?;
-
-    let master_shard_2 = secure_random(48);
-    let master_shard_2_check = kdf_danger_static(master_shard_2.as_bytes()).await?;
-
-    let master_full = format!("{}{}", master_shard_1, master_shard_2);
-    let master_key_hash = kdf_danger_static(master_full.as_bytes()).await?;
-    let master_key_check = kdf_danger_static(master_key_hash.as_bytes()).await?;
+    let (threshold, total, custodian_keys) = init_shares_config(&req)?;
+    let shares = MasterShares::generate(threshold, total).await?;
+    let master_shares = custodian::deliver_shares(&shares.shares, custodian_keys.as_deref())?;
+    let master_full = &shares.master_full;
+    let master_key_hash = &shares.master_key;
 
     let enc_key_str = secure_random(128);
     let enc_key_hash = digest::digest(&digest::SHA256, enc_key_str.as_bytes());
     let enc_key_bytes = enc_key_hash.as_ref();
-    let enc_key_encrypted = encrypt(enc_key_bytes, &master_key_hash).unwrap();
+    let enc_key_encrypted = encrypt(enc_key_bytes, master_key_hash).unwrap();
     let enc_key_id = Uuid::new_v4();
     let enc_key_alg = EncAlg::ChaCha20Poly1305;
 
@@ -63,8 +65,7 @@ pub async fn init(
     let now_ts = OffsetDateTime::now_utc().unix_timestamp().to_string();
 
     // create a password hash
-    let local_password_hash =
-        HashPassword::hash_password(&req.local_password, &master_full).await?;
+    let local_password_hash = HashPassword::hash_password(&req.local_password, master_full).await?;
 
     // generate a UUID for the new default certificate
     let uuid = Uuid::new_v4();
@@ -72,23 +73,11 @@ pub async fn init(
     // The whole initialization should be a big single transaction
     let mut txn = Db::txn().await?;
 
-    query!(
-        "INSERT INTO master_key (id, value) VALUES ('check_shard_1', $1)",
-        hex::encode(&master_shard_1_check)
-    )
-    .execute(&mut *txn)
-    .await?;
-
-    query!(
-        "INSERT INTO master_key (id, value) VALUES ('check_shard_2', $1)",
-        hex::encode(&master_shard_2_check)
-    )
-    .execute(&mut *txn)
-    .await?;
+    MasterKeyRow::save_shares(&shares, &mut txn).await?;
 
     query!(
         "INSERT INTO master_key (id, value) VALUES ('check_master', $1)",
-        hex::encode(&master_key_check)
+        hex::encode(&shares.master_key_check)
     )
     .execute(&mut *txn)
     .await?;
@@ -114,13 +103,13 @@ pub async fn init(
     .execute(&mut *txn)
     .await?;
 
-    query!(
-        "INSERT INTO enc_keys (id, alg, value) VALUES ($1, $2, $3)",
-        enc_key_id,
-        enc_key_alg.to_string(),
-        enc_key_encrypted,
-    )
-    .execute(&mut *txn)
+    EncKeyEntity {
+        id: enc_key_id,
+        alg: enc_key_alg.to_string(),
+        value: enc_key_encrypted,
+        created: Some(OffsetDateTime::now_utc()),
+    }
+    .insert(&mut txn)
     .await?;
 
     query!(
@@ -169,16 +158,38 @@ pub async fn init(
 
     // set the state to initialized
     state.write().await.init_key = None;
+    state.write().await.master_key_entity = MasterKeyEntity::build().await?;
 
     // create a new xsrf token
     state.write().await.xsrf_key = secure_random(48);
 
     Ok(InitResponse {
-        master_shard_1,
-        master_shard_2,
+        master_shares,
+        shares_threshold: shares.threshold,
+        encrypted: custodian_keys.is_some(),
     })
 }
 
+/// Returns the shares threshold and total with the defaults applied, and the parsed custodian keys
+fn init_shares_config(
+    req: &InitRequest,
+) -> Result<(u8, u8, Option<Vec<CustodianKey>>), ErrorResponse> {
+    let threshold = req.shares_threshold.unwrap_or(*UNSEAL_SHARES_THRESHOLD);
+    let total = req
+        .shares_total
+        .or(req.custodian_keys.as_ref().map(|keys| keys.len() as u8))
+        .unwrap_or(*UNSEAL_SHARES_TOTAL);
+    shamir::validate_config(threshold, total)?;
+
+    let custodian_keys = req
+        .custodian_keys
+        .as_ref()
+        .map(|keys| CustodianKey::parse_all(keys, total))
+        .transpose()?;
+
+    Ok((threshold, total, custodian_keys))
+}
+
 /// Checks the given Nioca init values for correctness, validity and consistency.
 pub async fn init_values_check(
     state: &ConfigSealed,
@@ -209,6 +220,9 @@ pub async fn init_values_check(
         ));
     }
 
+    // the custodian keys must be fine before any shares are created
+    init_shares_config(req)?;
+
     service::x509::x509_ca_validate(&req.root_pem, &req.it_pem, &req.it_key, &req.it_password).await
 }
 
@@ -238,19 +252,19 @@ pub async fn add_unseal_shard(
         ));
     }
 
-    let hash = kdf_danger_static(req.key.as_bytes()).await?;
-
+    // the master key may have been re-keyed in the meantime, which invalidates collected shares
     let master_key = MasterKeyEntity::build().await?;
+    if master_key.check_master != config.master_key_entity.check_master {
+        config.enc_keys.master_shares.clear();
+    }
+    config.master_key_entity = master_key;
 
     let mut is_match = false;
-    if config.enc_keys.master_shard_1.is_none() && master_key.check_shard_1.as_bytes() == hash {
-        config.enc_keys.master_shard_1 = Some(req.key.clone());
-        is_match = true;
-    } else if config.enc_keys.master_shard_2.is_none()
-        && master_key.check_shard_2.as_bytes() == hash
-    {
-        config.enc_keys.master_shard_2 = Some(req.key.clone());
-        is_match = true;
+    if let Some((index, share)) = shamir::check_share(&config.master_key_entity, &req.key).await? {
+        if let btree_map::Entry::Vacant(entry) = config.enc_keys.master_shares.entry(index) {
+            entry.insert(share);
+            is_match = true;
+        }
     }
 
     // set new rate limit timeout
@@ -265,17 +279,7 @@ pub async fn add_unseal_shard(
         let root_cert = CaCertX509Root::find_default(&config.enc_keys, true).await?;
         push_shard_to_remotes(req.key, root_cert.cert_pem.as_bytes()).await?;
 
-        let status = SealedStatus {
-            is_initialized: true,
-            is_sealed: true,
-            master_shard_1: config.enc_keys.master_shard_1.is_some(),
-            master_shard_2: config.enc_keys.master_shard_2.is_some(),
-            is_ready: config.enc_keys.master_shard_1.is_some()
-                && config.enc_keys.master_shard_2.is_some(),
-            key_add_rate_limit: *UNSEAL_RATE_LIMIT,
-        };
-
-        Ok(status)
+        Ok(sealed_status(&config))
     } else {
         Err(ErrorResponse::new(
             ErrorResponseType::BadRequest,
@@ -297,18 +301,8 @@ pub async fn unseal(state: AppStateSealedExtract, req: UnsealRequest) -> Result<
         ));
     }
 
-    // check if shards keys are present
-    if config.enc_keys.master_shard_1.is_none() || config.enc_keys.master_shard_2.is_none() {
-        return Err(ErrorResponse::new(
-            ErrorResponseType::BadRequest,
-            "Nioca is not ready to be unsealed. Master Keys are missing".to_string(),
-        ));
-    }
-
-    // build the master key
-    let master_shard_1 = config.enc_keys.master_shard_1.as_ref().unwrap();
-    let master_shard_2 = config.enc_keys.master_shard_2.as_ref().unwrap();
-    let master_full = format!("{}{}", master_shard_1, master_shard_2);
+    // build the master key, which fails if not enough shares are present
+    let master_full = shamir::combine(&config.master_key_entity, &config.enc_keys.master_shares)?;
     let master_key_hash = kdf_danger_static(master_full.as_bytes()).await?;
     // this is our master key for decryption end enc keys
     let master_key_bytes = master_key_hash.as_ref();
@@ -333,9 +327,9 @@ pub async fn unseal(state: AppStateSealedExtract, req: UnsealRequest) -> Result<
     let enc_uuid = Uuid::from_str(&enc_key_id).expect("Rebuilding UUID for enc kid");
     let enc_key = EncKeyEntity::find(&enc_uuid, master_key_bytes).await?;
 
+    // the shares are kept for the remote auto-unseal of other instances
     let enc_keys = EncKeys {
-        master_shard_1: None,
-        master_shard_2: None,
+        master_shares: config.enc_keys.master_shares.clone(),
         master_key: master_key_hash,
         pepper: master_full,
         enc_key: enc_key.clone(),
@@ -357,8 +351,7 @@ pub async fn unseal(state: AppStateSealedExtract, req: UnsealRequest) -> Result<
     // auto unsealing
     if *DEV_MODE {
         info!("######################################################");
-        info!(">>> master_shard_1: {}", master_shard_1);
-        info!(">>> master_shard_2: {}", master_shard_2);
+        log_dev_shares(&config.enc_keys.master_shares, &config.master_key_entity);
         info!(">>> enc_key_uuid: {}", enc_uuid);
         info!(">>> enc_key_value: {}", hex::encode(enc_key.value));
         info!("######################################################");
@@ -377,8 +370,39 @@ pub async fn unseal(state: AppStateSealedExtract, req: UnsealRequest) -> Result<
     Ok(())
 }
 
+/// Builds the current status from the collected master key shares
+pub fn sealed_status(config: &ConfigSealed) -> SealedStatus {
+    let threshold = config.master_key_entity.threshold();
+    let shares_added = config.enc_keys.master_shares.len() as u8;
+    SealedStatus {
+        is_initialized: config.init_key.is_none(),
+        is_sealed: true,
+        shares_added,
+        shares_threshold: threshold,
+        shares_total: config.master_key_entity.total(),
+        is_ready: shares_added >= threshold,
+        key_add_rate_limit: *UNSEAL_RATE_LIMIT,
+    }
+}
+
+/// Logs the shares in the format needed for the .env file with auto unsealing
+pub fn log_dev_shares(shares: &BTreeMap<u8, String>, mk_entity: &MasterKeyEntity) {
+    if mk_entity.shares_threshold.is_none() {
+        for (index, share) in shares {
+            info!(">>> AUTO_UNSEAL_SHARD_{}={}", index, share);
+        }
+    } else {
+        let threshold = mk_entity.threshold() as usize;
+        let shares = shares.values().take(threshold).cloned();
+        info!(
+            ">>> AUTO_UNSEAL_SHARES={}",
+            shares.collect::<Vec<_>>().join(",")
+        );
+    }
+}
+
 /// Does a lookup to the database to find existing remote instances waiting for an unseal and pushes
-/// the master shard keys to them from an alread unsealed instance.
+/// a master key share to them from an alread unsealed instance.
 pub async fn push_shard_to_remotes(key: String, root_pem: &[u8]) -> Result<(), ErrorResponse> {
     debug!("Running push_shard_to_remotes");
 
@@ -432,25 +456,33 @@ pub async fn push_shard_to_remotes(key: String, root_pem: &[u8]) -> Result<(), E
             }
         };
 
-        // send the key shard
+        // send the key share
         let req = AddMasterShardRequest {
             key: key.clone(),
             xsrf,
         };
         match client
-            .post(format!("{}/unseal/xsrf", s.url))
+            .post(format!("{}/unseal/key", s.url))
             .json(&req)
             .send()
             .await
         {
-            Ok(_) => {
+            Ok(res) if res.status().is_success() => {
                 info!(
-                    "Sent Master Shard key for Auto-Unseal to instance {} successfully",
+                    "Sent Master Key share for Auto-Unseal to instance {} successfully",
                     s.id
                 );
             }
+            Ok(res) => {
+                // an already added share is rejected as well
+                debug!(
+                    "Auto-Unseal POST key to instance {} returned {}",
+                    s.id,
+                    res.status()
+                );
+            }
             Err(err) => {
-                error!("Auto-Unseal GET xsrf error: {}", err);
+                error!("Auto-Unseal POST key error: {}", err);
                 continue;
             }
         };
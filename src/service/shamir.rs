@@ -0,0 +1,273 @@
+use crate::certificates::encryption::kdf_danger_static;
+use crate::models::api::error_response::{ErrorResponse, ErrorResponseType};
+use crate::models::db::master_key::MasterKeyEntity;
+use crate::util::secure_random;
+use sharks::{Share, Sharks};
+use std::collections::BTreeMap;
+
+/// Upper limit for the number of shares the master key can be split into
+pub const SHARES_TOTAL_MAX: u8 = 16;
+
+/// A newly generated master key, split into shares with Shamir's Secret Sharing
+#[derive(Debug)]
+pub struct MasterShares {
+    /// The full secret, which is used as the pepper for the local password as well
+    pub master_full: String,
+    pub master_key: Vec<u8>,
+    pub master_key_check: Vec<u8>,
+    pub threshold: u8,
+    pub total: u8,
+    /// All shares by their index
+    pub shares: BTreeMap<u8, String>,
+    /// The check hashes for each share by index
+    pub checks: BTreeMap<u8, Vec<u8>>,
+}
+
+impl MasterShares {
+    /// Creates a new random master key and splits it into `total` shares, of which any
+    /// `threshold` can rebuild it.
+    pub async fn generate(threshold: u8, total: u8) -> Result<Self, ErrorResponse> {
+        validate_config(threshold, total)?;
+
+        let master_full = secure_random(64);
+        let master_key = kdf_danger_static(master_full.as_bytes()).await?;
+        let master_key_check = kdf_danger_static(master_key.as_slice()).await?;
+
+        let shares = split(&master_full, threshold, total);
+        let mut checks = BTreeMap::new();
+        for (index, share) in shares.iter() {
+            checks.insert(*index, kdf_danger_static(share.as_bytes()).await?);
+        }
+
+        Ok(Self {
+            master_full,
+            master_key,
+            master_key_check,
+            threshold,
+            total,
+            shares,
+            checks,
+        })
+    }
+}
+
+pub fn validate_config(threshold: u8, total: u8) -> Result<(), ErrorResponse> {
+    if threshold < 2 || threshold > total || total > SHARES_TOTAL_MAX {
+        return Err(ErrorResponse::new(
+            ErrorResponseType::BadRequest,
+            format!(
+                "Invalid shares config {}-of-{}, must be 2 <= threshold <= total <= {}",
+                threshold, total, SHARES_TOTAL_MAX
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// Checks a single share against the check hashes of the master key.
+///
+/// Returns the index and the normalized share, if it is valid.
+pub async fn check_share(
+    mk_entity: &MasterKeyEntity,
+    share: &str,
+) -> Result<Option<(u8, String)>, ErrorResponse> {
+    // legacy instances use exactly 2 shards, which are concatenated in order
+    if mk_entity.shares_threshold.is_none() {
+        let hash = kdf_danger_static(share.as_bytes()).await?;
+        let res = mk_entity
+            .check_shares
+            .iter()
+            .find(|(_, check)| **check == hash)
+            .map(|(index, _)| (*index, share.to_string()));
+        return Ok(res);
+    }
+
+    let share = share.to_lowercase();
+    let index = match hex::decode(&share) {
+        Ok(bytes) if bytes.len() > 1 => bytes[0],
+        _ => return Ok(None),
+    };
+    let check = match mk_entity.check_shares.get(&index) {
+        Some(check) => check,
+        None => return Ok(None),
+    };
+
+    let hash = kdf_danger_static(share.as_bytes()).await?;
+    if *check == hash {
+        Ok(Some((index, share)))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Checks all given shares and returns them by index, if each one of them is valid
+pub async fn check_shares(
+    mk_entity: &MasterKeyEntity,
+    shares: &[String],
+) -> Result<BTreeMap<u8, String>, ErrorResponse> {
+    let mut res = BTreeMap::new();
+    for share in shares {
+        match check_share(mk_entity, share).await? {
+            Some((index, share)) => {
+                res.insert(index, share);
+            }
+            None => {
+                return Err(ErrorResponse::new(
+                    ErrorResponseType::BadRequest,
+                    "Incorrect Key Shard",
+                ));
+            }
+        }
+    }
+    Ok(res)
+}
+
+/// Rebuilds the full master secret from already checked shares
+pub fn combine(
+    mk_entity: &MasterKeyEntity,
+    shares: &BTreeMap<u8, String>,
+) -> Result<String, ErrorResponse> {
+    if shares.len() < mk_entity.threshold() as usize {
+        return Err(ErrorResponse::new(
+            ErrorResponseType::BadRequest,
+            format!(
+                "{} of {} master key shares are missing",
+                mk_entity.threshold() as usize - shares.len(),
+                mk_entity.threshold(),
+            ),
+        ));
+    }
+
+    match mk_entity.shares_threshold {
+        None => Ok(shares.values().map(String::as_str).collect()),
+        Some(threshold) => recover(threshold, shares.values()),
+    }
+}
+
+fn split(secret: &str, threshold: u8, total: u8) -> BTreeMap<u8, String> {
+    Sharks(threshold)
+        .dealer(secret.as_bytes())
+        .take(total as usize)
+        .map(|share| {
+            let bytes = Vec::from(&share);
+            (bytes[0], hex::encode(bytes))
+        })
+        .collect()
+}
+
+fn recover<'a, I>(threshold: u8, shares: I) -> Result<String, ErrorResponse>
+where
+    I: Iterator<Item = &'a String>,
+{
+    let mut parsed = Vec::new();
+    for share in shares {
+        let bytes = hex::decode(share).map_err(|_| {
+            ErrorResponse::new(ErrorResponseType::BadRequest, "Malformed master key share")
+        })?;
+        let share = Share::try_from(bytes.as_slice())
+            .map_err(|err| ErrorResponse::new(ErrorResponseType::BadRequest, err))?;
+        parsed.push(share);
+    }
+
+    let secret = Sharks(threshold)
+        .recover(parsed.iter())
+        .map_err(|err| ErrorResponse::new(ErrorResponseType::BadRequest, err))?;
+    String::from_utf8(secret).map_err(|_| {
+        ErrorResponse::new(
+            ErrorResponseType::BadRequest,
+            "The master key shares do not belong together",
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_recover() {
+        let secret = secure_random(64);
+        let shares = split(&secret, 3, 5);
+        assert_eq!(shares.len(), 5);
+        assert_eq!(
+            shares.keys().copied().collect::<Vec<_>>(),
+            vec![1, 2, 3, 4, 5]
+        );
+
+        // any 3 out of 5 must work
+        let subset = [&shares[&1], &shares[&3], &shares[&5]];
+        assert_eq!(recover(3, subset.into_iter()).unwrap(), secret);
+        let subset = [&shares[&2], &shares[&4], &shares[&5]];
+        assert_eq!(recover(3, subset.into_iter()).unwrap(), secret);
+        assert_eq!(recover(3, shares.values()).unwrap(), secret);
+
+        // 2 shares are not enough
+        let subset = [&shares[&1], &shares[&2]];
+        assert!(recover(3, subset.into_iter()).is_err());
+
+        assert!(validate_config(3, 5).is_ok());
+        assert!(validate_config(1, 5).is_err());
+        assert!(validate_config(4, 3).is_err());
+        assert!(validate_config(3, SHARES_TOTAL_MAX + 1).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_check_combine_shares() {
+        let master_full = secure_random(64);
+        let shares = split(&master_full, 2, 3);
+        // share 3 is left out to keep the slow kdf calls down, which makes it an unknown one
+        let mut checks = BTreeMap::new();
+        for index in [1, 2] {
+            let check = kdf_danger_static(shares[&index].as_bytes()).await.unwrap();
+            checks.insert(index, check);
+        }
+        let mk_entity = MasterKeyEntity {
+            shares_threshold: Some(2),
+            shares_total: Some(3),
+            check_shares: checks,
+            ..Default::default()
+        };
+
+        // shares are normalized to lowercase hex
+        let (index, share) = check_share(&mk_entity, &shares[&2].to_uppercase())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(index, 2);
+        assert_eq!(share, shares[&2]);
+
+        let mut tampered = shares[&1].clone();
+        let last = if tampered.ends_with('0') { "1" } else { "0" };
+        tampered.replace_range(tampered.len() - 1.., last);
+        assert!(check_share(&mk_entity, &tampered).await.unwrap().is_none());
+        assert!(check_share(&mk_entity, "no hex").await.unwrap().is_none());
+
+        let err = check_shares(&mk_entity, &[shares[&3].clone()])
+            .await
+            .unwrap_err();
+        assert_eq!(err.typ, ErrorResponseType::BadRequest);
+
+        let one = BTreeMap::from([(1, shares[&1].clone())]);
+        let err = combine(&mk_entity, &one).unwrap_err();
+        assert_eq!(err.typ, ErrorResponseType::BadRequest);
+
+        let two = BTreeMap::from([(1, shares[&1].clone()), (3, shares[&3].clone())]);
+        assert_eq!(combine(&mk_entity, &two).unwrap(), master_full);
+
+        // shares from another split must never rebuild the secret
+        let other = split(&secure_random(64), 2, 3);
+        let mixed = BTreeMap::from([(1, shares[&1].clone()), (2, other[&2].clone())]);
+        assert_ne!(combine(&mk_entity, &mixed).ok(), Some(master_full));
+    }
+
+    #[test]
+    fn test_combine_legacy_shards() {
+        let mk_entity = MasterKeyEntity::default();
+        let shards = BTreeMap::from([(1, "shard1".to_string()), (2, "shard2".to_string())]);
+        assert_eq!(combine(&mk_entity, &shards).unwrap(), "shard1shard2");
+
+        let shards = BTreeMap::from([(2, "shard2".to_string())]);
+        let err = combine(&mk_entity, &shards).unwrap_err();
+        assert_eq!(err.typ, ErrorResponseType::BadRequest);
+    }
+}
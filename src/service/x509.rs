@@ -1,18 +1,31 @@
 use crate::certificates::encryption::{decrypt, kdf_danger_static};
+use crate::certificates::key_storage;
+use crate::certificates::key_storage::CaKeyStorage;
 use crate::certificates::x509::cert_from_key_pem;
+use crate::certificates::x509::csr::X509Csr;
+use crate::certificates::x509::singing::{
+    gen_ecdsa_key_pair, gen_ed25519_key_pair, gen_rsa_key_pair,
+};
 use crate::certificates::x509::verification::{
     validate_x509, x509_der_from_bytes, x509_pem_from_bytes,
 };
+use crate::certificates::X509KeyAlg;
 use crate::config::{AppState, Db};
 use crate::models::api::error_response::{ErrorResponse, ErrorResponseType};
-use crate::models::api::request::X509CaAddRequest;
-use crate::models::api::response::{CertificateInspectResponse, X509CertificatesInspectResponse};
-use crate::models::db::ca_cert_x509::{CaCertX509Nioca, CaCertX509Root};
+use crate::models::api::request::{X509CaAddRequest, X509CaCertRequest, X509CaCsrRequest};
+use crate::models::api::response::{
+    CertificateInspectResponse, X509CaCsrResponse, X509CertificatesInspectResponse,
+};
+use crate::models::db::ca_cert_x509::{
+    CaCertX509Entity, CaCertX509Nioca, CaCertX509Root, CaCertX509Type,
+};
 use crate::util::fingerprint;
+use rcgen::{Certificate, CertificateParams, DnType};
 use time::OffsetDateTime;
 use tracing::error;
 use utoipa::ToSchema;
 use uuid::Uuid;
+use x509_parser::certificate::X509Certificate;
 use x509_parser::nom::AsBytes;
 
 #[derive(Debug, ToSchema)]
@@ -72,7 +85,6 @@ pub async fn x509_ca_validate(
 ) -> Result<(CheckedCerts, X509CertificatesInspectResponse), ErrorResponse> {
     // try to serialize the certificates
 
-    // root certificate
     let root_fingerprint = fingerprint(root_pem.trim().as_bytes());
     let root_cert_pem = x509_pem_from_bytes(root_pem.as_bytes()).map_err(|err| {
         ErrorResponse::new(
@@ -80,30 +92,6 @@ pub async fn x509_ca_validate(
             format!("Bad Root PEM: {}", err.message),
         )
     })?;
-    let root_cert = x509_der_from_bytes(root_cert_pem.contents.as_bytes())?;
-    if !root_cert.is_ca() {
-        return Err(ErrorResponse::new(
-            ErrorResponseType::BadRequest,
-            "The given root certificate is not a CA".to_string(),
-        ));
-    }
-    // root certificates are always self-signed
-    root_cert.verify_signature(None).map_err(|err| {
-        let e = ErrorResponse::from(err);
-        ErrorResponse::new(
-            ErrorResponseType::BadRequest,
-            format!("Root Certificate - {}", e.message),
-        )
-    })?;
-    // validate additional parts of the certificate
-    validate_x509(&root_cert).map_err(|_| {
-        ErrorResponse::new(
-            ErrorResponseType::BadRequest,
-            "The given certificate is invalid".to_string(),
-        )
-    })?;
-
-    // intermediate certificate
     let it_fingerprint = fingerprint(it_pem.trim().as_bytes());
     let it_cert_pem = x509_pem_from_bytes(it_pem.as_bytes()).map_err(|err| {
         ErrorResponse::new(
@@ -111,30 +99,10 @@ pub async fn x509_ca_validate(
             format!("Bad Intermediate PEM: {}", err.message),
         )
     })?;
-    let it_cert = x509_der_from_bytes(it_cert_pem.contents.as_bytes())?;
-    if !it_cert.is_ca() {
-        return Err(ErrorResponse::new(
-            ErrorResponseType::BadRequest,
-            "The given intermediate certificate is not a CA".to_string(),
-        ));
-    }
-    // verify the signature with the roots public key
-    it_cert
-        .verify_signature(Some(root_cert.public_key()))
-        .map_err(|err| {
-            let e = ErrorResponse::from(err);
-            ErrorResponse::new(
-                ErrorResponseType::BadRequest,
-                format!("Intermediate Certificate - {}", e.message),
-            )
-        })?;
-    // validate additional parts of the certificate
-    validate_x509(&it_cert).map_err(|_| {
-        ErrorResponse::new(
-            ErrorResponseType::BadRequest,
-            "The given certificate is invalid".to_string(),
-        )
-    })?;
+    let (root_cert, it_cert) = x509_ca_chain_validate(
+        root_cert_pem.contents.as_bytes(),
+        it_cert_pem.contents.as_bytes(),
+    )?;
 
     // try to decode the private key
     let key_bytes = match hex::decode(it_key.trim()) {
@@ -196,3 +164,200 @@ pub async fn x509_ca_validate(
 
     Ok((checked_certs, resp))
 }
+
+/// Validates the root and the intermediate certificate, which must be signed by the root
+fn x509_ca_chain_validate<'a>(
+    root_der: &'a [u8],
+    it_der: &'a [u8],
+) -> Result<(X509Certificate<'a>, X509Certificate<'a>), ErrorResponse> {
+    // root certificate
+    let root_cert = x509_der_from_bytes(root_der)?;
+    if !root_cert.is_ca() {
+        return Err(ErrorResponse::new(
+            ErrorResponseType::BadRequest,
+            "The given root certificate is not a CA".to_string(),
+        ));
+    }
+    // root certificates are always self-signed
+    root_cert.verify_signature(None).map_err(|err| {
+        let e = ErrorResponse::from(err);
+        ErrorResponse::new(
+            ErrorResponseType::BadRequest,
+            format!("Root Certificate - {}", e.message),
+        )
+    })?;
+    // validate additional parts of the certificate
+    validate_x509(&root_cert).map_err(|_| {
+        ErrorResponse::new(
+            ErrorResponseType::BadRequest,
+            "The given certificate is invalid".to_string(),
+        )
+    })?;
+
+    // intermediate certificate
+    let it_cert = x509_der_from_bytes(it_der)?;
+    if !it_cert.is_ca() {
+        return Err(ErrorResponse::new(
+            ErrorResponseType::BadRequest,
+            "The given intermediate certificate is not a CA".to_string(),
+        ));
+    }
+    // verify the signature with the roots public key
+    it_cert
+        .verify_signature(Some(root_cert.public_key()))
+        .map_err(|err| {
+            let e = ErrorResponse::from(err);
+            ErrorResponse::new(
+                ErrorResponseType::BadRequest,
+                format!("Intermediate Certificate - {}", e.message),
+            )
+        })?;
+    // validate additional parts of the certificate
+    validate_x509(&it_cert).map_err(|_| {
+        ErrorResponse::new(
+            ErrorResponseType::BadRequest,
+            "The given certificate is invalid".to_string(),
+        )
+    })?;
+
+    Ok((root_cert, it_cert))
+}
+
+/// Generates the key pair for a new intermediate CA and saves it together with its CSR.
+///
+/// The private key never leaves Nioca. The CSR must be signed with the offline root and the
+/// certificate added afterwards with `add_x509_ca_cert()`.
+pub async fn add_x509_ca_csr(
+    state: &AppState,
+    req: X509CaCsrRequest,
+) -> Result<X509CaCsrResponse, ErrorResponse> {
+    let (storage, key_pair) = if req.pkcs11.unwrap_or(false) {
+        key_storage::x509_generate_pkcs11(&req.name, &req.key_alg).await?
+    } else {
+        let key_pair = match req.key_alg {
+            X509KeyAlg::RSA => gen_rsa_key_pair(2048)?,
+            X509KeyAlg::ECDSA => gen_ecdsa_key_pair()?,
+            X509KeyAlg::EdDSA => gen_ed25519_key_pair()?,
+        };
+        (CaKeyStorage::Database(key_pair.serialize_pem()), key_pair)
+    };
+
+    let mut params = CertificateParams::default();
+    params.alg = key_pair.algorithm();
+    params.key_pair = Some(key_pair);
+    params
+        .distinguished_name
+        .push(DnType::CommonName, req.common_name);
+    if let Some(c) = req.country {
+        params.distinguished_name.push(DnType::CountryName, c);
+    }
+    if let Some(l) = req.locality {
+        params.distinguished_name.push(DnType::LocalityName, l);
+    }
+    if let Some(ou) = req.organizational_unit {
+        params
+            .distinguished_name
+            .push(DnType::OrganizationalUnitName, ou);
+    }
+    if let Some(o) = req.organization {
+        params.distinguished_name.push(DnType::OrganizationName, o);
+    }
+    if let Some(st) = req.state_or_province {
+        params
+            .distinguished_name
+            .push(DnType::StateOrProvinceName, st);
+    }
+    let csr_pem = Certificate::from_params(params)?.serialize_request_pem()?;
+
+    let enc_keys = state.read().await.enc_keys.clone();
+    let id = Uuid::new_v4();
+
+    let mut txn = Db::txn().await?;
+    CaCertX509Nioca::add_pending(
+        &enc_keys,
+        id,
+        req.name.clone(),
+        csr_pem.clone(),
+        &storage.value(),
+        &mut txn,
+    )
+    .await?;
+    txn.commit().await?;
+
+    Ok(X509CaCsrResponse {
+        id,
+        name: req.name,
+        csr_pem,
+    })
+}
+
+/// Completes a pending intermediate CA with the certificate signed by the offline root
+pub async fn add_x509_ca_cert(
+    state: &AppState,
+    id: Uuid,
+    req: X509CaCertRequest,
+) -> Result<String, ErrorResponse> {
+    let csr = CaCertX509Entity::find_by_id(&id, CaCertX509Type::Csr)
+        .await
+        .map_err(|_| {
+            ErrorResponse::new(
+                ErrorResponseType::NotFound,
+                "No pending CSR for this CA".to_string(),
+            )
+        })?;
+
+    let root_pem = req.root_pem.trim();
+    let it_pem = req.it_pem.trim();
+    let root_cert_pem = x509_pem_from_bytes(root_pem.as_bytes()).map_err(|err| {
+        ErrorResponse::new(
+            ErrorResponseType::BadRequest,
+            format!("Bad Root PEM: {}", err.message),
+        )
+    })?;
+    let it_cert_pem = x509_pem_from_bytes(it_pem.as_bytes()).map_err(|err| {
+        ErrorResponse::new(
+            ErrorResponseType::BadRequest,
+            format!("Bad Intermediate PEM: {}", err.message),
+        )
+    })?;
+    let (root_cert, it_cert) = x509_ca_chain_validate(
+        root_cert_pem.contents.as_bytes(),
+        it_cert_pem.contents.as_bytes(),
+    )?;
+
+    // the certificate must have been issued for the key, which has been generated with the CSR
+    let csr_key = X509Csr::from_pem(&csr.data)?.public_key;
+    if it_cert.public_key().subject_public_key.data.as_ref() != csr_key.as_slice() {
+        return Err(ErrorResponse::new(
+            ErrorResponseType::BadRequest,
+            "The intermediate certificate does not match the CSR".to_string(),
+        ));
+    }
+
+    let enc_keys = state.read().await.enc_keys.clone();
+    let mut txn = Db::txn().await?;
+    CaCertX509Root::add_new(
+        &enc_keys,
+        id,
+        csr.name.clone(),
+        root_pem.to_string(),
+        &fingerprint(root_pem.as_bytes()),
+        root_cert.validity.not_after.to_datetime(),
+        &mut txn,
+    )
+    .await?;
+    CaCertX509Nioca::add_cert(
+        &enc_keys,
+        id,
+        csr.name.clone(),
+        it_pem.to_string(),
+        &fingerprint(it_pem.as_bytes()),
+        it_cert.validity.not_after.to_datetime(),
+        &mut txn,
+    )
+    .await?;
+    CaCertX509Entity::delete(&id, CaCertX509Type::Csr, &mut txn).await?;
+    txn.commit().await?;
+
+    Ok(csr.name)
+}
@@ -0,0 +1,46 @@
+use crate::models::api::error_response::{ErrorResponse, ErrorResponseType};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde::Serialize;
+use tracing::error;
+
+/// An error in the format of the Vault HTTP API: `{"errors": ["..."]}`
+#[derive(Debug, Clone)]
+pub struct VaultError {
+    pub status: StatusCode,
+    pub message: String,
+}
+
+#[derive(Debug, Serialize)]
+struct VaultErrors {
+    errors: Vec<String>,
+}
+
+impl IntoResponse for VaultError {
+    fn into_response(self) -> Response {
+        let body = VaultErrors {
+            errors: vec![self.message],
+        };
+        (self.status, Json(body)).into_response()
+    }
+}
+
+impl From<ErrorResponse> for VaultError {
+    fn from(value: ErrorResponse) -> Self {
+        let status = match value.typ {
+            // Vault answers bad or missing tokens with a 403 'permission denied'
+            ErrorResponseType::Unauthorized | ErrorResponseType::InvalidToken => {
+                StatusCode::FORBIDDEN
+            }
+            _ => value.status_code(),
+        };
+        if status == StatusCode::INTERNAL_SERVER_ERROR {
+            error!("Vault API internal error: {:?}", value);
+        }
+        Self {
+            status,
+            message: value.message,
+        }
+    }
+}
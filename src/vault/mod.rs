@@ -0,0 +1,156 @@
+use crate::constants::VAULT_TOKEN_HEADER;
+use crate::models::api::error_response::{ErrorResponse, ErrorResponseType};
+use crate::models::db::groups::GroupEntity;
+use axum::http::{header, HeaderMap};
+
+pub mod error;
+pub mod models;
+
+/// The mount path of the PKI secrets engine, which is served by the default group
+pub const DEFAULT_MOUNT: &str = "pki";
+
+/// Resolves the mount path to a group. Each group is served under its name, and the
+/// default Vault mount `pki` falls back to the default group.
+pub async fn mount_group(mount: &str) -> Result<GroupEntity, ErrorResponse> {
+    match GroupEntity::find_by_name(mount).await {
+        Ok(group) => Ok(group),
+        Err(err) if err.typ == ErrorResponseType::NotFound && mount == DEFAULT_MOUNT => {
+            GroupEntity::find_by_id(&GroupEntity::find_default_id().await?).await
+        }
+        Err(err) if err.typ == ErrorResponseType::NotFound => Err(ErrorResponse::new(
+            ErrorResponseType::NotFound,
+            format!("No PKI mount '{}'", mount),
+        )),
+        Err(err) => Err(err),
+    }
+}
+
+/// Extracts the token, which is the API key of a client, from the `X-Vault-Token` header or
+/// from an `Authorization: Bearer` header.
+pub fn vault_token(headers: &HeaderMap) -> Result<&str, ErrorResponse> {
+    if let Some(token) = headers.get(VAULT_TOKEN_HEADER) {
+        return token.to_str().map_err(|_| {
+            ErrorResponse::new(ErrorResponseType::Unauthorized, "Malformed Vault token")
+        });
+    }
+
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .ok_or_else(|| ErrorResponse::new(ErrorResponseType::Unauthorized, "Missing Vault token"))
+}
+
+/// Parses a Vault TTL like `3600`, `30m`, `72h` or `1h30m` into seconds
+pub fn parse_ttl(ttl: &str) -> Result<i64, ErrorResponse> {
+    let err = || {
+        ErrorResponse::new(
+            ErrorResponseType::BadRequest,
+            format!("Invalid TTL '{}'", ttl),
+        )
+    };
+
+    if let Ok(secs) = ttl.parse::<i64>() {
+        return if secs > 0 { Ok(secs) } else { Err(err()) };
+    }
+
+    let mut secs = 0i64;
+    let mut num = String::new();
+    for c in ttl.chars() {
+        if c.is_ascii_digit() {
+            num.push(c);
+            continue;
+        }
+        let factor = match c {
+            's' => 1,
+            'm' => 60,
+            'h' => 3600,
+            'd' => 86400,
+            _ => return Err(err()),
+        };
+        let value = num.parse::<i64>().map_err(|_| err())?;
+        secs = value
+            .checked_mul(factor)
+            .and_then(|value| secs.checked_add(value))
+            .ok_or_else(err)?;
+        num.clear();
+    }
+    if !num.is_empty() || secs == 0 {
+        return Err(err());
+    }
+    Ok(secs)
+}
+
+/// Formats the raw serial of a certificate the way Vault does: colon separated hex bytes
+/// like `01:f4` without the leading zero bytes of the DER integer
+pub fn serial_to_hex(raw_serial: &[u8]) -> String {
+    let start = raw_serial
+        .iter()
+        .position(|b| *b != 0)
+        .unwrap_or(raw_serial.len().saturating_sub(1));
+    raw_serial[start..]
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<Vec<String>>()
+        .join(":")
+}
+
+/// Parses a serial in the Vault format, colons and dashes as separators are optional
+pub fn serial_from_hex(serial: &str) -> Result<i32, ErrorResponse> {
+    let hex = serial.replace([':', '-'], "");
+    u32::from_str_radix(&hex, 16)
+        .ok()
+        .and_then(|serial| i32::try_from(serial).ok())
+        .ok_or_else(|| {
+            ErrorResponse::new(
+                ErrorResponseType::BadRequest,
+                format!("Invalid serial number '{}'", serial),
+            )
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vault::models::VaultTtl;
+
+    #[test]
+    fn test_parse_ttl() {
+        assert_eq!(parse_ttl("3600").unwrap(), 3600);
+        assert_eq!(parse_ttl("30m").unwrap(), 1800);
+        assert_eq!(parse_ttl("72h").unwrap(), 259200);
+        assert_eq!(parse_ttl("1h30m10s").unwrap(), 5410);
+        assert_eq!(parse_ttl("2d").unwrap(), 172800);
+
+        assert!(parse_ttl("").is_err());
+        assert!(parse_ttl("0").is_err());
+        assert!(parse_ttl("-5").is_err());
+        assert!(parse_ttl("h").is_err());
+        assert!(parse_ttl("10").is_ok());
+        assert!(parse_ttl("10y").is_err());
+        assert!(parse_ttl("1h30").is_err());
+
+        assert_eq!(VaultTtl::Secs(1).hours().unwrap(), 1);
+        assert_eq!(VaultTtl::Secs(3600).hours().unwrap(), 1);
+        assert_eq!(VaultTtl::Secs(3601).hours().unwrap(), 2);
+        assert_eq!(VaultTtl::Duration("90m".to_string()).hours().unwrap(), 2);
+        assert_eq!(VaultTtl::Secs(i64::MAX).hours().unwrap(), i32::MAX);
+        let huge = format!("{}h", i64::MAX / 3600);
+        assert_eq!(VaultTtl::Duration(huge).hours().unwrap(), i32::MAX);
+        assert!(VaultTtl::Secs(0).hours().is_err());
+    }
+
+    #[test]
+    fn test_serial_hex() {
+        assert_eq!(serial_to_hex(&[1]), "01");
+        assert_eq!(serial_to_hex(&[0, 0, 1, 244]), "01:f4");
+        assert_eq!(serial_to_hex(&[0, 0xc3]), "c3");
+        assert_eq!(serial_to_hex(&[0]), "00");
+
+        assert_eq!(serial_from_hex("01:f4").unwrap(), 500);
+        assert_eq!(serial_from_hex("01-F4").unwrap(), 500);
+        assert_eq!(serial_from_hex("1f4").unwrap(), 500);
+        assert!(serial_from_hex("80:00:00:00").is_err());
+        assert!(serial_from_hex("zz").is_err());
+    }
+}
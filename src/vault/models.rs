@@ -0,0 +1,161 @@
+use crate::models::api::error_response::ErrorResponse;
+use crate::vault::parse_ttl;
+use serde::{Deserialize, Deserializer, Serialize};
+use uuid::Uuid;
+
+/// `pki/issue/:role`
+#[derive(Debug, Deserialize)]
+pub struct VaultIssueRequest {
+    pub common_name: String,
+    #[serde(default, deserialize_with = "comma_list")]
+    pub alt_names: Vec<String>,
+    #[serde(default, deserialize_with = "comma_list")]
+    pub ip_sans: Vec<String>,
+    pub ttl: Option<VaultTtl>,
+    #[serde(default)]
+    pub format: VaultFormat,
+    #[serde(default)]
+    pub exclude_cn_from_sans: bool,
+}
+
+/// `pki/sign/:role`
+#[derive(Debug, Deserialize)]
+pub struct VaultSignRequest {
+    pub csr: String,
+    pub ttl: Option<VaultTtl>,
+    #[serde(default)]
+    pub format: VaultFormat,
+}
+
+/// `pki/revoke`
+#[derive(Debug, Deserialize)]
+pub struct VaultRevokeRequest {
+    pub serial_number: String,
+}
+
+/// Vault accepts TTLs as seconds or as duration strings like `72h`
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum VaultTtl {
+    Secs(i64),
+    Duration(String),
+}
+
+impl VaultTtl {
+    pub fn secs(&self) -> Result<i64, ErrorResponse> {
+        match self {
+            Self::Secs(secs) => parse_ttl(&secs.to_string()),
+            Self::Duration(duration) => parse_ttl(duration),
+        }
+    }
+
+    /// The TTL rounded up to full hours, which is the resolution of the x509 clients
+    pub fn hours(&self) -> Result<i32, ErrorResponse> {
+        // clamped first to never overflow, `secs()` is always > 0
+        let secs = self.secs()?.min(i32::MAX as i64 * 3600);
+        Ok(((secs - 1) / 3600 + 1) as i32)
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VaultFormat {
+    #[default]
+    Pem,
+    /// Base64 encoded DER
+    Der,
+    /// The private key, if any, the certificate and the issuing CA in one PEM
+    PemBundle,
+}
+
+/// The envelope of each Vault API response
+#[derive(Debug, Serialize)]
+pub struct VaultResponse<T> {
+    pub request_id: Uuid,
+    pub lease_id: String,
+    pub renewable: bool,
+    pub lease_duration: i64,
+    pub data: T,
+    pub wrap_info: Option<()>,
+    pub warnings: Option<Vec<String>>,
+    pub auth: Option<()>,
+}
+
+impl<T> VaultResponse<T> {
+    pub fn new(data: T) -> Self {
+        Self {
+            request_id: Uuid::new_v4(),
+            lease_id: String::default(),
+            renewable: false,
+            lease_duration: 0,
+            data,
+            wrap_info: None,
+            warnings: None,
+            auth: None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct VaultCertData {
+    pub certificate: String,
+    pub issuing_ca: String,
+    pub ca_chain: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub private_key: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub private_key_type: Option<&'static str>,
+    pub serial_number: String,
+    /// not after as a unix timestamp in seconds in UTC format
+    pub expiration: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct VaultRevokeData {
+    pub revocation_time: i64,
+    pub revocation_time_rfc3339: String,
+}
+
+/// Vault accepts lists as comma separated strings and as JSON arrays
+fn comma_list<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum CommaList {
+        Csv(String),
+        List(Vec<String>),
+    }
+
+    let list = match CommaList::deserialize(deserializer)? {
+        CommaList::Csv(csv) => csv.split(',').map(|v| v.trim().to_string()).collect(),
+        CommaList::List(list) => list,
+    };
+    Ok(list.into_iter().filter(|v| !v.is_empty()).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_issue_request() {
+        let req: VaultIssueRequest = serde_json::from_str(
+            r#"{"common_name":"a.example.org","alt_names":"b.example.org, c.example.org","ttl":"72h"}"#,
+        )
+        .unwrap();
+        assert_eq!(req.alt_names, vec!["b.example.org", "c.example.org"]);
+        assert!(req.ip_sans.is_empty());
+        assert_eq!(req.ttl.unwrap().hours().unwrap(), 72);
+        assert_eq!(req.format, VaultFormat::Pem);
+
+        let req: VaultIssueRequest = serde_json::from_str(
+            r#"{"common_name":"a.example.org","ip_sans":["10.0.0.1"],"ttl":5400,"format":"pem_bundle"}"#,
+        )
+        .unwrap();
+        assert_eq!(req.ip_sans, vec!["10.0.0.1"]);
+        assert_eq!(req.ttl.unwrap().hours().unwrap(), 2);
+        assert_eq!(req.format, VaultFormat::PemBundle);
+    }
+}